@@ -0,0 +1,59 @@
+use assembler::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const BRAVO: &str = include_str!("../x69-bravo.conf");
+
+// An ISA with `count` mnemonic families, each with register/register, register/immediate, and
+// immediate-only forms, so the DFAs have a realistic amount of branching
+fn synthetic_config(count: usize) -> String {
+    let mut config = String::new();
+    for i in 0..count {
+        config += &format!("op{} r0, r1 -> {} [r0 | r1]\n", i, i & 0xFF);
+        config += &format!("op{} r0, i0:4 -> {} [i0 | r0]\n", i, i & 0xFF);
+        config += &format!("ld{} r0, i0:16 -> {} r0 i0\n", i, i & 0xFF);
+        config += &format!("op{} i0:8 -> {} i0\n", i, i & 0xFF);
+    }
+    config
+}
+
+fn synthetic_program(lines: usize) -> String {
+    let statements = ["nop", "add r1, r2", "sub r3, r4", "add r5, 7", "sub r6, 0x1234"];
+    let mut program = String::new();
+    for i in 0..lines {
+        program += statements[i % statements.len()];
+        program.push('\n');
+    }
+    program
+}
+
+fn load(config: &str) -> Assembler {
+    Config::new("[unknown]", config).load().unwrap().0.unwrap()
+}
+
+fn config_parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("config");
+    group.throughput(Throughput::Elements(BRAVO.lines().count() as u64));
+    group.bench_function("x69-bravo", |b| b.iter(|| load(BRAVO)));
+    for count in [16, 256] {
+        let config = synthetic_config(count);
+        group.throughput(Throughput::Elements(config.lines().count() as u64));
+        group.bench_with_input(BenchmarkId::new("synthetic", count), &config, |b, config| b.iter(|| load(config)));
+    }
+    group.finish();
+}
+
+fn assembly(c: &mut Criterion) {
+    let assembler = load(BRAVO);
+    let mut group = c.benchmark_group("assemble");
+    for lines in [1_000, 100_000] {
+        let program = synthetic_program(lines);
+        group.throughput(Throughput::Elements(lines as u64));
+        group.bench_with_input(BenchmarkId::new("lines", lines), &program, |b, program| b.iter(|| assembler.assemble(program)));
+        group.throughput(Throughput::Bytes(program.len() as u64));
+        group.bench_with_input(BenchmarkId::new("bytes", lines), &program, |b, program| b.iter(|| assembler.assemble(program)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, config_parsing, assembly);
+criterion_main!(benches);
@@ -0,0 +1,122 @@
+use crate::parser::*;
+
+// The bits of each encoded byte that are fixed by the pattern, as (mask, value)
+struct Encoding {
+    syntax: String,
+    name: String,
+    bytes: Vec<(u8, u8)>,
+}
+
+fn nibble(data: &CodegenData) -> (u8, u8) {
+    match *data {
+        CodegenData::Byte(b) => (0xF, b & 0xF),
+        _ => (0, 0),
+    }
+}
+
+fn encoding(name: &str, pattern: &Pattern) -> Encoding {
+    let mut bytes = Vec::new();
+    for codegen in pattern.codegen {
+        match codegen {
+            Codegen::Data(CodegenData::Byte(b)) => bytes.push((0xFF, *b)),
+            // Registers are at most 15, so the upper half of a register byte is always zero
+            Codegen::Data(CodegenData::Register(_)) => bytes.push((0xF0, 0)),
+            Codegen::Data(CodegenData::Immediate(_, width)) => bytes.extend(std::iter::repeat_n((0, 0), width / 8)),
+            Codegen::UpperLower(upper, lower) => {
+                let (upper_mask, upper_value) = nibble(upper);
+                let (lower_mask, lower_value) = nibble(lower);
+                bytes.push((upper_mask << 4 | lower_mask, upper_value << 4 | lower_value));
+            },
+            Codegen::Guard(_) => {},
+        }
+    }
+    Encoding { syntax: pattern.syntax(name), name: name.to_owned(), bytes }
+}
+
+impl Encoding {
+    // Whether the encoding can begin with (or is itself a prefix of) the given bytes
+    fn matches_prefix(&self, prefix: &[u8]) -> bool {
+        self.bytes.iter().zip(prefix).all(|((mask, value), byte)| byte & mask == *value)
+    }
+
+    // Whether some byte sequence starts with both encodings, making decoding ambiguous
+    fn overlaps(&self, other: &Encoding) -> bool {
+        self.bytes.iter().zip(&other.bytes).all(|((m1, v1), (m2, v2))| (v1 ^ v2) & m1 & m2 == 0)
+    }
+}
+
+pub struct Report {
+    pub depth: usize,
+    pub patterns: usize,
+    pub used: usize,
+    pub total: usize,
+    // Inclusive ranges of prefixes no pattern can start with
+    pub unused: Vec<(u32, u32)>,
+    // Syntaxes of overlapping patterns, and whether they belong to the same mnemonic
+    pub overlaps: Vec<(String, String, bool)>,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let digits = self.depth * 2;
+        writeln!(f, "{} patterns analyzed over {}-byte prefixes", self.patterns, self.depth)?;
+        writeln!(f, "density: {}/{} prefixes used ({:.2}%)", self.used, self.total, 100.0 * self.used as f64 / self.total as f64)?;
+        if !self.unused.is_empty() {
+            writeln!(f, "unused encodings:")?;
+            for (start, end) in &self.unused {
+                if start == end {
+                    writeln!(f, "  0x{:0width$X}", start, width = digits)?;
+                } else {
+                    writeln!(f, "  0x{:0width$X}-0x{:0width$X}", start, end, width = digits)?;
+                }
+            }
+        }
+        if !self.overlaps.is_empty() {
+            writeln!(f, "overlapping encodings:")?;
+            for (a, b, same) in &self.overlaps {
+                writeln!(f, "  '{}' and '{}'{}", a, b, if *same { " (same mnemonic)" } else { "" })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Enumerates every `depth` byte prefix (1 to 3 bytes) of the opcode space and compares every pair
+// of patterns for encodings that cannot be told apart
+pub fn analyze(assembler: &Assembler, depth: usize) -> Report {
+    let depth = depth.clamp(1, 3);
+    let mut names: Vec<_> = assembler.instructions.keys().collect();
+    names.sort();
+    let encodings: Vec<Encoding> = names.iter()
+        .flat_map(|name| assembler.instructions[*name].patterns().iter().map(|p| encoding(name, p)).collect::<Vec<_>>())
+        .collect();
+
+    let total = 1usize << (8 * depth);
+    let mut used = 0;
+    let mut unused = Vec::new();
+    let mut prefix = vec![0; depth];
+    for value in 0..total {
+        for (i, byte) in prefix.iter_mut().enumerate() {
+            *byte = (value >> (8 * (depth - 1 - i))) as u8;
+        }
+        if encodings.iter().any(|e| e.matches_prefix(&prefix)) {
+            used += 1;
+        } else {
+            match unused.last_mut() {
+                Some((_, end)) if *end + 1 == value as u32 => *end = value as u32,
+                _ => unused.push((value as u32, value as u32)),
+            }
+        }
+    }
+
+    let mut overlaps = Vec::new();
+    for (i, a) in encodings.iter().enumerate() {
+        for b in &encodings[i + 1..] {
+            if a.overlaps(b) {
+                overlaps.push((a.syntax.clone(), b.syntax.clone(), a.name == b.name));
+            }
+        }
+    }
+
+    Report { depth, patterns: encodings.len(), used, total, unused, overlaps }
+}
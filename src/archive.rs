@@ -0,0 +1,145 @@
+use crate::lexer::{Lexeme, Lexer, Token};
+use std::collections::HashSet;
+
+// A library of sources bundled by `x69asm ar`, from which a build takes only the members that
+// define a name it uses:
+//
+//     magic     "X69A"
+//     version   u8
+//     count     u32, the number of members
+//     members   count times the member's name, the names it exports, and its source
+//
+// Strings are a u32 length followed by UTF-8, lists of names a u32 count followed by the names,
+// and integers are little endian. Exports are what `.global` names in the source, read when the
+// archive is made so builds don't have to scan members they won't use.
+pub const MAGIC: &[u8; 4] = b"X69A";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub exports: Vec<String>,
+    pub source: String,
+}
+
+impl Member {
+    pub fn new(name: &str, source: String) -> Self {
+        Self { name: name.to_owned(), exports: exports(&source), source }
+    }
+}
+
+// Names given to `.global` in a source
+pub fn exports(source: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for line in source.lines() {
+        let lexemes: Vec<_> = Lexer::new(line).collect();
+        if let [Lexeme { token: Token::Directive(directive), .. }, rest @ ..] = lexemes.as_slice() {
+            if matches!(directive.to_lowercase().as_str(), ".global" | ".globl") {
+                names.extend(rest.iter().filter_map(|l| match l.token {
+                    Token::Ident(name) => Some(name.to_owned()),
+                    _ => None,
+                }));
+            }
+        }
+    }
+    names
+}
+
+// Adds the identifiers a source mentions to `used` and the labels it defines to `defined`
+fn scan(source: &str, used: &mut HashSet<String>, defined: &mut HashSet<String>) {
+    for line in source.lines() {
+        let lexemes: Vec<_> = Lexer::new(line).collect();
+        if let [Lexeme { token: Token::Ident(label), .. }, Lexeme { token: Token::Colon, .. }, ..] = lexemes.as_slice() {
+            defined.insert(label.to_string());
+        }
+        used.extend(lexemes.iter().filter_map(|l| match l.token {
+            Token::Ident(name) => Some(name.to_owned()),
+            _ => None,
+        }));
+    }
+}
+
+// Members the sources need, in archive order: each exports a name that the sources or the members
+// taken before it use without defining
+pub fn resolve<'a, S: AsRef<str>>(members: &'a [Member], sources: &[S]) -> Vec<&'a Member> {
+    let mut used = HashSet::new();
+    let mut defined = HashSet::new();
+    for source in sources {
+        scan(source.as_ref(), &mut used, &mut defined);
+    }
+    let mut taken = vec![false; members.len()];
+    let needed = |member: &Member, used: &HashSet<String>, defined: &HashSet<String>| {
+        member.exports.iter().any(|name| used.contains(name) && !defined.contains(name))
+    };
+    while let Some(index) = (0..members.len()).find(|i| !taken[*i] && needed(&members[*i], &used, &defined)) {
+        taken[index] = true;
+        scan(&members[index].source, &mut used, &mut defined);
+    }
+    members.iter().zip(taken).filter(|(_, taken)| *taken).map(|(member, _)| member).collect()
+}
+
+fn str(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend((s.len() as u32).to_le_bytes());
+    bytes.extend(s.as_bytes());
+}
+
+pub fn write(members: &[Member]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend((members.len() as u32).to_le_bytes());
+    for member in members {
+        str(&mut bytes, &member.name);
+        bytes.extend((member.exports.len() as u32).to_le_bytes());
+        for name in &member.exports {
+            str(&mut bytes, name);
+        }
+        str(&mut bytes, &member.source);
+    }
+    bytes
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<usize> { self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize) }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()?;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn member(&mut self) -> Option<Member> {
+        let name = self.str()?;
+        let exports = (0..self.u32()?).map(|_| self.str()).collect::<Option<_>>()?;
+        Some(Member { name, exports, source: self.str()? })
+    }
+}
+
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn read(bytes: &[u8]) -> Result<Vec<Member>, String> {
+    if !is_archive(bytes) {
+        return Err("not an x69 archive".to_owned());
+    }
+    if bytes.get(4) != Some(&VERSION) {
+        return Err("archive was written by a different version".to_owned());
+    }
+    let mut reader = Reader(&bytes[5..]);
+    let count = reader.u32().ok_or("archive is truncated")?;
+    let members = (0..count).map(|_| reader.member()).collect::<Option<Vec<_>>>().ok_or("archive is truncated")?;
+    if !reader.0.is_empty() {
+        return Err("archive has bytes after its last member".to_owned());
+    }
+    Ok(members)
+}
@@ -0,0 +1,100 @@
+use crate::lexer::{Lexeme, Lexer, Span, Token};
+use crate::new_parser::{parse_statement, Expr, Operand, OperandKind, Statement};
+use serde_json::{json, Value};
+
+// The statements of a source as JSON for tools built on the front end, one object per line:
+//
+//     { "line": 3, "statement": { "kind": "instruction", "mnemonic": ..., "operands": [...] } }
+//     { "line": 4, "errors": [{ "message": "unexpected token: ';'", "span": { "start": 9, "end": 10 } }] }
+//
+// Spans are byte offsets into the line, and lines count from 1. Directives aren't parsed into
+// statements, so they are given with the text of each operand. Macros are not expanded.
+pub fn source_json(source: &str) -> Value {
+    let lines: Vec<_> = source.lines().enumerate().map(|(line, text)| {
+        let mut node = match directive(text) {
+            Some(statement) => json!({ "statement": statement }),
+            None => match parse_statement(text).unwrap() {
+                (Some(statement), _) => json!({ "statement": statement_json(&statement) }),
+                (None, logs) => {
+                    let errors: Vec<_> = logs.iter().map(|log| json!({ "message": log.message(), "span": log.span().map(span_json) })).collect();
+                    json!({ "errors": errors })
+                },
+            },
+        };
+        node["line"] = json!(line + 1);
+        node
+    }).collect();
+    Value::Array(lines)
+}
+
+fn span_json(span: &Span) -> Value {
+    json!({ "start": span.start, "end": span.end })
+}
+
+// A directive line, possibly after a label, with its operands split at commas
+pub fn directive(text: &str) -> Option<Value> {
+    let lexemes: Vec<_> = Lexer::new(text).collect();
+    let (label, rest) = match lexemes.as_slice() {
+        [Lexeme { token: Token::Ident(name), span, .. }, Lexeme { token: Token::Colon, .. }, rest @ ..] => (Some((*name, span.clone())), rest),
+        rest => (None, rest),
+    };
+    let (name, operands) = match rest {
+        [Lexeme { token: Token::Directive(name), span, .. }, operands @ ..] => ((*name, span.clone()), operands),
+        _ => return None,
+    };
+    let operands: Vec<_> = operands.split(|l| l.token == Token::Comma).filter(|o| !o.is_empty()).map(|operand| {
+        let span = operand[0].span.start..operand[operand.len() - 1].span.end;
+        json!({ "text": &text[span.clone()], "span": span_json(&span) })
+    }).collect();
+    let statement = json!({ "kind": "directive", "name": name.0, "span": span_json(&name.1), "operands": operands });
+    Some(match label {
+        Some((label, span)) => json!({ "kind": "label", "name": label, "span": span_json(&span), "statement": statement }),
+        None => statement,
+    })
+}
+
+pub fn statement_json(statement: &Statement) -> Value {
+    match statement {
+        Statement::Empty => json!({ "kind": "empty" }),
+        Statement::Instruction { mnemonic, operands } => json!({
+            "kind": "instruction",
+            "mnemonic": { "name": mnemonic.node, "span": span_json(&mnemonic.span) },
+            "operands": operands.iter().map(operand_json).collect::<Vec<_>>(),
+        }),
+        Statement::Bundle(statements, span) => json!({
+            "kind": "bundle",
+            "span": span_json(span),
+            "statements": statements.iter().map(statement_json).collect::<Vec<_>>(),
+        }),
+        Statement::Label(label, statement) => json!({
+            "kind": "label",
+            "name": label.node,
+            "span": span_json(&label.span),
+            "statement": statement_json(statement),
+        }),
+    }
+}
+
+fn operand_json(operand: &Operand) -> Value {
+    let mut node = match &operand.node {
+        OperandKind::Register(r) => json!({ "kind": "register", "index": r }),
+        OperandKind::Immediate(expr) => json!({ "kind": "immediate", "value": expr_json(expr) }),
+        OperandKind::Literal(expr) => json!({ "kind": "literal", "value": expr_json(expr) }),
+        OperandKind::Comma => json!({ "kind": "comma" }),
+        OperandKind::Punct(symbol) => json!({ "kind": "punctuation", "symbol": symbol }),
+    };
+    node["span"] = span_json(&operand.span);
+    node
+}
+
+fn expr_json(expr: &Expr) -> Value {
+    match expr {
+        Expr::Integer(value) => json!({ "kind": "integer", "value": value }),
+        Expr::Symbol(name) => json!({ "kind": "symbol", "name": name }),
+        Expr::Bank(label) => json!({ "kind": "bank", "label": label }),
+        Expr::Offset(label) => json!({ "kind": "offset", "label": label }),
+        Expr::Unary(op, operand) => json!({ "kind": "unary", "op": format!("{:?}", op).to_lowercase(), "operand": expr_json(operand) }),
+        Expr::Binary(op, left, right) => json!({ "kind": "binary", "op": format!("{:?}", op).to_lowercase(), "left": expr_json(left), "right": expr_json(right) }),
+        Expr::Call(function, arguments) => json!({ "kind": "call", "function": function.name(), "arguments": arguments.iter().map(expr_json).collect::<Vec<_>>() }),
+    }
+}
@@ -0,0 +1,129 @@
+use crate::directives::Image;
+use crate::disasm::{Disassembler, Line};
+use crate::listing::Row;
+use crate::map::SymbolMap;
+
+// Instruction boundaries and text of a linear disassembly
+struct Listing {
+    // (offset, length, text), sorted by offset
+    instructions: Vec<(usize, usize, String)>,
+}
+
+impl Listing {
+    fn new(disassembler: &Disassembler, bytes: &[u8]) -> Self {
+        let instructions = disassembler.disassemble(bytes).into_iter().map(|line| match line {
+            Line::Instruction { decoded, .. } => (decoded.offset, decoded.bytes.len(), decoded.text),
+            Line::Undecodable { offset, byte } => (offset, 1, format!(".byte 0x{:02X}", byte)),
+        }).collect();
+        Self { instructions }
+    }
+    
+    // The instruction covering the address
+    fn at(&self, address: usize) -> Option<&(usize, usize, String)> {
+        let i = self.instructions.partition_point(|(offset, _, _)| *offset <= address);
+        self.instructions[..i].last().filter(|(offset, length, _)| address < offset + length)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+// Lists each run of differing bytes with the instructions covering it in both images
+pub fn diff(disassembler: &Disassembler, old: &[u8], new: &[u8], symbols: Option<&SymbolMap>) -> String {
+    let old_listing = Listing::new(disassembler, old);
+    let new_listing = Listing::new(disassembler, new);
+    let common = old.len().min(new.len());
+    let mut out = String::new();
+    let mut runs = 0;
+    let mut i = 0;
+    while i < common {
+        if old[i] == new[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < common && old[i] != new[i] {
+            i += 1;
+        }
+        runs += 1;
+        out += &format!("0x{:04X}-0x{:04X}", start, i - 1);
+        if let Some(symbol) = symbols.and_then(|s| s.nearest(start)) {
+            out += &format!(" <{}>", symbol);
+        }
+        out += &format!(":\n  - {}\n  + {}\n", hex(&old[start..i]), hex(&new[start..i]));
+        for (sign, listing) in [('-', &old_listing), ('+', &new_listing)] {
+            let mut address = start;
+            while address < i {
+                match listing.at(address) {
+                    Some((offset, length, text)) => {
+                        out += &format!("  {} 0x{:04X}: {}\n", sign, offset, text);
+                        address = offset + length;
+                    },
+                    None => break,
+                }
+            }
+        }
+    }
+    if old.len() != new.len() {
+        out += &format!("sizes differ: {} bytes -> {} bytes\n", old.len(), new.len());
+    }
+    out += &format!("{} differing region{}\n", runs, if runs == 1 { "" } else { "s" });
+    out
+}
+
+// Where each run of code in the listing came from: (address, length, site, text), with code a
+// macro expanded to attributed to the line invoking it, as in a source map
+fn sources(rows: &[Row]) -> Vec<(usize, usize, String, String)> {
+    let mut sources = Vec::new();
+    let mut line = None;
+    for row in rows {
+        if let Some(number) = row.line {
+            line = Some((&row.file, number, row.text.trim()));
+        }
+        if let (Some((file, number, text)), false) = (line, row.code.is_empty()) {
+            sources.push((row.address, row.code.len(), format!("{}:{}", file, number + 1), text.to_owned()));
+        }
+    }
+    sources
+}
+
+// Compares an image, which needs its listing for the sources of its code, with a golden binary of
+// it, listing the first `limit` runs of differing bytes with the lines they were assembled from.
+// Nothing is returned when they're the same.
+pub fn verify(golden: &[u8], image: &Image, limit: usize) -> Option<String> {
+    let code = &image.code;
+    if code.as_slice() == golden {
+        return None;
+    }
+    let sources = sources(&image.listing);
+    let common = code.len().min(golden.len());
+    let mut out = String::new();
+    let mut runs = 0;
+    let mut i = 0;
+    while i < common {
+        if code[i] == golden[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < common && code[i] != golden[i] {
+            i += 1;
+        }
+        runs += 1;
+        if runs > limit {
+            continue;
+        }
+        let (from, to) = (image.base + start, image.base + i);
+        out += &format!("0x{:04X}-0x{:04X}:\n  - {}\n  + {}\n", from, to - 1, hex(&golden[start..i]), hex(&code[start..i]));
+        for (_, _, site, text) in sources.iter().filter(|(address, length, _, _)| *address < to && from < address + length) {
+            out += &format!("  at {}: {}\n", site, text);
+        }
+    }
+    if code.len() != golden.len() {
+        out += &format!("sizes differ: {} bytes in the golden image, {} bytes assembled\n", golden.len(), code.len());
+    }
+    let shown = if runs > limit { format!(", the first {} shown", limit) } else { String::new() };
+    out += &format!("{} differing region{}{}\n", runs, if runs == 1 { "" } else { "s" }, shown);
+    Some(out)
+}
@@ -0,0 +1,437 @@
+use crate::lexer::Operator;
+use crate::macros::{Dialect, Limits, Macro, Terminator};
+use crate::new_parser::{Condition, PUNCTUATION};
+use crate::parser::*;
+use crate::hazard;
+use crate::linker::Placement;
+use crate::peephole;
+use crate::style::OperandStyle;
+use std::collections::HashMap;
+
+const MAGIC: &[u8; 4] = b"X69C";
+const VERSION: u8 = 27;
+
+// Operators of `if` conditions in codegen, by their number in the compiled config
+const COMPARISONS: [Operator; 6] = [Operator::Lt, Operator::Le, Operator::Gt, Operator::Ge, Operator::Eq, Operator::Ne];
+const REJECT: u32 = u32::MAX;
+
+// FNV-1a, used to detect when a compiled config is stale relative to its source
+pub fn hash_source(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) { self.0.push(v); }
+    fn u32(&mut self, v: u32) { self.0.extend(&v.to_le_bytes()); }
+    fn u64(&mut self, v: u64) { self.0.extend(&v.to_le_bytes()); }
+    fn usize(&mut self, v: usize) { self.u64(v as u64); }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.0.extend(s.as_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend(bytes);
+    }
+
+    fn transition(&mut self, t: &Transition) {
+        match *t {
+            Transition::Reject => self.u32(REJECT),
+            Transition::NextState(next) => self.u32(next as u32),
+        }
+    }
+
+    fn codegen_data(&mut self, data: &CodegenData) {
+        match *data {
+            CodegenData::Byte(b) => { self.u8(0); self.u8(b); },
+            CodegenData::Immediate(imm, width) => { self.u8(1); self.usize(imm); self.usize(width); },
+            CodegenData::Register(r) => { self.u8(2); self.usize(r); },
+        }
+    }
+
+    fn codegen(&mut self, codegen: &[Codegen]) {
+        self.u32(codegen.len() as u32);
+        for c in codegen {
+            match c {
+                Codegen::Data(data) => {
+                    self.u8(0);
+                    self.codegen_data(data);
+                },
+                Codegen::UpperLower(upper, lower) => {
+                    self.u8(1);
+                    self.codegen_data(upper);
+                    self.codegen_data(lower);
+                },
+                Codegen::Guard(condition) => {
+                    self.u8(2);
+                    self.usize(condition.immediate);
+                    self.u8(COMPARISONS.iter().position(|op| *op == condition.op).unwrap_or(0) as u8);
+                    self.usize(condition.value);
+                },
+            }
+        }
+    }
+
+    fn semantics(&mut self, semantics: &Option<String>) {
+        match semantics {
+            Some(semantics) => {
+                self.u8(1);
+                self.str(semantics);
+            },
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> { self.take(1).map(|b| b[0]) }
+    fn u32(&mut self) -> Option<u32> { self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])) }
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+    fn usize(&mut self) -> Option<usize> { self.u64().map(|v| v as usize) }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        self.take(len).map(|b| b.to_vec())
+    }
+
+    // Punctuation is only ever one of the symbols the parser knows
+    fn punct(&mut self) -> Option<&'static str> {
+        let punct = self.str()?;
+        PUNCTUATION.iter().find(|p| **p == punct).copied()
+    }
+
+    fn transition(&mut self) -> Option<Transition> {
+        match self.u32()? {
+            REJECT => Some(Transition::Reject),
+            next => Some(Transition::NextState(next as usize)),
+        }
+    }
+
+    fn codegen_data(&mut self) -> Option<CodegenData> {
+        match self.u8()? {
+            0 => Some(CodegenData::Byte(self.u8()?)),
+            1 => Some(CodegenData::Immediate(self.usize()?, self.usize()?)),
+            2 => Some(CodegenData::Register(self.usize()?)),
+            _ => None,
+        }
+    }
+
+    fn codegen(&mut self) -> Option<Vec<Codegen>> {
+        let mut codegen = Vec::new();
+        for _ in 0..self.u32()? {
+            codegen.push(match self.u8()? {
+                0 => Codegen::Data(self.codegen_data()?),
+                1 => Codegen::UpperLower(self.codegen_data()?, self.codegen_data()?),
+                2 => Codegen::Guard(Condition { immediate: self.usize()?, op: *COMPARISONS.get(self.u8()? as usize)?, value: self.usize()? }),
+                _ => return None,
+            });
+        }
+        Some(codegen)
+    }
+
+    fn semantics(&mut self) -> Option<Option<String>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => Some(Some(self.str()?)),
+        }
+    }
+}
+
+// Serializes an assembler into the compiled config format
+pub fn compile(assembler: &Assembler, source_hash: u64) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.0.extend(MAGIC);
+    w.u8(VERSION);
+    w.u64(source_hash);
+
+    // Sorted by name like the macros below, so compiling a config twice gives the same bytes
+    let mut instructions: Vec<_> = assembler.instructions.iter().collect();
+    instructions.sort_by_key(|(name, _)| *name);
+    w.u32(instructions.len() as u32);
+    for (name, instruction) in instructions {
+        w.str(name);
+        w.u32(instruction.states.len() as u32);
+        for state in &instruction.states {
+            w.transition(&state.register);
+            w.transition(&state.immediate);
+            w.transition(&state.comma);
+            w.u32(state.punctuation.len() as u32);
+            for (punct, next) in &state.punctuation {
+                w.str(punct);
+                w.u32(*next as u32);
+            }
+            w.u32(state.keywords.len() as u32);
+            for (keyword, next) in &state.keywords {
+                w.str(keyword);
+                w.u32(*next as u32);
+            }
+            match &state.accept_codegen {
+                Some(codegen) => {
+                    w.u8(1);
+                    w.codegen(codegen);
+                },
+                None => w.u8(0),
+            }
+            w.semantics(&state.accept_semantics);
+            w.u32(state.relaxations.len() as u32);
+            for (codegen, semantics) in &state.relaxations {
+                w.codegen(codegen);
+                w.semantics(semantics);
+            }
+        }
+    }
+
+    let mut slots: Vec<_> = assembler.slots.iter().collect();
+    slots.sort();
+    w.u32(slots.len() as u32);
+    for (name, mnemonics) in slots {
+        w.str(name);
+        w.u32(mnemonics.len() as u32);
+        mnemonics.iter().for_each(|m| w.str(m));
+    }
+    w.u32(assembler.bundles.len() as u32);
+    for format in &assembler.bundles {
+        w.u32(format.slots.len() as u32);
+        format.slots.iter().for_each(|s| w.str(s));
+        w.u32(format.header.len() as u32);
+        format.header.iter().for_each(|b| w.u8(*b));
+    }
+    w.u32(assembler.prefixes.len() as u32);
+    for prefix in &assembler.prefixes {
+        w.str(&prefix.name);
+        w.u32(prefix.bytes.len() as u32);
+        prefix.bytes.iter().for_each(|b| w.u8(*b));
+        w.u32(prefix.mnemonics.len() as u32);
+        prefix.mnemonics.iter().for_each(|m| w.str(m));
+    }
+    w.u32(assembler.regions.len() as u32);
+    for region in &assembler.regions {
+        w.str(&region.name);
+        w.usize(region.start);
+        w.usize(region.end);
+        w.bytes(&region.preamble);
+        w.bytes(&region.postamble);
+    }
+    w.u32(assembler.placements.len() as u32);
+    for placement in &assembler.placements {
+        w.str(&placement.section);
+        w.usize(placement.align);
+        w.u8(placement.fill.is_some() as u8);
+        w.u8(placement.fill.unwrap_or(0));
+        w.u8(placement.symbols as u8);
+    }
+    w.u8(assembler.src_first as u8);
+    let mut macros: Vec<_> = assembler.macros.iter().collect();
+    macros.sort_by_key(|(name, _)| name.as_str());
+    w.u32(macros.len() as u32);
+    for (name, definition) in macros {
+        w.str(name);
+        w.u32(definition.params.len() as u32);
+        definition.params.iter().for_each(|p| w.str(p));
+        w.u8(definition.variadic as u8);
+        w.u32(definition.body.len() as u32);
+        definition.body.iter().for_each(|l| w.str(l));
+    }
+    w.u32(assembler.reserved.len() as u32);
+    for (register, role) in &assembler.reserved {
+        w.usize(*register);
+        w.str(role);
+    }
+    w.u32(assembler.stack_effects.len() as u32);
+    for (mnemonic, effect) in &assembler.stack_effects {
+        w.str(mnemonic);
+        w.u64(*effect as u64);
+    }
+    w.u32(assembler.returns.len() as u32);
+    assembler.returns.iter().for_each(|m| w.str(m));
+    w.u32(assembler.cycles.len() as u32);
+    for (mnemonic, cycles) in &assembler.cycles {
+        w.str(mnemonic);
+        w.usize(*cycles);
+    }
+    w.u32(assembler.alignments.len() as u32);
+    for (mnemonic, alignment) in &assembler.alignments {
+        w.str(mnemonic);
+        w.usize(*alignment);
+    }
+    match assembler.misaligned {
+        Misaligned::Error => w.u8(0),
+        Misaligned::Pad => w.u8(1),
+        Misaligned::PadWith(byte) => {
+            w.u8(2);
+            w.u8(byte);
+        },
+    }
+    w.u8(assembler.dialect.terminator as u8);
+    w.u8(assembler.dialect.bare_labels as u8);
+    w.str(&assembler.mnemonic_chars);
+    w.u8(assembler.unicode_identifiers as u8);
+    w.u8(assembler.style.hex as u8);
+    w.str(&assembler.style.prefix);
+    w.u32(assembler.style.registers.len() as u32);
+    for (register, name) in &assembler.style.registers {
+        w.usize(*register);
+        w.str(name);
+    }
+    w.u32(assembler.peepholes.len() as u32);
+    assembler.peepholes.iter().for_each(|rule| w.str(&rule.text));
+    w.u32(assembler.delay_slots.len() as u32);
+    for (mnemonic, slots) in &assembler.delay_slots {
+        w.str(mnemonic);
+        w.usize(*slots);
+    }
+    w.u32(assembler.hazards.len() as u32);
+    for hazard in &assembler.hazards {
+        w.u8(hazard.illegal as u8);
+        w.str(&hazard.text);
+    }
+    w.u32(assembler.nop.len() as u32);
+    w.0.extend(&assembler.nop);
+    w.u32(assembler.categories.len() as u32);
+    for (mnemonic, category) in &assembler.categories {
+        w.str(mnemonic);
+        w.u8(Category::ALL.iter().position(|c| c == category).unwrap() as u8);
+    }
+    w.0
+}
+
+// Loads a compiled config; returns None if it is malformed or was built from a different source
+pub fn load(bytes: &[u8], source_hash: Option<u64>) -> Option<Assembler> {
+    let mut r = Reader(bytes);
+    if r.take(4)? != MAGIC || r.u8()? != VERSION {
+        return None;
+    }
+    let hash = r.u64()?;
+    if source_hash.is_some_and(|expected| expected != hash) {
+        return None;
+    }
+
+    let mut instructions = HashMap::new();
+    for _ in 0..r.u32()? {
+        let name = r.str()?;
+        let mut states = Vec::new();
+        for _ in 0..r.u32()? {
+            let register = r.transition()?;
+            let immediate = r.transition()?;
+            let comma = r.transition()?;
+            let punctuation = (0..r.u32()?).map(|_| Some((r.punct()?, r.u32()? as usize))).collect::<Option<Vec<_>>>()?;
+            let keywords = (0..r.u32()?).map(|_| Some((r.str()?, r.u32()? as usize))).collect::<Option<Vec<_>>>()?;
+            let accept_codegen = match r.u8()? {
+                0 => None,
+                _ => Some(r.codegen()?),
+            };
+            let accept_semantics = r.semantics()?;
+            let relaxations = (0..r.u32()?).map(|_| Some((r.codegen()?, r.semantics()?))).collect::<Option<Vec<_>>>()?;
+            states.push(TransitionTable { register, immediate, comma, punctuation, keywords, accept_codegen, accept_semantics, relaxations });
+        }
+        let in_bounds = |t: &Transition| match *t {
+            Transition::Reject => true,
+            Transition::NextState(next) => next < states.len(),
+        };
+        if states.is_empty() || !states.iter().all(|s| in_bounds(&s.register) && in_bounds(&s.immediate) && in_bounds(&s.comma) && s.punctuation.iter().all(|(_, next)| *next < states.len()) && s.keywords.iter().all(|(_, next)| *next < states.len())) {
+            return None;
+        }
+        instructions.insert(name, Instruction { states });
+    }
+
+    let mut slots = HashMap::new();
+    for _ in 0..r.u32()? {
+        let name = r.str()?;
+        let mnemonics = (0..r.u32()?).map(|_| r.str()).collect::<Option<Vec<_>>>()?;
+        slots.insert(name, mnemonics);
+    }
+    let mut bundles = Vec::new();
+    for _ in 0..r.u32()? {
+        let slots = (0..r.u32()?).map(|_| r.str()).collect::<Option<Vec<_>>>()?;
+        let header = (0..r.u32()?).map(|_| r.u8()).collect::<Option<Vec<_>>>()?;
+        bundles.push(BundleFormat { slots, header });
+    }
+    let mut prefixes = Vec::new();
+    for _ in 0..r.u32()? {
+        let name = r.str()?;
+        let bytes = (0..r.u32()?).map(|_| r.u8()).collect::<Option<Vec<_>>>()?;
+        let mnemonics = (0..r.u32()?).map(|_| r.str()).collect::<Option<Vec<_>>>()?;
+        prefixes.push(Prefix { name, bytes, mnemonics });
+    }
+    let regions = (0..r.u32()?).map(|_| Some(Region { name: r.str()?, start: r.usize()?, end: r.usize()?, preamble: r.bytes()?, postamble: r.bytes()? })).collect::<Option<Vec<_>>>()?;
+    let mut placements = Vec::new();
+    for _ in 0..r.u32()? {
+        let mut placement = Placement::new(&r.str()?, None);
+        placement.align = r.usize()?;
+        let (filled, fill) = (r.u8()? != 0, r.u8()?);
+        placement.fill = filled.then_some(fill);
+        placement.symbols = r.u8()? != 0;
+        placements.push(placement);
+    }
+    let src_first = r.u8()? != 0;
+    let mut macros = HashMap::new();
+    for _ in 0..r.u32()? {
+        let name = r.str()?;
+        let params = (0..r.u32()?).map(|_| r.str()).collect::<Option<Vec<_>>>()?;
+        let variadic = r.u8()? != 0;
+        let body = (0..r.u32()?).map(|_| r.str()).collect::<Option<Vec<_>>>()?;
+        macros.insert(name, Macro { params, variadic, body });
+    }
+    let reserved = (0..r.u32()?).map(|_| Some((r.usize()?, r.str()?))).collect::<Option<Vec<_>>>()?;
+    let stack_effects = (0..r.u32()?).map(|_| Some((r.str()?, r.u64()? as isize))).collect::<Option<Vec<_>>>()?;
+    let returns = (0..r.u32()?).map(|_| r.str()).collect::<Option<Vec<_>>>()?;
+    let cycles = (0..r.u32()?).map(|_| Some((r.str()?, r.usize()?))).collect::<Option<Vec<_>>>()?;
+    let alignments = (0..r.u32()?).map(|_| Some((r.str()?, r.usize()?))).collect::<Option<Vec<_>>>()?;
+    let misaligned = match r.u8()? {
+        0 => Misaligned::Error,
+        1 => Misaligned::Pad,
+        _ => Misaligned::PadWith(r.u8()?),
+    };
+    let terminator = match r.u8()? {
+        0 => Terminator::Newline,
+        1 => Terminator::Semicolon,
+        2 => Terminator::Period,
+        _ => return None,
+    };
+    let dialect = Dialect { terminator, bare_labels: r.u8()? != 0 };
+    let mnemonic_chars = r.str()?;
+    let unicode_identifiers = r.u8()? != 0;
+    let hex = r.u8()? != 0;
+    let prefix = r.str()?;
+    let registers = (0..r.u32()?).map(|_| Some((r.usize()?, r.str()?))).collect::<Option<Vec<_>>>()?;
+    let style = OperandStyle { hex, prefix, registers };
+    let peepholes = (0..r.u32()?).map(|_| peephole::parse_rule(&r.str()?).ok()).collect::<Option<Vec<_>>>()?;
+    let delay_slots = (0..r.u32()?).map(|_| Some((r.str()?, r.usize()?))).collect::<Option<Vec<_>>>()?;
+    let hazards = (0..r.u32()?).map(|_| {
+        let illegal = r.u8()? == 1;
+        hazard::parse_hazard(&r.str()?, illegal).ok()
+    }).collect::<Option<Vec<_>>>()?;
+    let length = r.u32()? as usize;
+    let nop = r.take(length)?.to_vec();
+    let categories = (0..r.u32()?).map(|_| Some((r.str()?, *Category::ALL.get(r.u8()? as usize)?))).collect::<Option<Vec<_>>>()?;
+
+    if r.0.is_empty() {
+        Some(Assembler { instructions, slots, bundles, prefixes, regions, config_hash: hash, timestamp: None, seed: 0, budgets: Vec::new(), placements, src_first, macros, reserved, listing: false, gc_sections: false, xref: false, program: false, stack_effects, returns, cycles, alignments, misaligned, dialect, mnemonic_chars, style, peepholes, peephole: false, thread_jumps: false, delay_slots, fill_delay_slots: false, hazards, cancel: None, progress: None, keep_going: false, include_commands: false, nop, unicode_identifiers, categories, arch: String::new(), archs: Vec::new(), limits: Limits::default() })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Stops an assembly between statements, when another thread asks it to or once a deadline passes,
+// so an editor can drop the assembly of a large file the user has since changed. Clones share the
+// flag, so the caller keeps one and gives the assembler another.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel {
+    flag: Arc<AtomicBool>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Also stops the assembly once the time has passed from now
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self { flag: Arc::default(), deadline: Some((Instant::now() + timeout, timeout)) }
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    // Why the assembly has to stop, if it does
+    pub fn reason(&self) -> Option<String> {
+        match self.deadline {
+            _ if self.flag.load(Ordering::Relaxed) => Some("assembly was cancelled".to_owned()),
+            Some((deadline, timeout)) if Instant::now() >= deadline => Some(format!("assembly timed out after {:?}", timeout)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::deadcode::successors;
+use crate::directives::Image;
+use crate::ir::Item;
+use crate::parser::{encoded_length, Assembler};
+use crate::semantics;
+use serde_json::json;
+
+// A run of instructions that is only entered at its first and only left after its last
+#[derive(Debug)]
+pub struct Block {
+    pub start: usize,
+    // Address just past the last instruction
+    pub end: usize,
+    // The label placed at the start, if any
+    pub label: Option<String>,
+    // Address and source text of each instruction
+    pub instructions: Vec<(usize, String)>,
+    // Addresses the last instruction can continue at, and whether each is the next instruction
+    pub successors: Vec<(usize, bool)>,
+}
+
+// Splits the operations of the program into basic blocks. A block starts at a label, at an address
+// something can continue at other than the next instruction, and after an instruction that
+// doesn't only fall through; data ends one as well. Targets come from the semantics of the form
+// an instruction was encoded with, or from its first immediate for a branch by its `.category`.
+// `text` gives the source of the statement written in a file at a line.
+pub fn blocks(assembler: &Assembler, image: &Image, text: &dyn Fn(&str, usize) -> Option<String>) -> Vec<Block> {
+    let mut operations: Vec<_> = image.program.iter().filter_map(|statement| match &statement.item {
+        Item::Operation(operation) => {
+            let length = encoded_length(&operation.codegen);
+            let immediates: Vec<_> = operation.immediates.iter().map(|i| Some(i.value)).collect();
+            let statements = operation.semantics.as_deref().and_then(|s| semantics::parse(s).ok());
+            let mut targets = successors(&immediates, length, assembler.category(&operation.mnemonic), statements.as_ref(), statement.address);
+            targets.sort_unstable();
+            targets.dedup();
+            let text = text(&statement.file, statement.line).unwrap_or_else(|| operation.mnemonic.clone());
+            Some((statement.address, length, text, targets))
+        },
+        Item::Bytes(_) => None,
+    }).collect();
+    operations.sort_by_key(|(address, ..)| *address);
+
+    let mut leaders: Vec<usize> = image.labels.iter().map(|(_, address)| *address).collect();
+    for (address, length, _, targets) in &operations {
+        if targets.as_slice() != [address + length] {
+            leaders.extend(targets);
+            leaders.push(address + length);
+        }
+    }
+    let mut blocks: Vec<Block> = Vec::new();
+    for (address, length, text, targets) in operations {
+        let next = address + length;
+        let continues = blocks.last().is_some_and(|block| block.end == address && block.successors == [(address, true)] && !leaders.contains(&address));
+        if !continues {
+            let label = image.labels.iter().find(|(_, a)| *a == address).map(|(name, _)| name.clone());
+            blocks.push(Block { start: address, end: address, label, instructions: Vec::new(), successors: Vec::new() });
+        }
+        let block = blocks.last_mut().unwrap();
+        block.end = next;
+        block.instructions.push((address, text));
+        block.successors = targets.into_iter().map(|target| (target, target == next)).collect();
+    }
+    blocks
+}
+
+// Name of the block starting at the address, or the address when no block does
+fn node(blocks: &[Block], address: usize) -> String {
+    match blocks.iter().position(|block| block.start == address) {
+        Some(index) => format!("b{}", index),
+        None => format!("\"{:#06x}\"", address),
+    }
+}
+
+// A digraph with a box for each block listing its instructions, and a dashed edge where a block
+// falls through to the next
+pub fn dot(blocks: &[Block]) -> String {
+    let mut out = "digraph cfg {\n    node [shape=box, fontname=monospace];\n".to_owned();
+    for (i, block) in blocks.iter().enumerate() {
+        let mut label = block.label.as_ref().map_or(String::new(), |label| format!("{}:\\l", label));
+        for (address, text) in &block.instructions {
+            label += &format!("{:#06x}  {}\\l", address, text.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        out += &format!("    b{} [label=\"{}\"];\n", i, label);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        for (target, falls) in &block.successors {
+            let style = if *falls { " [style=dashed]" } else { "" };
+            out += &format!("    b{} -> {}{};\n", i, node(blocks, *target), style);
+        }
+    }
+    out + "}\n"
+}
+
+// e.g. `{ "blocks": [{ "id": 0, "label": "start", "start": 0, "end": 3, "instructions": [{
+// "address": 0, "text": "jmp next" }], "successors": [{ "address": 7, "block": 2, "kind": "jump"
+// }] }] }`, where the block is null for an address no block starts at
+pub fn json(blocks: &[Block]) -> String {
+    let nodes: Vec<_> = blocks.iter().enumerate().map(|(i, block)| json!({
+        "id": i,
+        "label": block.label,
+        "start": block.start,
+        "end": block.end,
+        "instructions": block.instructions.iter().map(|(address, text)| json!({ "address": address, "text": text })).collect::<Vec<_>>(),
+        "successors": block.successors.iter().map(|(target, falls)| json!({
+            "address": target,
+            "block": blocks.iter().position(|b| b.start == *target),
+            "kind": if *falls { "fall" } else { "jump" },
+        })).collect::<Vec<_>>(),
+    })).collect();
+    serde_json::to_string_pretty(&json!({ "blocks": nodes })).unwrap() + "\n"
+}
@@ -1,226 +1,876 @@
-use crate::lexer::{Lexer, Lexeme, Token};
-use crate::log::{Logger, LoggedResult, Origin};
+use crate::lexer::{Lexer, Lexeme, Span, Token};
+use crate::log::{LogLevel, Logger, LoggedResult, Origin};
+use crate::macros::{Preprocessor, Terminator};
+use crate::new_parser::*;
 use crate::parser::*;
+use crate::cache::hash_source;
+use crate::files;
+use crate::hazard;
+use crate::isa;
+use crate::linker::Placement;
+use crate::peephole;
+use crate::progress::{Event, Progress};
+use crate::semantics;
 use std::collections::HashMap;
 
-fn codegen_brackets<'a>(lexer: &mut Lexer<'a, Token<'a>>, name: &str, registers: usize, immediates: &[(usize, usize)]) -> LoggedResult<Codegen> {
-    let mut logger = Logger::new(None);
-    
-    macro_rules! match_codegen_data_after {
-        ($after:expr) => {
-            match lexer.next() {
-                Some(Lexeme { token: Token::Integer(int), slice }) => {
-                    if int > 0xF {
-                        logger.log_warning(format!("{} is larger than 4 bits and will be truncated", slice));
-                    }
-                    CodegenData::Byte((int & 0xF) as u8)
-                },
-                Some(Lexeme { token: Token::Immediate(im), .. }) => {
-                    if im >= immediates.len() {
-                        logger.log_error(format!("'{}' uses immediate {} which is not given in the instruction pattern", name, im));
-                        return logger.into_none();
-                    }
-                    let immediate = immediates[im];
-                    if immediate.1 != 4 {
-                        logger.log_error("width of immediate in bracket group must be 4 (for now)".to_owned());
-                        return logger.into_none();
-                    }
-                    CodegenData::Immediate(immediate.0, immediate.1)
-                },
-                Some(Lexeme { token: Token::Register(r), .. }) => {
-                    if r >= registers {
-                        logger.log_error(format!("'{}' uses register {} which is not given in the instruction pattern", name, r));
-                    }
-                    CodegenData::Register(r)
-                },
-                Some(Lexeme { slice, .. }) => {
-                    logger.log_error(format!("expected a literal or register after '{}', but got '{}'", $after, slice));
-                    return logger.into_none();
+// A form accepted at a state of an instruction's pattern, along with where it was defined so a
+// conflict can point at the one that is kept
+struct Site {
+    length: usize,
+    // Chosen by an `if`, so it can have the same length as other forms
+    guarded: bool,
+    syntax: String,
+    origin: Option<Origin>,
+    span: Span,
+}
+
+impl Site {
+    fn describe(&self) -> String {
+        match &self.origin {
+            Some(origin) => format!("'{}' at {}:{}", self.syntax, origin.file, origin.line + 1),
+            None => format!("'{}'", self.syntax),
+        }
+    }
+}
+
+type Sites = HashMap<(String, usize), Vec<Site>>;
+
+// Wider immediates can only be emitted as a nibble in an `if` branch with a condition on them, which
+// is expected to keep the value within 4 bits
+fn codegen_nibble(logger: &mut Logger, span: Span, nibble: Nibble, name: &str, registers: usize, immediates: &[(usize, usize)], guards: &[Condition]) -> Option<CodegenData> {
+    match nibble {
+        Nibble::Integer(int) => {
+            if int > 0xF {
+                logger.log_permissive(Some(span), format!("{} is larger than 4 bits and will be truncated", int));
+            }
+            Some(CodegenData::Byte((int & 0xF) as u8))
+        },
+        Nibble::Immediate(im) => {
+            if im >= immediates.len() {
+                logger.log_error_at(span, format!("'{}' uses immediate {} which is not given in the instruction pattern", name, im));
+                return None;
+            }
+            let immediate = immediates[im];
+            if immediate.1 != 4 && !guards.iter().any(|guard| guard.immediate == im) {
+                logger.log_error_at(span, "width of immediate in bracket group must be 4 (for now)".to_owned());
+                return None;
+            }
+            Some(CodegenData::Immediate(im, 4))
+        },
+        Nibble::Register(r) => {
+            if r >= registers {
+                logger.log_error_at(span, format!("'{}' uses register {} which is not given in the instruction pattern", name, r));
+            }
+            Some(CodegenData::Register(r))
+        },
+    }
+}
+
+// Follows the transition out of the current state, creating a new state if there is none
+fn advance(states: &mut Vec<TransitionTable>, current: usize, transition: fn(&mut TransitionTable) -> &mut Transition) -> usize {
+    if let Transition::NextState(next) = *transition(&mut states[current]) {
+        return next;
+    }
+    let next = states.len();
+    *transition(&mut states[current]) = Transition::NextState(next);
+    states.push(TransitionTable::default());
+    next
+}
+
+fn advance_punct(states: &mut Vec<TransitionTable>, current: usize, punct: &'static str) -> usize {
+    if let Some((_, next)) = states[current].punctuation.iter().find(|(p, _)| *p == punct) {
+        return *next;
+    }
+    let next = states.len();
+    states[current].punctuation.push((punct, next));
+    states.push(TransitionTable::default());
+    next
+}
+
+// Keywords are kept in lowercase, since sources can write them in any case like mnemonics
+fn advance_keyword(states: &mut Vec<TransitionTable>, current: usize, keyword: &str) -> usize {
+    let keyword = keyword.to_lowercase();
+    if let Some((_, next)) = states[current].keywords.iter().find(|(k, _)| *k == keyword) {
+        return *next;
+    }
+    let next = states.len();
+    states[current].keywords.push((keyword, next));
+    states.push(TransitionTable::default());
+    next
+}
+
+// Leaves an instruction as it was before a malformed definition was added to it: the states the
+// definition added are removed along with the transitions into them, and the instruction is removed
+// when the definition was its first. `length` is the number of states it had before, if any.
+fn roll_back(map: &mut HashMap<String, Instruction>, name: &str, length: Option<usize>) {
+    let (Some(length), Some(instruction)) = (length, map.get_mut(name)) else {
+        map.remove(name);
+        return;
+    };
+    instruction.states.truncate(length);
+    for table in &mut instruction.states {
+        for transition in [&mut table.register, &mut table.immediate, &mut table.comma] {
+            if matches!(transition, Transition::NextState(next) if *next >= length) {
+                *transition = Transition::Reject;
+            }
+        }
+        table.punctuation.retain(|(_, next)| *next < length);
+        table.keywords.retain(|(_, next)| *next < length);
+    }
+}
+
+// Adds the definition, and when its last immediate is optional, the shorter pattern without it whose
+// codegen and semantics use the default value in its place
+fn add_definition(map: &mut HashMap<String, Instruction>, sites: &mut Sites, logger: &mut Logger, definition: &Definition) {
+    // The short form would only report the same problems again
+    if !add_form(map, sites, logger, definition) {
+        return;
+    }
+    let default = match &definition.default {
+        Some(default) => default,
+        None => return,
+    };
+    let (im, width) = match definition.pattern.last().map(|item| item.node) {
+        Some(PatternItem::Immediate(im, width)) => (im, width),
+        _ => return,
+    };
+    if width < usize::BITS as usize && default.node >> width != 0 {
+        logger.log_error_at(default.span.clone(), format!("default {} does not fit in i{}:{}", default.slice, im, width));
+        return;
+    }
+
+    let mut codegen = Vec::new();
+    for item in &definition.codegen {
+        let nibble = |nibble: Nibble| match nibble {
+            Nibble::Immediate(i) if i == im => Nibble::Integer(default.node & 0xF),
+            nibble => nibble,
+        };
+        match item.node {
+            CodegenItem::Immediate(i, emitted) if i == im => {
+                let bytes = default.node.to_le_bytes();
+                codegen.extend(bytes[..emitted.unwrap_or(width) / 8].iter().map(|b| Spanned { node: CodegenItem::Integer(*b as usize), ..item.clone() }));
+            },
+            CodegenItem::Bracket(upper, lower) => codegen.push(Spanned { node: CodegenItem::Bracket(nibble(upper), nibble(lower)), ..item.clone() }),
+            _ => codegen.push(item.clone()),
+        }
+    }
+    let semantics = definition.semantics.as_ref().map(|semantics| {
+        let mut text = String::new();
+        let mut end = 0;
+        for lexeme in Lexer::new(semantics.node).filter(|l| l.token == Token::Immediate(im)) {
+            text += &semantics.node[end..lexeme.span.start];
+            text += &default.node.to_string();
+            end = lexeme.span.end;
+        }
+        text + &semantics.node[end..]
+    });
+    // Conditions on the left out immediate are decided by the default
+    let mut guards = Vec::new();
+    for guard in &definition.guards {
+        match guard.immediate == im {
+            true if !guard.holds(default.node) => return,
+            true => {},
+            false => guards.push(*guard),
+        }
+    }
+    let short = Definition {
+        prefix: definition.prefix.clone(),
+        mnemonic: definition.mnemonic.clone(),
+        pattern: definition.pattern[..definition.pattern.len() - 2].to_vec(),
+        codegen,
+        semantics: definition.semantics.as_ref().zip(semantics.as_deref()).map(|(s, text)| Spanned { node: text, slice: text, span: s.span.clone() }),
+        default: None,
+        guards,
+    };
+    add_form(map, sites, logger, &short);
+}
+
+fn add_form(map: &mut HashMap<String, Instruction>, sites: &mut Sites, logger: &mut Logger, definition: &Definition) -> bool {
+    let errors = logger.errors();
+    let name = definition.name();
+    let length = map.get(&name).map(|instruction| instruction.states.len());
+    let instruction = map.entry(name.clone()).or_insert(Instruction { states: vec![TransitionTable::default()] });
+    let states = &mut instruction.states;
+    let mut current_state = 0;
+    let mut registers = 0;
+    let mut immediates = Vec::new();
+    // Numbers of the registers given so far, since an operand given twice in the pattern would
+    // be captured twice. Codegen can reference one operand as many times as it needs instead.
+    let mut register_numbers = Vec::new();
+
+    // Generate DFA
+    for item in &definition.pattern {
+        match item.node {
+            PatternItem::Immediate(im, _) if immediates.iter().any(|(i, _)| *i == im) => {
+                logger.log_error_at(item.span.clone(), format!("i{} is given twice in the pattern; reference it more than once in the codegen to encode it in several fields", im));
+            },
+            PatternItem::Register(r) if register_numbers.contains(&r) => {
+                logger.log_error_at(item.span.clone(), format!("r{} is given twice in the pattern; reference it more than once in the codegen to encode it in several fields", r));
+            },
+            PatternItem::Immediate(im, width) => {
+                if im != immediates.len() {
+                    logger.log_permissive(Some(item.span.clone()), format!("immediates are parsed in the order they appear regardless of number; {} will correspond to i{} in codegen", item.slice, immediates.len()));
                 }
-                None => {
-                    logger.log_error(format!("expected a literal or register after '{}'", $after));
-                    return logger.into_none();
+                immediates.push((im, width));
+                current_state = advance(states, current_state, |t| &mut t.immediate);
+            },
+            PatternItem::Register(r) => {
+                if r != registers {
+                    logger.log_permissive(Some(item.span.clone()), format!("registers are parsed in the order they appear regardless of number; {} will correspond to r{} in codegen", item.slice, registers));
                 }
-            };
+                current_state = advance(states, current_state, |t| &mut t.register);
+                register_numbers.push(r);
+                registers += 1;
+            },
+            PatternItem::Comma => current_state = advance(states, current_state, |t| &mut t.comma),
+            PatternItem::Punct(p) => current_state = advance_punct(states, current_state, p),
+            PatternItem::Keyword(word) => current_state = advance_keyword(states, current_state, word),
         }
     }
-    macro_rules! match_symbol {
-        ($token:pat, $symbol:expr) => {
-            match lexer.next() {
-                Some(Lexeme { token: $token, .. }) => {},
-                Some(Lexeme { slice, .. }) => {
-                    logger.log_error(format!("expected '{}' in bracket group, but got '{}'", $symbol, slice));
-                    return logger.into_none();
-                },
-                None => {
-                    logger.log_error(format!("expected '{}' in bracket group", $symbol));
-                    return logger.into_none();
+
+    let mut codegen = Vec::new();
+    for guard in &definition.guards {
+        if guard.immediate >= immediates.len() {
+            logger.log_error_at(definition.mnemonic.span.clone(), format!("'{}' compares immediate {} which is not given in the instruction pattern", name, guard.immediate));
+        }
+        codegen.push(Codegen::Guard(*guard));
+    }
+    for item in &definition.codegen {
+        let span = item.span.clone();
+        match item.node {
+            CodegenItem::Integer(int) => {
+                if int > 255 {
+                    logger.log_permissive(Some(span), format!("{} is larger than 8 bits and will be truncated", item.slice));
                 }
-            }
+                codegen.push(Codegen::byte(int as u8));
+            },
+            CodegenItem::Immediate(im, emitted) => {
+                if im >= immediates.len() {
+                    logger.log_error_at(span, format!("'{}' uses immediate {} which is not given in the instruction pattern", name, im));
+                    continue;
+                }
+                let declared = immediates[im].1;
+                let width = emitted.unwrap_or(declared);
+                if width > declared {
+                    logger.log_error_at(span, format!("'{}' is wider than the {} bits the pattern gives it", item.slice, declared));
+                } else if width < declared && !definition.guards.iter().any(|guard| guard.immediate == im) {
+                    // Only an `if` on the immediate can keep its value within the narrower field
+                    logger.log_error_at(span, format!("'{}' emits i{} in {} bits, but the pattern declares it {} bits wide; declare it as i{}:{}, or emit it in an 'if' branch that keeps it within {} bits", item.slice, im, width, declared, im, width, width));
+                } else if width % 8 != 0 {
+                    logger.log_error_at(span, "immediate width must be byte aligned (for now)".to_owned());
+                } else if width > usize::BITS as usize {
+                    logger.log_error_at(span, format!("immediates are at most {} bits wide", usize::BITS));
+                } else {
+                    codegen.push(Codegen::immediate(im, width));
+                }
+            },
+            CodegenItem::Register(r) => {
+                if r >= registers {
+                    logger.log_error_at(span, format!("'{}' uses register {} which is not given in the instruction pattern", name, r));
+                }
+                codegen.push(Codegen::register(r));
+            },
+            CodegenItem::Bracket(upper, lower) => {
+                let upper = codegen_nibble(logger, span.clone(), upper, &name, registers, &immediates, &definition.guards);
+                let lower = codegen_nibble(logger, span, lower, &name, registers, &immediates, &definition.guards);
+                if let (Some(upper), Some(lower)) = (upper, lower) {
+                    codegen.push(Codegen::UpperLower(upper, lower));
+                }
+            },
         }
     }
+    let semantics = definition.semantics.as_ref().and_then(|semantics| {
+        // Parse errors point at the token they were found at, and the rest at the whole semantics
+        let start = semantics.span.start;
+        let parsed = semantics::parse_spanned(semantics.node).map_err(|(message, span)| (message, start + span.start..start + span.end));
+        match parsed.and_then(|s| semantics::validate(&s, registers, immediates.len()).map_err(|message| (message, semantics.span.clone()))) {
+            Ok(()) => Some(semantics.node.trim().to_owned()),
+            Err((message, span)) => {
+                logger.log_error_at(span, message);
+                None
+            },
+        }
+    });
+    // A malformed definition is left out so the rest of the ISA still builds and is checked against
+    // the forms that are actually valid, though the config as a whole still fails to load
+    if logger.errors() > errors {
+        roll_back(map, &name, length);
+        return false;
+    }
+
+    // A pattern given again is a longer or shorter form of it, chosen by whether the immediates fit.
+    // Forms chosen by `if` can have the same length, since their conditions pick between them.
+    let encoded = encoded_length(&codegen);
+    let guarded = !definition.guards.is_empty();
+    let forms = sites.entry((name.clone(), current_state)).or_default();
+    if let Some(kept) = forms.iter().find(|site| immediates.is_empty() || site.length == encoded && !site.guarded && !guarded) {
+        logger.log_error_at(definition.mnemonic.span.clone(), format!("'{}' conflicts with {}, which is kept; other forms of a pattern must take immediates and differ in length, or be chosen by 'if'", definition.syntax(), kept.describe()));
+        roll_back(map, &name, length);
+        return false;
+    }
+    forms.push(Site { length: encoded, guarded, syntax: definition.syntax(), origin: logger.origin.clone(), span: definition.mnemonic.span.clone() });
     
-    let upper = match_codegen_data_after!('[');
-    match_symbol!(Token::Or, '|');
-    let lower = match_codegen_data_after!('|');
-    match_symbol!(Token::CloseBracket, ']');
-    
-    logger.into_result(|| Codegen::UpperLower(upper, lower))
+    let table = &mut map.get_mut(&name).unwrap().states[current_state];
+    if let Some(accepted) = table.accept_codegen.take() {
+        let mut forms = vec![(accepted, table.accept_semantics.take())];
+        forms.append(&mut table.relaxations);
+        forms.push((codegen, semantics));
+        forms.sort_by_key(|(c, _)| encoded_length(c));
+        // The shortest form is accepted and the rest are relaxations of it
+        let (shortest, semantics) = forms.remove(0);
+        table.accept_codegen = Some(shortest);
+        table.accept_semantics = semantics;
+        table.relaxations = forms;
+    } else {
+        table.accept_codegen = Some(codegen);
+        table.accept_semantics = semantics;
+    }
+    true
+}
+
+// Logs a malformed directive at its operands, or at the line when it has none
+fn log_usage<'a>(logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>], message: String) {
+    let span = lexemes.first().zip(lexemes.last()).map(|(first, last)| first.span.start..last.span.end);
+    logger.log_at(LogLevel::Error, span, message);
+}
+
+// `.slot name mnemonics...`, declaring the instructions a bundle slot accepts
+fn add_slot<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let name = match lexemes.first() {
+        Some(Lexeme { token: Token::Ident(name), .. }) => name.to_string(),
+        _ => {
+            log_usage(logger, lexemes, "expected '.slot <name> <mnemonics>'".to_owned());
+            return;
+        },
+    };
+    let mut mnemonics = Vec::new();
+    for lexeme in &lexemes[1..] {
+        match lexeme.token {
+            Token::Ident(mnemonic) if assembler.instructions.contains_key(&mnemonic.to_lowercase()) => mnemonics.push(mnemonic.to_lowercase()),
+            Token::Ident(mnemonic) => logger.log_error_at(lexeme.span.clone(), format!("unknown instruction '{}' in slot '{}'", mnemonic, name)),
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a mnemonic, but got '{}'", lexeme.slice)),
+        }
+    }
+    if assembler.slots.insert(name.clone(), mnemonics).is_some() {
+        logger.log_error_at(lexemes[0].span.clone(), format!("slot '{}' is already defined", name));
+    }
+}
+
+// `.bundle slots... -> header bytes`, a format for instructions issued together
+fn add_bundle<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (slots, header) = match lexemes.iter().position(|l| l.token == Token::Arrow) {
+        Some(arrow) => (&lexemes[..arrow], &lexemes[arrow + 1..]),
+        None => {
+            log_usage(logger, lexemes, "expected '->' following the slots of a bundle".to_owned());
+            return;
+        },
+    };
+    let mut format = BundleFormat { slots: Vec::new(), header: Vec::new() };
+    for lexeme in slots {
+        match lexeme.token {
+            Token::Comma => {},
+            Token::Ident(slot) if assembler.slots.contains_key(slot) => format.slots.push(slot.to_owned()),
+            Token::Ident(slot) => logger.log_error_at(lexeme.span.clone(), format!("unknown slot '{}'", slot)),
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a slot name, but got '{}'", lexeme.slice)),
+        }
+    }
+    for lexeme in header {
+        match lexeme.token {
+            Token::Integer(byte @ 0..=255) => format.header.push(byte as u8),
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a byte in the bundle header, but got '{}'", lexeme.slice)),
+        }
+    }
+    if format.slots.is_empty() {
+        log_usage(logger, slots, "a bundle needs at least one slot".to_owned());
+    }
+    assembler.bundles.push(format);
+}
+
+// `.operand_order dst_first` or `.operand_order src_first`
+fn set_operand_order<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    match lexemes {
+        [Lexeme { token: Token::Ident("dst_first"), .. }] => assembler.src_first = false,
+        [Lexeme { token: Token::Ident("src_first"), .. }] => assembler.src_first = true,
+        _ => log_usage(logger, lexemes, "expected '.operand_order dst_first' or '.operand_order src_first'".to_owned()),
+    }
+}
+
+// `.region name start, end`, an inclusive address range sections are placed in
+fn add_region<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (name, start, end) = match lexemes {
+        [Lexeme { token: Token::Ident(name), .. }, Lexeme { token: Token::Integer(start), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Integer(end), .. }] => (*name, *start, *end),
+        _ => {
+            log_usage(logger, lexemes, "expected '.region <name> <start>, <end>'".to_owned());
+            return;
+        },
+    };
+    if end < start {
+        logger.log_error_at(lexemes[3].span.clone(), format!("region '{}' ends before it starts", name));
+    } else if let Some(other) = assembler.regions.iter().find(|r| r.name == name || r.start <= end && start <= r.end) {
+        logger.log_error_at(lexemes[0].span.clone(), format!("region '{}' overlaps region '{}'", name, other.name));
+    } else {
+        assembler.regions.push(Region::new(name, start, end));
+    }
+}
+
+// `.preamble name bytes...` or `.postamble name bytes...`, fixed bytes such as a boot signature
+// placed at the start or the end of a region
+fn set_amble<'a>(assembler: &mut Assembler, logger: &mut Logger, directive: &str, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (name, span, bytes) = match lexemes {
+        [Lexeme { token: Token::Ident(name), span, .. }, bytes @ ..] if !bytes.is_empty() => (*name, span.clone(), bytes),
+        _ => {
+            log_usage(logger, lexemes, format!("expected '{} <region> <bytes>...'", directive));
+            return;
+        },
+    };
+    let mut code = Vec::new();
+    for lexeme in bytes {
+        match lexeme.token {
+            Token::Integer(byte @ 0..=255) => code.push(byte as u8),
+            Token::Comma => {},
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a byte of the {} of region '{}', but got '{}'", &directive[1..], name, lexeme.slice)),
+        }
+    }
+    if let Err(message) = assembler.set_amble(name, directive == ".postamble", code) {
+        logger.log_error_at(span, message);
+    }
+}
+
+// `.section_defaults .data align 4 fill 0xff symbols`, the alignment, fill, and bound symbols of a
+// section wherever the source or the linker script puts it. A linker script placing the section
+// gives all of them itself.
+fn add_section_defaults<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (name, options) = match lexemes {
+        [Lexeme { slice, token: Token::Directive(_) | Token::Ident(_), .. }, options @ ..] => (*slice, options),
+        _ => {
+            log_usage(logger, lexemes, "expected '.section_defaults <name> [align <bytes>] [fill <byte>] [symbols]'".to_owned());
+            return;
+        },
+    };
+    if assembler.placements.iter().any(|p| p.section == name) {
+        logger.log_error_at(lexemes[0].span.clone(), format!("section '{}' already has defaults", name));
+        return;
+    }
+    let mut placement = Placement::new(name, None);
+    let words: Vec<_> = options.iter().map(|l| l.slice).collect();
+    match placement.set_options(&words) {
+        Ok(()) => assembler.placements.push(placement),
+        Err(message) => log_usage(logger, options, message),
+    }
+}
+
+// `.reserved r15 sp`, a register the source should only write where it allows it
+fn add_reserved<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    match lexemes {
+        [Lexeme { token: Token::Register(r), span, .. }, Lexeme { token: Token::Ident(role), .. }] => {
+            if assembler.reserved.iter().any(|(reserved, _)| reserved == r) {
+                logger.log_error_at(span.clone(), format!("r{} is already reserved", r));
+            } else {
+                assembler.reserved.push((*r, role.to_string()));
+            }
+        },
+        _ => log_usage(logger, lexemes, "expected '.reserved <register> <role>'".to_owned()),
+    }
+}
+
+// `.prefix rep 0xF3 : movs stos`, bytes that can come before the instructions listed, or before
+// any instruction if none are
+fn add_prefix<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (name, rest) = match lexemes {
+        [Lexeme { token: Token::Ident(name), .. }, rest @ ..] if !rest.is_empty() => (name.to_lowercase(), rest),
+        _ => {
+            log_usage(logger, lexemes, "expected '.prefix <name> <bytes>... [: <mnemonics>...]'".to_owned());
+            return;
+        },
+    };
+    let (bytes, mnemonics) = match rest.iter().position(|l| l.token == Token::Colon) {
+        Some(colon) => (&rest[..colon], &rest[colon + 1..]),
+        None => (rest, &[][..]),
+    };
+    let mut prefix = Prefix { name: name.clone(), bytes: Vec::new(), mnemonics: Vec::new() };
+    for lexeme in bytes {
+        match lexeme.token {
+            Token::Integer(byte @ 0..=255) => prefix.bytes.push(byte as u8),
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a byte of prefix '{}', but got '{}'", name, lexeme.slice)),
+        }
+    }
+    for lexeme in mnemonics {
+        match lexeme.token {
+            Token::Ident(mnemonic) if assembler.instructions.contains_key(&mnemonic.to_lowercase()) => prefix.mnemonics.push(mnemonic.to_lowercase()),
+            Token::Ident(mnemonic) => logger.log_error_at(lexeme.span.clone(), format!("unknown instruction '{}' after prefix '{}'", mnemonic, name)),
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a mnemonic, but got '{}'", lexeme.slice)),
+        }
+    }
+    if prefix.bytes.is_empty() {
+        log_usage(logger, bytes, format!("prefix '{}' needs at least one byte", name));
+    } else if assembler.instructions.contains_key(&name) {
+        logger.log_error_at(lexemes[0].span.clone(), format!("prefix '{}' has the name of an instruction", name));
+    } else if assembler.prefixes.iter().any(|p| p.name == name) {
+        logger.log_error_at(lexemes[0].span.clone(), format!("prefix '{}' is already defined", name));
+    } else {
+        assembler.prefixes.push(prefix);
+    }
+}
+
+// `.mnemonic_chars .?`, characters besides letters, digits, and underscores mnemonics defined
+// after it can hold. A digit given lets mnemonics start with digits.
+fn set_mnemonic_chars<'a>(assembler: &mut Assembler, logger: &mut Logger, source: &str, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (first, last) = match (lexemes.first(), lexemes.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            log_usage(logger, lexemes, "expected '.mnemonic_chars <characters>'".to_owned());
+            return;
+        },
+    };
+    let chars: String = source[first.span.start..last.span.end].chars().filter(|c| !c.is_whitespace()).collect();
+    match chars.chars().find(|c| ",;:{}=\"'".contains(*c)) {
+        Some(c) => logger.log_error_at(first.span.start..last.span.end, format!("'{}' separates parts of a statement, so mnemonics can't hold it", c)),
+        None => assembler.mnemonic_chars = chars,
+    }
+}
+
+// `.display hex`, `.display decimal`, `.display prefix #`, or `.display register r13 sp`, how
+// disassembly and diagnostics write operands
+fn set_display<'a>(assembler: &mut Assembler, logger: &mut Logger, source: &str, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let style = &mut assembler.style;
+    match lexemes {
+        [Lexeme { token: Token::Ident("hex"), .. }] => style.hex = true,
+        [Lexeme { token: Token::Ident("decimal"), .. }] => style.hex = false,
+        [Lexeme { token: Token::Ident("prefix"), .. }, first, .., last] | [Lexeme { token: Token::Ident("prefix"), .. }, first @ last] => {
+            style.prefix = source[first.span.start..last.span.end].to_owned();
+        },
+        [Lexeme { token: Token::Ident("register"), .. }, Lexeme { token: Token::Register(r), span, .. }, Lexeme { token: Token::Ident(name), .. }] => {
+            if style.registers.iter().any(|(named, _)| named == r) {
+                logger.log_error_at(span.clone(), format!("r{} is already named", r));
+            } else {
+                style.registers.push((*r, name.to_string()));
+            }
+        },
+        _ => log_usage(logger, lexemes, "expected '.display hex', '.display decimal', '.display prefix <text>', or '.display register <register> <name>'".to_owned()),
+    }
+}
+
+// `.stack_push push [count]`, `.stack_pop pop [count]`, or `.stack_return ret`
+fn add_stack_effect<'a>(assembler: &mut Assembler, logger: &mut Logger, directive: &str, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (mnemonic, count) = match lexemes {
+        [Lexeme { token: Token::Ident(mnemonic), .. }] => (mnemonic.to_lowercase(), 1),
+        [Lexeme { token: Token::Ident(mnemonic), .. }, Lexeme { token: Token::Integer(count), .. }] if directive != ".stack_return" => (mnemonic.to_lowercase(), *count as isize),
+        _ => {
+            let count = if directive == ".stack_return" { "" } else { " [count]" };
+            log_usage(logger, lexemes, format!("expected '{} <mnemonic>{}'", directive, count));
+            return;
+        },
+    };
+    match directive {
+        ".stack_push" => assembler.stack_effects.push((mnemonic, count)),
+        ".stack_pop" => assembler.stack_effects.push((mnemonic, -count)),
+        _ => assembler.returns.push(mnemonic),
+    }
+}
+
+// `.cycles add 2`, the cycles every form of an instruction takes
+fn add_cycles<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    match lexemes {
+        [Lexeme { token: Token::Ident(mnemonic), span, .. }, Lexeme { token: Token::Integer(cycles), .. }] => {
+            let mnemonic = mnemonic.to_lowercase();
+            if assembler.cycles.iter().any(|(m, _)| *m == mnemonic) {
+                logger.log_error_at(span.clone(), format!("cycles of '{}' are already given", mnemonic));
+            } else {
+                assembler.cycles.push((mnemonic, *cycles));
+            }
+        },
+        _ => log_usage(logger, lexemes, "expected '.cycles <mnemonic> <count>'".to_owned()),
+    }
+}
+
+// `.category branch jmp jz call`, what the instructions listed do
+fn add_category<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (category, mnemonics) = match lexemes {
+        [Lexeme { token: Token::Ident(name), span, .. }, mnemonics @ ..] if !mnemonics.is_empty() => match Category::parse(name) {
+            Some(category) => (category, mnemonics),
+            None => {
+                let names: Vec<_> = Category::ALL.iter().map(|c| format!("'{}'", c.name())).collect();
+                logger.log_error_at(span.clone(), format!("unknown category '{}', expected one of {}", name, names.join(", ")));
+                return;
+            },
+        },
+        _ => {
+            log_usage(logger, lexemes, "expected '.category <category> <mnemonics>'".to_owned());
+            return;
+        },
+    };
+    for lexeme in mnemonics {
+        match lexeme.token {
+            Token::Ident(mnemonic) => {
+                let mnemonic = mnemonic.to_lowercase();
+                match assembler.categories.iter().find(|(m, _)| *m == mnemonic) {
+                    Some((_, given)) => logger.log_error_at(lexeme.span.clone(), format!("'{}' is already in category '{}'", mnemonic, given.name())),
+                    None => assembler.categories.push((mnemonic, category)),
+                }
+            },
+            _ => logger.log_error_at(lexeme.span.clone(), format!("expected a mnemonic, but got '{}'", lexeme.slice)),
+        }
+    }
+}
+
+// `.delay_slot jmp [slots]`, the instructions after a branch that run before it takes effect
+fn add_delay_slot<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    let (mnemonic, span, slots) = match lexemes {
+        [Lexeme { token: Token::Ident(mnemonic), span, .. }] => (mnemonic.to_lowercase(), span, 1),
+        [Lexeme { token: Token::Ident(mnemonic), span, .. }, Lexeme { token: Token::Integer(slots), .. }] if *slots > 0 => (mnemonic.to_lowercase(), span, *slots),
+        _ => {
+            log_usage(logger, lexemes, "expected '.delay_slot <mnemonic> [slots]'".to_owned());
+            return;
+        },
+    };
+    if assembler.delay_slots.iter().any(|(m, _)| *m == mnemonic) {
+        logger.log_error_at(span.clone(), format!("delay slots of '{}' are already given", mnemonic));
+    } else {
+        assembler.delay_slots.push((mnemonic, slots));
+    }
+}
+
+// `.instruction_align mnemonic bytes`, requiring the instruction to be placed at a multiple of bytes
+fn add_alignment<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    match lexemes {
+        [Lexeme { token: Token::Ident(mnemonic), span, .. }, Lexeme { token: Token::Integer(alignment), .. }] if *alignment > 0 => {
+            let mnemonic = mnemonic.to_lowercase();
+            if assembler.alignments.iter().any(|(m, _)| *m == mnemonic) {
+                logger.log_error_at(span.clone(), format!("alignment of '{}' is already given", mnemonic));
+            } else {
+                assembler.alignments.push((mnemonic, *alignment));
+            }
+        },
+        _ => log_usage(logger, lexemes, "expected '.instruction_align <mnemonic> <bytes>'".to_owned()),
+    }
+}
+
+// `.misaligned pad [byte]` to pad instructions up to their alignment with the no-op or the byte, or
+// `.misaligned error`
+fn set_misaligned<'a>(assembler: &mut Assembler, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+    match lexemes {
+        [Lexeme { token: Token::Ident("pad"), .. }] => assembler.misaligned = Misaligned::Pad,
+        [Lexeme { token: Token::Ident("pad"), .. }, Lexeme { token: Token::Integer(byte @ 0..=0xFF), .. }] => assembler.misaligned = Misaligned::PadWith(*byte as u8),
+        [Lexeme { token: Token::Ident("error"), .. }] => assembler.misaligned = Misaligned::Error,
+        _ => log_usage(logger, lexemes, "expected '.misaligned pad [byte]' or '.misaligned error'".to_owned()),
+    }
+}
+
+// The configs an assembler is made from, which are a base config followed by any extensions merged
+// into it, each with the origin diagnostics name it by
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    sources: Vec<(String, String)>,
+    // Told once the configs are parsed, and given to the assembler to report its assemblies to
+    progress: Option<Progress>,
+}
+
+impl Config {
+    pub fn new(origin: &str, source: &str) -> Self {
+        Self { sources: vec![(origin.to_owned(), source.to_owned())], progress: None }
+    }
+
+    // One of the configs built into the assembler, e.g. `x69-bravo`
+    pub fn builtin(name: &str) -> Result<Self, String> {
+        let path = isa::path(name)?;
+        Ok(Self::new(path, isa::source(path).unwrap_or_default()))
+    }
+
+    // Reads a config file, or the built in config an `isa:<name>` path names
+    pub fn read(path: &str) -> std::io::Result<Self> {
+        match isa::source(path) {
+            Some(source) => Ok(Self::new(path, source)),
+            None => Ok(Self::new(path, &files::decode(std::fs::read_to_string(path)?))),
+        }
+    }
+
+    // Merges another config into the ISA of those before it
+    pub fn extend(mut self, origin: &str, source: &str) -> Self {
+        self.sources.push((origin.to_owned(), source.to_owned()));
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn load(&self) -> LoggedResult<Assembler> {
+        let configs: Vec<_> = self.sources.iter().map(|(origin, source)| (origin.as_str(), source.as_str())).collect();
+        let progress = self.progress.clone();
+        create_assembler_from_configs(&configs).map(|mut assembler| {
+            if let Some(progress) = progress {
+                progress.report(Event::ConfigParsed { instructions: assembler.instructions.len() });
+                assembler.progress = Some(progress);
+            }
+            assembler
+        })
+    }
 }
 
+#[deprecated(since = "0.2.0", note = "use `Config::new(origin, source).load()`")]
 pub fn create_assembler_from_config(config: &str) -> LoggedResult<Assembler> {
-    let origin = "[unknown]";
-    let mut map = HashMap::new();
+    Config::new("[unknown]", config).load()
+}
+
+#[deprecated(since = "0.2.0", note = "use `Config::with_progress` and `Config::load`")]
+pub fn create_assembler_with_progress(configs: &[(&str, &str)], progress: Progress) -> LoggedResult<Assembler> {
+    let config = configs.iter().fold(Config::default(), |config, (origin, source)| config.extend(origin, source));
+    config.with_progress(progress).load()
+}
+
+// Merges configs in order, so later files extend the ISA of earlier ones with new instructions,
+// slots, bundles, and regions. Patterns may only be given again within the file that first
+// defines them, as other forms of the same pattern.
+pub fn create_assembler_from_configs(configs: &[(&str, &str)]) -> LoggedResult<Assembler> {
+    let mut assembler = Assembler::default();
     let mut logger = Logger::new(None);
+    // File and line that first defined each pattern
+    let mut defined: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut sites = Sites::new();
+    // Longest encoding any form may have, and where it was set
+    let mut max_length = None;
+    // Sizes given by `.instruction_size` that forms must encode to, with the mnemonics they are
+    // for, which are every instruction when there are none, and where they were set
+    let mut sizes: Vec<(usize, Vec<String>, String)> = Vec::new();
+    // Instruction given by `.nop`, and where
+    let mut nop = None;
+    // Collects the `.macro` blocks shipped with the ISA
+    let mut preprocessor = Preprocessor::new("");
     
-    for (line, source) in config.lines().enumerate() {
-        logger.origin = Some(Origin { file: origin.to_owned(), line });
-        let mut lexer = Lexer::new(source);
-        
-        // Only supports instructions right now
-        let name = match lexer.next() {
-            Some(Lexeme { token: Token::Ident(name), .. }) => name.to_lowercase(),
-            None => continue,
-            _ => {
-                logger.log_error("only instruction patterns are supported in the assembler config at the moment".to_owned());
+    for (file, (origin, config)) in configs.iter().enumerate() {
+        preprocessor.set_file(origin);
+        for (line, source) in config.lines().enumerate() {
+            // Logs about a whole line point at its text
+            let mut located = Origin::with_text(origin, line, source);
+            located.locate(source, &mut 0, source);
+            logger.origin = Some(located);
+            let lexemes: Vec<_> = Lexer::new(source).collect();
+            if preprocessor.is_defining() || matches!(lexemes.first().map(|l| &l.token), Some(Token::Directive(".macro" | ".endm" | ".namespace"))) {
+                preprocessor.process(line, source).if_ok(&mut logger, |_| {});
                 continue;
             }
-        };
-        
-        let instruction = &mut map.entry(name.clone()).or_insert(Instruction { syntaxes: Vec::new(), states: vec![TransitionTable::default()] });
-        
-        let states = &mut instruction.states;
-        let mut current_state = 0;
-        let mut registers = 0;
-        let mut immediates = Vec::new();
-        let mut accept_state = false;
-        
-        // Generate DFA
-        while let Some(token) = lexer.next() {
-            match token.token {
-                Token::Immediate(im) => {
-                    if im > immediates.len() {
-                        logger.log_warning(format!("immediates are parsed in the order they appear regardless of number; {} will correspond to i{} in codegen", token.slice, immediates.len()));
-                    }
-                    match lexer.next() {
-                        Some(Lexeme { token: Token::Colon, .. }) => {
-                            let width = match lexer.next() {
-                                Some(Lexeme { token: Token::Integer(width), .. }) => width,
-                                Some(Lexeme { slice, .. }) => {
-                                    logger.log_error(format!("expected width of immediate, but got: '{}'", slice));
-                                    break;
-                                },
-                                None => {
-                                    logger.log_error("expected width of immediate".to_owned());
-                                    break;
-                                }
-                            };
-                            immediates.push((im, width));
-                            if let Transition::NextState(next) = states[current_state].immediate {
-                                current_state = next;
-                            } else {
-                                states[current_state].immediate = Transition::NextState(states.len());
-                                current_state = states.len();
-                                states.push(TransitionTable::default());
-                            }
-                        },
-                        Some(Lexeme { slice, .. }) => logger.log_error(format!("expected width of immediate, but got '{}'", slice)),
-                        None => logger.log_error("expected width of immediate".to_owned()),
-                    }
-                }
-                
-                Token::Register(r) => {
-                    if r != registers {
-                        logger.log_warning(format!("registers are parsed in the order they appear regardless of number; {} will correspond to r{} in codegen", token.slice, registers));
-                    }
-                    if let Transition::NextState(next) = states[current_state].register {
-                        current_state = next;
-                    } else {
-                        states[current_state].register = Transition::NextState(states.len());
-                        current_state = states.len();
-                        states.push(TransitionTable::default());
-                    }
-                    registers += 1;
-                }
-
-                Token::Comma => {
-                    if let Transition::NextState(next) = states[current_state].comma {
-                        current_state = next;
-                    } else {
-                        states[current_state].comma = Transition::NextState(states.len());
-                        current_state = states.len();
-                        states.push(TransitionTable::default());
-                    }
+            match lexemes.first().map(|l| &l.token) {
+                Some(Token::Directive(".slot")) => add_slot(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".bundle")) => add_bundle(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".region")) => add_region(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(directive @ (".preamble" | ".postamble"))) => set_amble(&mut assembler, &mut logger, directive, &lexemes[1..]),
+                Some(Token::Directive(".section_defaults")) => add_section_defaults(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".operand_order")) => set_operand_order(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".prefix")) => add_prefix(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".mnemonic_chars")) => set_mnemonic_chars(&mut assembler, &mut logger, source, &lexemes[1..]),
+                Some(Token::Directive(".display")) => set_display(&mut assembler, &mut logger, source, &lexemes[1..]),
+                Some(Token::Directive(".reserved")) => add_reserved(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".cycles")) => add_cycles(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".category")) => add_category(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".delay_slot")) => add_delay_slot(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".instruction_align")) => add_alignment(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".misaligned")) => set_misaligned(&mut assembler, &mut logger, &lexemes[1..]),
+                Some(Token::Directive(".terminator")) => match &lexemes[1..] {
+                    [Lexeme { token: Token::Ident("newline"), .. }] => assembler.dialect.terminator = Terminator::Newline,
+                    [Lexeme { token: Token::Ident("semicolon"), .. }] => assembler.dialect.terminator = Terminator::Semicolon,
+                    [Lexeme { token: Token::Ident("period"), .. }] => assembler.dialect.terminator = Terminator::Period,
+                    rest => log_usage(&mut logger, rest, "expected '.terminator newline', '.terminator semicolon', or '.terminator period'".to_owned()),
+                },
+                Some(Token::Directive(".labels")) => match &lexemes[1..] {
+                    [Lexeme { token: Token::Ident("colon"), .. }] => assembler.dialect.bare_labels = false,
+                    [Lexeme { token: Token::Ident("bare"), .. }] => assembler.dialect.bare_labels = true,
+                    rest => log_usage(&mut logger, rest, "expected '.labels colon' or '.labels bare'".to_owned()),
+                },
+                Some(Token::Directive(".identifiers")) => match &lexemes[1..] {
+                    [Lexeme { token: Token::Ident("ascii"), .. }] => assembler.unicode_identifiers = false,
+                    [Lexeme { token: Token::Ident("unicode"), .. }] => assembler.unicode_identifiers = true,
+                    rest => log_usage(&mut logger, rest, "expected '.identifiers ascii' or '.identifiers unicode'".to_owned()),
+                },
+                Some(Token::Directive(".nop")) => match (lexemes.get(1), lexemes.last()) {
+                    (Some(first), Some(last)) => nop = Some((&source[first.span.start..last.span.end], logger.origin.clone(), first.span.start..last.span.end)),
+                    _ => log_usage(&mut logger, &lexemes[1..], "expected '.nop <instruction>'".to_owned()),
+                },
+                Some(Token::Directive(".max_length")) => match &lexemes[1..] {
+                    [Lexeme { token: Token::Integer(length), .. }] if *length > 0 => max_length = Some((*length, format!("{}:{}", origin, line + 1))),
+                    rest => log_usage(&mut logger, rest, "expected '.max_length <bytes>'".to_owned()),
+                },
+                Some(Token::Directive(".instruction_size")) => match &lexemes[1..] {
+                    [Lexeme { token: Token::Integer(size), .. }, mnemonics @ ..] if *size > 0 && mnemonics.iter().all(|l| matches!(l.token, Token::Ident(_))) => {
+                        let mnemonics: Vec<_> = mnemonics.iter().map(|l| l.slice.to_lowercase()).collect();
+                        match sizes.iter().find(|(_, given, _)| given.is_empty() && mnemonics.is_empty() || given.iter().any(|m| mnemonics.contains(m))) {
+                            Some((_, _, at)) => logger.log_error_at(lexemes[0].span.clone(), format!("'.instruction_size' was already given for these instructions at {}", at)),
+                            None => sizes.push((*size, mnemonics, format!("{}:{}", origin, line + 1))),
+                        }
+                    },
+                    rest => log_usage(&mut logger, rest, "expected '.instruction_size <bytes> [mnemonics]'".to_owned()),
+                },
+                Some(Token::Directive(directive @ (".stack_push" | ".stack_pop" | ".stack_return"))) => add_stack_effect(&mut assembler, &mut logger, directive, &lexemes[1..]),
+                Some(Token::Directive(".peephole")) => match peephole::parse_rule(&source[lexemes[0].span.end..]) {
+                    Ok(rule) => assembler.peepholes.push(rule),
+                    Err(message) => logger.log_error_at(lexemes[0].span.clone(), message),
+                },
+                Some(Token::Directive(directive @ (".hazard" | ".illegal"))) => match hazard::parse_hazard(&source[lexemes[0].span.end..], *directive == ".illegal") {
+                    Ok(hazard) => assembler.hazards.push(hazard),
+                    Err(message) => logger.log_error_at(lexemes[0].span.clone(), message),
                 },
-                
-                Token::Arrow => {
-                    if states[current_state].accept_codegen.is_some() {
-                        logger.log_error(format!("conflicting patterns for instruction '{}'", name));
-                    } else {
-                        let mut codegen = Vec::new();
-                        while let Some(token) = lexer.next() {
-                            match token.token {
-                                Token::Integer(int) => {
-                                    if int > 255 {
-                                        logger.log_warning(format!("{} is larger than 8 bits and will be truncated", token.slice));
-                                    }
-                                    codegen.push(Codegen::byte(int as u8));
-                                },
-                                
-                                Token::Immediate(im) => {
-                                    if im >= immediates.len() {
-                                        logger.log_error(format!("'{}' uses immediate {} which is not given in the instruction pattern", name, im));
-                                        break;
-                                    }
-                                    let immediate = immediates[im];
-                                    if immediate.1 % 8 != 0 {
-                                        logger.log_error("immediate width must be byte aligned (for now)".to_owned());
-                                    } else {
-                                        codegen.push(Codegen::immediate(immediate.0, immediate.1));
-                                    }
-                                },
-                                
-                                Token::Register(r) => {
-                                    if r >= registers {
-                                        logger.log_error(format!("'{}' uses register {} which is not given in the instruction pattern", name, r));
-                                    }
-                                    codegen.push(Codegen::register(r));
-                                }
-                                
-                                Token::OpenBracket => {
-                                    codegen_brackets(&mut lexer, &name, registers, &immediates).if_ok(&mut logger, |bracket| codegen.push(bracket));
-                                },
-                                
-                                _ => {
-                                    logger.log_error(format!("codegen only supports literal values, registers, and bracket groups, but got '{}'", token.slice));
-                                    break;
-                                },
+                _ => {
+                    let mut definitions = None;
+                    parse_definition_with(source, &assembler.mnemonic_chars).if_ok(&mut logger, |d| definitions = d);
+                    // One definition for each branch of the codegen, all with the same pattern
+                    for mut definition in definitions.unwrap_or_default() {
+                        if let Some(prefix) = definition.prefix.as_ref().filter(|p| !assembler.prefixes.iter().any(|q| q.name.eq_ignore_ascii_case(p.node))) {
+                            // Naming an instruction after an unknown prefix is most likely a form
+                            // for its mode with the prefix misspelled or declared too late
+                            if assembler.instructions.contains_key(&definition.mnemonic.node.to_lowercase()) {
+                                logger.log_error_at(prefix.span.clone(), format!("unknown prefix '{}'; a form for a mode must come after the '.prefix' it is for", prefix.slice));
+                                continue;
                             }
+                            definition = definition.with_leading_keyword().unwrap();
+                        }
+                        match *defined.entry(definition.syntax()).or_insert((file, line)) {
+                            (first, _) if first == file => add_definition(&mut assembler.instructions, &mut sites, &mut logger, &definition),
+                            (first, at) => logger.log_error_at(definition.mnemonic.span.clone(), format!("'{}' is already defined at {}:{}, which is kept; extensions can only add new patterns", definition.syntax(), configs[first].0, at + 1)),
                         }
-                        states[current_state].accept_codegen = Some(codegen);
                     }
-                    accept_state = true;
-                    break;
                 },
-                
-                _ => logger.log_error(format!("unexpected token in instrution pattern: '{}'", token.slice))
             }
         }
-        if !accept_state {
-            logger.log_error("expected '->' following an instruction pattern".to_owned());
-        } else {
-            let syntax = source.split_once("->").unwrap().0;
-            let lex_fold = Lexer::new(syntax).fold(String::with_capacity(16), |a, Lexeme{slice,..}| {
-                if a.is_empty() || a.ends_with(':') || slice == "," || slice == ":"{
-                    a + slice
-                } else {
-                    a + " " + slice
-                }
-            });
-            instruction.syntaxes.push(lex_fold.to_lowercase());
-        }
+        preprocessor.finish().if_ok(&mut logger, |_| {});
     }
     
+    // Checked once every form is known, since `.max_length` can come after the definitions
+    if let Some((max, at)) = max_length {
+        let mut long: Vec<_> = sites.values().flatten().filter(|site| site.length > max).collect();
+        long.sort_by_key(|site| (site.origin.as_ref().map(|o| (configs.iter().position(|(file, _)| *file == o.file), o.line)), site.span.start));
+        for site in long {
+            logger.origin = site.origin.clone();
+            logger.log_error_at(site.span.clone(), format!("'{}' encodes to {} bytes, more than the maximum of {} set at {}", site.syntax, site.length, max, at));
+        }
+    }
+    // Every form must encode to the size given for its mnemonic, or else to the one given for all
+    // instructions. Checked once every form is known, like `.max_length`.
+    if !sizes.is_empty() {
+        let mut forms: Vec<_> = sites.iter().flat_map(|((name, _), forms)| forms.iter().map(move |site| (name, site))).collect();
+        forms.sort_by_key(|(_, site)| (site.origin.as_ref().map(|o| (configs.iter().position(|(file, _)| *file == o.file), o.line)), site.span.start));
+        for (name, site) in forms {
+            // A form for the mode of a prefix is sized by its mnemonic
+            let mnemonic = name.rsplit(' ').next().unwrap_or(name);
+            let size = sizes.iter().find(|(_, mnemonics, _)| mnemonics.iter().any(|m| m == name || m == mnemonic)).or_else(|| sizes.iter().find(|(_, mnemonics, _)| mnemonics.is_empty()));
+            if let Some((size, _, at)) = size.filter(|(size, _, _)| *size != site.length) {
+                logger.origin = site.origin.clone();
+                logger.log_error_at(site.span.clone(), format!("'{}' encodes to {} bytes, but '.instruction_size' at {} makes it {} bytes", site.syntax, site.length, at, size));
+            }
+        }
+    }
+    // Assembled once every instruction is known, since `.nop` can come before the one it names
+    if let Some((statement, origin, span)) = nop {
+        logger.origin = origin;
+        match assembler.assemble_statement(statement).unwrap() {
+            (Some(code), _) if !code.is_empty() => assembler.nop = code,
+            (_, logs) => {
+                let reason = logs.iter().find(|log| log.is_error()).map_or("it encodes to nothing", |log| log.message());
+                logger.log_error_at(span, format!("'{}' can't be used as the no-op: {}", statement, reason));
+            },
+        }
+    }
+    assembler.macros = preprocessor.into_macros();
+    assembler.config_hash = hash_source(&configs.iter().map(|(_, config)| *config).collect::<Vec<_>>().join("\n"));
     // If an error was reported
-    logger.into_result(|| Assembler { instructions: map })
+    logger.into_result(|| assembler)
 }
@@ -0,0 +1,37 @@
+use crate::listing::Row;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Lines holding instructions, each with whether any of them ran. Statements a macro expanded to
+// are counted for the line that invoked it.
+fn lines(rows: &[Row], executed: &BTreeSet<usize>) -> BTreeMap<usize, bool> {
+    let mut lines = BTreeMap::new();
+    let mut current = None;
+    for row in rows {
+        current = row.line.or(current);
+        if let (Some(line), false) = (current, row.instructions.is_empty()) {
+            let hit = row.instructions.iter().any(|address| executed.contains(address));
+            *lines.entry(line).or_insert(false) |= hit;
+        }
+    }
+    lines
+}
+
+// The source with each line holding instructions marked `+` if one of them was executed and `-`
+// if none were, e.g. `   12 + inc r1`, followed by a summary
+pub fn report(source: &str, rows: &[Row], executed: &BTreeSet<usize>) -> String {
+    let lines = lines(rows, executed);
+    let mut out = String::new();
+    for (i, text) in source.lines().enumerate() {
+        let marker = match lines.get(&i) {
+            Some(true) => '+',
+            Some(false) => '-',
+            None => ' ',
+        };
+        out += format!("{:>5} {} {}", i + 1, marker, text).trim_end();
+        out.push('\n');
+    }
+    let covered = lines.values().filter(|hit| **hit).count();
+    let percent = if lines.is_empty() { 100 } else { covered * 100 / lines.len() };
+    out += &format!("covered {} of {} lines with instructions ({}%)\n", covered, lines.len(), percent);
+    out
+}
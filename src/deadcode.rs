@@ -0,0 +1,157 @@
+use crate::directives::Image;
+use crate::disasm::Disassembler;
+use crate::map::SymbolMap;
+use crate::parser::{Assembler, Category};
+use crate::semantics::{self, BinaryOp, Expr, Flag, Place, Statement, UnaryOp};
+
+const FLAGS: [Flag; 4] = [Flag::Zero, Flag::Carry, Flag::Negative, Flag::Overflow];
+
+// A run of bytes that no entry point reaches
+#[derive(Debug, Clone)]
+pub struct Dead {
+    pub start: usize,
+    pub end: usize,
+    // The closest label at or before the start, as `name` or `name+0x4`
+    pub label: Option<String>,
+}
+
+// Evaluates an expression with the flags set by bit in `flags`, or None when it depends on a
+// register or memory, which static analysis can't know
+fn eval(expr: &Expr, immediates: &[Option<usize>], next_pc: usize, flags: usize) -> Option<u64> {
+    Some(match expr {
+        Expr::Register(_) | Expr::Mem(_) => return None,
+        Expr::Immediate(i) => immediates.get(*i).copied().flatten()? as u64,
+        Expr::Integer(int) => *int,
+        Expr::Pc => next_pc as u64,
+        Expr::Flag(flag) => (flags >> FLAGS.iter().position(|f| f == flag)? & 1) as u64,
+        Expr::Unary(op, value) => {
+            let value = eval(value, immediates, next_pc, flags)?;
+            match op {
+                UnaryOp::Negate => value.wrapping_neg(),
+                UnaryOp::Not => !value,
+                UnaryOp::LogicalNot => (value == 0) as u64,
+            }
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            let (lhs, rhs) = (eval(lhs, immediates, next_pc, flags)?, eval(rhs, immediates, next_pc, flags)?);
+            match op {
+                BinaryOp::Add => lhs.wrapping_add(rhs),
+                BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                BinaryOp::Div => lhs.checked_div(rhs)?,
+                BinaryOp::Rem => lhs.checked_rem(rhs)?,
+                BinaryOp::And => lhs & rhs,
+                BinaryOp::Or => lhs | rhs,
+                BinaryOp::Xor => lhs ^ rhs,
+                BinaryOp::Shl => lhs.checked_shl(rhs as u32).unwrap_or(0),
+                BinaryOp::Shr => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                BinaryOp::Eq => (lhs == rhs) as u64,
+                BinaryOp::Ne => (lhs != rhs) as u64,
+                BinaryOp::Lt => (lhs < rhs) as u64,
+                BinaryOp::Le => (lhs <= rhs) as u64,
+                BinaryOp::Gt => (lhs > rhs) as u64,
+                BinaryOp::Ge => (lhs >= rhs) as u64,
+            }
+        },
+    })
+}
+
+fn reads_pc(expr: &Expr) -> bool {
+    match expr {
+        Expr::Pc => true,
+        Expr::Mem(e) | Expr::Unary(_, e) => reads_pc(e),
+        Expr::Binary(_, lhs, rhs) => reads_pc(lhs) || reads_pc(rhs),
+        _ => false,
+    }
+}
+
+// Addresses the instruction of `length` bytes at the address can continue at, given the values of
+// its immediates. Instructions without semantics fall through, and
+// branches by their category also go to their first immediate, taken as an address. A `pc`
+// assignment is tried with every combination of flags, so a conditional branch yields both its
+// target and the next instruction, and one that depends on a register (like a return) yields
+// nothing. Saving the address of the next instruction marks a call, which comes back to it.
+pub(crate) fn successors(immediates: &[Option<usize>], length: usize, category: Option<Category>, statements: Option<&Vec<Statement>>, address: usize) -> Vec<usize> {
+    let next_pc = address + length;
+    let statements = match (statements, category) {
+        (Some(statements), _) => statements,
+        (None, Some(Category::Branch)) => return immediates.first().copied().flatten().into_iter().chain(Some(next_pc)).collect(),
+        (None, _) => return vec![next_pc],
+    };
+    let mut targets = Vec::new();
+    let mut falls_through = true;
+    for statement in statements {
+        match statement {
+            Statement::Halt => falls_through = false,
+            Statement::Assign(Place::Pc, expr) => {
+                falls_through = false;
+                targets.extend((0..1 << FLAGS.len()).filter_map(|flags| eval(expr, immediates, next_pc, flags)).map(|t| t as usize));
+            },
+            Statement::Assign(Place::Register(_) | Place::Mem(_), expr) if reads_pc(expr) => targets.push(next_pc),
+            Statement::Assign(..) => {},
+        }
+    }
+    if falls_through {
+        targets.push(next_pc);
+    }
+    targets
+}
+
+// Follows every path from the entry points through the main image, returning the runs of bytes
+// no decoded instruction covers. Data the code only reads is reported too, since nothing jumps to it.
+pub fn dead_code(assembler: &Assembler, image: &Image, entries: &[usize]) -> Vec<Dead> {
+    let disassembler = Disassembler::new(assembler);
+    let semantics: Vec<_> = disassembler.patterns().iter()
+        .map(|(_, pattern)| pattern.semantics.and_then(|s| semantics::parse(s).ok()))
+        .collect();
+    let mut reached = vec![false; image.code.len()];
+    let mut visited = vec![false; image.code.len()];
+    let mut pending: Vec<_> = entries.to_vec();
+    while let Some(address) = pending.pop() {
+        let offset = match address.checked_sub(image.base) {
+            Some(offset) if offset < image.code.len() && !visited[offset] => offset,
+            _ => continue,
+        };
+        visited[offset] = true;
+        // Prefer patterns with semantics, like the emulator does
+        let matches = disassembler.matches_at(&image.code, offset);
+        let current = match matches.iter().find(|m| semantics[m.index].is_some()).or(matches.first()) {
+            Some(current) => current,
+            None => continue,
+        };
+        reached[offset..offset + current.length].iter_mut().for_each(|r| *r = true);
+        pending.extend(successors(&current.immediates, current.length, assembler.category(current.name), semantics[current.index].as_ref(), address));
+    }
+
+    let mut symbols = SymbolMap::default();
+    for (name, address) in &image.labels {
+        symbols.insert(*address, name);
+    }
+    let mut dead = Vec::new();
+    let mut offset = 0;
+    while offset < reached.len() {
+        if reached[offset] {
+            offset += 1;
+            continue;
+        }
+        let end = reached[offset..].iter().position(|r| *r).map_or(reached.len(), |len| offset + len);
+        let start = image.base + offset;
+        dead.push(Dead { start, end: image.base + end - 1, label: symbols.nearest(start) });
+        offset = end;
+    }
+    dead
+}
+
+// One line per run of dead bytes, followed by the total
+pub fn report(dead: &[Dead], image: &Image) -> String {
+    let mut out = String::new();
+    for run in dead {
+        let size = run.end - run.start + 1;
+        let label = run.label.as_deref().unwrap_or("-");
+        out += &format!("{:#06x}-{:#06x}  {:>6} bytes  {}\n", run.start, run.end, size, label);
+    }
+    let total: usize = dead.iter().map(|run| run.end - run.start + 1).sum();
+    let percent = if image.code.is_empty() { 0.0 } else { 100.0 * total as f64 / image.code.len() as f64 };
+    out += &format!("{} of {} bytes ({:.1}%) are unreachable from the entry points\n", total, image.code.len(), percent);
+    out
+}
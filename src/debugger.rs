@@ -0,0 +1,110 @@
+use crate::emulator::{Emulator, MEMORY_SIZE};
+use crate::snapshot;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until a breakpoint or the machine stops
+  b, break <addr>      set a breakpoint
+  d, delete <addr>     remove a breakpoint
+  breakpoints          list breakpoints
+  r, regs              show registers and flags
+  m, mem <addr> [len]  show memory
+  i, inst              show the instruction at pc
+  save <file>          save the machine state to a file
+  load <file>          restore a machine state saved with 'save'
+  q, quit              exit the debugger";
+
+fn parse_number(s: &str) -> Option<usize> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        usize::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn show_current<W: Write>(emulator: &Emulator, output: &mut W) -> std::io::Result<()> {
+    match emulator.current() {
+        Some(current) => writeln!(output, "0x{:04X}: {}", emulator.machine.pc, current.text()),
+        None => writeln!(output, "0x{:04X}: <undecodable>", emulator.machine.pc),
+    }
+}
+
+// Interactive debugging session reading commands from `input` until it ends or `quit` is given
+pub fn run<R: BufRead, W: Write>(emulator: &mut Emulator, input: R, mut output: W, max_steps: u64) -> std::io::Result<()> {
+    show_current(emulator, &mut output)?;
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("s") | Some("step") => {
+                let count = words.next().and_then(parse_number).unwrap_or(1) as u64;
+                match emulator.run(count) {
+                    Ok(stop) if stop != crate::emulator::Stop::StepLimit => writeln!(output, "{}", stop)?,
+                    Ok(_) => {},
+                    Err(message) => writeln!(output, "error: {}", message)?,
+                }
+                show_current(emulator, &mut output)?;
+            },
+            Some("c") | Some("continue") => {
+                match emulator.run(max_steps) {
+                    Ok(stop) => writeln!(output, "{}", stop)?,
+                    Err(message) => writeln!(output, "error: {}", message)?,
+                }
+                show_current(emulator, &mut output)?;
+            },
+            Some("b") | Some("break") => match words.next().and_then(parse_number) {
+                Some(address) => { emulator.breakpoints.insert(address); },
+                None => writeln!(output, "expected an address")?,
+            },
+            Some("d") | Some("delete") => match words.next().and_then(parse_number) {
+                Some(address) => { emulator.breakpoints.remove(&address); },
+                None => writeln!(output, "expected an address")?,
+            },
+            Some("breakpoints") => {
+                for address in &emulator.breakpoints {
+                    writeln!(output, "0x{:04X}", address)?;
+                }
+            },
+            Some("r") | Some("regs") => writeln!(output, "{}", emulator.machine)?,
+            Some("m") | Some("mem") => match words.next().and_then(parse_number) {
+                Some(address) => {
+                    let len = words.next().and_then(parse_number).unwrap_or(16);
+                    let end = address.saturating_add(len).min(MEMORY_SIZE);
+                    for (row, chunk) in emulator.machine.memory[address.min(end)..end].chunks(16).enumerate() {
+                        let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+                        writeln!(output, "0x{:04X}: {}", address + row * 16, bytes.join(" "))?;
+                    }
+                },
+                None => writeln!(output, "expected an address")?,
+            },
+            Some("i") | Some("inst") => show_current(emulator, &mut output)?,
+            Some("save") => match words.next() {
+                Some(path) => if let Err(e) = std::fs::write(path, snapshot::save(&emulator.machine)) {
+                    writeln!(output, "could not save '{}': {}", path, e)?;
+                },
+                None => writeln!(output, "expected a file")?,
+            },
+            Some("load") => match words.next() {
+                Some(path) => match std::fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| snapshot::restore(&bytes)) {
+                    Ok(machine) => {
+                        emulator.machine = machine;
+                        show_current(emulator, &mut output)?;
+                    },
+                    Err(message) => writeln!(output, "could not load '{}': {}", path, message)?,
+                },
+                None => writeln!(output, "expected a file")?,
+            },
+            Some("q") | Some("quit") => break,
+            Some("h") | Some("help") => writeln!(output, "{}", HELP)?,
+            Some(command) => writeln!(output, "unknown command '{}', try 'help'", command)?,
+            None => {},
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    Ok(())
+}
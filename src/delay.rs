@@ -0,0 +1,150 @@
+use crate::directives::Directives;
+use crate::ir::{Item, Operation};
+use crate::log::Logger;
+use crate::parser::{Assembler, Category};
+use crate::peephole::Previous;
+use crate::semantics::{self, Expr, Flag, Place, Statement};
+
+// Delay slots given by `.delay_slot`. The instructions in the slots after a branch run before it
+// takes effect, so a branch in the slot of another is an error. With `--fill-delay-slots` the
+// slots are filled by the assembler instead, with the instruction before the branch when moving it
+// is safe and with the no-op otherwise.
+
+// What an instruction reads or writes, as far as moving it past a branch is concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resource {
+    Register(u8),
+    Flag(Flag),
+    Memory,
+    Pc,
+}
+
+fn reads(operation: &Operation, expr: &Expr, resources: &mut Vec<Resource>) {
+    match expr {
+        Expr::Register(r) => resources.extend(operation.registers.get(*r).map(|r| Resource::Register(*r))),
+        Expr::Flag(flag) => resources.push(Resource::Flag(*flag)),
+        Expr::Pc => resources.push(Resource::Pc),
+        Expr::Mem(address) => {
+            resources.push(Resource::Memory);
+            reads(operation, address, resources);
+        },
+        Expr::Unary(_, operand) => reads(operation, operand, resources),
+        Expr::Binary(_, left, right) => {
+            reads(operation, left, resources);
+            reads(operation, right, resources);
+        },
+        Expr::Immediate(_) | Expr::Integer(_) => {},
+    }
+}
+
+// What the instruction reads and writes, which is unknown without semantics
+fn effects(operation: &Operation) -> Option<(Vec<Resource>, Vec<Resource>)> {
+    let statements = semantics::parse(operation.semantics.as_deref()?).ok()?;
+    let (mut read, mut written) = (Vec::new(), Vec::new());
+    for statement in &statements {
+        match statement {
+            Statement::Assign(place, expr) => {
+                reads(operation, expr, &mut read);
+                match place {
+                    Place::Register(r) => written.extend(operation.registers.get(*r).map(|r| Resource::Register(*r))),
+                    Place::Flag(flag) => written.push(Resource::Flag(*flag)),
+                    Place::Pc => written.push(Resource::Pc),
+                    Place::Mem(address) => {
+                        reads(operation, address, &mut read);
+                        written.push(Resource::Memory);
+                    },
+                }
+            },
+            Statement::Halt => written.push(Resource::Pc),
+        }
+    }
+    Some((read, written))
+}
+
+impl Assembler {
+    // Delay slots the config gives the instruction
+    fn slots(&self, mnemonic: &str) -> usize {
+        self.delay_slots.iter().find(|(m, _)| m == mnemonic).map_or(0, |(_, slots)| *slots)
+    }
+
+    // Whether the instruction can change where execution goes
+    fn branches(&self, operation: &Operation) -> bool {
+        self.slots(&operation.mnemonic) > 0 || self.category(&operation.mnemonic) == Some(Category::Branch) || effects(operation).is_some_and(|(_, written)| written.contains(&Resource::Pc))
+    }
+
+    // Counts the instruction against the delay slots of the branch before it, which can't be
+    // branches themselves
+    pub(crate) fn check_delay_slot(&self, logger: &mut Logger, operation: &Operation, site: &str, text: &str, directives: &mut Directives) {
+        // Moving or combining what's in a slot, or the branch, would change which paths run it
+        if let Some((slots, branch, at)) = directives.delay.take() {
+            if self.branches(operation) {
+                logger.log_error(format!("'{}' is in the delay slot of '{}' at {}, where a branch can't go", text, branch, at));
+            }
+            directives.previous = None;
+            if slots > 1 {
+                directives.delay = Some((slots - 1, branch, at));
+            }
+            return;
+        }
+        let slots = self.slots(&operation.mnemonic);
+        if slots > 0 {
+            directives.delay = Some((slots, text.to_owned(), site.to_owned()));
+            directives.previous = None;
+        }
+    }
+
+    // Moves the instruction before a branch into its first delay slot when nothing the branch
+    // depends on is changed by it and nothing jumps to the branch, returning the branch lowered
+    // again where the instruction was along with the instruction
+    pub(crate) fn hoist(&self, statement: &str, branch: &Operation, aligned: bool, directives: &mut Directives) -> Option<(Operation, Previous)> {
+        if self.slots(&branch.mnemonic) == 0 || directives.delay.is_some() || !aligned || directives.labelled() {
+            return None;
+        }
+        let previous = directives.previous.as_ref().filter(|p| p.address + p.length == directives.address())?;
+        let (branch_reads, branch_writes) = effects(branch)?;
+        let (reads, writes) = effects(&previous.operation)?;
+        let conflicts = writes.iter().any(|w| *w == Resource::Pc || branch_reads.contains(w) || branch_writes.contains(w))
+            || reads.iter().any(|r| branch_writes.contains(r));
+        // Nor is it moved to make a sequence given by `.hazard` or `.illegal`
+        let hazard = self.hazards.iter().any(|hazard| hazard.matches(branch, &previous.operation));
+        if conflicts || hazard || self.branches(&previous.operation) {
+            return None;
+        }
+        let previous = directives.previous.take()?;
+        directives.retract(previous.length);
+        match self.lower_statement_with(statement, directives).unwrap() {
+            (Some(Item::Operation(branch)), _) => Some((branch, previous)),
+            // Lowered before, so this doesn't happen, but the instruction is put back if it does
+            _ => {
+                directives.emit(&previous.operation.encode(&mut Logger::new(None)));
+                directives.previous = Some(previous);
+                None
+            },
+        }
+    }
+
+    // Code for the delay slots of a branch the assembler fills, after the instruction moved into
+    // the first one if there is one
+    pub(crate) fn fill_slots(&self, logger: &mut Logger, branch: &Operation, text: &str, site: &str, hoisted: Option<Previous>, directives: &mut Directives) -> Vec<Item> {
+        let slots = self.slots(&branch.mnemonic);
+        if slots == 0 {
+            return Vec::new();
+        }
+        directives.delay = None;
+        let mut items = Vec::new();
+        if let Some(hoisted) = hoisted {
+            directives.rewrites.push(format!("{}: moved '{}' into the delay slot of '{}'", hoisted.site, hoisted.text, text));
+            items.push(Item::Operation(hoisted.operation));
+        }
+        let nops = slots - items.len();
+        if nops > 0 {
+            if self.nop.is_empty() {
+                logger.log_error(format!("the delay slots of '{}' can't be filled without a no-op given by '.nop'", text));
+                return items;
+            }
+            directives.rewrites.push(format!("{}: filled {} delay slot{} of '{}' with the no-op", site, nops, if nops == 1 { "" } else { "s" }, text));
+            items.extend((0..nops).map(|_| Item::Bytes(self.nop.clone())));
+        }
+        items
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::Write;
+
+// Hardware mapped over a range of the emulator's addresses, which sees loads and stores to that
+// range in place of memory. Offsets are relative to the start of the range.
+pub trait Device {
+    fn read(&mut self, offset: usize) -> u8;
+    fn write(&mut self, offset: usize, value: u8);
+    // Called after every instruction
+    fn tick(&mut self) {}
+}
+
+// Writes each byte stored at offset 0 to its output. Loads give 0.
+pub struct Console {
+    output: Box<dyn Write>,
+}
+
+impl Console {
+    pub const SIZE: usize = 1;
+    
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self { output }
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, _offset: usize) -> u8 {
+        0
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        if offset == 0 {
+            // Output is best effort, like a real port nothing is listening on
+            let _ = self.output.write_all(&[value]).and_then(|_| self.output.flush());
+        }
+    }
+}
+
+// Counts instructions executed since it was last reset. Offsets 0 and 1 load the low and high
+// bytes of the count, and a store to either resets it.
+#[derive(Debug, Default)]
+pub struct Timer {
+    count: u16,
+}
+
+impl Timer {
+    pub const SIZE: usize = 2;
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: usize) -> u8 {
+        match offset {
+            0 => self.count as u8,
+            1 => (self.count >> 8) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _offset: usize, _value: u8) {
+        self.count = 0;
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+}
+
+// A device made of closures, for scripting devices through the library
+pub struct Callback<R, W> {
+    pub read: R,
+    pub write: W,
+}
+
+impl<R: FnMut(usize) -> u8, W: FnMut(usize, u8)> Device for Callback<R, W> {
+    fn read(&mut self, offset: usize) -> u8 {
+        (self.read)(offset)
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        (self.write)(offset, value)
+    }
+}
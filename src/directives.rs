@@ -0,0 +1,1472 @@
+use crate::lexer::{Lexer, Lexeme, Operator, Span, Token};
+use crate::linker::Placement;
+use crate::ir;
+use crate::listing::Row;
+use crate::log::{Logger, LoggedResult};
+use crate::macros::DEFAULT_SECTION;
+use crate::new_parser::{constant, Expr, Function};
+use crate::parser::{fits_in, width_of, Region};
+use crate::peephole::Previous;
+use crate::semantics::{BinaryOp, UnaryOp};
+use crate::symbols::SymbolTable;
+use crate::xref::Site;
+use std::collections::{HashMap, HashSet};
+
+// Bytes in a `.word` and `.dword` (or `.long`)
+pub const WORD: usize = 2;
+pub const DWORD: usize = 4;
+
+#[derive(Debug)]
+enum Block {
+    // `.struct name` ... `.ends`, with the fields so far and their total size
+    Struct { name: String, fields: Vec<(String, usize)>, size: usize },
+    // `.enum [name] [, start [, stride]]` ... `.ende`, with the value of the next member. Members
+    // of a named enum are defined as `name.member`.
+    Enum { name: Option<String>, next: usize, stride: usize },
+}
+
+// Integer and fractional bits of `.fixed` values until `.qformat` is given
+pub const DEFAULT_QFORMAT: (usize, usize) = (8, 8);
+
+// Bytes in a literal pool entry
+pub const LITERAL: usize = WORD;
+
+// Symbol holding the address of a value's entry in a literal pool, which can't clash with a label
+fn literal_symbol(pool: usize, value: usize) -> String {
+    format!("={}:{}", pool, value)
+}
+
+// How characters of string data are turned into bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    // UTF-8
+    #[default]
+    Raw,
+    // Characters outside of ASCII are errors
+    Ascii,
+    // Characters are looked up in the table built by `.charmap`, e.g. for a custom font
+    Charmap,
+}
+
+// What `.overflow` makes of constant expressions whose value doesn't fit in 64 bits as either a
+// signed or an unsigned number, and of division by zero
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    // The low 64 bits are kept with a warning, and division by zero is an error
+    #[default]
+    Wrap,
+    // The value is clamped to the largest unsigned or smallest signed value, whichever side it
+    // overflowed on, and division by zero gives the largest value with the dividend as remainder
+    Saturate,
+    // Both are errors
+    Error,
+}
+
+// Warning for an expression whose value wrapped to its low 64 bits
+fn wrapping(expr: &Expr, wrapped: usize) -> String {
+    format!("'{}' overflows 64 bits and wraps to 0x{:X}", expr, wrapped)
+}
+
+// A character of a string or character literal, or a byte given as `\xNN`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+// Decodes the `\n`, `\t`, `\r`, `\0`, `\xNN`, `\\`, `\"` and `\'` escapes in a literal
+pub fn unescape(literal: &str) -> Result<Vec<Unit>, String> {
+    let mut units = Vec::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            units.push(Unit::Char(c));
+            continue;
+        }
+        units.push(match chars.next() {
+            Some('n') => Unit::Char('\n'),
+            Some('t') => Unit::Char('\t'),
+            Some('r') => Unit::Char('\r'),
+            Some('0') => Unit::Byte(0),
+            Some(c @ ('\\' | '"' | '\'')) => Unit::Char(c),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if digits.len() == 2 => Unit::Byte(byte),
+                    _ => return Err(format!("expected two hex digits after '\\x', but got '{}'", digits)),
+                }
+            },
+            Some(c) => return Err(format!("unknown escape sequence '\\{}'", c)),
+            None => return Err("expected an escape sequence after '\\'".to_owned()),
+        });
+    }
+    Ok(units)
+}
+
+// Directives that name symbols without the code using them
+const DECLARATIONS: &[&str] = &[".function", ".global", ".globl", ".local", ".weak", ".entry"];
+
+// Every directive a source file can use, including those of the preprocessor and `.include`, for
+// editors and generated grammars
+pub const DIRECTIVES: &[&str] = &[
+    ".allow", ".arch", ".ascii", ".asciz", ".bank", ".buildid", ".byte", ".charmap", ".disallow", ".double", ".dword",
+    ".encoding", ".endm", ".endr", ".ende", ".ends", ".entry", ".enum", ".equ", ".fixed", ".float", ".function", ".global",
+    ".globl", ".hex", ".include", ".include_cmd", ".irp", ".keep", ".local", ".long", ".macro", ".namespace", ".org",
+    ".overflow", ".pic", ".pool", ".pop_depth", ".push_depth", ".qformat", ".radix", ".res", ".section", ".set",
+    ".size_assert", ".struct", ".syntax", ".tag", ".weak", ".word",
+];
+
+// Code placed with `.section name [, region]`. Sections are laid out one after another in the order
+// they first appear, from the start of their region.
+#[derive(Debug)]
+struct Section {
+    name: String,
+    region: Option<usize>,
+    // Set for sections opened with `.bank n`, which all start at the start of their region and are
+    // output as images of their own
+    bank: Option<usize>,
+    // Address of the section, from the sizes of the sections before it in the previous pass
+    base: usize,
+    // Set for sections opened with `.org`, which start at the address given rather than after the
+    // sections before them
+    origin: bool,
+    // Multiple of bytes the section starts at, given by the linker script
+    align: usize,
+    // Identifiers its statements mention, and whether `.keep` was used in it, which decide whether
+    // `--gc-sections` removes it
+    uses: HashSet<String>,
+    keep: bool,
+    code: Vec<u8>,
+    // Bytes placed in the section, which includes code streamed elsewhere
+    size: usize,
+    // ISA its instructions were encoded with, when a source switches between them with `.arch`
+    arch: Option<String>,
+}
+
+// Symbol holding the size of a section in the previous pass, which can't clash with a label
+fn size_symbol(section: &str) -> String {
+    format!("{}:size", section)
+}
+
+// Symbols holding the bank of a banked label and its offset from the start of the bank
+pub fn bank_symbol(label: &str) -> String {
+    format!("{}:bank", label)
+}
+
+pub fn offset_symbol(label: &str) -> String {
+    format!("{}:offset", label)
+}
+
+// Where the jump placed at a label goes, which jumps to the label are redirected to
+pub fn jump_symbol(label: &str) -> String {
+    format!("{}:jump", label)
+}
+
+// `length` bytes of padding made of whole no-ops where they fit, or zeros when there is no no-op
+pub fn fill(nop: &[u8], length: usize) -> Vec<u8> {
+    let mut padding: Vec<_> = nop.iter().copied().cycle().take(length - length % nop.len().max(1)).collect();
+    padding.resize(length, 0);
+    padding
+}
+
+// Assembled code and the address it starts at, along with the image of each bank by number
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
+pub struct Image {
+    pub base: usize,
+    pub code: Vec<u8>,
+    pub banks: Vec<(usize, Image)>,
+    // Labels outside any bank, and those named by `.global`, with their addresses. Labels are
+    // local unless named by `.global`
+    pub labels: Vec<(String, usize)>,
+    pub exports: Vec<(String, usize)>,
+    // Address given by `.entry`, where execution starts
+    pub entry: Option<usize>,
+    // Code of each section outside a bank along with its address, for writing over an existing
+    // image without the gaps between sections
+    pub segments: Vec<(usize, Vec<u8>)>,
+    // Name, address, and size of each section holding code, banks included, for reports
+    pub sections: Vec<(String, usize, usize)>,
+    // Rows of the listing, when the assembler was asked for one
+    pub listing: Vec<Row>,
+    // Every symbol with its value and the statements that mention it, when the assembler was
+    // asked for a cross-reference report
+    pub xref: Vec<(String, usize, Vec<Site>)>,
+    // Statements of the final pass as operations and bytes, when the assembler was asked for them
+    pub program: Vec<ir::Statement>,
+    // What each rewrite given by `.peephole` changed, when asked for with `--peephole`
+    pub rewrites: Vec<String>,
+    // Operands truncated to fit their fields or relaxed to longer forms
+    pub adjustments: Vec<ir::Adjustment>,
+    // Name of each section holding instructions along with the ISA they were encoded with, when
+    // the source switches between ISAs with `.arch`
+    pub archs: Vec<(String, String)>,
+}
+
+// What tools outside the crate read of an image, which the prelude keeps stable while the fields
+// change with the assembler's own needs
+impl Image {
+    pub fn base(&self) -> usize { self.base }
+    pub fn code(&self) -> &[u8] { &self.code }
+    pub fn banks(&self) -> &[(usize, Image)] { &self.banks }
+    pub fn labels(&self) -> &[(String, usize)] { &self.labels }
+    pub fn exports(&self) -> &[(String, usize)] { &self.exports }
+    pub fn entry(&self) -> Option<usize> { self.entry }
+    pub fn sections(&self) -> &[(String, usize, usize)] { &self.sections }
+}
+
+// Handles directives in expanded statements, keeping the symbols they define and the sections code
+// is placed in
+#[derive(Debug, Default)]
+pub struct Directives {
+    pub symbols: SymbolTable,
+    block: Option<Block>,
+    // Address of the statement being processed
+    address: usize,
+    regions: Vec<Region>,
+    // Regions, alignment, fill, and bound symbols of sections given by the linker script or the
+    // config
+    placements: Vec<Placement>,
+    sections: Vec<Section>,
+    current: usize,
+    // Labels along with their addresses and sections, to name the one that overflows a region
+    labels: Vec<(String, usize, usize)>,
+    // Set when the caller writes code as it is produced, which only works with a single section
+    pub streaming: bool,
+    // Set to leave out sections nothing kept refers to
+    pub gc_sections: bool,
+    // Rows of the listing so far, if one is being recorded
+    pub listing: Option<Vec<Row>>,
+    // Symbols mentioned by each statement so far, if a cross-reference report is being made
+    pub references: Option<Vec<(String, Site)>>,
+    // Statements lowered so far, if the program is being recorded
+    pub program: Option<Vec<ir::Statement>>,
+    // Instruction emitted by the statement before, and what the rewrites so far changed
+    pub previous: Option<Previous>,
+    pub rewrites: Vec<String>,
+    // Operands of the statements so far that were truncated or relaxed
+    pub adjustments: Vec<ir::Adjustment>,
+    // ISA chosen by the last `.arch`, by its position among the others the assembler was given,
+    // or None for its own
+    pub arch: Option<usize>,
+    // Delay slots left after the last branch that has them, with its text and site
+    pub delay: Option<(usize, String, String)>,
+    // Instruction emitted last, which the next is checked against for hazards
+    pub executed: Option<Previous>,
+    // Files the one being assembled was included from, to catch a file including itself
+    pub including: Vec<String>,
+    // Values of `=value` operands waiting for the next `.pool`, and the number of pools so far
+    literals: Vec<usize>,
+    pools: usize,
+    // Whether the statement being processed has a `=value` operand, so its encoding depends on its address
+    uses_literal: bool,
+    // Integer and fractional bits of `.fixed` values
+    qformat: (usize, usize),
+    encoding: Encoding,
+    overflow: Overflow,
+    charmap: HashMap<char, u8>,
+    // Set by `.pic`, after which labels are encoded relative to the statement using them so the
+    // code can be loaded at any address
+    pic: bool,
+    // Emitted by `.buildid`; only set when whole sources are assembled, since it needs their hash
+    pub build_id: Option<BuildId>,
+    // Largest size allowed for each named section
+    pub budgets: Vec<(String, usize)>,
+    // The ISA's no-op, which gaps between sections are filled with
+    pub nop: Vec<u8>,
+    // Statements encoded so far in this pass
+    pub statements: usize,
+    // Names given to `.global`, which are the entry points of the image
+    exports: Vec<String>,
+    // Names given to `.local`, which must never be exported
+    locals: Vec<String>,
+    // Names given to `.weak` and not yet defined, whose next definitions are defaults that any
+    // other definition replaces
+    weak: Vec<String>,
+    // The label given by `.entry`
+    entry: Option<String>,
+    // Reserved registers the source may write, given by `.allow` until `.disallow`
+    allowed: Vec<usize>,
+    // Net entries pushed since the routine being assembled started, and the first label in it
+    pub stack_depth: isize,
+    pub routine: Option<String>,
+    // Cycles taken since the basic block being listed started
+    pub block_cycles: usize,
+}
+
+// Bytes emitted by `.buildid`: the source hash, the config hash, and the timestamp, each as a
+// little-endian u64. The timestamp is 0 when there is none, so the layout never changes.
+pub const BUILD_ID: usize = 24;
+
+// Identifies the inputs an image was built from
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BuildId {
+    pub source: u64,
+    pub config: u64,
+    // Seconds since the Unix epoch, or None for bit-reproducible output
+    pub timestamp: Option<u64>,
+}
+
+impl BuildId {
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BUILD_ID);
+        bytes.extend(&self.source.to_le_bytes());
+        bytes.extend(&self.config.to_le_bytes());
+        bytes.extend(&self.timestamp.unwrap_or(0).to_le_bytes());
+        bytes
+    }
+}
+
+// Splits operands at the commas outside of parentheses, so `min(a, b)` is one operand
+fn operands<'a, 'b>(lexemes: &'b [Lexeme<'a, Token<'a>>]) -> Vec<&'b [Lexeme<'a, Token<'a>>]> {
+    let mut operands = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, lexeme) in lexemes.iter().enumerate() {
+        match lexeme.token {
+            Token::OpenParen => depth += 1,
+            Token::CloseParen => depth = depth.saturating_sub(1),
+            Token::Comma if depth == 0 => {
+                operands.push(&lexemes[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    operands.push(&lexemes[start..]);
+    operands
+}
+
+// Parses a comma separated list of numbers, which may be negative
+fn numbers<'a>(logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) -> Vec<f64> {
+    let mut values = Vec::new();
+    for operand in lexemes.split(|l| l.token == Token::Comma) {
+        let value = match operand {
+            [Lexeme { token: Token::Integer(int), .. }] => *int as f64,
+            [Lexeme { token: Token::Float(float), .. }] => *float,
+            [Lexeme { token: Token::Operator(Operator::Sub), .. }, Lexeme { token: Token::Integer(int), .. }] => -(*int as f64),
+            [Lexeme { token: Token::Operator(Operator::Sub), .. }, Lexeme { token: Token::Float(float), .. }] => -float,
+            [first, .., last] | [first @ last] => {
+                logger.log_error_at(first.span.start..last.span.end, format!("expected a number, but got '{}'", first.slice));
+                continue;
+            },
+            [] => {
+                logger.log_error("expected a number".to_owned());
+                continue;
+            },
+        };
+        values.push(value);
+    }
+    values
+}
+
+// Parses the data of a `.hex` line, such as `DEADBEEF` or `0x12 0x34`, into bytes in the order
+// their digits are written, which is how hand-assembled listings give them
+fn hex_bytes(logger: &mut Logger, source: &str, data: Span) -> Vec<u8> {
+    let mut words = Vec::new();
+    let mut word = None;
+    for (i, c) in source[data.clone()].char_indices().map(|(i, c)| (data.start + i, c)).chain([(data.end, ' ')]) {
+        match (c.is_whitespace() || c == ',', word) {
+            (true, Some(from)) => {
+                words.push(from..i);
+                word = None;
+            },
+            (false, None) => word = Some(i),
+            _ => {},
+        }
+    }
+    if words.is_empty() {
+        logger.log_error("expected hex digits after '.hex'".to_owned());
+    }
+    let mut bytes = Vec::new();
+    for span in words {
+        let text = &source[span.clone()];
+        let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            logger.log_error_at(span, format!("expected hex digits, but got '{}'", text));
+        } else if digits.len() % 2 == 1 {
+            logger.log_error_at(span, format!("'{}' has an odd number of digits, so it doesn't make whole bytes", text));
+        } else {
+            bytes.extend((0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()));
+        }
+    }
+    bytes
+}
+
+impl Directives {
+    pub fn new() -> Self {
+        Self::with_symbols(SymbolTable::new(), &[])
+    }
+    
+    pub fn with_symbols(symbols: SymbolTable, regions: &[Region]) -> Self {
+        Self::with_layout(symbols, regions, &[])
+    }
+    
+    // Starts in the default section, placed in the first region if there are any and the linker
+    // script doesn't place it elsewhere
+    pub fn with_layout(symbols: SymbolTable, regions: &[Region], placements: &[Placement]) -> Self {
+        let mut directives = Self { symbols, qformat: DEFAULT_QFORMAT, regions: regions.to_vec(), placements: placements.to_vec(), ..Self::default() };
+        let region = directives.default_region(DEFAULT_SECTION);
+        directives.current = directives.open_section(DEFAULT_SECTION, region, None);
+        directives.place_ambles();
+        directives
+    }
+    
+    // Places the preamble of each region at its start and the postamble at its end, in kept
+    // sections of their own, with `__<region>_preamble` and `__<region>_postamble` labels at them.
+    // Sections opened in the region start after its preamble.
+    fn place_ambles(&mut self) {
+        let current = self.current;
+        for r in 0..self.regions.len() {
+            let Region { name, start, end, preamble, postamble } = self.regions[r].clone();
+            for (kind, base, code) in [("preamble", start, preamble), ("postamble", end + 1 - postamble.len(), postamble)] {
+                if code.is_empty() {
+                    continue;
+                }
+                let label = format!("__{}_{}", name, kind);
+                self.sections.push(Section { name: format!(".{}.{}", kind, name), region: Some(r), bank: None, base, origin: true, align: 1, uses: HashSet::new(), keep: true, code: Vec::new(), size: 0, arch: None });
+                self.current = self.sections.len() - 1;
+                self.emit(&code);
+                if self.symbols.define_label(&label, base).is_ok() {
+                    self.labels.push((label, base, self.current));
+                }
+            }
+        }
+        self.current = current;
+    }
+    
+    // Region of a section opened without one: where the linker script places it, or the first
+    fn default_region(&self, section: &str) -> Option<usize> {
+        match self.placements.iter().find(|p| p.section == section).and_then(|p| p.region) {
+            Some(region) => Some(region),
+            None if self.regions.is_empty() => None,
+            None => Some(0),
+        }
+    }
+    
+    // Records that the current section holds instructions of an ISA, which has to be the one any
+    // instructions already in it were encoded with
+    pub fn mark_arch(&mut self, arch: &str) -> Result<(), String> {
+        let section = &mut self.sections[self.current];
+        match &section.arch {
+            Some(other) if other != arch => Err(format!("section '{}' already holds '{}' instructions, so '{}' instructions need a section of their own", section.name, other, arch)),
+            Some(_) => Ok(()),
+            None => {
+                section.arch = Some(arch.to_owned());
+                Ok(())
+            },
+        }
+    }
+    
+    // Address the next statement is placed at
+    pub fn address(&self) -> usize {
+        let section = &self.sections[self.current];
+        section.base + section.size
+    }
+    
+    // Appends code to the current section
+    pub fn emit(&mut self, code: &[u8]) {
+        let section = &mut self.sections[self.current];
+        section.code.extend(code);
+        section.size += code.len();
+    }
+    
+    // Moves the statement up to a multiple of `alignment`, along with the labels placed where it
+    // was, returning how many bytes of padding the caller has to emit before its code
+    pub fn align(&mut self, alignment: usize) -> usize {
+        let padding = (alignment - self.address % alignment) % alignment;
+        if padding == 0 {
+            return 0;
+        }
+        self.move_labels(self.address, self.address + padding);
+        self.address += padding;
+        padding
+    }
+    
+    // Moves the labels placed at an address of the current section to another
+    fn move_labels(&mut self, from: usize, to: usize) {
+        let Section { bank, base, .. } = self.sections[self.current];
+        for (label, address, section) in &mut self.labels {
+            if *address == from && *section == self.current {
+                *address = to;
+                self.symbols.redefine(label, to);
+                if bank.is_some() {
+                    self.symbols.redefine(&offset_symbol(label), to - base);
+                }
+            }
+        }
+    }
+    
+    // Takes back the last bytes emitted to the current section, along with the statement of the
+    // program they were recorded for. Labels placed after them move back with the next statement.
+    pub fn retract(&mut self, length: usize) {
+        let end = self.address();
+        let section = &mut self.sections[self.current];
+        section.code.truncate(section.code.len() - length);
+        section.size -= length;
+        let address = self.address();
+        self.move_labels(end, address);
+        self.address = address;
+        if let Some(program) = &mut self.program {
+            if program.last().is_some_and(|statement| statement.address == address) {
+                program.pop();
+            }
+        }
+    }
+    
+    // Whether a label is placed where the next statement goes
+    pub fn labelled(&self) -> bool {
+        !self.labels_at(self.address()).is_empty()
+    }
+    
+    // Labels placed at an address of the current section
+    pub fn labels_at(&self, address: usize) -> Vec<&str> {
+        self.labels.iter().filter(|(_, a, section)| *a == address && *section == self.current).map(|(label, _, _)| label.as_str()).collect()
+    }
+    
+    // Takes the code emitted to the current section so far for the caller to write out itself,
+    // leaving the addresses of what follows as they were
+    pub fn take_code(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sections[self.current].code)
+    }
+    
+    // Returns the index of the section, adding it after the sections already in its region
+    fn open_section(&mut self, name: &str, region: Option<usize>, bank: Option<usize>) -> usize {
+        if let Some(i) = self.sections.iter().position(|s| s.name == name) {
+            return i;
+        }
+        let start = region.map_or(0, |r| self.regions[r].start + self.regions[r].preamble.len());
+        let align = self.placements.iter().find(|p| p.section == name).map_or(1, |p| p.align);
+        let round = |address: usize, align: usize| address + (align - address % align) % align;
+        let base = match bank {
+            Some(_) => start,
+            None => round(self.sections.iter()
+                .filter(|s| s.region == region && s.bank.is_none() && !s.origin)
+                .fold(start, |end, s| round(end, s.align) + self.symbols.get(&size_symbol(&s.name)).unwrap_or(0)), align),
+        };
+        self.sections.push(Section { name: name.to_owned(), region, bank, base, origin: false, align, uses: HashSet::new(), keep: false, code: Vec::new(), size: 0, arch: None });
+        self.sections.len() - 1
+    }
+    
+    // `.org address`, placing the following code at the address in a section of its own. Giving
+    // the same address again continues that section.
+    fn set_origin<'a>(&mut self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        if lexemes.is_empty() {
+            logger.log_error("expected an address after '.org'".to_owned());
+            return;
+        }
+        if self.streaming {
+            logger.log_error("'.org' can't be used when streaming output, since code is written as it is assembled".to_owned());
+            return;
+        }
+        let address = self.integer(logger, lexemes);
+        let name = format!(".org {:#06x}", address);
+        self.current = self.sections.iter().position(|s| s.name == name).unwrap_or_else(|| {
+            self.sections.push(Section { name, region: None, bank: None, base: address, origin: true, align: 1, uses: HashSet::new(), keep: false, code: Vec::new(), size: 0, arch: None });
+            self.sections.len() - 1
+        });
+    }
+    
+    // `.section name [, region]`
+    fn set_section<'a>(&mut self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        let (name, region) = match lexemes {
+            [Lexeme { slice, token: Token::Directive(_) | Token::Ident(_), .. }] => (*slice, None),
+            [Lexeme { slice, token: Token::Directive(_) | Token::Ident(_), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Ident(region), span, .. }] => {
+                match self.regions.iter().position(|r| r.name == *region) {
+                    Some(region) => (*slice, Some(region)),
+                    None => {
+                        logger.log_error_at(span.clone(), format!("unknown region '{}'", region));
+                        return;
+                    },
+                }
+            },
+            _ => {
+                logger.log_error("expected '.section <name> [, <region>]'".to_owned());
+                return;
+            },
+        };
+        let existing = self.sections.iter().position(|s| s.name == name);
+        if self.streaming && existing != Some(0) {
+            logger.log_error(format!("section '{}' can't be used when streaming output, since code is written as it is assembled", name));
+            return;
+        }
+        if let (Some(placed), Some(region)) = (self.placements.iter().find(|p| p.section == name).and_then(|p| p.region), region) {
+            if placed != region {
+                logger.log_error(format!("section '{}' is placed in region '{}' by the linker script", name, self.regions[placed].name));
+                return;
+            }
+        }
+        match (existing, region) {
+            (Some(i), Some(region)) if self.sections[i].region != Some(region) => {
+                logger.log_error(format!("section '{}' is already placed in another region", name));
+            },
+            (Some(i), _) => self.current = i,
+            (None, region) => {
+                let region = region.or_else(|| self.default_region(name));
+                self.current = self.open_section(name, region, None);
+            },
+        }
+    }
+    
+    // `.bank n [, region]`, placing the following code in the bank
+    fn set_bank<'a>(&mut self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        let (bank, region) = match lexemes {
+            [Lexeme { token: Token::Integer(bank), .. }] => (*bank, None),
+            [Lexeme { token: Token::Integer(bank), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Ident(region), span, .. }] => {
+                match self.regions.iter().position(|r| r.name == *region) {
+                    Some(region) => (*bank, Some(region)),
+                    None => {
+                        logger.log_error_at(span.clone(), format!("unknown region '{}'", region));
+                        return;
+                    },
+                }
+            },
+            _ => {
+                logger.log_error("expected '.bank <number> [, <region>]'".to_owned());
+                return;
+            },
+        };
+        if self.streaming {
+            logger.log_error("banks can't be used when streaming output, since code is written as it is assembled".to_owned());
+            return;
+        }
+        let region = region.or(if self.regions.is_empty() { None } else { Some(0) });
+        self.current = self.open_section(&format!(".bank{}", bank), region, Some(bank));
+    }
+    
+    // Value of a symbol used as an operand of the current statement
+    pub fn resolve(&self, name: &str) -> Option<usize> {
+        let value = self.symbols.get(name)?;
+        match self.pic && self.symbols.is_label(name) {
+            true => Some(value.wrapping_sub(self.address)),
+            false => Some(value),
+        }
+    }
+    
+    // Value of a constant expression in an operand of the current statement. A value that wraps on
+    // overflow is kept, with a warning at the span of the operand.
+    pub fn evaluate(&self, logger: &mut Logger, span: &Span, expr: &Expr) -> Result<usize, String> {
+        let banked = |label: &str, symbol: String| match self.symbols.get(&symbol) {
+            Some(value) => Ok(value),
+            None if self.symbols.get(label).is_some() => Err(format!("'{}' is not in a bank", label)),
+            None => Err(format!("unknown symbol: '{}'", label)),
+        };
+        Ok(match expr {
+            Expr::Integer(int) => *int,
+            Expr::Symbol(symbol) => self.resolve(symbol).ok_or_else(|| format!("unknown symbol: '{}'", symbol))?,
+            Expr::Bank(label) => banked(label, bank_symbol(label))?,
+            Expr::Offset(label) => banked(label, offset_symbol(label))?,
+            Expr::Unary(op, value) => {
+                let value = self.evaluate(logger, span, value)?;
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::LogicalNot => (value == 0) as usize,
+                }
+            },
+            Expr::Binary(op, lhs_expr, rhs_expr) => {
+                let (lhs, rhs) = (self.evaluate(logger, span, lhs_expr)?, self.evaluate(logger, span, rhs_expr)?);
+                // An integer written above the largest signed value is unsigned, so
+                // `0xFFFFFFFFFFFFFFFF * 16` overflows rather than being read as `-1 * 16`
+                let unsigned_only = [lhs_expr, rhs_expr].iter().any(|side| matches!(side.as_ref(), Expr::Integer(int) if *int > i64::MAX as usize));
+                // A value too large for both is clamped towards the side the signed operation
+                // saturates on
+                let mut checked = |unsigned: Option<usize>, signed: Option<i64>, wrapped: usize, saturated: i64| match (unsigned, signed.filter(|_| !unsigned_only), self.overflow) {
+                    (Some(value), _, _) => Ok(value),
+                    (_, Some(value), _) => Ok(value as usize),
+                    (None, None, Overflow::Wrap) => {
+                        logger.log_warning_at(span.clone(), wrapping(expr, wrapped));
+                        Ok(wrapped)
+                    },
+                    (None, None, Overflow::Saturate) if saturated == i64::MIN => Ok(i64::MIN as usize),
+                    (None, None, Overflow::Saturate) => Ok(usize::MAX),
+                    (None, None, Overflow::Error) => Err(format!("'{}' overflows 64 bits", expr)),
+                };
+                let divided = |value: Option<usize>| match (value, self.overflow) {
+                    (Some(value), _) => Ok(value),
+                    (None, Overflow::Saturate) if *op == BinaryOp::Div => Ok(usize::MAX),
+                    (None, Overflow::Saturate) => Ok(lhs),
+                    (None, _) => match rhs_expr.as_ref() {
+                        Expr::Integer(_) => Err(format!("division by zero in '{}'", expr)),
+                        divisor => Err(format!("division by zero in '{}', since '{}' is 0", expr, divisor)),
+                    },
+                };
+                let (l, r) = (lhs as i64, rhs as i64);
+                match op {
+                    BinaryOp::Add => checked(lhs.checked_add(rhs), l.checked_add(r), lhs.wrapping_add(rhs), l.saturating_add(r))?,
+                    BinaryOp::Sub => checked(lhs.checked_sub(rhs), l.checked_sub(r), lhs.wrapping_sub(rhs), l.saturating_sub(r))?,
+                    BinaryOp::Mul => checked(lhs.checked_mul(rhs), l.checked_mul(r), lhs.wrapping_mul(rhs), l.saturating_mul(r))?,
+                    BinaryOp::Div => divided(lhs.checked_div(rhs))?,
+                    BinaryOp::Rem => divided(lhs.checked_rem(rhs))?,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    // Bits shifted out are lost unless shifting back gets the value again, as an
+                    // unsigned or a signed number
+                    BinaryOp::Shl => {
+                        let shifted = lhs.checked_shl(rhs as u32).unwrap_or(0);
+                        let unsigned = (lhs == 0 || rhs < 64 && shifted >> rhs == lhs).then_some(shifted);
+                        let signed = (rhs < 64 && (shifted as i64) >> rhs == l).then_some(shifted as i64);
+                        checked(unsigned, signed, shifted, if l < 0 { i64::MIN } else { i64::MAX })?
+                    },
+                    BinaryOp::Shr => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                    BinaryOp::Eq => (lhs == rhs) as usize,
+                    BinaryOp::Ne => (lhs != rhs) as usize,
+                    BinaryOp::Lt => (lhs < rhs) as usize,
+                    BinaryOp::Le => (lhs <= rhs) as usize,
+                    BinaryOp::Gt => (lhs > rhs) as usize,
+                    BinaryOp::Ge => (lhs >= rhs) as usize,
+                }
+            },
+            Expr::Call(function, arguments) => {
+                let values = arguments.iter().map(|argument| self.evaluate(logger, span, argument)).collect::<Result<Vec<_>, _>>()?;
+                match (function, values.as_slice()) {
+                    (Function::Min, [_, ..]) => values.into_iter().min().unwrap(),
+                    (Function::Max, [_, ..]) => values.into_iter().max().unwrap(),
+                    (Function::Abs, [value]) => (*value as isize).unsigned_abs(),
+                    (Function::Log2, [0]) => return Err("'log2' of 0 is undefined".to_owned()),
+                    (Function::Log2, [value]) => value.ilog2() as usize,
+                    (Function::AlignUp, [_, 0]) => return Err("'align_up' to a multiple of 0".to_owned()),
+                    (Function::AlignUp, [value, multiple]) => match (value.div_ceil(*multiple).checked_mul(*multiple), self.overflow) {
+                        (Some(aligned), _) => aligned,
+                        (None, Overflow::Wrap) => {
+                            let wrapped = value.div_ceil(*multiple).wrapping_mul(*multiple);
+                            logger.log_warning_at(span.clone(), wrapping(expr, wrapped));
+                            wrapped
+                        },
+                        (None, Overflow::Saturate) => usize::MAX,
+                        (None, Overflow::Error) => return Err(format!("'{}' overflows 64 bits", expr)),
+                    },
+                    (Function::Min | Function::Max, _) => return Err(format!("'{}' takes at least one value", function.name())),
+                    (Function::Abs | Function::Log2, _) => return Err(format!("'{}' takes one value", function.name())),
+                    (Function::AlignUp, _) => return Err("expected 'align_up(value, multiple)'".to_owned()),
+                }
+            },
+        })
+    }
+    
+    // Offset from the current statement to the pool entry of a `=value` operand, once the pool has
+    // been placed in a previous pass
+    pub fn literal(&self, value: usize) -> Option<usize> {
+        self.symbols.get(&literal_symbol(self.pools, value)).map(|entry| entry.wrapping_sub(self.address))
+    }
+    
+    // Identifies everything the encoding of the current statement depends on
+    pub fn fingerprint(&self) -> u64 {
+        match self.uses_literal || self.pic {
+            true => self.symbols.fingerprint() ^ (self.address as u64).wrapping_mul(0x100000001b3),
+            false => self.symbols.fingerprint(),
+        }
+    }
+    
+    // Adds the values of `=value` operands to the pending pool
+    fn collect_literals<'a>(&mut self, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        for pair in lexemes.windows(2) {
+            let value = match pair {
+                [Lexeme { token: Token::Assign, .. }, Lexeme { token: Token::Integer(value), .. }] => Some(*value),
+                [Lexeme { token: Token::Assign, .. }, Lexeme { token: Token::Ident(name), .. }] => self.symbols.get(name),
+                _ => continue,
+            };
+            self.uses_literal = true;
+            if let Some(value) = value.filter(|v| !self.literals.contains(v)) {
+                self.literals.push(value);
+            }
+        }
+    }
+    
+    // Encodes values as two's complement fixed point in the current Q format
+    fn fixed(&self, logger: &mut Logger, values: &[f64]) -> Vec<u8> {
+        let (integer, fraction) = self.qformat;
+        let bits = integer + fraction;
+        let mut output = Vec::with_capacity(values.len() * bits / 8);
+        for value in values {
+            let scaled = (value * (fraction as f64).exp2()).round();
+            if scaled < -(bits as f64 - 1.0).exp2() || scaled >= (bits as f64 - 1.0).exp2() {
+                logger.log_permissive(None, format!("'{}' is out of range for Q{}.{} and will be truncated", value, integer, fraction));
+            }
+            output.extend(&(scaled as i64).to_le_bytes()[..bits / 8]);
+        }
+        output
+    }
+    
+    // `.size_assert start, end, limit`, failing when the code between the labels is larger than the
+    // limit. Labels after the directive have their addresses from the previous pass, which are
+    // final in the last one.
+    fn size_assert<'a>(&self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        let (start, end, limit) = match lexemes {
+            [Lexeme { token: Token::Ident(start), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Ident(end), .. }, Lexeme { token: Token::Comma, .. }, limit @ ..] => (*start, *end, limit),
+            _ => {
+                logger.log_error("expected '.size_assert <start label>, <end label>, <size>'".to_owned());
+                return;
+            },
+        };
+        let limit = self.integer(logger, limit);
+        let (start_address, end_address) = match (self.symbols.get(start), self.symbols.get(end)) {
+            (Some(start_address), Some(end_address)) => (start_address, end_address),
+            (None, _) => return logger.log_error(format!("unknown symbol: '{}'", start)),
+            (_, None) => return logger.log_error(format!("unknown symbol: '{}'", end)),
+        };
+        let size = end_address.wrapping_sub(start_address);
+        if end_address < start_address {
+            logger.log_error(format!("'{}' comes before '{}'", end, start));
+        } else if size > limit {
+            logger.log_error(format!("'{}' is {} bytes, over its budget of {} by {}", start, size, limit, size - limit));
+        }
+    }
+    
+    // Value of an integer, symbol, or constant expression. Errors are reported and give 0, so the
+    // size of the directive holding the value doesn't change.
+    fn integer<'a>(&self, logger: &mut Logger, operand: &[Lexeme<'a, Token<'a>>]) -> usize {
+        match operand {
+            [Lexeme { token: Token::Integer(int), .. }] => *int,
+            [Lexeme { token: Token::Ident(name), span, .. }] => self.symbols.get(name).unwrap_or_else(|| {
+                logger.log_error_at(span.clone(), format!("unknown symbol: '{}'", name));
+                0
+            }),
+            [first, .., last] | [first @ last] => match constant(operand) {
+                Some(expr) => self.evaluate(logger, &(first.span.start..last.span.end), &expr).unwrap_or_else(|message| {
+                    logger.log_error_at(first.span.start..last.span.end, message);
+                    0
+                }),
+                None => {
+                    logger.log_error_at(first.span.start..last.span.end, format!("expected an integer, but got '{}'", first.slice));
+                    0
+                },
+            },
+            [] => {
+                logger.log_error("expected an integer".to_owned());
+                0
+            },
+        }
+    }
+    
+    // Encodes the values in a `.byte`, `.word`, or `.long` directive, `size` bytes each. Values
+    // computed by an expression are reported with its text from the source.
+    fn integers<'a>(&self, logger: &mut Logger, source: &str, lexemes: &[Lexeme<'a, Token<'a>>], size: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(lexemes.len() * size);
+        for operand in operands(lexemes) {
+            let value = self.integer(logger, operand);
+            match operand {
+                _ if fits_in(value, size * 8) => {},
+                [first, _, ..] => {
+                    let span = first.span.start..operand[operand.len() - 1].span.end;
+                    let message = format!("'{}' is {} ({} bits), which will be truncated to {} bits", &source[span.clone()], value as isize, width_of(value), size * 8);
+                    logger.log_permissive(Some(span), message);
+                },
+                _ => logger.log_permissive(None, format!("{} does not fit in {} bits and will be truncated", value as isize, size * 8)),
+            }
+            output.extend(&value.to_le_bytes()[..size]);
+        }
+        output
+    }
+    
+    // Encodes the strings in a `.ascii` or `.asciz` directive, with a NUL after each if `terminate`
+    fn strings<'a>(&self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>], terminate: bool) -> Vec<u8> {
+        let mut output = Vec::new();
+        for operand in lexemes.split(|l| l.token == Token::Comma) {
+            let (literal, span) = match operand {
+                [Lexeme { token: Token::String(literal), span, .. }] => (*literal, span.clone()),
+                [first, .., last] | [first @ last] => {
+                    logger.log_error_at(first.span.start..last.span.end, format!("expected a string, but got '{}'", first.slice));
+                    continue;
+                },
+                [] => {
+                    logger.log_error("expected a string".to_owned());
+                    continue;
+                },
+            };
+            let units = match unescape(literal) {
+                Ok(units) => units,
+                Err(message) => {
+                    logger.log_error_at(span, message);
+                    continue;
+                },
+            };
+            for unit in units {
+                match (unit, self.encoding) {
+                    (Unit::Byte(byte), _) => output.push(byte),
+                    (Unit::Char(c), Encoding::Ascii) if !c.is_ascii() => {
+                        logger.log_error_at(span.clone(), format!("'{}' is not an ASCII character", c));
+                    },
+                    (Unit::Char(c), Encoding::Charmap) => match self.charmap.get(&c) {
+                        Some(byte) => output.push(*byte),
+                        None => logger.log_error_at(span.clone(), format!("'{}' is not in the character map", c.escape_default())),
+                    },
+                    (Unit::Char(c), _) => output.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                }
+            }
+            if terminate {
+                output.push(0);
+            }
+        }
+        output
+    }
+    
+    // `.charmap "chars", value` maps the characters to consecutive bytes starting at the value
+    fn add_charmap<'a>(&mut self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        let (literal, span, start) = match lexemes {
+            [Lexeme { token: Token::String(literal), span, .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Integer(start), .. }] => (*literal, span, *start),
+            _ => {
+                logger.log_error("expected '.charmap \"<characters>\", <first byte>'".to_owned());
+                return;
+            },
+        };
+        let units = match unescape(literal) {
+            Ok(units) => units,
+            Err(message) => {
+                logger.log_error_at(span.clone(), message);
+                return;
+            },
+        };
+        if start + units.len() > 0x100 {
+            logger.log_error_at(span.clone(), format!("mapping {} characters from {} goes past 255", units.len(), start));
+            return;
+        }
+        for (i, unit) in units.into_iter().enumerate() {
+            match unit {
+                Unit::Char(c) => {
+                    self.charmap.insert(c, (start + i) as u8);
+                },
+                Unit::Byte(_) => logger.log_error_at(span.clone(), "'\\x' escapes can't be mapped since they are bytes already".to_owned()),
+            }
+        }
+    }
+    
+    fn set_qformat<'a>(&mut self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        match lexemes {
+            [Lexeme { token: Token::Integer(integer), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Integer(fraction), .. }] if matches!(integer + fraction, 8 | 16 | 32 | 64) => {
+                self.qformat = (*integer, *fraction);
+            },
+            _ => logger.log_error("expected '.qformat <integer bits>, <fraction bits>' totalling 8, 16, 32, or 64 bits".to_owned()),
+        }
+    }
+    
+    // Places the pending literals at the current address, defining the symbols for their entries
+    fn pool(&mut self, logger: &mut Logger) -> Vec<u8> {
+        let address = self.address();
+        let mut output = Vec::with_capacity(self.literals.len() * LITERAL);
+        for (i, value) in std::mem::take(&mut self.literals).into_iter().enumerate() {
+            if value >> (LITERAL * 8) != 0 {
+                logger.log_permissive(None, format!("literal '{}' will be truncated to {} bits", value, LITERAL * 8));
+            }
+            self.define(logger, &literal_symbol(self.pools, value), address + i * LITERAL);
+            output.extend(&value.to_le_bytes()[..LITERAL]);
+        }
+        self.pools += 1;
+        output
+    }
+    
+    fn define(&mut self, logger: &mut Logger, name: &str, value: usize) {
+        if let Err(message) = self.symbols.define(name, value) {
+            logger.log_error(message);
+        }
+    }
+    
+    // A `name .byte`, `name .word`, `name .dword`, `name .res n`, or `name .tag struct` line
+    fn field<'a>(&self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) -> Option<(String, usize)> {
+        let size = match lexemes {
+            [_, Lexeme { token: Token::Directive(kind), .. }, rest @ ..] => match (kind.to_lowercase().as_str(), rest) {
+                (".byte", []) => 1,
+                (".word", []) => WORD,
+                (".dword", []) => DWORD,
+                (".res", [Lexeme { token: Token::Integer(size), .. }]) => *size,
+                (".tag", [Lexeme { token: Token::Ident(name), span, .. }]) => match self.symbols.get(&format!("{}.size", name)) {
+                    Some(size) => size,
+                    None => {
+                        logger.log_error_at(span.clone(), format!("unknown struct '{}'", name));
+                        return None;
+                    },
+                },
+                _ => {
+                    logger.log_error_at(lexemes[1].span.clone(), "expected '.byte', '.word', '.dword', '.res <size>', or '.tag <struct>' after the field name".to_owned());
+                    return None;
+                },
+            },
+            _ => {
+                logger.log_error("expected a struct field as '<name> <size directive>'".to_owned());
+                return None;
+            },
+        };
+        match &lexemes[0].token {
+            Token::Ident("size") => logger.log_error_at(lexemes[0].span.clone(), "'size' is reserved for the size of the struct".to_owned()),
+            Token::Ident(name) => return Some((name.to_string(), size)),
+            _ => logger.log_error_at(lexemes[0].span.clone(), format!("expected a field name, but got '{}'", lexemes[0].slice)),
+        }
+        None
+    }
+    
+    fn struct_line<'a>(&mut self, logger: &mut Logger, name: String, mut fields: Vec<(String, usize)>, mut size: usize, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        match lexemes {
+            [] => {},
+            [Lexeme { token: Token::Directive(directive), .. }] if directive.eq_ignore_ascii_case(".ends") => {
+                for (field, offset) in &fields {
+                    self.define(logger, &format!("{}.{}", name, field), *offset);
+                }
+                self.define(logger, &format!("{}.size", name), size);
+                return;
+            },
+            _ => if let Some((field, length)) = self.field(logger, lexemes) {
+                if fields.iter().any(|(f, _)| *f == field) {
+                    logger.log_error(format!("struct '{}' already has a field '{}'", name, field));
+                } else {
+                    fields.push((field, size));
+                    size += length;
+                }
+            },
+        }
+        self.block = Some(Block::Struct { name, fields, size });
+    }
+    
+    // A `member` or `member = value` line, where later members continue on from the value
+    fn enum_line<'a>(&mut self, logger: &mut Logger, name: Option<String>, mut next: usize, stride: usize, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        let member = match lexemes {
+            [] => None,
+            [Lexeme { token: Token::Directive(directive), .. }] if directive.eq_ignore_ascii_case(".ende") => return,
+            [Lexeme { token: Token::Ident(member), .. }] => Some(member),
+            [Lexeme { token: Token::Ident(member), .. }, Lexeme { token: Token::Assign, .. }, Lexeme { token: Token::Integer(value), .. }] => {
+                next = *value;
+                Some(member)
+            },
+            [Lexeme { token: Token::Ident(_), .. }, Lexeme { token: Token::Assign, .. }, rest @ ..] => {
+                let span = rest.first().map_or(lexemes[1].span.clone(), |l| l.span.start..lexemes[lexemes.len() - 1].span.end);
+                logger.log_error_at(span, "expected an integer value for the enum member".to_owned());
+                None
+            },
+            [lexeme, ..] => {
+                logger.log_error_at(lexeme.span.clone(), "expected an enum member as '<name>' or '<name> = <value>'".to_owned());
+                None
+            },
+        };
+        if let Some(member) = member {
+            let symbol = name.as_ref().map_or(member.to_string(), |name| format!("{}.{}", name, member));
+            self.define(logger, &symbol, next);
+            next += stride;
+        }
+        self.block = Some(Block::Enum { name, next, stride });
+    }
+    
+    // Parses `[name] [, start [, stride]]` following `.enum`
+    fn start_enum<'a>(&mut self, logger: &mut Logger, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        let (name, rest) = match lexemes {
+            [Lexeme { token: Token::Ident(name), .. }, rest @ ..] => (Some(name.to_string()), rest),
+            _ => (None, lexemes),
+        };
+        let rest = match (&name, rest) {
+            (Some(_), [Lexeme { token: Token::Comma, .. }, rest @ ..]) => rest,
+            _ => rest,
+        };
+        let (start, stride) = match rest {
+            [] => (0, 1),
+            [Lexeme { token: Token::Integer(start), .. }] => (*start, 1),
+            [Lexeme { token: Token::Integer(start), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Integer(stride), .. }] => (*start, *stride),
+            [first, .., last] | [first @ last] => {
+                logger.log_error_at(first.span.start..last.span.end, "expected '.enum [name] [, start [, stride]]'".to_owned());
+                (0, 1)
+            },
+        };
+        self.block = Some(Block::Enum { name, next: start, stride });
+    }
+    
+    // Handles the statement if it is a label, a directive, or inside a directive block, returning
+    // the bytes it emits; None means it is an instruction to be encoded. Either way the caller
+    // passes the code on to `emit`.
+    pub fn process(&mut self, source: &str) -> LoggedResult<Option<Vec<u8>>> {
+        let mut logger = Logger::new(None);
+        let lexemes: Vec<_> = Lexer::new(source).collect();
+        self.address = self.address();
+        self.uses_literal = false;
+        if let Some(block) = self.block.take() {
+            match block {
+                Block::Struct { name, fields, size } => self.struct_line(&mut logger, name, fields, size, &lexemes),
+                Block::Enum { name, next, stride } => self.enum_line(&mut logger, name, next, stride, &lexemes),
+            }
+            return logger.into_result(|| Some(Vec::new()));
+        }
+        let directive = match lexemes.as_slice() {
+            [Lexeme { token: Token::Directive(directive), .. }, ..] => directive.to_lowercase(),
+            [Lexeme { token: Token::Ident(label), .. }, Lexeme { token: Token::Colon, .. }] => {
+                self.label(&mut logger, label);
+                return logger.into_result(|| Some(Vec::new()));
+            },
+            _ => {
+                self.collect_literals(&lexemes);
+                self.record_uses(&lexemes);
+                return logger.into_result(|| None);
+            },
+        };
+        if !DECLARATIONS.contains(&directive.as_str()) {
+            self.record_uses(&lexemes);
+        }
+        match (directive.as_str(), &lexemes[1..]) {
+            (".struct", [Lexeme { token: Token::Ident(name), .. }]) => {
+                self.block = Some(Block::Struct { name: name.to_string(), fields: Vec::new(), size: 0 });
+            },
+            (".struct", _) => logger.log_error("expected a struct name after '.struct'".to_owned()),
+            (".enum", rest) => self.start_enum(&mut logger, rest),
+            (".ende", _) => logger.log_error_at(lexemes[0].span.clone(), "'.ende' without a matching '.enum'".to_owned()),
+            (".float", rest) => {
+                let output = numbers(&mut logger, rest).into_iter().flat_map(|v| (v as f32).to_le_bytes()).collect();
+                return logger.into_result(|| Some(output));
+            },
+            (".double", rest) => {
+                let output = numbers(&mut logger, rest).into_iter().flat_map(f64::to_le_bytes).collect();
+                return logger.into_result(|| Some(output));
+            },
+            (".fixed", rest) => {
+                let values = numbers(&mut logger, rest);
+                let output = self.fixed(&mut logger, &values);
+                return logger.into_result(|| Some(output));
+            },
+            (".qformat", rest) => self.set_qformat(&mut logger, rest),
+            (".hex", _) => {
+                // Up to the last lexeme, which leaves out a comment
+                let output = hex_bytes(&mut logger, source, lexemes[0].span.end..lexemes[lexemes.len() - 1].span.end);
+                return logger.into_result(|| Some(output));
+            },
+            (".byte" | ".word" | ".long", rest) => {
+                let size = match directive.as_str() {
+                    ".byte" => 1,
+                    ".word" => WORD,
+                    _ => DWORD,
+                };
+                let output = self.integers(&mut logger, source, rest, size);
+                return logger.into_result(|| Some(output));
+            },
+            (".set" | ".equ", [Lexeme { token: Token::Ident(name), .. }, Lexeme { token: Token::Comma, .. }, value @ ..]) => {
+                let value = self.integer(&mut logger, value);
+                self.define(&mut logger, name, value);
+            },
+            (".allow" | ".disallow", rest) => {
+                for operand in rest.split(|l| l.token == Token::Comma) {
+                    match operand {
+                        [Lexeme { token: Token::Register(r), .. }] if directive == ".allow" => self.allowed.push(*r),
+                        [Lexeme { token: Token::Register(r), .. }] => self.allowed.retain(|a| a != r),
+                        _ => logger.log_error_at(lexemes[0].span.clone(), format!("expected '{} <register>, ...'", directive)),
+                    }
+                }
+            },
+            // Stack effects of code the config can't see, like a store through the stack pointer
+            (".push_depth" | ".pop_depth", rest) => {
+                let count = match rest {
+                    [] => 1,
+                    rest => self.integer(&mut logger, rest) as isize,
+                };
+                self.stack_depth += if directive == ".push_depth" { count } else { -count };
+            },
+            (".global" | ".globl" | ".local" | ".weak", rest) => {
+                for operand in rest.split(|l| l.token == Token::Comma) {
+                    match operand {
+                        [Lexeme { token: Token::Ident(name), .. }] if directive == ".local" => self.locals.push(name.to_string()),
+                        [Lexeme { token: Token::Ident(name), .. }] if directive == ".weak" => self.weak.push(name.to_string()),
+                        [Lexeme { token: Token::Ident(name), .. }] => self.exports.push(name.to_string()),
+                        _ => logger.log_error_at(lexemes[0].span.clone(), format!("expected '{} <label>, ...'", directive)),
+                    }
+                }
+            },
+            (".entry", [Lexeme { token: Token::Ident(name), span, .. }]) => match &self.entry {
+                Some(entry) => logger.log_error_at(span.clone(), format!("the entry point is already '{}'", entry)),
+                None => self.entry = Some(name.to_string()),
+            },
+            (".entry", _) => logger.log_error_at(lexemes[0].span.clone(), "expected '.entry <label>'".to_owned()),
+            (".set" | ".equ", _) => logger.log_error_at(lexemes[0].span.clone(), format!("expected '{} <name>, <value>'", directive)),
+            (".ascii" | ".asciz", rest) => {
+                let output = self.strings(&mut logger, rest, directive == ".asciz");
+                return logger.into_result(|| Some(output));
+            },
+            (".encoding", [Lexeme { token: Token::Ident(encoding), span, .. }]) => match encoding.to_lowercase().as_str() {
+                "raw" => self.encoding = Encoding::Raw,
+                "ascii" => self.encoding = Encoding::Ascii,
+                "charmap" => self.encoding = Encoding::Charmap,
+                _ => logger.log_error_at(span.clone(), format!("unknown encoding '{}', expected 'raw', 'ascii', or 'charmap'", encoding)),
+            },
+            (".charmap", rest) => self.add_charmap(&mut logger, rest),
+            (".encoding", _) => logger.log_error("expected an encoding after '.encoding'".to_owned()),
+            (".overflow", [Lexeme { token: Token::Ident(policy), span, .. }]) => match policy.to_lowercase().as_str() {
+                "wrap" => self.overflow = Overflow::Wrap,
+                "saturate" => self.overflow = Overflow::Saturate,
+                "error" => self.overflow = Overflow::Error,
+                _ => logger.log_error_at(span.clone(), format!("unknown overflow policy '{}', expected 'wrap', 'saturate', or 'error'", policy)),
+            },
+            (".overflow", _) => logger.log_error("expected 'wrap', 'saturate', or 'error' after '.overflow'".to_owned()),
+            (".section", rest) => self.set_section(&mut logger, rest),
+            (".org", rest) => self.set_origin(&mut logger, rest),
+            (".bank", rest) => self.set_bank(&mut logger, rest),
+            (".size_assert", rest) => self.size_assert(&mut logger, rest),
+            (".buildid", []) => match self.build_id {
+                Some(build_id) => return logger.into_result(|| Some(build_id.bytes())),
+                None => logger.log_error_at(lexemes[0].span.clone(), "'.buildid' is only available when assembling whole sources".to_owned()),
+            },
+            (".buildid", [lexeme, ..]) => logger.log_error_at(lexeme.span.clone(), "'.buildid' takes no operands".to_owned()),
+            // `.function name` starts a section of its own holding the routine, so `--gc-sections`
+            // can leave it out when nothing calls it
+            (".function", [Lexeme { token: Token::Ident(name), .. }]) => {
+                let section = format!("{}.{}", DEFAULT_SECTION, name);
+                if self.streaming {
+                    logger.log_error(format!("section '{}' can't be used when streaming output, since code is written as it is assembled", section));
+                } else {
+                    let region = self.default_region(&section);
+                    self.current = self.open_section(&section, region, None);
+                    self.address = self.address();
+                    self.label(&mut logger, name);
+                }
+            },
+            (".function", _) => logger.log_error_at(lexemes[0].span.clone(), "expected '.function <name>'".to_owned()),
+            (".keep", []) => self.sections[self.current].keep = true,
+            (".keep", [lexeme, ..]) => logger.log_error_at(lexeme.span.clone(), "'.keep' takes no operands".to_owned()),
+            (".pic", []) => self.pic = true,
+            (".pic", [lexeme, ..]) => logger.log_error_at(lexeme.span.clone(), "'.pic' takes no operands".to_owned()),
+            (".pool", []) => {
+                let output = self.pool(&mut logger);
+                return logger.into_result(|| Some(output));
+            },
+            (".pool", [lexeme, ..]) => logger.log_error_at(lexeme.span.clone(), "'.pool' takes no operands".to_owned()),
+            (".ends", _) => logger.log_error_at(lexemes[0].span.clone(), "'.ends' without a matching '.struct'".to_owned()),
+            _ => logger.log_error_at(lexemes[0].span.clone(), format!("unknown directive '{}'", lexemes[0].slice)),
+        }
+        logger.into_result(|| Some(Vec::new()))
+    }
+    
+    // A `label:` line, which a later definition replaces if it follows `.weak label`
+    fn label(&mut self, logger: &mut Logger, label: &str) {
+        let weak = match self.weak.iter().position(|name| name == label) {
+            Some(index) => {
+                self.weak.remove(index);
+                true
+            },
+            None => false,
+        };
+        if !weak && self.symbols.is_weak(label) {
+            self.labels.retain(|(name, _, _)| name != label);
+        }
+        let defined = match weak {
+            true => self.symbols.define_weak_label(label, self.address),
+            false => match self.symbols.define_label(label, self.address) {
+                Ok(()) => true,
+                Err(message) => {
+                    logger.log_error(message);
+                    false
+                },
+            },
+        };
+        if defined {
+            self.labels.push((label.to_string(), self.address, self.current));
+        }
+        self.routine.get_or_insert_with(|| label.to_string());
+        self.block_cycles = 0;
+        let Section { bank, base, .. } = self.sections[self.current];
+        if let Some(bank) = bank {
+            let offset = self.address - base;
+            for (name, value) in [(bank_symbol(label), bank), (offset_symbol(label), offset)] {
+                if weak {
+                    self.symbols.define_weak(&name, value);
+                } else {
+                    self.define(logger, &name, value);
+                }
+            }
+        }
+    }
+    
+    fn record_uses<'a>(&mut self, lexemes: &[Lexeme<'a, Token<'a>>]) {
+        if !self.gc_sections {
+            return;
+        }
+        let uses = &mut self.sections[self.current].uses;
+        uses.extend(lexemes.iter().filter_map(|l| match l.token {
+            Token::Ident(name) => Some(name.to_owned()),
+            _ => None,
+        }));
+    }
+    
+    // Records what a statement of the final pass lowered to, if the program is being recorded
+    pub fn record_item(&mut self, file: &str, line: usize, address: usize, item: ir::Item) {
+        if let Some(program) = &mut self.program {
+            program.push(ir::Statement { file: file.to_owned(), line, address, item });
+        }
+    }
+    
+    // Records the symbols a statement mentions for the cross-reference report, if one is being made
+    pub fn record_references(&mut self, statement: &str, site: Site) {
+        if self.references.is_none() {
+            return;
+        }
+        let lexemes: Vec<_> = Lexer::new(statement).collect();
+        let operands = match lexemes.as_slice() {
+            [Lexeme { token: Token::Ident(_), .. }, Lexeme { token: Token::Colon, .. }] => return,
+            [Lexeme { token: Token::Directive(directive), .. }, ..] if DECLARATIONS.contains(&directive.to_lowercase().as_str()) => return,
+            // The name `.set` and `.equ` define isn't a use of it
+            [Lexeme { token: Token::Directive(directive), .. }, _, rest @ ..] if matches!(directive.to_lowercase().as_str(), ".set" | ".equ") => rest,
+            lexemes => lexemes,
+        };
+        let mut names: Vec<_> = operands.iter()
+            .filter_map(|l| match l.token {
+                Token::Ident(name) if self.symbols.get(name).is_some() => Some(name),
+                _ => None,
+            })
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        if let Some(references) = &mut self.references {
+            references.extend(names.into_iter().map(|name| (name.to_owned(), site.clone())));
+        }
+    }
+    
+    // Sections `--gc-sections` keeps: the default section, banks, `.org` sections, those with
+    // `.keep`, those holding an export or the entry point, and every section holding a label that
+    // a kept section mentions
+    fn live_sections(&self) -> Vec<bool> {
+        let mut live: Vec<_> = self.sections.iter().enumerate()
+            .map(|(i, s)| i == 0 || s.keep || s.bank.is_some() || s.origin)
+            .collect();
+        for name in self.exports.iter().chain(&self.entry) {
+            if let Some((_, _, section)) = self.labels.iter().find(|(label, _, _)| label == name) {
+                live[*section] = true;
+            }
+        }
+        let mut pending: Vec<_> = (0..live.len()).filter(|i| live[*i]).collect();
+        while let Some(i) = pending.pop() {
+            for (label, _, section) in &self.labels {
+                if !live[*section] && self.sections[i].uses.contains(label) {
+                    live[*section] = true;
+                    pending.push(*section);
+                }
+            }
+        }
+        live
+    }
+    
+    pub fn allows(&self, register: usize) -> bool { self.allowed.contains(&register) }
+    
+    // Reports a block left open at the end of the source, places the literals not yet in a pool,
+    // checks that every region holds its sections, and returns the sections laid out as one image
+    // along with the image of each bank
+    pub fn finish(&mut self) -> LoggedResult<Image> {
+        let mut logger = Logger::new(None);
+        if !self.literals.is_empty() {
+            let pool = self.pool(&mut logger);
+            self.emit(&pool);
+        }
+        match &self.block {
+            Some(Block::Struct { name, .. }) => logger.log_error(format!("struct '{}' is missing '.ends'", name)),
+            Some(Block::Enum { .. }) => logger.log_error("'.enum' is missing '.ende'".to_owned()),
+            None => {},
+        }
+        let live = match self.gc_sections {
+            true => self.live_sections(),
+            false => vec![true; self.sections.len()],
+        };
+        for (section, live) in self.sections.iter_mut().zip(&live) {
+            if !live {
+                section.code.clear();
+                section.size = 0;
+            }
+        }
+        for i in 0..self.sections.len() {
+            let (name, size) = (size_symbol(&self.sections[i].name), self.sections[i].size);
+            self.define(&mut logger, &name, size);
+        }
+        let bounds: Vec<_> = self.placements.iter().filter(|p| p.symbols).filter_map(|p| {
+            let section = self.sections.iter().find(|s| s.name == p.section)?;
+            Some((p.bounds(), section.base, section.base + section.size))
+        }).collect();
+        for ((start_name, end_name), start, end) in bounds {
+            for (name, address) in [(start_name, start), (end_name, end)] {
+                if let Err(message) = self.symbols.define_label(&name, address) {
+                    logger.log_error(message);
+                }
+            }
+        }
+        for (name, budget) in &self.budgets {
+            match self.sections.iter().find(|s| s.name == *name) {
+                Some(section) if section.size > *budget => {
+                    logger.log_error(format!("section '{}' is {} bytes, over its budget of {} by {}", name, section.size, budget, section.size - budget));
+                },
+                Some(_) => {},
+                None => logger.log_warning(format!("section '{}' has a budget but is never used", name)),
+            }
+        }
+        for (i, section) in self.sections.iter().enumerate() {
+            let region = match section.region {
+                Some(r) => &self.regions[r],
+                None => continue,
+            };
+            let end = section.base + section.size;
+            // Sections other than the postamble itself have to end before the postamble
+            let limit = match section.origin {
+                true => region.end + 1,
+                false => region.end + 1 - region.postamble.len(),
+            };
+            if end <= limit {
+                continue;
+            }
+            // The label holding the first byte past the end of the region
+            let label = self.labels.iter()
+                .filter(|(_, address, s)| *s == i && *address <= limit)
+                .max_by_key(|(_, address, _)| *address);
+            let at = label.map_or_else(|| format!("section '{}'", section.name), |(name, _, _)| format!("'{}'", name));
+            logger.log_error(format!("region '{}' ({:#06x}-{:#06x}) overflows by {} bytes at {}", region.name, region.start, region.end, end - limit, at));
+        }
+
+        let mut banks: Vec<_> = self.sections.iter()
+            .filter_map(|s| Some((s.bank?, Image { base: s.base, code: s.code.clone(), ..Image::default() })))
+            .collect();
+        banks.sort_by_key(|(bank, _)| *bank);
+        let mut placed: Vec<_> = self.sections.iter().filter(|s| s.size > 0).map(|s| (s.name.clone(), s.base, s.size)).collect();
+        placed.sort_by_key(|(_, base, _)| *base);
+        let mut sections: Vec<_> = self.sections.iter().filter(|s| s.size > 0 && s.bank.is_none()).collect();
+        sections.sort_by_key(|s| s.base);
+        let start = sections.first().map_or(0, |s| s.base);
+        let segments = sections.iter().map(|s| (s.base, s.code.clone())).collect();
+        let mut image = Vec::new();
+        for section in sections {
+            if start + image.len() > section.base {
+                logger.log_error(format!("section '{}' at {:#06x} overlaps the section before it", section.name, section.base));
+                continue;
+            }
+            let gap = section.base - start - image.len();
+            match self.placements.iter().find(|p| p.section == section.name).and_then(|p| p.fill) {
+                Some(byte) => image.extend(vec![byte; gap]),
+                None => image.extend(fill(&self.nop, gap)),
+            }
+            image.extend(&section.code);
+        }
+        let labels: Vec<_> = self.labels.iter()
+            .filter(|(_, _, s)| self.sections[*s].bank.is_none() && live[*s])
+            .map(|(name, address, _)| (name.clone(), *address))
+            .collect();
+        let mut exports = Vec::new();
+        for name in &self.exports {
+            match self.symbols.get(name) {
+                Some(address) => exports.push((name.clone(), address)),
+                None => logger.log_error(format!("'{}' is exported by '.global' but never defined", name)),
+            }
+        }
+        for name in &self.locals {
+            if self.exports.contains(name) {
+                logger.log_error(format!("'{}' is both '.local' and '.global'", name));
+            } else if self.symbols.get(name).is_none() {
+                logger.log_error(format!("'{}' is made local by '.local' but never defined", name));
+            }
+        }
+        for name in &self.weak {
+            logger.log_error(format!("'{}' is made weak by '.weak' but never defined after it", name));
+        }
+        let entry = self.entry.as_ref().and_then(|name| {
+            let address = self.symbols.get(name);
+            if address.is_none() {
+                logger.log_error(format!("entry point '{}' is never defined", name));
+            }
+            address
+        });
+        let archs = self.sections.iter().filter_map(|s| Some((s.name.clone(), s.arch.clone()?))).collect();
+        let listing = self.listing.take().unwrap_or_default();
+        let program = self.program.take().unwrap_or_default();
+        // Symbols like `label:bank` are the assembler's own, so they aren't listed
+        let xref = self.references.take().map_or_else(Vec::new, |references| {
+            self.symbols.sorted().into_iter()
+                .filter(|(name, _)| !name.contains(':'))
+                .map(|(name, value)| (name.to_owned(), value, references.iter().filter(|(n, _)| n == name).map(|(_, site)| site.clone()).collect()))
+                .collect()
+        });
+        logger.into_result(|| Image { base: start, code: image, banks, labels, exports, entry, segments, sections: placed, listing, xref, program, rewrites: std::mem::take(&mut self.rewrites), adjustments: std::mem::take(&mut self.adjustments), archs })
+    }
+}
@@ -0,0 +1,295 @@
+use crate::lexer::Operator;
+use crate::new_parser::{push_item, Condition};
+use crate::parser::*;
+use crate::style::OperandStyle;
+
+// Operand values recovered from the encoded bytes; None if the codegen never emits that operand
+struct Captures {
+    registers: Vec<Option<usize>>,
+    immediates: Vec<Option<usize>>,
+}
+
+impl Captures {
+    // Binds `value` to whatever the codegen data refers to, failing on a mismatch with a literal
+    // or with an earlier binding of the same operand
+    fn bind(&mut self, data: &CodegenData, value: usize, mask: usize) -> bool {
+        let slot = match *data {
+            CodegenData::Byte(b) => return b as usize & mask == value,
+            CodegenData::Register(_) if value > 15 => return false,
+            CodegenData::Register(r) => self.registers.get_mut(r),
+            CodegenData::Immediate(imm, _) => self.immediates.get_mut(imm),
+        };
+        match slot {
+            Some(Some(prev)) => *prev & mask == value,
+            Some(slot) => { *slot = Some(value); true },
+            None => false,
+        }
+    }
+}
+
+// A pattern that matches the bytes at an offset, with the operand values it encodes
+#[derive(Debug)]
+pub struct Match<'a> {
+    pub name: &'a str,
+    // Index into `Disassembler::patterns`
+    pub index: usize,
+    pub pattern: &'a Pattern<'a>,
+    pub offset: usize,
+    pub length: usize,
+    pub registers: Vec<Option<usize>>,
+    pub immediates: Vec<Option<usize>>,
+    // Prefixes in the order their bytes come, which are written before the mnemonic, and the
+    // length of those bytes
+    pub prefixes: Vec<&'a str>,
+    pub prefix_length: usize,
+    style: &'a OperandStyle,
+}
+
+impl Match<'_> {
+    // The instruction as the config's `.display` writes it
+    pub fn text(&self) -> String {
+        self.styled_text(self.style)
+    }
+
+    // The instruction as sources write it, which assembles back to the same bytes
+    pub fn source_text(&self) -> String {
+        self.styled_text(&OperandStyle::default())
+    }
+
+    fn styled_text(&self, style: &OperandStyle) -> String {
+        // A form for the mode of a prefix is named after it, which is among the prefixes
+        let mnemonic = self.name.split_once(' ').map_or(self.name, |(_, mnemonic)| mnemonic);
+        let mut text = self.prefixes.iter().map(|p| format!("{} ", p)).collect::<String>() + mnemonic;
+        let (mut register, mut immediate) = (0, 0);
+        for token in &self.pattern.tokens {
+            match token {
+                PatternToken::Register => {
+                    match self.registers[register] {
+                        Some(r) => push_item(&mut text, &style.register(r)),
+                        None => push_item(&mut text, "r?"),
+                    }
+                    register += 1;
+                },
+                PatternToken::Immediate => {
+                    match self.immediates[immediate] {
+                        Some(i) => {
+                            let immediate = style.immediate(&text, i);
+                            push_item(&mut text, &immediate);
+                        },
+                        None => push_item(&mut text, "?"),
+                    }
+                    immediate += 1;
+                },
+                PatternToken::Comma => push_item(&mut text, ","),
+                PatternToken::Punct(p) => push_item(&mut text, p),
+                PatternToken::Keyword(word) => push_item(&mut text, word),
+            }
+        }
+        text
+    }
+}
+
+#[derive(Debug)]
+pub struct Decoded {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum Line {
+    Instruction {
+        decoded: Decoded,
+        // Other patterns that also match at this offset
+        alternatives: Vec<String>,
+        round_trips: bool,
+    },
+    Undecodable { offset: usize, byte: u8 },
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Line::Instruction { decoded, alternatives, round_trips } => {
+                let bytes = decoded.bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+                write!(f, "{:04X}: {:<12} {}", decoded.offset, bytes, decoded.text)?;
+                if !alternatives.is_empty() {
+                    write!(f, " ; ambiguous, also: {}", alternatives.join(" | "))?;
+                }
+                if !round_trips {
+                    write!(f, " ; does not round-trip")?;
+                }
+                Ok(())
+            },
+            Line::Undecodable { offset, byte } => write!(f, "{:04X}: {:<12} ; undecodable", offset, format!("{:02X}", byte)),
+        }
+    }
+}
+
+// Explains which bit fields of the matched bytes hold which operand or fixed value
+pub fn explain(m: &Match, bytes: &[u8]) -> String {
+    let mut text = format!("{}    ({})\n", m.text(), m.pattern.describe(m.name));
+    let field = |name: &str, value: u8| {
+        if name.chars().all(|c| c == '0' || c == '1') {
+            format!("fixed {}", name)
+        } else {
+            format!("{} = 0x{:X}", name, value)
+        }
+    };
+    let start = m.offset + m.prefix_length;
+    if m.prefix_length > 0 {
+        let prefix = bytes[m.offset..start].iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(" ");
+        text += &format!("  prefix {}\n", prefix);
+    }
+    let fields = crate::doc::encoding(m.pattern.codegen);
+    for (i, (upper, lower)) in fields.iter().enumerate() {
+        let byte = bytes[start + i];
+        text += &format!("  byte {} = 0x{:02X}: bits 7-4 {}, bits 3-0 {}\n", i, byte, field(upper, byte >> 4), field(lower, byte & 0xF));
+    }
+    text
+}
+
+pub struct Disassembler<'a> {
+    assembler: &'a Assembler,
+    patterns: Vec<(&'a str, Pattern<'a>)>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(assembler: &'a Assembler) -> Self {
+        let mut names: Vec<_> = assembler.instructions.keys().collect();
+        names.sort();
+        let patterns = names.into_iter()
+            .flat_map(|name| assembler.instructions[name].patterns().into_iter().map(move |p| (name.as_str(), p)))
+            .collect();
+        Self { assembler, patterns }
+    }
+
+    fn match_pattern(&self, index: usize, bytes: &[u8], offset: usize) -> Option<Match<'_>> {
+        let (name, pattern) = &self.patterns[index];
+        let mut captures = Captures {
+            registers: vec![None; pattern.registers()],
+            immediates: vec![None; pattern.immediates()],
+        };
+        let mut cursor = offset;
+        for codegen in pattern.codegen {
+            match codegen {
+                Codegen::Data(data @ CodegenData::Immediate(_, width)) => {
+                    let len = width / 8;
+                    let field = bytes.get(cursor..cursor + len)?;
+                    let value = field.iter().rev().fold(0, |acc, b| acc << 8 | *b as usize);
+                    let mask = if len >= std::mem::size_of::<usize>() { usize::MAX } else { (1 << (len * 8)) - 1 };
+                    if !captures.bind(data, value, mask) {
+                        return None;
+                    }
+                    cursor += len;
+                },
+                Codegen::Data(data) => {
+                    if !captures.bind(data, *bytes.get(cursor)? as usize, 0xFF) {
+                        return None;
+                    }
+                    cursor += 1;
+                },
+                Codegen::UpperLower(upper, lower) => {
+                    let byte = *bytes.get(cursor)? as usize;
+                    if !captures.bind(upper, byte >> 4, 0xF) || !captures.bind(lower, byte & 0xF, 0xF) {
+                        return None;
+                    }
+                    cursor += 1;
+                },
+                Codegen::Guard(_) => {},
+            }
+        }
+        if cursor == offset {
+            return None;
+        }
+        // Bytes of a form chosen by `if` only decode to it when its conditions hold
+        let holds = |guard: &Condition| captures.immediates.get(guard.immediate).copied().flatten().is_none_or(|value| guard.holds(value));
+        if !pattern.guards().all(holds) {
+            return None;
+        }
+        // An immediate the branch doesn't emit is known when its condition pins it to one value
+        for guard in pattern.guards().filter(|guard| guard.op == Operator::Eq) {
+            if let Some(slot @ None) = captures.immediates.get_mut(guard.immediate) {
+                *slot = Some(guard.value);
+            }
+        }
+        Some(Match {
+            name,
+            index,
+            pattern,
+            offset,
+            length: cursor - offset,
+            registers: captures.registers,
+            immediates: captures.immediates,
+            prefixes: Vec::new(),
+            prefix_length: 0,
+            style: &self.assembler.style,
+        })
+    }
+    
+    pub fn patterns(&self) -> &[(&'a str, Pattern<'a>)] { &self.patterns }
+
+    // Every pattern that matches the bytes at the offset, longest encoding first
+    pub fn matches_at(&self, bytes: &[u8], offset: usize) -> Vec<Match<'_>> {
+        let mut matches = Vec::new();
+        self.prefixed_matches(bytes, offset, &mut Vec::new(), &mut matches);
+        matches.sort_by_key(|m| std::cmp::Reverse(m.length));
+        matches
+    }
+
+    // Matches following the prefixes read so far, then following each other prefix whose bytes
+    // come next. As when assembling, a form for the mode of a prefix is named after it and only
+    // matches following it, and the other prefixes must be allowed before the instruction.
+    fn prefixed_matches<'s>(&'s self, bytes: &[u8], offset: usize, prefixes: &mut Vec<&'s Prefix>, matches: &mut Vec<Match<'s>>) {
+        let start = offset - prefixes.iter().map(|p| p.bytes.len()).sum::<usize>();
+        let legal = |name: &str| match name.split_once(' ') {
+            Some((mode, mnemonic)) => prefixes.iter().any(|p| p.name == mode) && prefixes.iter().all(|p| p.name == mode || p.allows(mnemonic)),
+            None => prefixes.iter().all(|p| p.allows(name) && !self.assembler.instructions.contains_key(&format!("{} {}", p.name, name))),
+        };
+        for index in (0..self.patterns.len()).filter(|index| legal(self.patterns[*index].0)) {
+            if let Some(m) = self.match_pattern(index, bytes, offset) {
+                let names = prefixes.iter().map(|p| p.name.as_str()).collect();
+                matches.push(Match { prefixes: names, prefix_length: offset - start, offset: start, length: m.length + offset - start, ..m });
+            }
+        }
+        for prefix in &self.assembler.prefixes {
+            if !prefixes.iter().any(|p| p.name == prefix.name) && bytes[offset..].starts_with(&prefix.bytes) {
+                prefixes.push(prefix);
+                self.prefixed_matches(bytes, offset + prefix.bytes.len(), prefixes, matches);
+                prefixes.pop();
+            }
+        }
+    }
+
+    pub fn decode_at(&self, bytes: &[u8], offset: usize) -> Vec<Decoded> {
+        self.matches_at(bytes, offset).into_iter()
+            .map(|m| Decoded { offset, bytes: bytes[offset..offset + m.length].to_vec(), text: m.text() })
+            .collect()
+    }
+
+    pub fn disassemble(&self, bytes: &[u8]) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let mut matches = self.matches_at(bytes, offset).into_iter();
+            match matches.next() {
+                Some(m) => {
+                    let decoded = Decoded { offset, bytes: bytes[offset..offset + m.length].to_vec(), text: m.text() };
+                    let (code, _) = self.assembler.assemble_statement(&m.source_text()).unwrap();
+                    let round_trips = code.is_some_and(|code| code == decoded.bytes);
+                    offset += decoded.bytes.len();
+                    lines.push(Line::Instruction {
+                        decoded,
+                        alternatives: matches.map(|m| m.text()).collect(),
+                        round_trips,
+                    });
+                },
+                None => {
+                    lines.push(Line::Undecodable { offset, byte: bytes[offset] });
+                    offset += 1;
+                },
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,140 @@
+use crate::parser::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+struct Operand {
+    name: String,
+    kind: &'static str,
+    width: String,
+}
+
+struct PatternDoc {
+    syntax: String,
+    operands: Vec<Operand>,
+    // (bits 7-4, bits 3-0) of each encoded byte
+    bytes: Vec<(String, String)>,
+}
+
+fn nibble(data: &CodegenData) -> String {
+    match *data {
+        CodegenData::Byte(b) => format!("{:04b}", b & 0xF),
+        CodegenData::Register(r) => format!("r{}[3:0]", r),
+        CodegenData::Immediate(i, _) => format!("i{}[3:0]", i),
+    }
+}
+
+// Names the upper and lower half of every byte the codegen emits, e.g. `("i0[3:0]", "0101")`
+pub fn encoding(codegen: &[Codegen]) -> Vec<(String, String)> {
+    let mut bytes = Vec::new();
+    for c in codegen {
+        match c {
+            Codegen::Data(CodegenData::Byte(b)) => bytes.push((format!("{:04b}", b >> 4), format!("{:04b}", b & 0xF))),
+            Codegen::Data(CodegenData::Register(r)) => bytes.push((format!("r{}[7:4]", r), format!("r{}[3:0]", r))),
+            Codegen::Data(CodegenData::Immediate(i, width)) => {
+                for k in 0..width / 8 {
+                    bytes.push((format!("i{}[{}:{}]", i, 8 * k + 7, 8 * k + 4), format!("i{}[{}:{}]", i, 8 * k + 3, 8 * k)));
+                }
+            },
+            Codegen::UpperLower(upper, lower) => bytes.push((nibble(upper), nibble(lower))),
+            Codegen::Guard(_) => {},
+        }
+    }
+    bytes
+}
+
+// Bits of a register operand the codegen emits, which is a whole byte or a nibble
+pub fn register_width(pattern: &Pattern, register: usize) -> usize {
+    let whole_byte = pattern.codegen.iter().any(|c| matches!(c, Codegen::Data(CodegenData::Register(r)) if *r == register));
+    if whole_byte { 8 } else { 4 }
+}
+
+fn document(name: &str, pattern: &Pattern, src_first: bool) -> PatternDoc {
+    let mut operands = Vec::new();
+    for r in 0..pattern.registers() {
+        operands.push(Operand { name: format!("r{}", r), kind: "register", width: register_width(pattern, r).to_string() });
+    }
+    for i in 0..pattern.immediates() {
+        let width = pattern.immediate_width(i).map_or("unused".to_owned(), |w| w.to_string());
+        operands.push(Operand { name: format!("i{}", i), kind: "immediate", width });
+    }
+    let mut syntax = pattern.source_syntax(name, src_first);
+    for (i, condition) in pattern.guards().enumerate() {
+        syntax += if i == 0 { " if " } else { " and " };
+        syntax += &condition.to_string();
+    }
+    PatternDoc { syntax, operands, bytes: encoding(pattern.codegen) }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn render_markdown(out: &mut String, name: &str, category: Option<Category>, patterns: &[PatternDoc]) {
+    *out += &format!("## {}\n\n", name);
+    if let Some(category) = category {
+        *out += &format!("Category: {}\n\n", category.name());
+    }
+    for pattern in patterns {
+        *out += &format!("### `{}`\n\n", pattern.syntax);
+        if !pattern.operands.is_empty() {
+            *out += "| Operand | Kind | Width |\n|---|---|---|\n";
+            for operand in &pattern.operands {
+                *out += &format!("| {} | {} | {} |\n", operand.name, operand.kind, operand.width);
+            }
+            out.push('\n');
+        }
+        *out += "| Byte | Bits 7-4 | Bits 3-0 |\n|---|---|---|\n";
+        for (i, (upper, lower)) in pattern.bytes.iter().enumerate() {
+            *out += &format!("| {} | `{}` | `{}` |\n", i, upper, lower);
+        }
+        out.push('\n');
+    }
+}
+
+fn render_html(out: &mut String, name: &str, category: Option<Category>, patterns: &[PatternDoc]) {
+    *out += &format!("<h2 id=\"{0}\">{0}</h2>\n", escape_html(name));
+    if let Some(category) = category {
+        *out += &format!("<p>Category: {}</p>\n", category.name());
+    }
+    for pattern in patterns {
+        *out += &format!("<h3><code>{}</code></h3>\n", escape_html(&pattern.syntax));
+        if !pattern.operands.is_empty() {
+            *out += "<table>\n<tr><th>Operand</th><th>Kind</th><th>Width</th></tr>\n";
+            for operand in &pattern.operands {
+                *out += &format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n", operand.name, operand.kind, operand.width);
+            }
+            *out += "</table>\n";
+        }
+        *out += "<table>\n<tr><th>Byte</th><th>Bits 7-4</th><th>Bits 3-0</th></tr>\n";
+        for (i, (upper, lower)) in pattern.bytes.iter().enumerate() {
+            *out += &format!("<tr><td>{}</td><td><code>{}</code></td><td><code>{}</code></td></tr>\n", i, upper, lower);
+        }
+        *out += "</table>\n";
+    }
+}
+
+// Renders a reference page with a section per mnemonic, in alphabetical order
+pub fn generate(assembler: &Assembler, format: Format) -> String {
+    let mut names: Vec<_> = assembler.instructions.keys().collect();
+    names.sort();
+    let mut out = String::new();
+    match format {
+        Format::Markdown => out += "# Instruction set reference\n\n",
+        Format::Html => out += "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Instruction set reference</title></head>\n<body>\n<h1>Instruction set reference</h1>\n",
+    }
+    for name in names {
+        let patterns: Vec<PatternDoc> = assembler.instructions[name].patterns().iter().map(|p| document(name, p, assembler.src_first)).collect();
+        match format {
+            Format::Markdown => render_markdown(&mut out, name, assembler.category(name), &patterns),
+            Format::Html => render_html(&mut out, name, assembler.category(name), &patterns),
+        }
+    }
+    if format == Format::Html {
+        out += "</body>\n</html>\n";
+    }
+    out
+}
@@ -0,0 +1,66 @@
+use crate::directives::{unescape, Unit};
+use crate::export::isa_json;
+use crate::lexer::{column, Lexer, Token};
+use crate::parser::Assembler;
+use crate::symbols::SymbolTable;
+use std::path::Path;
+
+// The state of a run written by `--debug-dump dir`, for attaching to bug reports:
+//
+//     config.json         the ISA as the configs were understood
+//     tokens/N-name.txt   the tokens of the Nth source, one per line with its line and column
+//     symbols.first.txt   symbols after the first pass, before forward references are resolved
+//     symbols.final.txt   symbols after the last pass
+pub fn write(dir: &Path, assembler: &Assembler, files: &[(String, String)], passes: &[SymbolTable]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir.join("tokens"))?;
+    std::fs::write(dir.join("config.json"), isa_json(assembler))?;
+    for (i, (origin, source)) in files.iter().enumerate() {
+        let name = Path::new(origin).file_name().map_or_else(|| origin.clone(), |n| n.to_string_lossy().into_owned());
+        std::fs::write(dir.join("tokens").join(format!("{}-{}.txt", i, name)), tokens(source))?;
+    }
+    if let (Some(first), Some(last)) = (passes.first(), passes.last()) {
+        std::fs::write(dir.join("symbols.first.txt"), symbols(first))?;
+        std::fs::write(dir.join("symbols.final.txt"), symbols(last))?;
+    }
+    Ok(())
+}
+
+// What a token's text was read as, where that isn't obvious from the text
+fn value(token: &Token) -> Option<String> {
+    match token {
+        Token::Integer(value) => Some(format!("{} ({:#x})", value, value)),
+        Token::Register(r) => Some(format!("register {}", r)),
+        Token::Immediate(i) => Some(format!("immediate {}", i)),
+        Token::Char(literal) => Some(match unescape(literal).as_deref() {
+            Ok([Unit::Char(c)]) => format!("{} ({:#x})", *c as u32, *c as u32),
+            Ok([Unit::Byte(b)]) => format!("{} ({:#x})", b, b),
+            Ok(_) => "more than one character".to_owned(),
+            Err(message) => message.clone(),
+        }),
+        Token::Error => Some("not a token, or a number too large or with digits outside its radix".to_owned()),
+        _ => None,
+    }
+}
+
+// Each lexeme of each line with its span, kind, and value, e.g.
+//
+//     3:5-8        Integer      0x1F  = 31 (0x1f)
+pub fn tokens(source: &str) -> String {
+    let mut text = String::new();
+    for (line, source) in source.lines().enumerate() {
+        for lexeme in Lexer::new(source) {
+            // Columns of the first and last character, counted by grapheme
+            let span = format!("{}:{}-{}", line + 1, column(source, lexeme.span.start) + 1, column(source, lexeme.span.end));
+            let row = format!("{:<12} {:<12} {}", span, lexeme.token.kind(), lexeme.slice);
+            text += &match value(&lexeme.token) {
+                Some(value) => format!("{}  = {}\n", row, value),
+                None => format!("{}\n", row),
+            };
+        }
+    }
+    text
+}
+
+fn symbols(table: &SymbolTable) -> String {
+    table.sorted().iter().map(|(name, value)| format!("{} = {:#06x}\n", name, value)).collect()
+}
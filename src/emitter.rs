@@ -0,0 +1,203 @@
+use crate::directives::Image;
+use crate::executable;
+use crate::listing;
+use crate::manifest::Format;
+use crate::parser::Region;
+use std::path::Path;
+
+// Writes an assembled image in one output form. The assembler only produces the image, so a new
+// form is another emitter rather than a change to how statements are encoded.
+pub trait CodeEmitter {
+    // Whether the emitter reads the rows of the listing, which are only recorded when asked for
+    fn needs_rows(&self) -> bool {
+        false
+    }
+
+    // Whether the output describes the code rather than holding it, so it is worth writing for an
+    // assembly with errors
+    fn is_report(&self) -> bool {
+        false
+    }
+
+    fn emit(&self, image: &Image) -> Vec<u8>;
+}
+
+// The code as is
+pub struct Binary;
+
+// Intel HEX records
+pub struct IntelHex;
+
+// The bootloader's format, with the entry point and a segment table
+pub struct Executable;
+
+// Each line with the code it assembled to
+pub struct Listing;
+
+// The line each address was assembled from, e.g. `0x0004 main.s:12`, for debuggers and profilers
+pub struct SourceMap;
+
+// The code placed in a memory region, from its start to the last byte placed in it, for a chip
+// programmed on its own. Intel HEX leaves out the space nothing was placed in.
+pub struct RegionSplit {
+    pub region: Region,
+    pub hex: bool,
+}
+
+// The code a source file assembled to, from its first byte to its last, with code from other files
+// in between left as zeros in a binary and left out of Intel HEX
+pub struct FileSplit {
+    pub file: String,
+    pub hex: bool,
+}
+
+impl CodeEmitter for Binary {
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        image.code.clone()
+    }
+}
+
+impl CodeEmitter for IntelHex {
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        let segments: Vec<_> = image.segments.iter().map(|(address, code)| (*address, code.as_slice())).collect();
+        intel_hex(&segments).into_bytes()
+    }
+}
+
+impl CodeEmitter for Executable {
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        executable::write(image)
+    }
+}
+
+impl CodeEmitter for Listing {
+    fn needs_rows(&self) -> bool {
+        true
+    }
+
+    fn is_report(&self) -> bool {
+        true
+    }
+
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        listing::render(&image.listing).into_bytes()
+    }
+}
+
+impl CodeEmitter for SourceMap {
+    fn needs_rows(&self) -> bool {
+        true
+    }
+
+    fn is_report(&self) -> bool {
+        true
+    }
+
+    // Code a macro expanded to is mapped to the line invoking it
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        let mut text = String::new();
+        let mut line = None;
+        for row in &image.listing {
+            if let Some(number) = row.line {
+                line = Some((&row.file, number));
+            }
+            if let (Some((file, number)), false) = (line, row.code.is_empty()) {
+                text += &format!("{:#06x} {}:{}\n", row.address, file, number + 1);
+            }
+        }
+        text.into_bytes()
+    }
+}
+
+impl CodeEmitter for RegionSplit {
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        let (start, end) = (self.region.start, self.region.end + 1);
+        let placed: Vec<_> = image.segments.iter().filter_map(|(address, code)| {
+            let (from, to) = (start.max(*address), end.min(address + code.len()));
+            (from < to).then(|| (from, &code[from - address..to - address]))
+        }).collect();
+        split(&placed, start, self.hex)
+    }
+}
+
+impl CodeEmitter for FileSplit {
+    fn needs_rows(&self) -> bool {
+        true
+    }
+
+    fn emit(&self, image: &Image) -> Vec<u8> {
+        let placed: Vec<_> = image.listing.iter()
+            .filter(|row| row.file == self.file && !row.code.is_empty())
+            .map(|row| (row.address, row.code.as_slice()))
+            .collect();
+        let start = placed.iter().map(|(address, _)| *address).min().unwrap_or(0);
+        split(&placed, start, self.hex)
+    }
+}
+
+// Code placed at addresses from `start` on, with the gaps between left as zeros, or as Intel HEX
+// records of the code alone
+fn split(placed: &[(usize, &[u8])], start: usize, hex: bool) -> Vec<u8> {
+    if hex {
+        return intel_hex(placed).into_bytes();
+    }
+    let end = placed.iter().map(|(address, code)| address + code.len()).max().unwrap_or(start);
+    let mut code = vec![0; end - start];
+    for (address, bytes) in placed {
+        code[address - start..address - start + bytes.len()].copy_from_slice(bytes);
+    }
+    code
+}
+
+// The name of a split output, with `{region}` or `{file}` in the template replaced by the region or
+// the file's name without its directory or extension
+pub fn split_name(template: &str, placeholder: &str, name: &str) -> String {
+    let stem = Path::new(name).file_stem().map_or(name.into(), |stem| stem.to_string_lossy());
+    template.replace(placeholder, &stem)
+}
+
+impl Format {
+    // The image as a file of this format
+    pub fn emit(self, image: &Image) -> Vec<u8> {
+        emitter(self).emit(image)
+    }
+}
+
+// The emitter for an output format of a build
+pub fn emitter(format: Format) -> Box<dyn CodeEmitter> {
+    match format {
+        Format::Binary => Box::new(Binary),
+        Format::Hex => Box::new(IntelHex),
+        Format::Executable => Box::new(Executable),
+    }
+}
+
+// Renders code placed at addresses as Intel HEX, with up to 16 bytes per data record. Nothing is
+// written for the space between, and an extended linear address record moves to another 64K.
+pub fn intel_hex(placed: &[(usize, &[u8])]) -> String {
+    let mut text = String::new();
+    let mut record = |kind: u8, address: u16, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+        bytes.extend(data);
+        let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+        text += ":";
+        bytes.iter().chain(Some(&checksum)).for_each(|b| text += &format!("{:02X}", b));
+        text += "\n";
+    };
+    let mut upper = 0;
+    for (mut address, mut code) in placed.iter().copied() {
+        while !code.is_empty() {
+            if address >> 16 != upper {
+                upper = address >> 16;
+                record(4, 0, &[(upper >> 8) as u8, upper as u8]);
+            }
+            // A record ends where the 64K does, since its address is only the low 16 bits
+            let length = code.len().min(16).min(0x10000 - (address & 0xFFFF));
+            record(0, address as u16, &code[..length]);
+            (address, code) = (address + length, &code[length..]);
+        }
+    }
+    record(1, 0, &[]);
+    text
+}
+
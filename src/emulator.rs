@@ -0,0 +1,390 @@
+use crate::devices::Device;
+use crate::disasm::{Disassembler, Match};
+use crate::executable::Executable;
+use crate::parser::Assembler;
+use crate::semantics::{self, BinaryOp, Expr, Flag, Place, Statement, UnaryOp};
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::ops::Range;
+
+pub const REGISTERS: usize = 16;
+pub const REGISTER_BITS: u32 = 16;
+pub const MEMORY_SIZE: usize = 0x10000;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Flags {
+    pub zero: bool,
+    pub carry: bool,
+    pub negative: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    fn get(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::Zero => self.zero,
+            Flag::Carry => self.carry,
+            Flag::Negative => self.negative,
+            Flag::Overflow => self.overflow,
+        }
+    }
+
+    fn set(&mut self, flag: Flag, value: bool) {
+        match flag {
+            Flag::Zero => self.zero = value,
+            Flag::Carry => self.carry = value,
+            Flag::Negative => self.negative = value,
+            Flag::Overflow => self.overflow = value,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub registers: [u64; REGISTERS],
+    pub flags: Flags,
+    pub pc: usize,
+    pub memory: Vec<u8>,
+    pub halted: bool,
+    pub steps: u64,
+}
+
+impl std::fmt::Display for Machine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, r) in self.registers.iter().enumerate() {
+            write!(f, "r{:<2} = 0x{:04X}", i, r)?;
+            f.write_str(if i % 4 == 3 { "\n" } else { "  " })?;
+        }
+        write!(f, "pc  = 0x{:04X}  z={} c={} n={} v={}  steps={}",
+            self.pc, self.flags.zero as u8, self.flags.carry as u8, self.flags.negative as u8, self.flags.overflow as u8, self.steps)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    // A `halt` statement was executed
+    Halted,
+    // The program counter left the loaded program
+    EndOfProgram,
+    StepLimit,
+    Breakpoint(usize),
+}
+
+impl std::fmt::Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Stop::Halted => write!(f, "halted"),
+            Stop::EndOfProgram => write!(f, "reached the end of the program"),
+            Stop::StepLimit => write!(f, "reached the step limit"),
+            Stop::Breakpoint(address) => write!(f, "breakpoint at 0x{:04X}", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // One JSON object per instruction:
+    // `{"step":1,"pc":0,"bytes":"00000011","instruction":"add r1, r1","registers":{"r1":2},
+    // "flags":{"z":0},"reads":[],"writes":[{"address":16,"value":7}],"next_pc":4}`
+    Json,
+    // One line per instruction for comparing against simulations of the core, with fields
+    // separated by spaces and numbers in fixed-width hex:
+    // `<step> <pc> <bytes> [r<n>=<value>]... [<flag>=<0|1>]... [rd[<address>]=<byte>]... [wr[<address>]=<byte>]...`
+    // such as `000001 0000 00000011 r1=0002 z=0 wr[0010]=07`. Every write is listed, even one
+    // that leaves the value unchanged, in the order the semantics perform them.
+    Text,
+}
+
+// Registers, flags, and memory an instruction accessed, in order
+#[derive(Debug, Default)]
+struct Accesses {
+    registers: Vec<(usize, u64)>,
+    flags: Vec<(Flag, bool)>,
+    reads: Vec<(usize, u8)>,
+    writes: Vec<(usize, u8)>,
+}
+
+type Mapping<'a> = (Range<usize>, RefCell<Box<dyn Device + 'a>>);
+
+pub struct Emulator<'a> {
+    disassembler: Disassembler<'a>,
+    // Parsed semantics of each of the disassembler's patterns
+    semantics: Vec<Option<Vec<Statement>>>,
+    program_len: usize,
+    pub machine: Machine,
+    pub breakpoints: BTreeSet<usize>,
+    // Receives a record of each executed instruction
+    pub trace: Option<Box<dyn Write>>,
+    pub trace_format: TraceFormat,
+    // Devices along with the addresses they are mapped at. They are borrowed mutably while the
+    // semantics, which only read the machine, are evaluated.
+    devices: Vec<Mapping<'a>>,
+    // Addresses of the instructions executed so far, when coverage is being recorded
+    pub executed: Option<BTreeSet<usize>>,
+    // Times each instruction executed, when a profile is being recorded
+    pub counts: Option<BTreeMap<usize, usize>>,
+}
+
+struct Context<'m, 'a> {
+    registers: &'m [Option<usize>],
+    immediates: &'m [Option<usize>],
+    next_pc: usize,
+    // Memory read while evaluating the semantics, with the values read
+    reads: RefCell<Vec<(usize, u8)>>,
+    devices: &'m [Mapping<'a>],
+}
+
+impl<'m, 'a> Context<'m, 'a> {
+    fn register(&self, r: usize) -> Result<usize, String> {
+        self.registers.get(r).copied().flatten().ok_or_else(|| format!("register {} is not encoded by the instruction", r))
+    }
+
+    fn immediate(&self, i: usize) -> Result<u64, String> {
+        self.immediates.get(i).copied().flatten().map(|i| i as u64).ok_or_else(|| format!("immediate {} is not encoded by the instruction", i))
+    }
+    
+    // The device mapped at the address, with the address's offset into it
+    fn device(&self, address: usize) -> Option<(&'m RefCell<Box<dyn Device + 'a>>, usize)> {
+        self.devices.iter().find(|(range, _)| range.contains(&address)).map(|(range, device)| (device, address - range.start))
+    }
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(assembler: &'a Assembler, program: &[u8]) -> Self {
+        let disassembler = Disassembler::new(assembler);
+        let semantics = disassembler.patterns().iter()
+            .map(|(_, pattern)| pattern.semantics.and_then(|s| semantics::parse(s).ok()))
+            .collect();
+        let mut memory = vec![0; MEMORY_SIZE];
+        let program_len = program.len().min(MEMORY_SIZE);
+        memory[..program_len].copy_from_slice(&program[..program_len]);
+        Self {
+            disassembler,
+            semantics,
+            program_len,
+            machine: Machine {
+                registers: [0; REGISTERS],
+                flags: Flags::default(),
+                pc: 0,
+                memory,
+                halted: false,
+                steps: 0,
+            },
+            breakpoints: BTreeSet::new(),
+            trace: None,
+            trace_format: TraceFormat::Json,
+            devices: Vec::new(),
+            executed: None,
+            counts: None,
+        }
+    }
+    
+    // Places an executable's segments at their addresses and starts at its entry point
+    pub fn load(&mut self, executable: &Executable) -> Result<(), String> {
+        for (address, code) in &executable.segments {
+            let end = address + code.len();
+            if end > MEMORY_SIZE {
+                return Err(format!("segment at 0x{:04X} ends past the end of memory", address));
+            }
+            self.machine.memory[*address..end].copy_from_slice(code);
+            self.program_len = self.program_len.max(end);
+        }
+        self.machine.pc = executable.entry;
+        Ok(())
+    }
+    
+    // Maps a device over `size` addresses from `start`, in place of the memory there
+    pub fn map(&mut self, start: usize, size: usize, device: Box<dyn Device + 'a>) -> Result<(), String> {
+        let range = start..start + size;
+        if let Some((existing, _)) = self.devices.iter().find(|(r, _)| r.start < range.end && range.start < r.end) {
+            return Err(format!("0x{:04X}-0x{:04X} overlaps the device at 0x{:04X}-0x{:04X}", range.start, range.end - 1, existing.start, existing.end - 1));
+        }
+        self.devices.push((range, RefCell::new(device)));
+        Ok(())
+    }
+
+    // The instruction at the program counter, preferring patterns that have semantics
+    pub fn current(&self) -> Option<Match<'_>> {
+        Self::decode(&self.disassembler, &self.semantics, &self.machine.memory[..self.program_len], self.machine.pc)
+    }
+    
+    fn decode<'d>(disassembler: &'d Disassembler<'a>, semantics: &[Option<Vec<Statement>>], memory: &[u8], pc: usize) -> Option<Match<'d>> {
+        let matches = disassembler.matches_at(memory, pc);
+        let executable = matches.iter().position(|m| semantics[m.index].is_some());
+        let mut matches = matches.into_iter();
+        match executable {
+            Some(i) => matches.nth(i),
+            None => matches.next(),
+        }
+    }
+
+    fn eval(machine: &Machine, context: &Context, expr: &Expr) -> Result<u64, String> {
+        Ok(match expr {
+            Expr::Register(r) => machine.registers[context.register(*r)?],
+            Expr::Immediate(i) => context.immediate(*i)?,
+            Expr::Integer(int) => *int,
+            Expr::Pc => context.next_pc as u64,
+            Expr::Flag(flag) => machine.flags.get(*flag) as u64,
+            Expr::Mem(address) => {
+                let address = Self::eval(machine, context, address)? as usize % MEMORY_SIZE;
+                let value = match context.device(address) {
+                    Some((device, offset)) => device.borrow_mut().read(offset),
+                    None => machine.memory[address],
+                };
+                context.reads.borrow_mut().push((address, value));
+                value as u64
+            },
+            Expr::Unary(op, value) => {
+                let value = Self::eval(machine, context, value)?;
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::LogicalNot => (value == 0) as u64,
+                }
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = Self::eval(machine, context, lhs)?;
+                let rhs = Self::eval(machine, context, rhs)?;
+                match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div => lhs.checked_div(rhs).ok_or("division by zero in semantics")?,
+                    BinaryOp::Rem => lhs.checked_rem(rhs).ok_or("division by zero in semantics")?,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::Shl => lhs.checked_shl(rhs as u32).unwrap_or(0),
+                    BinaryOp::Shr => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                    BinaryOp::Eq => (lhs == rhs) as u64,
+                    BinaryOp::Ne => (lhs != rhs) as u64,
+                    BinaryOp::Lt => (lhs < rhs) as u64,
+                    BinaryOp::Le => (lhs <= rhs) as u64,
+                    BinaryOp::Gt => (lhs > rhs) as u64,
+                    BinaryOp::Ge => (lhs >= rhs) as u64,
+                }
+            },
+        })
+    }
+
+    // Executes the instruction at the program counter
+    pub fn step(&mut self) -> Result<(), String> {
+        if self.machine.halted {
+            return Ok(());
+        }
+        let pc = self.machine.pc;
+        let current = Self::decode(&self.disassembler, &self.semantics, &self.machine.memory[..self.program_len], pc).ok_or_else(|| format!("undecodable instruction at 0x{:04X}", pc))?;
+        let statements = self.semantics[current.index].as_ref()
+            .ok_or_else(|| format!("'{}' at 0x{:04X} has no semantics", current.text(), pc))?;
+        let context = Context { registers: &current.registers, immediates: &current.immediates, next_pc: pc + current.length, reads: RefCell::default(), devices: &self.devices };
+
+        let machine = &mut self.machine;
+        let mut accesses = Accesses::default();
+        let mut next_pc = context.next_pc;
+        for statement in statements {
+            match statement {
+                Statement::Halt => machine.halted = true,
+                Statement::Assign(place, expr) => {
+                    let value = Self::eval(machine, &context, expr)?;
+                    match place {
+                        Place::Register(r) => {
+                            let r = context.register(*r)?;
+                            machine.registers[r] = value & ((1 << REGISTER_BITS) - 1);
+                            accesses.registers.push((r, machine.registers[r]));
+                        },
+                        Place::Pc => next_pc = value as usize,
+                        Place::Flag(flag) => {
+                            machine.flags.set(*flag, value != 0);
+                            accesses.flags.push((*flag, value != 0));
+                        },
+                        Place::Mem(address) => {
+                            let address = Self::eval(machine, &context, address)? as usize % MEMORY_SIZE;
+                            match context.device(address) {
+                                Some((device, offset)) => device.borrow_mut().write(offset, value as u8),
+                                None => machine.memory[address] = value as u8,
+                            }
+                            accesses.writes.push((address, value as u8));
+                        },
+                    }
+                },
+            }
+        }
+        machine.pc = next_pc;
+        machine.steps += 1;
+        if let Some(executed) = &mut self.executed {
+            executed.insert(pc);
+        }
+        if let Some(counts) = &mut self.counts {
+            *counts.entry(pc).or_insert(0) += 1;
+        }
+        
+        accesses.reads = context.reads.into_inner();
+        for (_, device) in &self.devices {
+            device.borrow_mut().tick();
+        }
+        
+        if let Some(trace) = &mut self.trace {
+            let bytes: String = machine.memory[pc..pc + current.length].iter().map(|b| format!("{:02X}", b)).collect();
+            let line = match self.trace_format {
+                TraceFormat::Json => Self::json_record(machine, pc, &bytes, &current.text(), &accesses),
+                TraceFormat::Text => Self::text_record(machine, pc, &bytes, &accesses),
+            };
+            writeln!(trace, "{}", line).map_err(|e| format!("could not write trace: {}", e))?;
+        }
+        Ok(())
+    }
+    
+    fn json_record(machine: &Machine, pc: usize, bytes: &str, text: &str, accesses: &Accesses) -> String {
+        let registers: serde_json::Map<_, _> = accesses.registers.iter().map(|(r, value)| (format!("r{}", r), json!(value))).collect();
+        let flags: serde_json::Map<_, _> = accesses.flags.iter().map(|(flag, value)| (flag.name().to_owned(), json!(*value as u8))).collect();
+        let memory = |accesses: &[(usize, u8)]| accesses.iter().map(|(address, value)| json!({ "address": address, "value": value })).collect::<Vec<_>>();
+        json!({
+            "step": machine.steps,
+            "pc": pc,
+            "bytes": bytes,
+            "instruction": text,
+            "registers": registers,
+            "flags": flags,
+            "reads": memory(&accesses.reads),
+            "writes": memory(&accesses.writes),
+            "next_pc": machine.pc,
+        }).to_string()
+    }
+    
+    fn text_record(machine: &Machine, pc: usize, bytes: &str, accesses: &Accesses) -> String {
+        let mut line = format!("{:06} {:04X} {}", machine.steps, pc, bytes);
+        for (r, value) in &accesses.registers {
+            line += &format!(" r{}={:04X}", r, value);
+        }
+        for (flag, value) in &accesses.flags {
+            line += &format!(" {}={}", flag.name(), *value as u8);
+        }
+        for (address, value) in &accesses.reads {
+            line += &format!(" rd[{:04X}]={:02X}", address, value);
+        }
+        for (address, value) in &accesses.writes {
+            line += &format!(" wr[{:04X}]={:02X}", address, value);
+        }
+        line
+    }
+
+    // Runs until the machine stops, a breakpoint is reached, or the step limit is hit. A breakpoint
+    // at the starting address is ignored so that running can resume from it.
+    pub fn run(&mut self, max_steps: u64) -> Result<Stop, String> {
+        for step in 0..max_steps {
+            if self.machine.halted {
+                return Ok(Stop::Halted);
+            }
+            if self.machine.pc >= self.program_len {
+                return Ok(Stop::EndOfProgram);
+            }
+            if step > 0 && self.breakpoints.contains(&self.machine.pc) {
+                return Ok(Stop::Breakpoint(self.machine.pc));
+            }
+            self.step()?;
+        }
+        Ok(if self.machine.halted { Stop::Halted } else { Stop::StepLimit })
+    }
+}
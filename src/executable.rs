@@ -0,0 +1,70 @@
+use crate::directives::Image;
+
+// The executable format the x69 bootloader loads, which places each segment at its address and
+// jumps to the entry point:
+//
+//     magic     "X69X"
+//     version   u8
+//     entry     u32
+//     count     u16, the number of segments
+//     table     count times the address and length of a segment, both u32
+//     data      the bytes of each segment in table order
+//
+// Integers are little endian. Without `.entry`, execution starts at the lowest address.
+pub const MAGIC: &[u8; 4] = b"X69X";
+const VERSION: u8 = 1;
+
+pub struct Executable {
+    pub entry: usize,
+    pub segments: Vec<(usize, Vec<u8>)>,
+}
+
+pub fn write(image: &Image) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend((image.entry.unwrap_or(image.base) as u32).to_le_bytes());
+    let segments: Vec<_> = image.segments.iter().filter(|(_, code)| !code.is_empty()).collect();
+    bytes.extend((segments.len() as u16).to_le_bytes());
+    for (address, code) in &segments {
+        bytes.extend((*address as u32).to_le_bytes());
+        bytes.extend((code.len() as u32).to_le_bytes());
+    }
+    for (_, code) in segments {
+        bytes.extend(code);
+    }
+    bytes
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<usize> {
+    let value = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([value[0], value[1], value[2], value[3]]) as usize)
+}
+
+pub fn is_executable(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn read(bytes: &[u8]) -> Result<Executable, String> {
+    if !is_executable(bytes) {
+        return Err("not an x69 executable".to_owned());
+    }
+    if bytes.get(4) != Some(&VERSION) {
+        return Err("executable was written by a different version".to_owned());
+    }
+    let truncated = || "executable is truncated".to_owned();
+    let entry = u32_at(bytes, 5).ok_or_else(truncated)?;
+    let count = bytes.get(9..11).map(|c| u16::from_le_bytes([c[0], c[1]]) as usize).ok_or_else(truncated)?;
+    let mut data = 11 + count * 8;
+    let mut segments = Vec::with_capacity(count);
+    for i in 0..count {
+        let address = u32_at(bytes, 11 + i * 8).ok_or_else(truncated)?;
+        let length = u32_at(bytes, 15 + i * 8).ok_or_else(truncated)?;
+        let code = bytes.get(data..data + length).ok_or_else(truncated)?;
+        segments.push((address, code.to_vec()));
+        data += length;
+    }
+    if data != bytes.len() {
+        return Err("executable has bytes after its last segment".to_owned());
+    }
+    Ok(Executable { entry, segments })
+}
@@ -0,0 +1,65 @@
+use crate::doc::{encoding, register_width};
+use crate::parser::*;
+use crate::style::OperandStyle;
+use serde_json::{json, Value};
+
+fn operands(pattern: &Pattern) -> Vec<Value> {
+    let (mut registers, mut immediates) = (0, 0);
+    let mut operands = Vec::new();
+    for token in &pattern.tokens {
+        operands.push(match token {
+            PatternToken::Register => {
+                registers += 1;
+                json!({ "kind": "register", "index": registers - 1, "width": register_width(pattern, registers - 1) })
+            },
+            PatternToken::Immediate => {
+                immediates += 1;
+                json!({ "kind": "immediate", "index": immediates - 1, "width": pattern.immediate_width(immediates - 1) })
+            },
+            PatternToken::Comma => json!({ "kind": "comma" }),
+            PatternToken::Punct(p) => json!({ "kind": "punctuation", "symbol": p }),
+            PatternToken::Keyword(word) => json!({ "kind": "keyword", "word": word }),
+        });
+    }
+    operands
+}
+
+fn pattern(name: &str, pattern: &Pattern) -> Value {
+    json!({
+        "syntax": pattern.syntax(name),
+        "operands": operands(pattern),
+        "codegen": pattern.codegen.iter().filter(|c| !matches!(c, Codegen::Guard(_))).map(|c| c.to_string()).collect::<Vec<_>>().join(" "),
+        // Conditions on the immediates under which an `if` branch picks this encoding
+        "conditions": pattern.guards().map(|c| c.to_string()).collect::<Vec<_>>(),
+        "length": encoded_length(pattern.codegen),
+        // Upper and lower nibble of each encoded byte, e.g. `["0001", "r0[3:0]"]`
+        "bytes": encoding(pattern.codegen).into_iter().map(|(upper, lower)| [upper, lower]).collect::<Vec<_>>(),
+        "semantics": pattern.semantics,
+    })
+}
+
+// Dumps the instruction map, along with the bundle formats and memory regions, as JSON for tools
+// that consume the ISA without parsing configs. Mnemonics are in alphabetical order and patterns
+// in the order the DFA accepts them, so the output is stable.
+pub fn isa_json(assembler: &Assembler) -> String {
+    let mut names: Vec<_> = assembler.instructions.keys().collect();
+    names.sort();
+    let instructions: serde_json::Map<_, _> = names.into_iter().map(|name| {
+        let instruction = &assembler.instructions[name];
+        let value = json!({
+            "category": assembler.category(name).map(Category::name),
+            "syntaxes": instruction.syntaxes(name, false, &OperandStyle::default()),
+            "patterns": instruction.patterns().iter().map(|p| pattern(name, p)).collect::<Vec<_>>(),
+        });
+        (name.clone(), value)
+    }).collect();
+    let mut slots: Vec<_> = assembler.slots.iter().collect();
+    slots.sort();
+    let isa = json!({
+        "instructions": instructions,
+        "slots": slots.into_iter().map(|(name, mnemonics)| json!({ "name": name, "mnemonics": mnemonics })).collect::<Vec<_>>(),
+        "bundles": assembler.bundles.iter().map(|b| json!({ "slots": b.slots, "header": b.header })).collect::<Vec<_>>(),
+        "regions": assembler.regions.iter().map(|r| json!({ "name": r.name, "start": r.start, "end": r.end, "preamble": r.preamble, "postamble": r.postamble })).collect::<Vec<_>>(),
+    });
+    serde_json::to_string_pretty(&isa).unwrap() + "\n"
+}
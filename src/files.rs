@@ -0,0 +1,104 @@
+use std::path::Path;
+
+// Where sources are read from. Files named by `.include` are read through it too, so callers such
+// as the language server can assemble unsaved buffers under the paths they will be saved at, and
+// diagnostics name those paths.
+pub trait FileProvider {
+    fn read(&self, path: &str) -> Result<String, String>;
+}
+
+// Files on disk
+pub struct Disk;
+
+// Sources held in memory by path, with the rest read from another provider if there is one
+#[derive(Default)]
+pub struct VirtualFiles {
+    files: Vec<(String, String)>,
+    fallback: Option<Box<dyn FileProvider>>,
+}
+
+impl FileProvider for Disk {
+    fn read(&self, path: &str) -> Result<String, String> {
+        std::fs::read_to_string(path).map(decode).map_err(|e| e.to_string())
+    }
+}
+
+impl VirtualFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reads files not held in memory from the provider, usually `Disk`
+    pub fn with_fallback(fallback: Box<dyn FileProvider>) -> Self {
+        Self { files: Vec::new(), fallback: Some(fallback) }
+    }
+
+    // Holds the source under the path, replacing what was there
+    pub fn insert(&mut self, path: &str, source: &str) {
+        let path = normalize(path);
+        match self.files.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, existing)) => *existing = decode(source.to_owned()),
+            None => self.files.push((path, decode(source.to_owned()))),
+        }
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        let path = normalize(path);
+        self.files.retain(|(p, _)| *p != path);
+    }
+}
+
+impl FileProvider for VirtualFiles {
+    fn read(&self, path: &str) -> Result<String, String> {
+        let normalized = normalize(path);
+        match (self.files.iter().find(|(p, _)| *p == normalized), &self.fallback) {
+            (Some((_, source)), _) => Ok(source.clone()),
+            (None, Some(fallback)) => fallback.read(path),
+            (None, None) => Err("no such file".to_owned()),
+        }
+    }
+}
+
+// The source as it is assembled, without the byte order mark editors on Windows may start it with
+// and with `\r\n` and lone `\r` line endings made `\n`, so a carriage return is never read as part
+// of the last token on a line or counted in its columns
+pub fn decode(source: String) -> String {
+    let source = match source.strip_prefix('\u{feff}') {
+        Some(rest) => rest.to_owned(),
+        None => source,
+    };
+    match source.contains('\r') {
+        true => source.replace("\r\n", "\n").replace('\r', "\n"),
+        false => source,
+    }
+}
+
+// The path with `.` and `..` taken out where they can be, so `src/./a.s` and `lib/../src/a.s` name
+// the same file as `src/a.s`
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "." => {},
+            "" if !parts.is_empty() => {},
+            ".." if parts.last().is_some_and(|last| !matches!(*last, ".." | "")) => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+    match parts.as_slice() {
+        [] => ".".to_owned(),
+        [""] => "/".to_owned(),
+        _ => parts.join("/"),
+    }
+}
+
+// The file an `.include` names, relative to the file including it. The output of a command run by
+// `.include_cmd` is named after the command, and includes files relative to the working directory.
+pub fn resolve(including: &str, path: &str) -> String {
+    match Path::new(including).parent().filter(|_| !path.starts_with('/') && !including.starts_with('`')) {
+        Some(dir) => normalize(&dir.join(path).to_string_lossy()),
+        None => normalize(path),
+    }
+}
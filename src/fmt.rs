@@ -0,0 +1,103 @@
+use crate::lexer::{Lexer, Lexeme, Token};
+
+// Column that instructions start at; labels are placed at column 0
+pub const INDENT: usize = 4;
+
+// Spaces between the longest statement in a block and its aligned trailing comments
+const COMMENT_GAP: usize = 2;
+
+struct Line {
+    code: String,
+    comment: Option<String>,
+}
+
+// Splits a `//` comment off the line, ignoring slashes inside strings
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_string = false;
+    let bytes = line.as_bytes();
+    for i in 0..bytes.len() {
+        match bytes[i] {
+            b'"' => in_string = !in_string,
+            b'/' if !in_string && bytes.get(i + 1) == Some(&b'/') => return (&line[..i], Some(line[i..].trim_end())),
+            _ => {},
+        }
+    }
+    (line, None)
+}
+
+// Renders the tokens of a statement with canonical spacing, or None if the lexer rejects it
+fn format_code(code: &str) -> Option<String> {
+    let lexemes: Vec<_> = Lexer::new(code).collect();
+    let (label, rest) = match lexemes.as_slice() {
+        // `math::mul16` is a macro qualified by its namespace rather than a label
+        [Lexeme { token: Token::Ident(label), .. }, Lexeme { token: Token::Colon, .. }, rest @ ..] if !matches!(rest.first(), Some(Lexeme { token: Token::Colon, .. })) => (Some(*label), rest),
+        rest => (None, rest),
+    };
+    let mut statement = String::new();
+    let mut previous: Option<&Token> = None;
+    // Set after a prefix operator such as `-` in `-1`, which is kept next to its operand
+    let mut attach = false;
+    for (i, Lexeme { token, slice, .. }) in rest.iter().enumerate() {
+        match token {
+            Token::Error => return None,
+            Token::Comma | Token::Colon | Token::CloseBracket | Token::CloseParen => statement += slice,
+            // A call such as `bank(label)` in an operand
+            Token::OpenParen if i > 0 && matches!(previous, Some(Token::Ident(_))) => statement += slice,
+            _ if statement.is_empty() || attach || matches!(previous, Some(Token::OpenBracket | Token::OpenParen)) => statement += slice,
+            _ => {
+                statement.push(' ');
+                statement += slice;
+            },
+        }
+        // `#`, `=`, `::`, and operators following the mnemonic, a separator, or another operator are prefixes
+        attach = matches!(token, Token::Hash | Token::Assign) || matches!(token, Token::Colon) && matches!(previous, Some(Token::Colon)) || matches!(token, Token::Operator(_)) && (i == 1 || matches!(previous, Some(Token::Comma | Token::OpenBracket | Token::OpenParen | Token::Hash | Token::Operator(_))));
+        previous = Some(token);
+    }
+    Some(match (label, statement.is_empty()) {
+        (Some(label), true) => format!("{}:", label),
+        (Some(label), false) => format!("{:width$}{}", format!("{}:", label), statement, width = (label.len() + 2).max(INDENT)),
+        (None, true) => String::new(),
+        (None, false) => format!("{:width$}{}", "", statement, width = INDENT),
+    })
+}
+
+// Normalizes whitespace, operand spacing, label columns, and trailing comment alignment
+pub fn format_source(source: &str) -> String {
+    let lines: Vec<Line> = source.lines().map(|line| {
+        // Block comments are left alone since they may span lines
+        if line.contains("/*") || line.contains("*/") {
+            return Line { code: line.trim_end().to_owned(), comment: None };
+        }
+        let (code, comment) = split_comment(line);
+        match format_code(code) {
+            Some(code) => Line { code, comment: comment.map(str::to_owned) },
+            None => Line { code: line.trim_end().to_owned(), comment: None },
+        }
+    }).collect();
+
+    let mut output = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < lines.len() {
+        // Trailing comments are aligned across each run of consecutive commented statements
+        let run = lines[i..].iter().take_while(|l| !l.code.is_empty() && l.comment.is_some()).count();
+        if run == 0 {
+            let line = &lines[i];
+            output += &line.code;
+            if let Some(comment) = &line.comment {
+                output += comment;
+            }
+            output.push('\n');
+            i += 1;
+            continue;
+        }
+        let column = lines[i..i + run].iter().map(|l| l.code.len()).max().unwrap_or(0) + COMMENT_GAP;
+        for line in &lines[i..i + run] {
+            output += &format!("{:width$}{}\n", line.code, line.comment.as_deref().unwrap_or_default(), width = column);
+        }
+        i += run;
+    }
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+    output
+}
@@ -0,0 +1,31 @@
+use crate::cache;
+use crate::config::Config;
+use crate::log::{LoggedResult, Logger};
+use crate::parser::Assembler;
+
+// Entry points for fuzz harnesses such as cargo-fuzz targets, which take arbitrary bytes. None of
+// them panic on any input; malformed input is reported in the logs like any other error, or is
+// None for compiled configs.
+
+pub fn load_unchecked_config(config: &[u8]) -> LoggedResult<Assembler> {
+    Config::new("[unknown]", &String::from_utf8_lossy(config)).load()
+}
+
+pub fn load_unchecked_compiled_config(bytes: &[u8]) -> Option<Assembler> {
+    cache::load(bytes, None)
+}
+
+// Assembles the source with the ISA of the config, or reports why the config can't be loaded
+pub fn assemble_unchecked_input(config: &[u8], source: &[u8]) -> LoggedResult<Vec<u8>> {
+    let mut logger = Logger::new(None);
+    let mut assembler = None;
+    load_unchecked_config(config).if_ok(&mut logger, |a| assembler = Some(a));
+    let mut code = None;
+    if let Some(assembler) = assembler {
+        assembler.assemble(&String::from_utf8_lossy(source)).if_ok(&mut logger, |c| code = Some(c));
+    }
+    match code {
+        Some(code) => logger.into_result(|| code),
+        None => logger.into_none(),
+    }
+}
@@ -0,0 +1,101 @@
+use crate::directives::DIRECTIVES;
+use crate::emulator::REGISTERS;
+use crate::parser::Assembler;
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Vim,
+    TextMate,
+    // Plain keyword lists, e.g. for generating a tree-sitter grammar
+    Json,
+}
+
+fn mnemonics(assembler: &Assembler) -> Vec<&str> {
+    let mut names: Vec<_> = assembler.instructions.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+}
+
+// Macros defined by the ISA config, such as `inc`, which are used like instructions
+fn macros(assembler: &Assembler) -> Vec<&str> {
+    let mut names: Vec<_> = assembler.macros.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+}
+
+// Directive names without their leading `.`
+fn directives() -> Vec<&'static str> {
+    DIRECTIVES.iter().map(|directive| &directive[1..]).collect()
+}
+
+// Names as alternatives of a regex, with the characters the regex would treat as syntax escaped
+fn alternation(names: &[&str]) -> String {
+    let escaped: Vec<_> = names.iter().map(|name| name.chars().fold(String::new(), |mut out, c| {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+        out
+    })).collect();
+    escaped.join("|")
+}
+
+fn vim(assembler: &Assembler) -> String {
+    let mut out = String::from("\" Generated by x69asm from the ISA config\nif exists(\"b:current_syntax\")\n  finish\nendif\n\nsyntax case ignore\n");
+    for name in mnemonics(assembler) {
+        out += &format!("syntax keyword x69Mnemonic {}\n", name);
+    }
+    for name in macros(assembler) {
+        out += &format!("syntax keyword x69Macro {}\n", name);
+    }
+    out += &format!("syntax match x69Register /\\<r\\({}\\)\\>/\n", (0..REGISTERS).rev().map(|r| r.to_string()).collect::<Vec<_>>().join("\\|"));
+    out += &format!("syntax match x69Directive /\\.\\({}\\)\\>/\n", directives().join("\\|"));
+    out += "syntax match x69Label /^\\s*\\h\\w*:/\n";
+    out += "syntax match x69Number /\\<\\(0x\\x\\+\\|0b[01]\\+\\|\\d\\+\\)\\>/\n";
+    out += "syntax region x69String start=/\"/ end=/\"/\n";
+    out += "syntax match x69Comment /\\/\\/.*/\n";
+    out += "syntax region x69Comment start=/\\/\\*/ end=/\\*\\//\n\n";
+    for (group, link) in [("Mnemonic", "Keyword"), ("Macro", "Macro"), ("Register", "Identifier"), ("Directive", "PreProc"), ("Label", "Label"), ("Number", "Number"), ("String", "String"), ("Comment", "Comment")] {
+        out += &format!("highlight default link x69{} {}\n", group, link);
+    }
+    out + "\nlet b:current_syntax = \"x69asm\"\n"
+}
+
+fn textmate(assembler: &Assembler) -> String {
+    let grammar = json!({
+        "name": "x69 Assembly",
+        "scopeName": "source.x69asm",
+        "fileTypes": ["s", "asm"],
+        "patterns": [
+            { "name": "comment.line.double-slash.x69asm", "match": "//.*$" },
+            { "name": "comment.block.x69asm", "begin": "/\\*", "end": "\\*/" },
+            { "name": "string.quoted.double.x69asm", "begin": "\"", "end": "\"" },
+            { "name": "entity.name.label.x69asm", "match": "^\\s*[_a-zA-Z]\\w*:" },
+            { "name": "keyword.control.directive.x69asm", "match": format!("(?i)\\.({})\\b", directives().join("|")) },
+            { "name": "keyword.other.mnemonic.x69asm", "match": format!("(?i)\\b({})\\b", alternation(&mnemonics(assembler))) },
+            { "name": "entity.name.function.macro.x69asm", "match": format!("(?i)\\b({})\\b", alternation(&macros(assembler))) },
+            { "name": "variable.language.register.x69asm", "match": format!("(?i)\\br({})\\b", (0..REGISTERS).rev().map(|r| r.to_string()).collect::<Vec<_>>().join("|")) },
+            { "name": "constant.numeric.x69asm", "match": "\\b(0[xX][0-9a-fA-F]+|0[bB][01]+|\\d+)\\b" },
+        ],
+    });
+    serde_json::to_string_pretty(&grammar).unwrap() + "\n"
+}
+
+fn keywords(assembler: &Assembler) -> String {
+    let keywords = json!({
+        "mnemonics": mnemonics(assembler),
+        "registers": (0..REGISTERS).map(|r| format!("r{}", r)).collect::<Vec<_>>(),
+        "directives": DIRECTIVES,
+        "macros": macros(assembler),
+    });
+    serde_json::to_string_pretty(&keywords).unwrap() + "\n"
+}
+
+pub fn generate(assembler: &Assembler, format: Format) -> String {
+    match format {
+        Format::Vim => vim(assembler),
+        Format::TextMate => textmate(assembler),
+        Format::Json => keywords(assembler),
+    }
+}
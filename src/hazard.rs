@@ -0,0 +1,72 @@
+use crate::directives::Directives;
+use crate::ir::Operation;
+use crate::log::Logger;
+use crate::parser::Assembler;
+use crate::peephole::{self, Bindings, Pattern, Previous};
+
+// A sequence given by `.hazard`, which is warned about, or by `.illegal`, which is an error, such as
+//
+//     .hazard out r0, i0 ; ld | st : memory is accessed right after a port write
+//
+// Patterns are written like those of `.peephole`, with variables standing for the same operand
+// in both, and a pattern can also be mnemonics joined by `|` to match any of them with any
+// operands. Only instructions next to each other in the code are checked, whatever labels are
+// between them.
+#[derive(Debug, Clone)]
+pub struct Hazard {
+    // The sequence as written after the directive, which compiled configs keep it as
+    pub text: String,
+    pub illegal: bool,
+    sequence: Vec<Vec<Pattern>>,
+    message: Option<String>,
+}
+
+// Parses what follows `.hazard` or `.illegal`
+pub fn parse_hazard(text: &str, illegal: bool) -> Result<Hazard, String> {
+    let (sequence, message) = match text.split_once(':') {
+        Some((sequence, message)) => (sequence, Some(message.trim().to_owned()).filter(|m| !m.is_empty())),
+        None => (text, None),
+    };
+    let sequence = sequence.split(';').map(|patterns| match patterns.contains('|') {
+        true => patterns.split('|').map(|mnemonic| match peephole::pattern(mnemonic)? {
+            pattern if mnemonic.split_whitespace().count() == 1 => Ok(pattern),
+            _ => Err(format!("expected mnemonics with no operands on either side of '|', found '{}'", patterns.trim())),
+        }).collect(),
+        false => peephole::pattern(patterns).map(|pattern| vec![pattern]),
+    }).collect::<Result<Vec<_>, _>>()?;
+    if sequence.len() != 2 {
+        return Err(format!("expected '.{} <pattern> ; <pattern> [: <message>]'", if illegal { "illegal" } else { "hazard" }));
+    }
+    Ok(Hazard { text: text.trim().to_owned(), illegal, sequence, message })
+}
+
+impl Hazard {
+    pub(crate) fn matches(&self, first: &Operation, second: &Operation) -> bool {
+        let mut bindings = Bindings::default();
+        self.sequence[0].iter().any(|pattern| pattern.matches(first, &mut bindings))
+            && self.sequence[1].iter().any(|pattern| pattern.matches(second, &mut bindings))
+    }
+}
+
+impl Assembler {
+    // Checks the instruction against the one emitted right before it
+    pub(crate) fn check_hazards(&self, logger: &mut Logger, executed: Previous, directives: &mut Directives) {
+        if self.hazards.is_empty() {
+            return;
+        }
+        let (operation, text) = (&executed.operation, &executed.text);
+        if let Some(first) = directives.executed.take().filter(|p| p.address + p.length == executed.address) {
+            for hazard in self.hazards.iter().filter(|hazard| hazard.matches(&first.operation, operation)) {
+                let message = match &hazard.message {
+                    Some(message) => format!("'{}' right after '{}' at {}: {}", text, first.text, first.site, message),
+                    None => format!("'{}' right after '{}' at {} is declared {} by '.{} {}'", text, first.text, first.site, if hazard.illegal { "illegal" } else { "a hazard" }, if hazard.illegal { "illegal" } else { "hazard" }, hazard.text),
+                };
+                match hazard.illegal {
+                    true => logger.log_error(message),
+                    false => logger.log_warning(message),
+                }
+            }
+        }
+        directives.executed = Some(executed);
+    }
+}
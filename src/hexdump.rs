@@ -0,0 +1,36 @@
+use crate::disasm::{Disassembler, Line};
+use crate::map::SymbolMap;
+
+// Widest instruction that still lines up with the others, in bytes
+const COLUMNS: usize = 8;
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect()
+}
+
+// One row per decoded instruction with its address, bytes, and ASCII, preceded by any labels
+// the map defines at that address
+pub fn dump(disassembler: &Disassembler, bytes: &[u8], symbols: Option<&SymbolMap>) -> String {
+    let mut out = String::new();
+    for line in disassembler.disassemble(bytes) {
+        let (offset, length, text) = match line {
+            Line::Instruction { decoded, .. } => (decoded.offset, decoded.bytes.len(), decoded.text),
+            Line::Undecodable { offset, .. } => (offset, 1, "??".to_owned()),
+        };
+        if let Some(symbols) = symbols {
+            // Labels inside an instruction are shown too, since they point at its middle
+            for address in offset..offset + length {
+                for name in symbols.at(address) {
+                    match address - offset {
+                        0 => out += &format!("{}:\n", name),
+                        inside => out += &format!("{}:    ; inside the next instruction (+{})\n", name, inside),
+                    }
+                }
+            }
+        }
+        let code = &bytes[offset..offset + length];
+        let hex: Vec<String> = code.iter().map(|b| format!("{:02X}", b)).collect();
+        out += &format!("{:08X}  {:<width$}  |{:<columns$}|  {}\n", offset, hex.join(" "), ascii(code), text, width = COLUMNS * 3 - 1, columns = COLUMNS);
+    }
+    out
+}
@@ -0,0 +1,255 @@
+use serde_json::Value;
+
+// Converts opcode tables kept in spreadsheets or generated by other tools into config source, one
+// definition per line. Each entry has a mnemonic, its operands in pattern notation (`r0, i0:8`), its
+// encoding in codegen notation (`0x10 [r0 | r1] i0`), and optionally its semantics.
+
+const COLUMNS: [&str; 4] = ["mnemonic", "operands", "encoding", "semantics"];
+
+fn definition(mnemonic: &str, operands: &str, encoding: &str, semantics: &str) -> String {
+    let mut line = format!("{} {} -> {}", mnemonic.trim(), operands.trim(), encoding.trim());
+    if !semantics.trim().is_empty() {
+        line += &format!(" {{ {} }}", semantics.trim());
+    }
+    line
+}
+
+// Splits a CSV row into its fields, where quoted fields may hold commas and `""` is a quote
+fn fields(row: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = row.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_owned());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+// A CSV table with a header row naming the columns, which may be in any order. Lines of the output
+// correspond to lines of the table, so diagnostics point at the right row.
+pub fn from_csv(source: &str) -> Result<String, String> {
+    let mut lines = source.lines();
+    let header = fields(lines.next().ok_or_else(|| "line 1: expected a header row".to_owned())?)?;
+    let header: Vec<_> = header.iter().map(|h| h.trim().to_lowercase()).collect();
+    let mut columns = [None; 4];
+    for (column, name) in COLUMNS.iter().enumerate() {
+        columns[column] = header.iter().position(|h| h == name);
+        if columns[column].is_none() && *name != "semantics" {
+            return Err(format!("line 1: missing the '{}' column", name));
+        }
+    }
+    let mut config = String::from("// imported from CSV\n");
+    for (line, row) in lines.enumerate() {
+        if row.trim().is_empty() {
+            config += "\n";
+            continue;
+        }
+        let row = fields(row).map_err(|message| format!("line {}: {}", line + 2, message))?;
+        let get = |column: usize| columns[column].and_then(|i| row.get(i)).map_or("", String::as_str);
+        config += &definition(get(0), get(1), get(2), get(3));
+        config += "\n";
+    }
+    Ok(config)
+}
+
+// A JSON array of objects with the same keys as the CSV columns. Line n of the output, counting
+// from 1, is entry n.
+pub fn from_json(source: &str) -> Result<String, String> {
+    let entries: Vec<Value> = serde_json::from_str(source).map_err(|e| e.to_string())?;
+    let mut config = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let get = |key: &str| match entry.get(key) {
+            Some(Value::String(s)) => Ok(s.as_str()),
+            None if key == "semantics" => Ok(""),
+            _ => Err(format!("entry {}: '{}' must be a string", i + 1, key)),
+        };
+        config += &definition(get("mnemonic")?, get("operands")?, get("encoding")?, get("semantics")?);
+        config += "\n";
+    }
+    Ok(config)
+}
+
+// Whether the source holds customasm `#ruledef` blocks rather than config definitions
+pub fn is_customasm(source: &str) -> bool {
+    source.lines().any(|line| line.trim_start().starts_with("#ruledef"))
+}
+
+// A field of a customasm encoding such as `0x1 @ r @ imm`
+enum Field {
+    Nibble(u8),
+    Register(usize),
+    // Immediate and its width in bits
+    Immediate(usize, usize),
+}
+
+fn nibble(field: &Field) -> Option<String> {
+    match *field {
+        Field::Nibble(n) => Some(n.to_string()),
+        Field::Register(r) => Some(format!("r{}", r)),
+        Field::Immediate(i, 4) => Some(format!("i{}", i)),
+        Field::Immediate(..) => None,
+    }
+}
+
+// Parses `{name: type}`, returning the operand it becomes in the pattern. Parameters of the integer
+// types `uN`, `sN` and `iN` are immediates, and any other type is taken to be a register.
+fn parameter(text: &str, registers: &mut Vec<String>, immediates: &mut Vec<(String, usize)>) -> Result<String, String> {
+    let (name, kind) = text.split_once(':').ok_or_else(|| format!("expected '{{name: type}}', but got '{{{}}}'", text))?;
+    let (name, kind) = (name.trim().to_owned(), kind.trim());
+    let width = match kind.get(..1) {
+        Some("u" | "s" | "i") => kind[1..].parse::<usize>().ok(),
+        _ => None,
+    };
+    match width {
+        Some(width) if width == 4 || width % 8 == 0 => {
+            immediates.push((name, width));
+            Ok(format!("i{}:{}", immediates.len() - 1, width))
+        },
+        Some(width) => Err(format!("'{}' is {} bits wide, but immediates must be 4 bits or a whole number of bytes", name, width)),
+        None => {
+            registers.push(name);
+            Ok(format!("r{}", registers.len() - 1))
+        },
+    }
+}
+
+// Splits a literal of `digits` in the given base into nibbles, most significant first
+fn literal(field: &str, digits: &str, radix: u32, width: usize) -> Result<Vec<Field>, String> {
+    if !width.is_multiple_of(4) {
+        return Err(format!("'{}' is not a whole number of nibbles", field));
+    }
+    let value = u64::from_str_radix(digits, radix).map_err(|_| format!("bad value '{}'", field))?;
+    Ok((0..width / 4).rev().map(|n| Field::Nibble((value >> (4 * n)) as u8 & 0xF)).collect())
+}
+
+// Converts one `pattern => encoding` rule into a config definition
+fn rule(text: &str) -> Result<String, String> {
+    let (pattern, encoding) = text.split_once("=>").ok_or_else(|| "expected 'pattern => encoding'".to_owned())?;
+    let pattern = pattern.trim();
+    let (mnemonic, mut rest) = pattern.split_once(char::is_whitespace).unwrap_or((pattern, ""));
+    let (mut registers, mut immediates) = (Vec::new(), Vec::new());
+    let mut operands = String::new();
+    loop {
+        rest = rest.trim_start();
+        match rest.chars().next() {
+            None => break,
+            Some(',') => {
+                operands += ", ";
+                rest = &rest[1..];
+            },
+            Some('{') => {
+                let end = rest.find('}').ok_or_else(|| "unterminated '{' in pattern".to_owned())?;
+                operands += &parameter(&rest[1..end], &mut registers, &mut immediates)?;
+                rest = &rest[end + 1..];
+            },
+            Some(_) => return Err(format!("unsupported text '{}' in pattern; only parameters and commas can follow the mnemonic", rest)),
+        }
+    }
+    
+    let mut fields = Vec::new();
+    for field in encoding.split('@').map(str::trim) {
+        let (value, slice) = match field.split_once('`') {
+            Some((value, width)) => (value.trim(), Some(width.trim().parse::<usize>().map_err(|_| format!("bad width in '{}'", field))?)),
+            None => (field, None),
+        };
+        if let Some(digits) = value.strip_prefix("0x") {
+            fields.extend(literal(field, digits, 16, slice.unwrap_or(4 * digits.len()))?);
+        } else if let Some(digits) = value.strip_prefix("0b") {
+            fields.extend(literal(field, digits, 2, slice.unwrap_or(digits.len()))?);
+        } else if let Some(r) = registers.iter().position(|r| r == value) {
+            if slice.is_some_and(|width| width != 4) {
+                return Err(format!("register '{}' must be encoded in 4 bits", value));
+            }
+            fields.push(Field::Register(r));
+        } else if let Some(i) = immediates.iter().position(|(name, _)| name == value) {
+            let width = immediates[i].1;
+            if slice.is_some_and(|slice| slice != width) {
+                return Err(format!("'{}' must be encoded in its declared width of {} bits", value, width));
+            }
+            fields.push(Field::Immediate(i, width));
+        } else {
+            return Err(format!("unsupported encoding field '{}'", field));
+        }
+    }
+    
+    // Pairs nibbles into bytes, leaving immediates of whole bytes as they are
+    let mut codegen = Vec::new();
+    let mut fields = fields.into_iter();
+    while let Some(field) = fields.next() {
+        let lower = match field {
+            Field::Immediate(i, width) if width != 4 => {
+                codegen.push(format!("i{}", i));
+                continue;
+            },
+            _ => fields.next(),
+        };
+        codegen.push(match (field, lower) {
+            (Field::Nibble(upper), Some(Field::Nibble(lower))) => format!("{:#04x}", upper << 4 | lower),
+            (upper, Some(lower)) => match (nibble(&upper), nibble(&lower)) {
+                (Some(upper), Some(lower)) => format!("[{} | {}]", upper, lower),
+                _ => return Err("immediates wider than 4 bits must start on a byte boundary".to_owned()),
+            },
+            (_, None) => return Err("the encoding must be a whole number of bytes".to_owned()),
+        });
+    }
+    Ok(format!("{} {} -> {}", mnemonic, operands, codegen.join(" ")))
+}
+
+// Converts the `#ruledef` blocks of a customasm source into config source. Lines of the output
+// correspond to lines of the source, so diagnostics point at the right rule. Immediates wider than
+// a byte are encoded little-endian like those of any other definition, where customasm would
+// concatenate them most significant byte first.
+pub fn from_customasm(source: &str) -> Result<String, String> {
+    let mut config = String::new();
+    // Whether we are inside a `#ruledef { ... }` block, or waiting for its opening brace
+    let (mut inside, mut opening) = (false, false);
+    for (line, text) in source.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", line + 1, message);
+        let text = text.split(';').next().unwrap_or_default().trim();
+        match text {
+            "" => {},
+            _ if text.starts_with("#ruledef") && !inside => {
+                inside = text.ends_with('{');
+                opening = !inside;
+            },
+            // Instructions are always encoded in bytes
+            "#bits 8" => {},
+            _ if text.starts_with('#') => return Err(error(format!("'{}' is not supported", text))),
+            "{" if opening => {
+                inside = true;
+                opening = false;
+            },
+            "}" if inside => inside = false,
+            _ if inside => config += &rule(text).map_err(error)?,
+            _ => return Err(error(format!("expected a '#ruledef' block, but got '{}'", text))),
+        }
+        config += "\n";
+    }
+    if inside || opening {
+        return Err("'#ruledef' block is missing its closing '}'".to_owned());
+    }
+    Ok(config)
+}
+
+// Config source for a file, converting opcode tables by their extension or contents
+pub fn convert(path: &str, source: String) -> Result<String, String> {
+    match std::path::Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("csv") => from_csv(&source),
+        Some("json") => from_json(&source),
+        _ if is_customasm(&source) => from_customasm(&source),
+        _ => Ok(source),
+    }
+}
@@ -0,0 +1,114 @@
+use crate::directives::Directives;
+use crate::lexer::BlockComments;
+use crate::log::{Logger, LoggedResult, Origin};
+use crate::macros::Preprocessor;
+use crate::parser::{arch, converge, include, log_unclosed, Assembler, Included};
+use crate::symbols::SymbolTable;
+use std::collections::HashMap;
+
+// Encodings of previously assembled statements, keyed by their text. A statement's encoding also
+// depends on the symbols defined before it, so entries are only reused when the symbol table's
+// fingerprint matches; entries not seen in the latest run are evicted.
+#[derive(Default)]
+pub struct EncodingCache {
+    entries: HashMap<String, (u64, LoggedResult<Vec<u8>>)>,
+    hits: usize,
+    misses: usize,
+}
+
+impl EncodingCache {
+    pub fn new() -> Self { Self::default() }
+    
+    // (hits, misses) of the most recent assembly
+    pub fn stats(&self) -> (usize, usize) { (self.hits, self.misses) }
+    
+    // Encoding of a statement seen in the most recent assembly
+    pub fn get(&self, statement: &str) -> Option<&LoggedResult<Vec<u8>>> { self.entries.get(statement).map(|(_, result)| result) }
+}
+
+impl Assembler {
+    // Same as `assemble`, but only re-encodes statements not found in the cache
+    pub fn assemble_incremental(&self, origin: &str, source: &str, cache: &mut EncodingCache) -> LoggedResult<Vec<u8>> {
+        converge(self.cancel.as_ref(), self.progress.as_ref(), |symbols| self.incremental_pass(origin, source, symbols, cache))
+    }
+    
+    fn incremental_pass(&self, origin: &str, source: &str, symbols: SymbolTable, cache: &mut EncodingCache) -> (LoggedResult<Vec<u8>>, SymbolTable) {
+        let mut output = Vec::new();
+        let mut logger = Logger::new(None);
+        let mut entries = HashMap::with_capacity(cache.entries.len());
+        let (mut hits, mut misses) = (0, 0);
+        
+        let mut preprocessor = Preprocessor::with_macros(origin, &self.macros);
+        preprocessor.set_dialect(self.dialect);
+        preprocessor.set_seed(self.seed);
+        preprocessor.set_limits(self.limits);
+        let mut directives = Directives::with_symbols(symbols, &self.regions);
+        directives.nop = self.nop.clone();
+        let mut comments = BlockComments::new();
+        
+        for (line, written) in source.lines().enumerate() {
+            if let Some(reason) = self.cancelled() {
+                logger.log_error(reason);
+                return (logger.into_none(), directives.symbols);
+            }
+            // Statements are read with comments blanked out, and logs show the line as written
+            logger.origin = Some(Origin::with_text(origin, line, written));
+            let source = comments.line(line, written);
+            let mut statements = Vec::new();
+            preprocessor.process(line, &source).if_ok(&mut logger, |s| statements = s);
+            let mut cursor = 0;
+            for statement in statements {
+                if let Some(origin) = &mut logger.origin {
+                    origin.locate(&source, &mut cursor, &statement);
+                }
+                // Only the statements of the watched source are cached and checked against it
+                let unsupported = match include(&statement) {
+                    Some(Ok(Included::Command(_))) => Some(".include_cmd"),
+                    Some(_) => Some(".include"),
+                    None => arch(&statement).map(|_| ".arch"),
+                };
+                if let Some(directive) = unsupported {
+                    logger.log_error(format!("'{}' is not supported when watching", directive));
+                    continue;
+                }
+                // Directives change the symbol table, so they are never cached
+                let mut code = None;
+                directives.process(&statement).if_ok(&mut logger, |c| code = Some(c));
+                match code {
+                    Some(Some(code)) => {
+                        directives.emit(&code);
+                        continue;
+                    },
+                    Some(None) => {},
+                    None => continue,
+                }
+                // Padding moves the statement, so it comes before the fingerprint taken at its address
+                let padding = self.align(&mut logger, &statement, &mut directives);
+                let fingerprint = directives.fingerprint();
+                let cached = entries.get(&statement).or_else(|| cache.entries.get(&statement));
+                let result = match cached.filter(|(f, _)| *f == fingerprint) {
+                    Some((_, result)) => {
+                        hits += 1;
+                        LoggedResult::clone(result)
+                    },
+                    None => {
+                        misses += 1;
+                        self.assemble_statement_with(&statement, &directives)
+                    },
+                };
+                entries.insert(statement, (fingerprint, result.clone()));
+                result.if_ok(&mut logger, |code| directives.emit(&[padding, code].concat()));
+            }
+        }
+        if let Some(line) = comments.unclosed() {
+            log_unclosed(&mut logger, origin, line, source.lines().nth(line).unwrap_or_default());
+        }
+        preprocessor.finish().if_ok(&mut logger, |_| {});
+        directives.finish().if_ok(&mut logger, |image| output = image.code);
+        
+        cache.entries = entries;
+        cache.hits = hits;
+        cache.misses = misses;
+        (logger.into_result(||output), directives.symbols)
+    }
+}
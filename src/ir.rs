@@ -0,0 +1,210 @@
+use crate::lexer::Span;
+use crate::log::Logger;
+use crate::parser::{fits_in, width_of, Codegen, CodegenData};
+use smallvec::SmallVec;
+
+// The program between matching and encoding. Instructions are matched against the config once,
+// into operations with their operands resolved and the form of their codegen chosen, so passes
+// over the program work on operations rather than on the text they were written as.
+
+// An immediate operand once resolved, along with how it was written for diagnostics
+#[derive(Debug, Clone)]
+pub struct Immediate {
+    pub value: usize,
+    pub span: Span,
+    // How many bits a `0x` or `0b` literal is written with
+    pub written: Option<usize>,
+    // The label it names, or the text of the expression that computed it
+    pub label: Option<String>,
+    pub expression: Option<String>,
+}
+
+// An instruction matched against the config. Registers and immediates are numbered like in its
+// codegen, and held inline since an instruction rarely has more than four of either.
+#[derive(Debug, Clone, Default)]
+pub struct Operation {
+    pub mnemonic: String,
+    pub registers: SmallVec<[u8; 4]>,
+    pub register_spans: SmallVec<[Span; 4]>,
+    pub immediates: SmallVec<[Immediate; 4]>,
+    // Form of the codegen the operands were matched to, and its semantics for the emulator
+    pub codegen: Vec<Codegen>,
+    pub semantics: Option<String>,
+    // The immediate that didn't fit the form declared first, and the syntax of that form, when a
+    // longer form was used instead
+    pub relaxed: Option<(usize, String)>,
+}
+
+// What a statement assembles to: an operation, or the bytes of a directive or a bundle. Most
+// statements are operations, so they aren't boxed, which would allocate for each one.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Item {
+    Operation(Operation),
+    Bytes(Vec<u8>),
+}
+
+// An operand the assembler changed, or that changed how the assembler encoded its instruction:
+// one truncated to fit its field, or one too wide for the form declared first, so a longer one was
+// used. Listed and reported so hardware debugging can rule out the assembler as the cause of a
+// value it didn't expect.
+#[derive(Debug, Clone)]
+pub struct Adjustment {
+    pub file: String,
+    pub line: usize,
+    pub address: usize,
+    // The operand as written, its value, and the bits of it the field holds
+    pub expression: String,
+    pub value: usize,
+    pub encoded: usize,
+    // The field it was placed in, e.g. `i0:4 of 'add'`
+    pub field: String,
+    // Syntax of the form declared first, when the operand was relaxed to a longer one
+    pub relaxed_from: Option<String>,
+}
+
+impl Adjustment {
+    // e.g. `'table + 300' = 0x12c, truncated to 0x2c in i0:8 of 'ld'`
+    pub fn describe(&self) -> String {
+        match &self.relaxed_from {
+            Some(from) => format!("'{}' = {:#x}, too wide for '{}', placed in {}", self.expression, self.value, from, self.field),
+            None => format!("'{}' = {:#x}, truncated to {:#x} in {}", self.expression, self.value, self.encoded, self.field),
+        }
+    }
+}
+
+// A statement of the final pass, with where it was written and placed
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub file: String,
+    pub line: usize,
+    pub address: usize,
+    pub item: Item,
+}
+
+impl Operation {
+    pub fn new(mnemonic: &str) -> Self {
+        Self { mnemonic: mnemonic.to_owned(), ..Self::default() }
+    }
+
+    pub fn immediate(&mut self, value: usize, span: Span, written: Option<usize>, label: Option<&str>) {
+        self.immediates.push(Immediate { value, span, written, label: label.map(str::to_owned), expression: None });
+    }
+
+    pub fn expression(&mut self, value: usize, span: Span, text: &str) {
+        self.immediates.push(Immediate { value, span, written: None, label: None, expression: Some(text.to_owned()) });
+    }
+
+    fn value(&self, index: usize) -> usize {
+        self.immediates.get(index).map_or(0, |immediate| immediate.value)
+    }
+
+    // Each immediate along with the width of the field the codegen first places it in
+    fn fields(&self) -> Vec<(usize, usize)> {
+        let mut fields: Vec<(usize, usize)> = Vec::new();
+        for data in self.codegen.iter().flat_map(|codegen| match codegen {
+            Codegen::Data(data) => vec![data],
+            Codegen::UpperLower(upper, lower) => vec![upper, lower],
+            Codegen::Guard(_) => Vec::new(),
+        }) {
+            if let CodegenData::Immediate(index, width) = *data {
+                if !fields.iter().any(|(i, _)| *i == index) {
+                    fields.push((index, width));
+                }
+            }
+        }
+        fields
+    }
+
+    // The operands truncated to fit their fields and the one the form was relaxed for, with the
+    // source of the statement the operation was lowered from, which their spans point into
+    pub fn adjustments(&self, source: &str, file: &str, line: usize, address: usize) -> Vec<Adjustment> {
+        self.fields().into_iter().filter_map(|(index, width)| {
+            let immediate = self.immediates.get(index)?;
+            let relaxed_from = self.relaxed.as_ref().filter(|(relaxed, _)| *relaxed == index).map(|(_, from)| from.clone());
+            if fits_in(immediate.value, width) && relaxed_from.is_none() {
+                return None;
+            }
+            let mask = if width >= usize::BITS as usize { usize::MAX } else { (1 << width) - 1 };
+            Some(Adjustment {
+                file: file.to_owned(),
+                line,
+                address,
+                expression: source.get(immediate.span.clone()).unwrap_or_default().trim().to_owned(),
+                value: immediate.value,
+                encoded: immediate.value & mask,
+                field: format!("i{}:{} of '{}'", index, width, self.mnemonic),
+                relaxed_from,
+            })
+        }).collect()
+    }
+
+    // Emits the codegen with the operands filled in, warning about immediates that don't fit
+    pub fn encode(&self, logger: &mut Logger) -> Vec<u8> {
+        let mut output = Vec::new();
+        let decode = |codegen: &CodegenData| match *codegen {
+            CodegenData::Byte(b) => b,
+            CodegenData::Register(r) => self.registers.get(r).copied().unwrap_or(0),
+            CodegenData::Immediate(imm, _) => self.value(imm) as u8,
+        };
+
+        // Immediates already warned about, since codegen can emit one more than once
+        let mut truncated = Vec::new();
+        let mut check_width = |logger: &mut Logger, index: usize, b: usize| {
+            let immediate = match self.immediates.get(index) {
+                Some(immediate) => immediate,
+                None => return,
+            };
+            if truncated.contains(&index) {
+                return;
+            }
+            let (imm, span) = (immediate.value, immediate.span.clone());
+            if !fits_in(imm, b) {
+                truncated.push(index);
+                match (&immediate.label, &immediate.expression) {
+                    (Some(label), _) => logger.log_permissive(Some(span), format!("'{}' is at {:#x}, which will be truncated to {} bits", label, imm, b)),
+                    (None, Some(text)) => logger.log_permissive(Some(span), format!("'{}' is {} ({} bits), which will be truncated to {} bits", text, imm as isize, width_of(imm), b)),
+                    (None, None) => logger.log_permissive(Some(span), format!("'{}' will be truncated to {} bits", imm as isize, b)),
+                }
+            } else if let Some(written) = immediate.written.filter(|written| *written > b) {
+                // The value fits, but leading digits suggest the literal was meant for a wider field
+                truncated.push(index);
+                logger.log_warning_at(span, format!("immediate is written with {} bits but the field is {} bits wide", written, b));
+            }
+        };
+        for data in &self.codegen {
+            match data {
+                Codegen::Data(data) => {
+                    match *data {
+                        CodegenData::Immediate(index, b) => {
+                            check_width(logger, index, b);
+                            output.extend(self.value(index).to_le_bytes().iter().take(b / 8));
+                        },
+                        _ => output.push(decode(data)),
+                    }
+                },
+                Codegen::UpperLower(upper, lower) => {
+                    for data in [upper, lower] {
+                        if let CodegenData::Immediate(index, b) = *data {
+                            check_width(logger, index, b);
+                        }
+                    }
+                    let upper = decode(upper);
+                    let lower = decode(lower);
+                    output.push((upper & 0xF) << 4 | (lower & 0xF));
+                },
+                Codegen::Guard(_) => {},
+            }
+        }
+        output
+    }
+}
+
+impl Item {
+    pub fn encode(&self, logger: &mut Logger) -> Vec<u8> {
+        match self {
+            Item::Operation(operation) => operation.encode(logger),
+            Item::Bytes(bytes) => bytes.clone(),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Configs of the official x69 variants, built into the assembler so a source can be assembled
+// with `--isa x69-bravo` without tracking down the config file. Each is found by a path of the
+// form `isa:<name>` wherever a config path is taken.
+const BUILTIN: [(&str, &str); 1] = [
+    ("isa:x69-bravo", include_str!("../x69-bravo.conf")),
+];
+
+const PREFIX: &str = "isa:";
+
+// Names `--isa` accepts, e.g. `x69-bravo`
+pub fn names() -> Vec<&'static str> {
+    BUILTIN.iter().map(|(path, _)| &path[PREFIX.len()..]).collect()
+}
+
+// The path a built in config is found by, or an error listing the names there are
+pub fn path(name: &str) -> Result<&'static str, String> {
+    match BUILTIN.iter().find(|(path, _)| path[PREFIX.len()..] == *name) {
+        Some((path, _)) => Ok(path),
+        None => Err(format!("there's no built in ISA named '{}'; it can be one of {}", name, names().join(", "))),
+    }
+}
+
+// The source of the built in config a path names, or None for a path to a file
+pub fn source(path: &str) -> Option<&'static str> {
+    BUILTIN.iter().find(|(builtin, _)| *builtin == path).map(|(_, source)| *source)
+}
+
+// Name `.arch` switches to the ISA of a config by, which is the name of a built in one or the
+// file name of a config without its extension, e.g. `x69-bravo` for `configs/x69-bravo.conf`
+pub fn arch_name(path: &str) -> String {
+    match path.strip_prefix(PREFIX) {
+        Some(name) => name.to_owned(),
+        None => std::path::Path::new(path).file_stem().map_or_else(|| path.to_owned(), |stem| stem.to_string_lossy().into_owned()),
+    }
+}
@@ -0,0 +1,91 @@
+use crate::directives::{jump_symbol, Directives};
+use crate::ir::{Item, Operation};
+use crate::lexer::{Lexeme, Lexer, Token};
+use crate::parser::Assembler;
+use crate::peephole::Previous;
+use crate::semantics::{self, Expr, Place};
+
+// Threading of jumps, made with `--thread-jumps`. A jump straight to the statement after it is
+// taken back so execution falls through, and a jump to a label placed at another jump goes where
+// that one does. Both are decided again in every pass, and labels settle on the result like they
+// do on relaxed code.
+
+// The immediate an unconditional jump sets `pc` to, if the operation is one. Jumps relative to
+// `pc` aren't threaded, since their immediate isn't the address of the label.
+fn target(operation: &Operation) -> Option<usize> {
+    let statements = semantics::parse(operation.semantics.as_deref()?).ok()?;
+    statements.iter().find_map(|statement| match statement {
+        semantics::Statement::Assign(Place::Pc, Expr::Immediate(immediate)) => Some(*immediate),
+        _ => None,
+    })
+}
+
+// The label an unconditional jump goes to, with the index of its immediate. Labels are encoded
+// relative to the statement after `.pic`, so the immediate has to be the address of the label, or
+// of where it was redirected to.
+fn destination<'a>(operation: &'a Operation, directives: &Directives) -> Option<(usize, &'a str)> {
+    let index = target(operation)?;
+    let immediate = operation.immediates.get(index)?;
+    let label = immediate.label.as_deref()?;
+    let absolute = [label.to_owned(), jump_symbol(label)].iter().any(|symbol| directives.symbols.get(symbol) == Some(immediate.value));
+    absolute.then_some((index, label))
+}
+
+impl Assembler {
+    // A jump to a label placed at another jump goes where that one does instead
+    pub(crate) fn redirect(&self, statement: &str, site: &str, operation: Operation, directives: &mut Directives) -> Operation {
+        let (index, label) = match destination(&operation, directives) {
+            Some(destination) => destination,
+            None => return operation,
+        };
+        let immediate = &operation.immediates[index];
+        let address = match directives.symbols.get(&jump_symbol(label)) {
+            Some(address) if address != immediate.value => address,
+            _ => return operation,
+        };
+        let text = format!("{}{}{}", &statement[..immediate.span.start], address, &statement[immediate.span.end..]);
+        match self.lower_statement_with(&text, directives).unwrap() {
+            (Some(Item::Operation(mut redirected)), _) => {
+                directives.rewrites.push(format!("{}: redirected '{}' to {:#06x}, where the jump at '{}' goes", site, statement.trim(), address, label));
+                // Still named by its label, so it falls through when the label follows it
+                redirected.immediates[index].label = Some(label.to_owned());
+                redirected
+            },
+            _ => operation,
+        }
+    }
+
+    // Labels placed at an unconditional jump are where it goes as far as jumps to them are
+    // concerned. That is the label it names rather than where it was redirected to, since following
+    // every jump along a loop of them would never settle.
+    pub(crate) fn mark_trampoline(&self, operation: &Operation, address: usize, directives: &mut Directives) {
+        let destination = match destination(operation, directives).and_then(|(_, label)| directives.symbols.get(label)) {
+            Some(destination) => destination,
+            None => return,
+        };
+        let labels: Vec<_> = directives.labels_at(address).into_iter().map(jump_symbol).collect();
+        for label in labels {
+            directives.symbols.redefine(&label, destination);
+        }
+    }
+
+    // Takes back the jump just before a statement defining the label it goes to, so execution falls
+    // through instead, returning the jump
+    pub(crate) fn fall_through(&self, statement: &str, directives: &mut Directives) -> Option<Previous> {
+        let lexemes: Vec<_> = Lexer::new(statement).take(2).collect();
+        let label = match lexemes.as_slice() {
+            [Lexeme { token: Token::Ident(label), .. }, Lexeme { token: Token::Colon, .. }] => *label,
+            _ => return None,
+        };
+        let previous = directives.previous.as_ref()?;
+        let index = target(&previous.operation)?;
+        let jumps_here = previous.operation.immediates.get(index).and_then(|immediate| immediate.label.as_deref()) == Some(label);
+        if !jumps_here || previous.address + previous.length != directives.address() {
+            return None;
+        }
+        let previous = directives.previous.take()?;
+        directives.retract(previous.length);
+        directives.rewrites.push(format!("{}: removed '{}', which jumps to the statement after it", previous.site, previous.text));
+        Some(previous)
+    }
+}
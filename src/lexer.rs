@@ -6,6 +6,8 @@ fn trim<'a>(lex: &mut logos::Lexer<'a, Token<'a>>, begin: usize, end: usize) ->
     &s[begin..s.len()-end]
 }
 
+// Numbers without a `0x` or `0b` prefix are in the radix held in the lexer's extras, where 0
+// means decimal
 #[inline]
 fn parse_int<'a>(lex: &mut logos::Lexer<'a, Token<'a>>) -> Result<usize, std::num::ParseIntError> {
     let slice = lex.slice();
@@ -14,13 +16,103 @@ fn parse_int<'a>(lex: &mut logos::Lexer<'a, Token<'a>>) -> Result<usize, std::nu
     } else if slice.starts_with("0b") || slice.starts_with("0B") {
         usize::from_str_radix(&slice[2..], 2)
     } else {
-        slice.parse()
+        usize::from_str_radix(slice, if lex.extras == 0 { 10 } else { lex.extras })
     }
 }
 
+// The number of bits a `0x` or `0b` literal is written with, leading zeros included, so
+// `0b000000001` is 9 bits wide. Decimal literals have no written width.
+pub fn written_width(slice: &str) -> Option<usize> {
+    if slice.starts_with("0x") || slice.starts_with("0X") {
+        Some((slice.len() - 2) * 4)
+    } else if slice.starts_with("0b") || slice.starts_with("0B") {
+        Some(slice.len() - 2)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+    LogicalNot,
+}
+
+impl Operator {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Rem => "%",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::Xor => "^",
+            Operator::Not => "~",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::LogicalAnd => "&&",
+            Operator::LogicalOr => "||",
+            Operator::LogicalNot => "!",
+        }
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+// Words reserved in instruction semantics; elsewhere they lex as identifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword<'a> {
+    Pc,
+    Mem,
+    Halt,
+    // `flags.z` and so on, holding the field after `flags`
+    Flag(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // Assembly source and instruction patterns in the config
+    Assembly,
+    // Register transfer semantics of config instructions
+    Semantics,
+}
+
 #[derive(Debug, Logos, PartialEq, Clone)]
+#[logos(extras = u32)]
 pub enum Token<'a> {
-    #[regex("[_a-zA-Z]\\w*")]
+    // Dotted names such as `player.hp` are single identifiers. Letters outside of ASCII are read
+    // here, and sources can only use them with `.identifiers unicode` in the config.
+    #[regex("[_\\p{L}]\\w*(\\.[_\\p{L}]\\w*)*")]
     Ident(&'a str),
     
     #[regex("[rR]\\d+", |lex| trim(lex, 1, 0).parse())]
@@ -35,12 +127,20 @@ pub enum Token<'a> {
     #[regex("\\.[_a-zA-Z0-9]\\w*")]
     Directive(&'a str),
     
-    #[regex("\"[^\"]*\"", |lex| trim(lex, 1, 1))]
+    // Strings and characters hold their contents with escape sequences still in place
+    #[regex("\"([^\"\\\\]|\\\\.)*\"", |lex| trim(lex, 1, 1))]
     String(&'a str),
     
-    #[regex("(0[xX][\\da-fA-F]+)|(0[bB][01]+)|\\d+", parse_int)]
+    #[regex("'([^'\\\\]|\\\\.)+'", |lex| trim(lex, 1, 1))]
+    Char(&'a str),
+    
+    // Digits run into letters so numbers such as `1F` can be read in other radixes
+    #[regex("\\d\\w*", parse_int)]
     Integer(usize),
     
+    #[regex("\\d+\\.\\d+([eE][+-]?\\d+)?", |lex| lex.slice().parse())]
+    Float(f64),
+    
     #[token("->")]
     Arrow,
     
@@ -50,44 +150,258 @@ pub enum Token<'a> {
     #[token(":")]
     Colon,
     
-    #[token("|")]
-    Or,
+    #[token(";")]
+    Semicolon,
+    
+    #[token("=")]
+    Assign,
+    
+    #[token("#")]
+    Hash,
+    
+    #[token("+", |_| Operator::Add)]
+    #[token("-", |_| Operator::Sub)]
+    #[token("*", |_| Operator::Mul)]
+    #[token("/", |_| Operator::Div)]
+    #[token("%", |_| Operator::Rem)]
+    #[token("&", |_| Operator::And)]
+    #[token("|", |_| Operator::Or)]
+    #[token("^", |_| Operator::Xor)]
+    #[token("~", |_| Operator::Not)]
+    #[token("<<", |_| Operator::Shl)]
+    #[token(">>", |_| Operator::Shr)]
+    #[token("==", |_| Operator::Eq)]
+    #[token("!=", |_| Operator::Ne)]
+    #[token("<", |_| Operator::Lt)]
+    #[token("<=", |_| Operator::Le)]
+    #[token(">", |_| Operator::Gt)]
+    #[token(">=", |_| Operator::Ge)]
+    #[token("&&", |_| Operator::LogicalAnd)]
+    #[token("||", |_| Operator::LogicalOr)]
+    #[token("!", |_| Operator::LogicalNot)]
+    Operator(Operator),
     
     #[token("[")]
     OpenBracket,
     #[token("]")]
     CloseBracket,
     
+    #[token("{")]
+    OpenBrace,
+    #[token("}")]
+    CloseBrace,
+    
+    #[token("(")]
+    OpenParen,
+    #[token(")")]
+    CloseParen,
+    
     #[regex("(/\\*([^*]|\\*[^/])+\\*/)|//.*", logos::skip)]
     Comment,
     
     #[error]
-    #[regex("[ \t\r]*", logos::skip)]
+    #[regex("[ \t\r\n]*", logos::skip)]
     Error,
+    
+    // Only produced in semantics mode
+    Keyword(Keyword<'a>),
 }
 
-#[repr(transparent)]
-pub struct Lexer<'a, T: Logos<'a>>(logos::Lexer<'a, T>);
+impl Token<'_> {
+    // Name of the token's kind, without its value
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Ident(_) => "Ident",
+            Token::Register(_) => "Register",
+            Token::Immediate(_) => "Immediate",
+            Token::Directive(_) => "Directive",
+            Token::String(_) => "String",
+            Token::Char(_) => "Char",
+            Token::Integer(_) => "Integer",
+            Token::Float(_) => "Float",
+            Token::Arrow => "Arrow",
+            Token::Comma => "Comma",
+            Token::Colon => "Colon",
+            Token::Semicolon => "Semicolon",
+            Token::Assign => "Assign",
+            Token::Hash => "Hash",
+            Token::Operator(_) => "Operator",
+            Token::OpenBracket => "OpenBracket",
+            Token::CloseBracket => "CloseBracket",
+            Token::OpenBrace => "OpenBrace",
+            Token::CloseBrace => "CloseBrace",
+            Token::OpenParen => "OpenParen",
+            Token::CloseParen => "CloseParen",
+            Token::Comment => "Comment",
+            Token::Error => "Error",
+            Token::Keyword(_) => "Keyword",
+        }
+    }
+}
+
+pub struct Lexer<'a, T: Logos<'a>> {
+    inner: logos::Lexer<'a, T>,
+    mode: Mode,
+    // Characters besides letters, digits, and underscores mnemonics can hold, given by
+    // `.mnemonic_chars` in the config
+    mnemonic_chars: &'a str,
+    // Set where a mnemonic can come next: at the start, after a label, and within a bundle
+    mnemonic_next: bool,
+    index: usize,
+}
+// Byte range of a token or AST node within the line it was parsed from
+pub type Span = std::ops::Range<usize>;
+
 pub struct Lexeme<'a, T: logos::Logos<'a>> {
     pub token: T,
     pub slice: &'a <T as Logos<'a>>::Source,
+    pub span: Span,
 }
 
 impl<'a> Lexer<'a, Token<'a>> {
     pub fn new(source: &'a str) -> Self {
-        Self(Token::lexer(source))
+        Self::with_mode(source, Mode::Assembly)
+    }
+    
+    pub fn with_mode(source: &'a str, mode: Mode) -> Self {
+        Self { inner: Token::lexer(source), mode, mnemonic_chars: "", mnemonic_next: true, index: 0 }
+    }
+    
+    // Reads numbers without a prefix in the radix, which must be between 2 and 36
+    pub fn with_radix(source: &'a str, radix: u32) -> Self {
+        Self { inner: Token::lexer_with_extras(source, radix), mode: Mode::Assembly, mnemonic_chars: "", mnemonic_next: true, index: 0 }
+    }
+    
+    // Reads a mnemonic such as `add.8` or `?jmp` as one identifier when the characters it holds
+    // besides letters, digits, and underscores are given, and lets it start with any of them
+    pub fn with_mnemonic_chars(source: &'a str, chars: &'a str) -> Self {
+        Self { mnemonic_chars: chars, ..Self::new(source) }
+    }
+    
+    // Extends the token to the mnemonic starting where it does, if it is at least as long
+    fn mnemonic(&mut self, token: Token<'a>) -> Token<'a> {
+        let rest = &self.inner.source()[self.inner.span().start..];
+        let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || self.mnemonic_chars.contains(c);
+        let starts = rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || self.mnemonic_chars.contains(c));
+        let length = rest.find(|c: char| !allowed(c)).unwrap_or(rest.len());
+        match token {
+            Token::Directive(_) => token,
+            _ if starts && length >= self.inner.slice().len() => {
+                self.inner.bump(length - self.inner.slice().len());
+                Token::Ident(self.inner.slice())
+            },
+            _ => token,
+        }
     }
 }
 
-impl<'a, T: Logos<'a, Source = str>> Iterator for Lexer<'a, T> {
-    type Item = Lexeme<'a, T>;
+impl<'a> Iterator for Lexer<'a, Token<'a>> {
+    type Item = Lexeme<'a, Token<'a>>;
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.0.next();
-        next.map(|t| {
-            Lexeme {
-                token: t,
-                slice: self.0.slice(),
+        let mut token = self.inner.next()?;
+        if self.mnemonic_next && !self.mnemonic_chars.is_empty() {
+            token = self.mnemonic(token);
+        }
+        self.mnemonic_next = matches!(token, Token::OpenBrace | Token::Semicolon) || token == Token::Colon && self.index == 1;
+        self.index += 1;
+        let token = match (self.mode, token) {
+            (Mode::Semantics, Token::Ident("pc")) => Token::Keyword(Keyword::Pc),
+            (Mode::Semantics, Token::Ident("mem")) => Token::Keyword(Keyword::Mem),
+            (Mode::Semantics, Token::Ident("halt")) => Token::Keyword(Keyword::Halt),
+            (Mode::Semantics, Token::Ident(name)) if name.starts_with("flags.") => Token::Keyword(Keyword::Flag(&name[5..])),
+            (_, token) => token,
+        };
+        Some(Lexeme { token, slice: self.inner.slice(), span: self.inner.span() })
+    }
+}
+
+// Whether the character belongs to the grapheme before it: combining marks, variation selectors,
+// skin tones, and the joiner of an emoji sequence. This covers the clusters sources hold in
+// practice without the Unicode tables.
+fn extends_grapheme(c: char) -> bool {
+    matches!(c as u32, 0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F | 0x200D | 0x1F3FB..=0x1F3FF | 0xE0100..=0xE01EF)
+}
+
+// The graphemes of the text, each as the characters it is written with
+pub fn graphemes(text: &str) -> Vec<&str> {
+    let mut graphemes: Vec<&str> = Vec::new();
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        let joined = previous == Some('\u{200D}') || previous.is_some() && extends_grapheme(c);
+        match graphemes.last_mut() {
+            Some(last) if joined => *last = &text[i - last.len()..i + c.len_utf8()],
+            _ => graphemes.push(&text[i..i + c.len_utf8()]),
+        }
+        previous = Some(c);
+    }
+    graphemes
+}
+
+// Column of a byte offset within a line, counted in graphemes from 0, so `é` written as `e` and a
+// combining accent is one column like any other character
+pub fn column(line: &str, offset: usize) -> usize {
+    graphemes(line.get(..offset).unwrap_or(line)).len()
+}
+
+// Blanks out `/* */` comments line by line, carrying one that is still open over to the next
+// line, so the line-oriented passes see a comment spanning lines as whitespace. Every byte of a
+// comment becomes a space, which keeps spans pointing into the line as it is written.
+#[derive(Debug, Default)]
+pub struct BlockComments {
+    // Line the comment still open was started on
+    open: Option<usize>,
+}
+
+impl BlockComments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn line(&mut self, line: usize, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        let mut quote = None;
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let next = chars.peek().map(|(_, c)| *c);
+            match (self.open, quote, c, next) {
+                (Some(_), _, '*', Some('/')) => {
+                    chars.next();
+                    self.open = None;
+                    output += "  ";
+                },
+                (Some(_), _, c, _) => output.extend(std::iter::repeat_n(' ', c.len_utf8())),
+                (None, Some(_), '\\', Some(escaped)) => {
+                    chars.next();
+                    output.push(c);
+                    output.push(escaped);
+                },
+                (None, Some(q), c, _) => {
+                    if c == q {
+                        quote = None;
+                    }
+                    output.push(c);
+                },
+                (None, None, '"' | '\'', _) => {
+                    quote = Some(c);
+                    output.push(c);
+                },
+                (None, None, '/', Some('/')) => {
+                    output += &text[i..];
+                    break;
+                },
+                (None, None, '/', Some('*')) => {
+                    chars.next();
+                    self.open = Some(line);
+                    output += "  ";
+                },
+                (None, None, c, _) => output.push(c),
             }
-        })
+        }
+        output
+    }
+    
+    // Line of a comment that is never closed
+    pub fn unclosed(&self) -> Option<usize> {
+        self.open
     }
 }
@@ -0,0 +1,62 @@
+pub mod analysis;
+pub mod archive;
+pub mod ast;
+pub mod bindiff;
+pub mod cache;
+pub mod cancel;
+pub mod cfg;
+pub mod config;
+pub mod coverage;
+pub mod deadcode;
+pub mod devices;
+pub mod debugger;
+pub mod delay;
+pub mod directives;
+pub mod disasm;
+pub mod doc;
+pub mod emitter;
+pub mod dump;
+pub mod emulator;
+pub mod executable;
+pub mod export;
+pub mod files;
+pub mod fmt;
+pub mod fuzz;
+pub mod grammar;
+pub mod hazard;
+pub mod hexdump;
+pub mod import;
+pub mod incremental;
+pub mod ir;
+pub mod isa;
+pub mod jumps;
+pub mod lexer;
+pub mod linker;
+pub mod listing;
+pub mod log;
+pub mod macros;
+pub mod lint;
+pub mod lsp;
+pub mod manifest;
+pub mod map;
+pub mod new_parser;
+pub mod parser;
+pub mod patch;
+pub mod pack;
+pub mod peephole;
+pub mod postprocess;
+pub mod prelude;
+pub mod profile;
+pub mod progress;
+pub mod remote;
+pub mod repl;
+pub mod report;
+pub mod semantics;
+pub mod session;
+pub mod snapshot;
+pub mod style;
+pub mod symbols;
+pub mod vectors;
+pub mod viewer;
+pub mod wizard;
+pub mod xref;
@@ -0,0 +1,165 @@
+use crate::log::{Logger, LoggedResult, Origin};
+use crate::parser::Region;
+
+// A linker script, which lays out memory for a build instead of the regions of the config:
+//
+//     // Memory, from the first address to the last
+//     region ROM 0x0000 0x7fff
+//     region RAM 0x8000 0xffff
+//
+//     // Sections placed in a region, in the order they are given there, optionally aligned, with
+//     // the gap before them filled with a byte instead of no-ops, and with `__data_start` and
+//     // `__data_end` defined at their bounds
+//     section .text ROM
+//     section .rodata ROM align 0x100
+//     section .data RAM align 4 fill 0xff symbols
+//
+//     // Symbols the sources can use, from integers and the bounds of regions
+//     symbol __stack_top = RAM.end
+//     symbol __heap = RAM.start + 0x800
+#[derive(Debug, Default, Clone)]
+pub struct Script {
+    pub regions: Vec<Region>,
+    pub placements: Vec<Placement>,
+    pub symbols: Vec<(String, usize)>,
+}
+
+// Where a `section` line puts a section: the index of its region in the script, which defaults
+// given by `.section_defaults` in the config don't have, and the multiple of bytes its start is
+// rounded up to
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub section: String,
+    pub region: Option<usize>,
+    pub align: usize,
+    // Byte the gap before the section is filled with instead of no-ops
+    pub fill: Option<u8>,
+    // Set to define `__name_start` and `__name_end` at the bounds of the section
+    pub symbols: bool,
+}
+
+fn integer(text: &str) -> Option<usize> {
+    let text = text.replace('_', "");
+    match text.get(..2) {
+        Some("0x" | "0X") => usize::from_str_radix(&text[2..], 16).ok(),
+        Some("0b" | "0B") => usize::from_str_radix(&text[2..], 2).ok(),
+        _ => text.parse().ok(),
+    }
+}
+
+impl Placement {
+    pub fn new(section: &str, region: Option<usize>) -> Self {
+        Self { section: section.to_owned(), region, align: 1, fill: None, symbols: false }
+    }
+    
+    // Sets the options after a section: `align <bytes>`, `fill <byte>`, and `symbols`
+    pub fn set_options(&mut self, mut words: &[&str]) -> Result<(), String> {
+        loop {
+            words = match words {
+                [] => return Ok(()),
+                ["align", align, rest @ ..] => {
+                    self.align = integer(align).filter(|align| *align > 0).ok_or_else(|| format!("alignment of section '{}' must be a positive integer", self.section))?;
+                    rest
+                },
+                ["fill", byte, rest @ ..] => {
+                    self.fill = Some(integer(byte).filter(|byte| *byte <= 0xFF).ok_or_else(|| format!("fill of section '{}' must be a byte", self.section))? as u8);
+                    rest
+                },
+                ["symbols", rest @ ..] => {
+                    self.symbols = true;
+                    rest
+                },
+                [word, ..] => return Err(format!("unknown option '{}', expected 'align <bytes>', 'fill <byte>', or 'symbols'", word)),
+            };
+        }
+    }
+    
+    // Symbols defined at the start of the section and just past its end, e.g. `__data_start` and
+    // `__data_end` for `.data`
+    pub fn bounds(&self) -> (String, String) {
+        let name: String = self.section.trim_start_matches('.').chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        (format!("__{}_start", name), format!("__{}_end", name))
+    }
+}
+
+impl Script {
+    pub fn parse(origin: &str, source: &str) -> LoggedResult<Script> {
+        let mut logger = Logger::new(None);
+        let mut script = Script::default();
+        
+        for (line, text) in source.lines().enumerate() {
+            logger.origin = Some(Origin::new(origin, line));
+            let words: Vec<_> = text.split("//").next().unwrap_or_default().split_whitespace().collect();
+            let result = match words.as_slice() {
+                [] => Ok(()),
+                ["region", name, start, end] => script.region(name, start, end),
+                ["region", ..] => Err("expected 'region <name> <start> <end>'".to_owned()),
+                ["section", name, region, options @ ..] => script.section(name, region, options),
+                ["section", ..] => Err("expected 'section <name> <region> [align <bytes>] [fill <byte>] [symbols]'".to_owned()),
+                ["symbol", name, "=", value @ ..] if !value.is_empty() => script.symbol(name, value),
+                ["symbol", ..] => Err("expected 'symbol <name> = <value>'".to_owned()),
+                [word, ..] => Err(format!("unknown command '{}', expected 'region', 'section', or 'symbol'", word)),
+            };
+            if let Err(message) = result {
+                logger.log_error(message);
+            }
+        }
+        
+        logger.into_result(|| script)
+    }
+    
+    fn region(&mut self, name: &str, start: &str, end: &str) -> Result<(), String> {
+        let bound = |text: &str| integer(text).ok_or_else(|| format!("'{}' is not an address", text));
+        let (start, end) = (bound(start)?, bound(end)?);
+        if end < start {
+            return Err(format!("region '{}' ends before it starts", name));
+        }
+        if let Some(other) = self.regions.iter().find(|r| r.name == name || r.start <= end && start <= r.end) {
+            return Err(format!("region '{}' overlaps region '{}'", name, other.name));
+        }
+        self.regions.push(Region::new(name, start, end));
+        Ok(())
+    }
+    
+    fn section(&mut self, name: &str, region: &str, options: &[&str]) -> Result<(), String> {
+        if self.placements.iter().any(|p| p.section == name) {
+            return Err(format!("section '{}' is already placed", name));
+        }
+        let region = self.regions.iter().position(|r| r.name == region).ok_or_else(|| format!("unknown region '{}'", region))?;
+        let mut placement = Placement::new(name, Some(region));
+        placement.set_options(options)?;
+        self.placements.push(placement);
+        Ok(())
+    }
+    
+    // `<term> [+|- <term>]...`, where a term is an integer, `REGION.start`, or `REGION.end`
+    fn symbol(&mut self, name: &str, value: &[&str]) -> Result<(), String> {
+        if self.symbols.iter().any(|(n, _)| n == name) {
+            return Err(format!("symbol '{}' is already defined", name));
+        }
+        let term = |text: &str| {
+            let bound = text.split_once('.').and_then(|(region, bound)| {
+                let region = self.regions.iter().find(|r| r.name == region)?;
+                match bound {
+                    "start" => Some(region.start),
+                    "end" => Some(region.end),
+                    _ => None,
+                }
+            });
+            bound.or_else(|| integer(text)).ok_or_else(|| format!("expected an integer, '<region>.start', or '<region>.end', but got '{}'", text))
+        };
+        let mut total = term(value[0])? as isize;
+        for pair in value[1..].chunks(2) {
+            match pair {
+                ["+", text] => total += term(text)? as isize,
+                ["-", text] => total -= term(text)? as isize,
+                _ => return Err(format!("expected '+ <term>' or '- <term>' in the value of '{}'", name)),
+            }
+        }
+        if total < 0 {
+            return Err(format!("symbol '{}' is negative", name));
+        }
+        self.symbols.push((name.to_owned(), total as usize));
+        Ok(())
+    }
+}
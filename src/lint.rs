@@ -0,0 +1,131 @@
+use crate::lexer::{Lexer, Span, Token};
+use crate::log::{Logger, LoggedResult, Origin};
+use crate::new_parser::*;
+use std::collections::HashMap;
+
+// A definition along with what the checks need from it
+struct Form {
+    line: usize,
+    span: Span,
+    syntax: String,
+    // Widths of the immediates in the order they appear
+    widths: Vec<usize>,
+    length: usize,
+    // Chosen by an `if` in the codegen rather than by relaxation
+    guarded: bool,
+}
+
+fn codegen_length(definition: &Definition, widths: &[usize]) -> usize {
+    definition.codegen.iter().map(|item| match item.node {
+        CodegenItem::Immediate(_, Some(width)) => width / 8,
+        CodegenItem::Immediate(im, None) => widths.get(im).map_or(1, |width| width / 8),
+        _ => 1,
+    }).sum()
+}
+
+// Checks one definition on its own, reporting immediates the codegen never emits and registers
+// the pattern doesn't capture
+fn check(logger: &mut Logger, definition: &Definition, widths: &[usize], registers: usize) {
+    let mut used = vec![false; widths.len()];
+    // Conditions of an `if` branch read the immediate, even when the branch doesn't emit it
+    for guard in &definition.guards {
+        if let Some(used) = used.get_mut(guard.immediate) {
+            *used = true;
+        }
+    }
+    let mut use_nibble = |logger: &mut Logger, nibble: Nibble, span: &Span| match nibble {
+        Nibble::Immediate(im) if im < used.len() => used[im] = true,
+        Nibble::Register(r) if r >= registers => logger.log_warning_at(span.clone(), format!("r{} is emitted but not captured by the pattern", r)),
+        _ => {},
+    };
+    for item in &definition.codegen {
+        match item.node {
+            CodegenItem::Immediate(im, _) => use_nibble(logger, Nibble::Immediate(im), &item.span),
+            CodegenItem::Register(r) => use_nibble(logger, Nibble::Register(r), &item.span),
+            CodegenItem::Bracket(upper, lower) => {
+                use_nibble(logger, upper, &item.span);
+                use_nibble(logger, lower, &item.span);
+            },
+            CodegenItem::Integer(_) => {},
+        }
+    }
+    let immediates = definition.pattern.iter().filter(|item| matches!(item.node, PatternItem::Immediate(..)));
+    for (item, used) in immediates.zip(used) {
+        if !used {
+            logger.log_warning_at(item.span.clone(), format!("'{}' is declared but never emitted by the codegen", item.slice));
+        }
+    }
+}
+
+// Checks a config for mistakes that still load: forms of a pattern that relaxation can never pick,
+// immediates the codegen never emits, registers emitted but not captured, and encodings that are
+// not a whole number of `word`-byte words
+pub fn lint(origin: &str, config: &str, word: usize) -> LoggedResult<()> {
+    let mut logger = Logger::new(None);
+    // Forms of each pattern, keyed by mnemonic and operand kinds without widths
+    let mut patterns: HashMap<String, Vec<Form>> = HashMap::new();
+    let mut order = Vec::new();
+    // Whether the line is inside a `.macro` block, whose body is source rather than definitions
+    let mut in_macro = false;
+    
+    for (line, source) in config.lines().enumerate() {
+        logger.origin = Some(Origin::new(origin, line));
+        let first = Lexer::new(source).next().map(|l| l.token);
+        match first {
+            Some(Token::Directive(".macro")) => in_macro = true,
+            Some(Token::Directive(".endm")) => in_macro = false,
+            _ => {},
+        }
+        if in_macro || matches!(first, Some(Token::Directive(_))) {
+            continue;
+        }
+        let mut definitions = None;
+        parse_definition(source).if_ok(&mut logger, |d| definitions = d);
+        for definition in definitions.unwrap_or_default() {
+            add_form(&mut logger, &mut patterns, &mut order, line, &definition, word);
+        }
+    }
+    
+    // Relaxation picks the shortest form whose immediates fit, so a longer form is never picked
+    // when a shorter one takes immediates at least as wide. Forms chosen by `if` are left out.
+    for key in order {
+        let forms = &patterns[&key];
+        for form in forms.iter().filter(|form| !form.guarded) {
+            let shadow = forms.iter().find(|other| !other.guarded && other.length < form.length && other.widths.iter().zip(&form.widths).all(|(a, b)| a >= b));
+            if let Some(shadow) = shadow {
+                logger.origin = Some(Origin::new(origin, form.line));
+                logger.log_warning_at(form.span.clone(), format!("'{}' is unreachable, since the shorter '{}' on line {} takes every value it does", form.syntax, shadow.syntax, shadow.line + 1));
+            }
+        }
+    }
+    logger.into_result(|| ())
+}
+
+// Checks one form and adds it to the forms of its pattern
+fn add_form(logger: &mut Logger, patterns: &mut HashMap<String, Vec<Form>>, order: &mut Vec<String>, line: usize, definition: &Definition, word: usize) {
+    let widths: Vec<_> = definition.pattern.iter().filter_map(|item| match item.node {
+        PatternItem::Immediate(_, width) => Some(width),
+        _ => None,
+    }).collect();
+    let registers = definition.pattern.iter().filter(|item| matches!(item.node, PatternItem::Register(_))).count();
+    check(logger, definition, &widths, registers);
+    
+    let length = codegen_length(definition, &widths);
+    if word > 1 && !length.is_multiple_of(word) {
+        logger.log_warning_at(definition.mnemonic.span.clone(), format!("'{}' encodes to {} bytes, which is not a whole number of {}-byte words", definition.syntax(), length, word));
+    }
+    let mut key = definition.name();
+    for item in &definition.pattern {
+        match item.node {
+            PatternItem::Register(_) => key += " r",
+            PatternItem::Immediate(..) => key += " i",
+            PatternItem::Comma => key += ",",
+            PatternItem::Punct(p) => push_item(&mut key, p),
+            PatternItem::Keyword(word) => push_item(&mut key, &word.to_lowercase()),
+        }
+    }
+    if !patterns.contains_key(&key) {
+        order.push(key.clone());
+    }
+    patterns.entry(key).or_default().push(Form { line, span: definition.mnemonic.span.clone(), syntax: definition.syntax(), widths, length, guarded: !definition.guards.is_empty() });
+}
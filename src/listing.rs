@@ -0,0 +1,53 @@
+// Bytes shown on each row; longer code continues on rows of its own
+const BYTES_PER_ROW: usize = 8;
+
+// A line of source with the code it assembled to, or a statement a macro on that line expanded
+// to, which has no line number of its own and is indented by its expansion depth
+#[derive(Debug, Clone, Default)]
+pub struct Row {
+    pub file: String,
+    pub line: Option<usize>,
+    pub address: usize,
+    pub code: Vec<u8>,
+    pub text: String,
+    pub depth: usize,
+    // Cycles the row's instructions take, and the total since its basic block started, when the
+    // config gives cycle counts
+    pub cycles: Option<(usize, usize)>,
+    // Addresses of the instructions among the code, as opposed to data
+    pub instructions: Vec<usize>,
+    // Operands of the row that were truncated or relaxed, listed beneath it
+    pub notes: Vec<String>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+// One row per line, e.g. `   12  0003  00 00 11                  inc r1`. When the config gives
+// cycle counts, each instruction's count and the running total of its basic block come before the
+// source, as in `   2    14  inc r1`. Operands that were truncated or relaxed are noted on lines of
+// their own after the row, as comments under its source.
+pub fn render(rows: &[Row]) -> String {
+    let timed = rows.iter().any(|row| row.cycles.is_some());
+    let mut out = String::new();
+    for row in rows {
+        let line = row.line.map_or(String::new(), |line| (line + 1).to_string());
+        let text = format!("{}{}", "  ".repeat(row.depth), row.text.trim_end());
+        let mut chunks = row.code.chunks(BYTES_PER_ROW);
+        let first = chunks.next().map(hex).unwrap_or_default();
+        let cycles = match row.cycles {
+            Some((cycles, total)) => format!("{:>4}  {:>4}  ", cycles, total),
+            None if timed => " ".repeat(12),
+            None => String::new(),
+        };
+        out += &format!("{:>5}  {:04X}  {:<w$}  {}{}\n", line, row.address, first, cycles, text.trim_end(), w = BYTES_PER_ROW * 3 - 1);
+        for (i, chunk) in chunks.enumerate() {
+            out += &format!("{:>5}  {:04X}  {}\n", "", row.address + (i + 1) * BYTES_PER_ROW, hex(chunk));
+        }
+        for note in &row.notes {
+            out += &format!("{:>5}  {:4}  {:w$}  {}; {}\n", "", "", "", "  ".repeat(row.depth), note, w = BYTES_PER_ROW * 3 - 1);
+        }
+    }
+    out
+}
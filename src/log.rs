@@ -1,7 +1,12 @@
+use crate::lexer::{column, graphemes, Span};
 use colored::Colorize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum LogLevel {
+    // Explains a choice the assembler made, which is neither a mistake nor suspicious
+    Note,
     Warning,
     Error,
 }
@@ -10,38 +15,150 @@ pub enum LogLevel {
 pub struct Origin {
     pub file: String,
     pub line: usize,
+    // Where the statement being worked on starts in the line, which the spans of its logs are
+    // relative to, and the span of its text, which logs without a span of their own are given. A
+    // statement a macro expanded to isn't written in the line, so its logs are all given the span
+    // of the invocation.
+    pub column: Option<usize>,
+    pub statement: Option<Span>,
+    // The line as it is written, when known, which columns are counted in by grapheme and which
+    // is shown under the message with the span underlined
+    pub text: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Log {
     origin: Option<Origin>,
+    // Location within the origin's line, if the log is about a specific part of it
+    span: Option<Span>,
     message: String,
     level: LogLevel,
+    // A warning about input that is accepted for compatibility but rejected by `--strict`
+    permissive: bool,
+}
+
+impl Origin {
+    pub fn new(file: &str, line: usize) -> Self {
+        Self { file: file.to_owned(), line, column: None, statement: None, text: None }
+    }
+    
+    pub fn with_text(file: &str, line: usize, text: &str) -> Self {
+        Self { text: Some(text.to_owned()), ..Self::new(file, line) }
+    }
+    
+    // Points at a statement written in the line, searching from where the statement before it
+    // ended. A statement the preprocessor rewrote may not be found, and is then pointed at by
+    // spans within it as before.
+    pub fn locate(&mut self, line: &str, from: &mut usize, statement: &str) {
+        let text = statement.trim();
+        let lead = statement.len() - statement.trim_start().len();
+        let start = line.get(*from..).filter(|_| !text.is_empty()).and_then(|rest| rest.find(text)).map(|i| *from + i);
+        (self.column, self.statement) = match start {
+            Some(start) => {
+                *from = start + text.len();
+                (start.checked_sub(lead), Some(start..start + text.len()))
+            },
+            None => (None, None),
+        };
+    }
+    
+    // Points every log at the invocation of the macro the statement was expanded from
+    pub fn expanded_from(&mut self, invocation: Option<Span>) {
+        (self.column, self.statement) = (None, invocation);
+    }
+    
+    // A span within the statement as a span within the line
+    fn within(&self, span: Option<Span>) -> Option<Span> {
+        match (self.column, span, &self.statement) {
+            (Some(column), Some(span), _) => Some(column + span.start..column + span.end),
+            (_, _, Some(statement)) => Some(statement.clone()),
+            (_, span, None) => span,
+        }
+    }
 }
 
 impl Log {
     pub fn new(level: LogLevel, origin: Option<Origin>, message: String) -> Self {
         Self {
             origin,
+            span: None,
             message,
             level,
+            permissive: false,
         }
     }
     
     pub fn is_error(&self) -> bool { matches!(self.level, LogLevel::Error) }
+    pub fn level(&self) -> &LogLevel { &self.level }
+    pub fn origin(&self) -> Option<&Origin> { self.origin.as_ref() }
+    pub fn span(&self) -> Option<&Span> { self.span.as_ref() }
+    pub fn message(&self) -> &str { &self.message }
 }
 
 impl std::fmt::Display for Log {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self.level {
+            LogLevel::Note => write!(f, "{}", "Note: ".cyan().bold())?,
             LogLevel::Warning => write!(f, "{}", "Warning: ".yellow().bold())?,
             LogLevel::Error => write!(f, "{}", "Error: ".red().bold())?,
         };
-        match &self.origin {
-            Some(origin) => write!(f, "{}:{}: {}", origin.file, origin.line + 1, self.message),
-            None => write!(f, "{}", self.message),
+        match (&self.origin, &self.span) {
+            (Some(origin), Some(span)) => match &origin.text {
+                Some(text) => write!(f, "{}:{}:{}: {}\n{}", origin.file, origin.line + 1, column(text, span.start) + 1, self.message, underline(text, span)),
+                None => write!(f, "{}:{}:{}: {}", origin.file, origin.line + 1, span.start + 1, self.message),
+            },
+            (Some(origin), None) => write!(f, "{}:{}: {}", origin.file, origin.line + 1, self.message),
+            (None, _) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// Width tabs are shown at in the lines under messages, or 0 to keep them as tabs
+static TAB_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+// Shows tabs in the lines under messages as spaces up to the next multiple of the width, for
+// output read somewhere tabs aren't as wide as the terminal that wrote it showed them. The column
+// in the message still counts a tab as one, as editors do.
+pub fn set_tab_width(width: usize) {
+    TAB_WIDTH.store(width, Ordering::Relaxed);
+}
+
+// The line with the span underlined beneath it, e.g.
+//
+//     ld naïve, 300
+//        ^^^^^
+//
+// Tabs before the span are kept so the underline lines up however wide the terminal shows them,
+// unless a tab width is set.
+fn underline(text: &str, span: &Span) -> String {
+    let text = text.trim_end();
+    let before = text.get(..span.start).unwrap_or(text);
+    match TAB_WIDTH.load(Ordering::Relaxed) {
+        0 => {
+            let width = column(text, span.end).saturating_sub(column(text, span.start)).max(1);
+            let indent: String = graphemes(before).into_iter().map(|g| if g == "\t" { '\t' } else { ' ' }).collect();
+            format!("    {}\n    {}{}", text, indent, "^".repeat(width))
+        },
+        tab => {
+            let start = expand_tabs(before, tab).len();
+            let end = expand_tabs(text.get(..span.end).unwrap_or(text), tab).len();
+            let width = end.saturating_sub(start).max(1);
+            format!("    {}\n    {}{}", expand_tabs(text, tab).concat(), " ".repeat(start), "^".repeat(width))
+        },
+    }
+}
+
+// The graphemes of the text with each tab as the spaces up to the next multiple of the width, one
+// per column
+fn expand_tabs(text: &str, width: usize) -> Vec<&str> {
+    let mut columns = Vec::new();
+    for grapheme in graphemes(text) {
+        match grapheme {
+            "\t" => columns.extend(std::iter::repeat_n(" ", width - columns.len() % width)),
+            grapheme => columns.push(grapheme),
         }
     }
+    columns
 }
 
 #[derive(Debug)]
@@ -58,22 +175,65 @@ impl Logger {
         }
     }
     
+    pub fn log_at(&mut self, level: LogLevel, span: Option<Span>, message: String) {
+        let span = self.within(span);
+        self.logs.push(Log { span, ..Log::new(level, self.origin.clone(), message) });
+    }
+    
     pub fn log_warning(&mut self, message: String) {
-        self.logs.push(Log::new(LogLevel::Warning, self.origin.clone(), message));
+        self.log_at(LogLevel::Warning, None, message);
     }
     
     pub fn log_error(&mut self, message: String) {
-        self.logs.push(Log::new(LogLevel::Error, self.origin.clone(), message));
+        self.log_at(LogLevel::Error, None, message);
+    }
+    
+    pub fn log_note(&mut self, message: String) {
+        self.log_at(LogLevel::Note, None, message);
+    }
+    
+    pub fn log_note_at(&mut self, span: Span, message: String) {
+        self.log_at(LogLevel::Note, Some(span), message);
+    }
+    
+    pub fn log_warning_at(&mut self, span: Span, message: String) {
+        self.log_at(LogLevel::Warning, Some(span), message);
+    }
+    
+    pub fn log_error_at(&mut self, span: Span, message: String) {
+        self.log_at(LogLevel::Error, Some(span), message);
+    }
+    
+    // Warns about input that is accepted, such as a truncated literal, but that is an error with `--strict`
+    pub fn log_permissive(&mut self, span: Option<Span>, message: String) {
+        let span = self.within(span);
+        self.logs.push(Log { span, permissive: true, ..Log::new(LogLevel::Warning, self.origin.clone(), message) });
+    }
+    
+    fn within(&self, span: Option<Span>) -> Option<Span> {
+        match &self.origin {
+            Some(origin) => origin.within(span),
+            None => span,
+        }
     }
     
     pub fn is_error(&self) -> bool {
         self.logs.iter().any(Log::is_error)
     }
     
+    pub fn errors(&self) -> usize {
+        self.logs.iter().filter(|log| log.is_error()).count()
+    }
+    
     pub fn into_none<T>(self) -> LoggedResult<T> {
         LoggedResult { result: None, logs: self.logs }
     }
     
+    // Keeps the result whether or not there were errors, for callers inspecting a failed run
+    pub fn into_partial<T>(self, result: T) -> LoggedResult<T> {
+        LoggedResult { result: Some(result), logs: self.logs }
+    }
+    
     pub fn into_result<T, F: FnOnce() -> T>(self, callback: F) -> LoggedResult<T> {
         let result = if self.is_error() {
             None
@@ -84,6 +244,7 @@ impl Logger {
     }
 }
 
+#[derive(Clone)]
 pub struct LoggedResult<T> {
     result: Option<T>,
     logs: Vec<Log>,
@@ -91,10 +252,55 @@ pub struct LoggedResult<T> {
 
 impl<T> LoggedResult<T> {
     pub fn unwrap(self) -> (Option<T>, Vec<Log>) { (self.result, self.logs) }
+    pub fn result(&self) -> Option<&T> { self.result.as_ref() }
+    pub fn logs(&self) -> &[Log] { &self.logs }
+    pub fn is_error(&self) -> bool { self.logs.iter().any(Log::is_error) }
+    
+    pub fn map<U, F: FnOnce(T) -> U>(self, callback: F) -> LoggedResult<U> {
+        LoggedResult { result: self.result.map(callback), logs: self.logs }
+    }
+    
+    // Groups the logs by file in the order the files are given, keeping the order within each
+    // file, so the output doesn't depend on the order files were worked on. Logs of a file one of
+    // them includes stay where they were logged among the logs of the file before them, or of the
+    // first file logged when none was before them. Logs without a file come last.
+    pub fn group_by_file(mut self, files: &[&str]) -> Self {
+        let mut previous = None;
+        let groups: Vec<_> = self.logs.iter().map(|log| match log.origin.as_ref().map(|o| files.iter().position(|f| *f == o.file)) {
+            Some(Some(position)) => {
+                previous = Some(position);
+                previous
+            },
+            Some(None) => previous,
+            None => Some(files.len()),
+        }).collect();
+        let first = groups.iter().flatten().find(|&&group| group < files.len()).copied().unwrap_or(files.len());
+        let mut logs: Vec<_> = groups.into_iter().map(|group| group.unwrap_or(first)).zip(self.logs).collect();
+        logs.sort_by_key(|(group, _)| *group);
+        self.logs = logs.into_iter().map(|(_, log)| log).collect();
+        self
+    }
+    
+    // Makes the warnings about permissive input errors, failing the result if there are any
+    pub fn strict(self) -> Self {
+        self.strict_keeping(false)
+    }
+    
+    // Like `strict`, but with `keep` a result that fails is kept as `Logger::into_partial` keeps it
+    pub fn strict_keeping(mut self, keep: bool) -> Self {
+        for log in self.logs.iter_mut().filter(|log| log.permissive) {
+            log.level = LogLevel::Error;
+            if !keep {
+                self.result = None;
+            }
+        }
+        self
+    }
     
     pub fn if_ok<F: FnOnce(T)>(self, logger: &mut Logger, callback: F) {
         for mut log in self.logs {
             if log.origin.is_none() {
+                log.span = logger.within(log.span);
                 log.origin = logger.origin.clone();
             }
             logger.logs.push(log);
@@ -0,0 +1,328 @@
+use crate::config;
+use crate::files::{self, Disk, VirtualFiles};
+use crate::import;
+use crate::lexer::{Lexeme, Lexer, Token};
+use crate::log::LogLevel;
+use crate::parser::Assembler;
+use crate::session::Session;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::time::SystemTime;
+
+// Reads one JSON-RPC message framed by a Content-Length header
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let mut body = vec![0; length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// The path of a document, which `.include` paths in it are relative to
+fn path(uri: &str) -> &str {
+    uri.strip_prefix("file://").unwrap_or(uri)
+}
+
+// The identifier under the cursor, if any
+fn word_at(text: &str, line: usize, character: usize) -> Option<&str> {
+    let line = text.lines().nth(line)?;
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let offset = line.char_indices().nth(character).map_or(line.len(), |(i, _)| i);
+    let start = line[..offset].rfind(|c| !is_word(c)).map_or(0, |i| i + 1);
+    let end = line[offset..].find(|c| !is_word(c)).map_or(line.len(), |i| offset + i);
+    if start < end {
+        Some(&line[start..end])
+    } else {
+        None
+    }
+}
+
+// The line of the text that defines a label or macro by the name, as `name:` or `.macro name`
+fn defining_line(text: &str, name: &str) -> Option<(usize, usize)> {
+    text.lines().enumerate().find_map(|(line, source)| {
+        let lexemes: Vec<_> = Lexer::new(source).take(2).collect();
+        let span = match lexemes.as_slice() {
+            [Lexeme { token: Token::Ident(n), span, .. }, Lexeme { token: Token::Colon, .. }] if *n == name => span.clone(),
+            [Lexeme { token: Token::Directive(".macro"), .. }, Lexeme { token: Token::Ident(n), span, .. }] if *n == name => span.clone(),
+            _ => return None,
+        };
+        let character = |offset: usize| source[..offset].chars().count();
+        Some((line, character(span.start)))
+    })
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub struct Server {
+    assembler: Assembler,
+    documents: HashMap<String, String>,
+    // The config the assembler was loaded from and when it was last modified, so edits to the ISA
+    // take effect without restarting the server
+    config: Option<(String, Option<SystemTime>)>,
+    // What each document defined the last time it assembled, even with errors, which hovers,
+    // completions, and definitions of its labels use
+    sessions: HashMap<String, Session>,
+}
+
+impl Server {
+    // Documents are assembled keeping going past errors, which they usually have while being
+    // typed, so the labels they define are still known
+    pub fn new(mut assembler: Assembler) -> Self {
+        assembler.set_keep_going(true);
+        Self { assembler, documents: HashMap::new(), config: None, sessions: HashMap::new() }
+    }
+    
+    pub fn with_config(assembler: Assembler, path: &str) -> Self {
+        Self { config: Some((path.to_owned(), modified(path))), ..Self::new(assembler) }
+    }
+    
+    // Rebuilds the instruction tables when the config has changed since it was loaded, sending its
+    // logs to the client and diagnosing every open document again. A config that no longer loads
+    // leaves the previous tables in place.
+    fn reload_config(&mut self) -> Vec<Value> {
+        let (path, last_modified) = match &mut self.config {
+            Some(config) => config,
+            None => return Vec::new(),
+        };
+        let now = modified(path);
+        if now == *last_modified {
+            return Vec::new();
+        }
+        *last_modified = now;
+        let source = std::fs::read_to_string(&*path).map(files::decode).map_err(|e| e.to_string()).and_then(|source| import::convert(path, source));
+        let log_message = |level: &LogLevel, message: String| json!({
+            "jsonrpc": "2.0",
+            "method": "window/logMessage",
+            "params": { "type": match level { LogLevel::Error => 1, LogLevel::Warning => 2, LogLevel::Note => 3 }, "message": message },
+        });
+        let mut messages = Vec::new();
+        let assembler = match source {
+            Ok(source) => {
+                let (assembler, logs) = config::create_assembler_from_configs(&[(path.as_str(), source.as_str())]).unwrap();
+                messages.extend(logs.iter().map(|log| log_message(log.level(), log.to_string())));
+                assembler
+            },
+            Err(message) => {
+                messages.push(log_message(&LogLevel::Error, format!("{}: {}", path, message)));
+                None
+            },
+        };
+        if let Some(mut assembler) = assembler {
+            assembler.set_keep_going(true);
+            self.assembler = assembler;
+            let mut uris: Vec<_> = self.documents.keys().cloned().collect();
+            uris.sort();
+            messages.extend(uris.iter().map(|uri| self.diagnostics(uri)));
+        }
+        messages
+    }
+
+    // Open documents are assembled as they are in the editor, including those they include, and
+    // other files are read from disk
+    fn diagnostics(&mut self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let mut files = VirtualFiles::with_fallback(Box::new(Disk));
+        for (uri, text) in &self.documents {
+            files.insert(path(uri), text);
+        }
+        let path = files::normalize(path(uri));
+        let (image, logs) = self.assembler.assemble_from(&files, &[&path], &[]).unwrap();
+        // The image of a document with errors leaves out the statements that failed, but not its
+        // labels. A document that doesn't assemble at all keeps what it defined last.
+        if let Some(image) = &image {
+            self.sessions.insert(uri.to_owned(), Session::from_image(image));
+        }
+        // Logs in included files are shown when they are opened
+        let logs = logs.iter().filter(|log| log.origin().is_none_or(|o| o.file == path));
+        let diagnostics: Vec<Value> = logs.map(|log| {
+            let line = log.origin().map_or(0, |o| o.line);
+            let source = text.lines().nth(line).unwrap_or_default();
+            // Logs without a span cover the whole line
+            let span = log.span().cloned().unwrap_or(0..source.len());
+            let character = |offset: usize| source.get(..offset).map_or(0, |s| s.chars().count());
+            json!({
+                "range": {
+                    "start": { "line": line, "character": character(span.start) },
+                    "end": { "line": line, "character": character(span.end) },
+                },
+                "severity": match log.level() { LogLevel::Error => 1, LogLevel::Warning => 2, LogLevel::Note => 3 },
+                "source": "x69asm",
+                "message": log.message(),
+            })
+        }).collect();
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    // Mnemonics and the config's macros, followed by the labels the document defined the last
+    // time it assembled, errors or not
+    fn completion(&self, uri: &str) -> Value {
+        let mut names: Vec<_> = self.assembler.instructions.keys().collect();
+        names.sort();
+        let mut items: Vec<Value> = names.into_iter().map(|name| json!({
+            "label": name,
+            "kind": 14,
+            "detail": self.assembler.instructions[name].syntaxes(name, self.assembler.src_first, &self.assembler.style).join("\n"),
+        })).collect();
+        let mut macros: Vec<_> = self.assembler.macros.iter().collect();
+        macros.sort_by_key(|(name, _)| *name);
+        items.extend(macros.into_iter().map(|(name, definition)| json!({
+            "label": name,
+            "kind": 3,
+            "detail": format!("{} {}", name, definition.params.join(", ")).trim_end().to_owned(),
+        })));
+        if let Some(session) = self.sessions.get(uri) {
+            items.extend(session.symbols.iter().map(|(name, address)| json!({
+                "label": name,
+                "kind": 6,
+                "detail": format!("{:#06x}", address),
+            })));
+        }
+        Value::Array(items)
+    }
+    
+    // Where the label or macro under the cursor is defined, looking in the document first and
+    // then in the other open documents
+    fn definition(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let line = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or_default() as usize;
+        let word = match self.documents.get(uri).and_then(|text| word_at(text, line, character)) {
+            Some(word) => word,
+            None => return Value::Null,
+        };
+        let mut others: Vec<_> = self.documents.keys().filter(|other| *other != uri).collect();
+        others.sort();
+        let found = std::iter::once(uri).chain(others.into_iter().map(String::as_str))
+            .find_map(|document| Some((document, defining_line(&self.documents[document], word)?)));
+        match found {
+            Some((document, (line, character))) => json!({
+                "uri": document,
+                "range": {
+                    "start": { "line": line, "character": character },
+                    "end": { "line": line, "character": character + word.chars().count() },
+                },
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let line = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or_default() as usize;
+        let word = self.documents.get(uri).and_then(|text| word_at(text, line, character));
+        let name = word.map(str::to_lowercase);
+        let symbol = word.and_then(|word| Some((word, self.sessions.get(uri)?.symbols.get(word)?)));
+        if let Some((word, address)) = symbol {
+            return json!({ "contents": { "kind": "markdown", "value": format!("**{}** = `{:#06x}`", word, address) } });
+        }
+        match name.as_ref().and_then(|name| self.assembler.instructions.get(name).map(|i| (name, i))) {
+            Some((name, instruction)) => {
+                let encodings: Vec<String> = instruction.patterns().iter().map(|p| p.describe(name)).collect();
+                json!({
+                    "contents": {
+                        "kind": "markdown",
+                        "value": format!("**{}**\n\n```\n{}\n```", name, encodings.join("\n")),
+                    }
+                })
+            },
+            None => Value::Null,
+        }
+    }
+
+    // Handles one message, returning the messages to send back
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_owned();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": {},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": { "name": "x69asm" },
+            }),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_owned());
+                return vec![self.diagnostics(&uri)];
+            },
+            "textDocument/didChange" => {
+                if let Some(text) = params["contentChanges"].as_array().and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.clone(), text.to_owned());
+                }
+                return vec![self.diagnostics(&uri)];
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.sessions.remove(&uri);
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })];
+            },
+            "textDocument/completion" => self.completion(&uri),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "shutdown" => Value::Null,
+            _ => {
+                if message.get("id").is_some() {
+                    return vec![json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": { "code": -32601, "message": format!("unsupported method '{}'", method) },
+                    })];
+                }
+                return Vec::new();
+            },
+        };
+        match message.get("id") {
+            Some(id) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => Vec::new(),
+        }
+    }
+
+    // Serves requests until the client sends `exit` or closes the input
+    pub fn run<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        while let Some(message) = read_message(&mut input)? {
+            if message["method"] == "exit" {
+                break;
+            }
+            let mut responses = self.reload_config();
+            responses.extend(self.handle(&message));
+            for response in responses {
+                write_message(&mut output, &response)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,732 @@
+use crate::directives::{unescape, Unit};
+use crate::lexer::{Lexer, Lexeme, Span, Token};
+use crate::log::{Logger, LoggedResult};
+use std::collections::hash_map::{Entry, HashMap};
+
+// Limit on nested expansions, which catches macros that invoke themselves, and on the statements
+// one line can expand to, which catches macros that invoke others several times each
+pub const MAX_DEPTH: usize = 64;
+pub const MAX_STATEMENTS: usize = 1 << 20;
+
+// Invocations shown at each end of a longer invocation stack
+const STACK_ENDS: usize = 4;
+
+// How far expansion can go before it's taken to be runaway, given by `--max-depth` and
+// `--max-expansion`. The depth limits `.include` nesting as well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Limits {
+    pub depth: usize,
+    pub statements: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { depth: MAX_DEPTH, statements: MAX_STATEMENTS }
+    }
+}
+
+// The invocations or files that led somewhere, outermost first, leaving out the middle of a long
+// one, e.g. `'outer' -> 'inner 3' -> ... 57 more ... -> 'inner 0'`
+pub fn invocation_stack(frames: &[String]) -> String {
+    let quoted: Vec<_> = frames.iter().map(|frame| format!("'{}'", frame)).collect();
+    if quoted.len() <= 2 * STACK_ENDS + 1 {
+        return quoted.join(" -> ");
+    }
+    let skipped = format!("... {} more ...", quoted.len() - 2 * STACK_ENDS);
+    let ends = quoted[..STACK_ENDS].iter().chain(Some(&skipped)).chain(&quoted[quoted.len() - STACK_ENDS..]);
+    ends.cloned().collect::<Vec<_>>().join(" -> ")
+}
+
+// Functions evaluated by the preprocessor
+const FUNCTIONS: &[&str] = &["rand", "unique", "concat", "upper", "strlen", "substr"];
+
+// Section code is placed in until `.section` is given
+pub const DEFAULT_SECTION: &str = ".text";
+
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub params: Vec<String>,
+    // Whether the last parameter is declared `name:vararg` and takes all remaining arguments
+    pub variadic: bool,
+    pub body: Vec<String>,
+}
+
+// What a line expanded to, in order, for listings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expanded {
+    // The next statement returned, along with its expansion depth, 0 for those written in the line
+    Statement(usize),
+    // A macro invocation, with its depth and text, followed by what it expanded to
+    Invocation(usize, String),
+}
+
+// What ends a statement, set in the config by `.terminator newline`, `.terminator semicolon`, or
+// `.terminator period`. The end of a line always ends one.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Terminator {
+    #[default]
+    Newline,
+    Semicolon,
+    Period,
+}
+
+// Statement and label syntax of the sources, so the dialect of other x69 assemblers and their
+// documentation can be read as is
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    pub terminator: Terminator,
+    // Set by `.labels bare`, in which a name starting in the first column is a label whether or
+    // not a colon follows it. Statements must then be indented.
+    pub bare_labels: bool,
+}
+
+#[derive(Debug)]
+enum Block {
+    Macro(String, Macro),
+    // `.irp param, values` ... `.endr`, along with the number of nested `.irp` blocks still open
+    Repeat { param: String, values: Vec<String>, body: Vec<String>, nested: usize },
+}
+
+// Expands `.macro name a, b` ... `.endm` definitions and `.irp` loops line by line so sources can
+// be streamed
+#[derive(Debug, Default)]
+pub struct Preprocessor {
+    // Name of the source file and the line being processed, for `__FILE__` and `__LINE__`
+    file: String,
+    line: usize,
+    macros: HashMap<String, Macro>,
+    // Block currently being collected
+    defining: Option<Block>,
+    // Number of expansions so far, substituted for `\@` to make labels in macro bodies unique
+    expansions: usize,
+    // Number of `unique()` calls so far
+    uniques: usize,
+    // Mixed into every `rand(seed, max)`, so builds differ unless they are given the same seed
+    seed: u64,
+    // Radix set by `.radix` for numbers without a prefix, or 0 for decimal
+    radix: u32,
+    // Section named by the last `.section`, for `__SECT__`
+    section: Option<String>,
+    // Set by `.syntax gnu`, in which `#` starts a comment and `.L` labels are accepted
+    gnu: bool,
+    dialect: Dialect,
+    // What the last line expanded to
+    expanded: Vec<Expanded>,
+    // Set by `.namespace name`, which places the macros defined after it under `name::`. Within
+    // the body of a namespaced macro, names are first looked up in its namespace.
+    namespace: Option<String>,
+    limits: Limits,
+    // Invocations being expanded, outermost first, and whether the line has gone past a limit,
+    // after which nothing more of it is expanded
+    stack: Vec<String>,
+    exceeded: bool,
+}
+
+// Splits the operands of an invocation at top-level commas
+fn arguments(source: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = None;
+    let mut end = 0;
+    for Lexeme { token, span, .. } in Lexer::new(source) {
+        match token {
+            Token::Comma if depth == 0 => {
+                arguments.push(start.map_or("", |start| &source[start..end]));
+                start = None;
+                continue;
+            },
+            Token::OpenBracket | Token::OpenParen => depth += 1,
+            Token::CloseBracket | Token::CloseParen => depth = depth.saturating_sub(1),
+            _ => {},
+        }
+        start.get_or_insert(span.start);
+        end = span.end;
+    }
+    if let Some(start) = start {
+        arguments.push(&source[start..end]);
+    } else if !arguments.is_empty() {
+        arguments.push("");
+    }
+    arguments
+}
+
+// Applies the GNU as conventions the rest of the assembler doesn't know about: `#` comments are
+// stripped and local `.Lname` labels become `__Lname`, since names can't start with a dot
+fn gnu_line(source: &str) -> String {
+    let mut text = String::with_capacity(source.len());
+    let mut end = 0;
+    for Lexeme { token, span, slice } in Lexer::new(source) {
+        match token {
+            Token::Hash => return text + &source[end..span.start],
+            Token::Directive(name) if name.starts_with(".L") => {
+                text += &source[end..span.start];
+                text += "__";
+                text += &slice[1..];
+                end = span.end;
+            },
+            _ => {},
+        }
+    }
+    text + &source[end..]
+}
+
+// Rewrites a line of the dialect as the native statements it holds, giving bare labels a colon and
+// splitting at terminators outside of bundles
+fn dialect_statements(source: &str, dialect: Dialect) -> Vec<String> {
+    let lexemes: Vec<_> = Lexer::new(source).collect();
+    let mut line = source.to_owned();
+    let mut shift = 0;
+    if let [Lexeme { token: Token::Ident(_), span, .. }, rest @ ..] = lexemes.as_slice() {
+        let labelled = matches!(rest.first(), Some(Lexeme { token: Token::Colon | Token::Assign, .. }));
+        if dialect.bare_labels && span.start == 0 && !labelled {
+            line.insert(span.end, ':');
+            shift = 1;
+        }
+    }
+    // A period isn't a token of its own, so it's read as an error
+    let terminator = match dialect.terminator {
+        Terminator::Newline => None,
+        Terminator::Semicolon => Some(";"),
+        Terminator::Period => Some("."),
+    };
+    let mut statements = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, lexeme) in lexemes.iter().enumerate() {
+        match lexeme.token {
+            Token::OpenBrace => depth += 1,
+            Token::CloseBrace => depth = depth.saturating_sub(1),
+            Token::Semicolon | Token::Error if depth == 0 && terminator == Some(lexeme.slice) => {
+                // The shift only moves lexemes after a bare label, which is always the first
+                let end = lexeme.span.start + if i > 0 { shift } else { 0 };
+                statements.push(line[start..end].to_owned());
+                start = end + 1;
+            },
+            _ => {},
+        }
+    }
+    statements.push(line[start..].to_owned());
+    statements.retain(|s| !s.trim().is_empty());
+    if statements.is_empty() {
+        statements.push(String::new());
+    }
+    statements
+}
+
+// Each character of a literal as it is written, with an escape sequence as one
+fn characters(literal: &str) -> Vec<&str> {
+    let mut characters = Vec::new();
+    let mut rest = literal;
+    while !rest.is_empty() {
+        let count = match rest.strip_prefix('\\').map(|escape| escape.chars().next()) {
+            Some(Some('x')) => 4,
+            Some(Some(_)) => 2,
+            _ => 1,
+        };
+        let end = rest.char_indices().nth(count).map_or(rest.len(), |(i, _)| i);
+        characters.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    characters
+}
+
+// SplitMix64's finalizer, which spreads nearby seeds far apart
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Replaces `\name` with its bound value and `\@` with the expansion number
+fn substitute(line: &str, bindings: &[(&str, String)], expansion: usize) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find('\\') {
+        text += &rest[..i];
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('@') {
+            text += &expansion.to_string();
+            rest = after;
+            continue;
+        }
+        let length = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        match bindings.iter().find(|(name, _)| *name == &rest[..length]) {
+            Some((_, value)) => text += value,
+            None => text += &format!("\\{}", &rest[..length]),
+        }
+        rest = &rest[length..];
+    }
+    text + rest
+}
+
+fn directive<'a>(first: &Option<Lexeme<'a, Token<'a>>>) -> Option<String> {
+    match first {
+        Some(Lexeme { token: Token::Directive(directive), .. }) => Some(directive.to_lowercase()),
+        _ => None,
+    }
+}
+
+// The name a statement starts with, which may be qualified by a namespace as in `math::mul16`,
+// along with where it ends
+fn leading_name(source: &str) -> Option<(String, usize)> {
+    let lexemes: Vec<_> = Lexer::new(source).take(4).collect();
+    match lexemes.as_slice() {
+        [Lexeme { token: Token::Ident(namespace), span: start, .. }, Lexeme { token: Token::Colon, span: first, .. }, Lexeme { token: Token::Colon, span: second, .. }, Lexeme { token: Token::Ident(name), span, .. }]
+            if start.end == first.start && first.end == second.start && second.end == span.start => Some((format!("{}::{}", namespace, name), span.end)),
+        [Lexeme { token: Token::Ident(name), span, .. }, ..] => Some((name.to_string(), span.end)),
+        _ => None,
+    }
+}
+
+impl Preprocessor {
+    pub fn new(file: &str) -> Self {
+        Self { file: file.to_owned(), ..Self::default() }
+    }
+
+    // Starts with macros already defined, like those shipped with the config
+    pub fn with_macros(file: &str, macros: &HashMap<String, Macro>) -> Self {
+        Self { file: file.to_owned(), macros: macros.clone(), ..Self::default() }
+    }
+
+    // Whether a `.macro` or `.irp` block is still being collected
+    pub fn is_defining(&self) -> bool { self.defining.is_some() }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) { self.dialect = dialect; }
+
+    pub fn set_seed(&mut self, seed: u64) { self.seed = seed; }
+
+    pub fn set_limits(&mut self, limits: Limits) { self.limits = limits; }
+
+    // Reports going past a limit along with the invocations that led there, once per line
+    fn exceed(&mut self, logger: &mut Logger, message: String) {
+        if self.exceeded {
+            return;
+        }
+        self.exceeded = true;
+        logger.log_error(message);
+        if !self.stack.is_empty() {
+            logger.log_note(format!("invocation stack, outermost first: {}", invocation_stack(&self.stack)));
+        }
+    }
+
+    pub fn into_macros(self) -> HashMap<String, Macro> { self.macros }
+
+    pub fn macros(&self) -> &HashMap<String, Macro> { &self.macros }
+
+    pub fn expanded(&self) -> &[Expanded] { &self.expanded }
+
+    // Moves on to the next file of a multi-file build, keeping the macros defined so far. A block
+    // left open in the previous file has already been reported by `finish`.
+    pub fn set_file(&mut self, file: &str) {
+        self.file = file.to_owned();
+        self.defining = None;
+        self.namespace = None;
+    }
+    
+    // Moves into a file named by `.include`, returning the file and namespace to go back to
+    pub fn enter_file(&mut self, file: &str) -> (String, Option<String>) {
+        (std::mem::replace(&mut self.file, file.to_owned()), self.namespace.take())
+    }
+    
+    // Goes back to the including file. A block left open in the included one has been reported.
+    pub fn leave_file(&mut self, (file, namespace): (String, Option<String>)) {
+        self.file = file;
+        self.namespace = namespace;
+        self.defining = None;
+    }
+    
+    // The macro a name refers to, preferring one in the current namespace
+    fn resolve(&self, name: &str) -> Option<String> {
+        let local = self.namespace.as_ref().filter(|_| !name.contains("::")).map(|namespace| format!("{}::{}", namespace, name));
+        local.into_iter().chain(Some(name.to_owned())).find(|name| self.macros.contains_key(name))
+    }
+
+    // Value of a predefined symbol, as source text
+    fn builtin(&self, name: &str) -> Option<String> {
+        match name {
+            "__FILE__" => Some(format!("\"{}\"", self.file)),
+            "__LINE__" => Some((self.line + 1).to_string()),
+            "__SECT__" => Some(format!("\"{}\"", self.section.as_deref().unwrap_or(DEFAULT_SECTION))),
+            "__VERSION__" => Some(format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+            _ => None,
+        }
+    }
+
+    // Value of a call to a builtin function, given the text of its arguments:
+    //
+    //     rand(seed, max)             below `max`, and the same for the same seeds
+    //     unique()                    a different number each time
+    //     concat(a, b, ...)           the arguments joined together
+    //     upper(a)                    the argument in upper case
+    //     strlen(a)                   the number of characters in the argument
+    //     substr(a, start, length)    `length` characters of the argument from `start`
+    //
+    // Strings give strings, but names and numbers give names, so `concat(entry_, \i)` can be a label
+    fn function(&mut self, name: &str, arguments: &str) -> Result<String, String> {
+        let lexemes: Vec<_> = Lexer::new(arguments).collect();
+        // Text of each argument, and whether it was a string
+        let texts: Vec<_> = match lexemes.is_empty() {
+            true => Vec::new(),
+            false => lexemes.split(|l| l.token == Token::Comma).map(|argument| match argument {
+                [Lexeme { token: Token::String(text), .. }] => (text.to_string(), true),
+                [first, .., last] => (arguments[first.span.start..last.span.end].to_owned(), false),
+                [only] => (only.slice.to_owned(), false),
+                [] => (String::new(), false),
+            }).collect(),
+        };
+        let integers: Vec<_> = lexemes.split(|l| l.token == Token::Comma).map(|argument| match argument {
+            [Lexeme { token: Token::Integer(value), .. }] => Some(*value as u64),
+            _ => None,
+        }).collect();
+        let quote = |text: String, quoted: bool| if quoted { format!("\"{}\"", text) } else { text };
+        match (name, texts.as_slice(), integers.as_slice()) {
+            ("rand", _, [Some(seed), Some(max)]) if *max > 0 => Ok((mix(self.seed ^ mix(*seed)) % max).to_string()),
+            ("rand", _, _) => Err("expected 'rand(seed, max)' with integers and a max above 0".to_owned()),
+            ("unique", [], _) => {
+                self.uniques += 1;
+                Ok((self.uniques - 1).to_string())
+            },
+            ("unique", _, _) => Err("'unique()' takes no arguments".to_owned()),
+            ("concat", [_, ..], _) => Ok(quote(texts.iter().map(|(text, _)| text.as_str()).collect(), texts.iter().any(|(_, quoted)| *quoted))),
+            ("concat", _, _) => Err("expected 'concat(a, b, ...)' with at least one argument".to_owned()),
+            ("upper", [(text, quoted)], _) => {
+                let text = characters(text).iter().map(|c| if c.starts_with('\\') { c.to_string() } else { c.to_uppercase() }).collect();
+                Ok(quote(text, *quoted))
+            },
+            ("upper", _, _) => Err("expected 'upper(a)'".to_owned()),
+            ("strlen", [(text, _)], _) => Ok(characters(text).len().to_string()),
+            ("strlen", _, _) => Err("expected 'strlen(a)'".to_owned()),
+            ("substr", [(text, quoted), ..], [_, Some(start), Some(length)]) => {
+                let characters = characters(text);
+                match characters.get(*start as usize..(*start).saturating_add(*length) as usize) {
+                    Some(characters) => Ok(quote(characters.concat(), *quoted)),
+                    None => Err(format!("'{}' has only {} characters", text, characters.len())),
+                }
+            },
+            _ => Err("expected 'substr(a, start, length)' with an integer start and length".to_owned()),
+        }
+    }
+
+    // Replaces predefined symbols and calls to builtin functions with their values. The arguments
+    // of a call are replaced first, so calls can be nested.
+    fn substitute_builtins(&mut self, logger: &mut Logger, source: &str) -> String {
+        let lexemes: Vec<_> = Lexer::new(source).collect();
+        let mut text = String::with_capacity(source.len());
+        let mut end = 0;
+        let mut i = 0;
+        while i < lexemes.len() {
+            let Lexeme { token, span, .. } = &lexemes[i];
+            i += 1;
+            let value = match (token, lexemes.get(i).map(|l| &l.token)) {
+                (Token::Ident(name), Some(Token::OpenParen)) if FUNCTIONS.contains(name) => {
+                    let mut depth = 0usize;
+                    let close = lexemes[i..].iter().position(|l| {
+                        match l.token {
+                            Token::OpenParen => depth += 1,
+                            Token::CloseParen => depth -= 1,
+                            _ => {},
+                        }
+                        depth == 0
+                    });
+                    let close = match close {
+                        Some(close) => i + close,
+                        None => {
+                            logger.log_error_at(span.clone(), format!("'{}(' is missing ')'", name));
+                            break;
+                        },
+                    };
+                    let arguments = self.substitute_builtins(logger, &source[lexemes[i].span.end..lexemes[close].span.start]);
+                    let value = self.function(name, &arguments);
+                    i = close + 1;
+                    match value {
+                        Ok(value) => (value, span.start..lexemes[close].span.end),
+                        Err(message) => {
+                            logger.log_error_at(span.start..lexemes[close].span.end, message);
+                            continue;
+                        },
+                    }
+                },
+                (Token::Ident(name), _) => match self.builtin(name) {
+                    Some(value) => (value, span.clone()),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            text += &source[end..value.1.start];
+            text += &value.0;
+            end = value.1.end;
+        }
+        text + &source[end..]
+    }
+
+    // Rewrites numbers in the active radix and character literals as decimal, so later stages don't
+    // need to know about either
+    fn substitute_numbers(&self, logger: &mut Logger, source: &str) -> String {
+        let mut text = String::with_capacity(source.len());
+        let mut end = 0;
+        for Lexeme { token, span, .. } in Lexer::with_radix(source, self.radix) {
+            let value = match token {
+                Token::Integer(value) if self.radix != 0 => value,
+                Token::Char(literal) => match unescape(literal).as_deref() {
+                    Ok([Unit::Char(c)]) => *c as usize,
+                    Ok([Unit::Byte(b)]) => *b as usize,
+                    Ok(_) => {
+                        logger.log_error_at(span, "character literals must hold a single character".to_owned());
+                        continue;
+                    },
+                    Err(message) => {
+                        logger.log_error_at(span, message.clone());
+                        continue;
+                    },
+                },
+                _ => continue,
+            };
+            text += &source[end..span.start];
+            text += &value.to_string();
+            end = span.end;
+        }
+        text + &source[end..]
+    }
+
+    fn set_radix(&mut self, logger: &mut Logger, source: &str, span: Span) {
+        let mut lexer = Lexer::new(&source[span.end..]);
+        match (lexer.next(), lexer.next()) {
+            (Some(Lexeme { token: Token::Integer(radix @ 2..=36), .. }), None) => self.radix = if radix == 10 { 0 } else { radix as u32 },
+            _ => logger.log_error_at(span, "expected a radix between 2 and 36 after '.radix'".to_owned()),
+        }
+    }
+
+    // `.syntax gnu` or `.syntax native`
+    fn set_syntax(&mut self, logger: &mut Logger, source: &str, span: Span) {
+        let mut lexer = Lexer::new(&source[span.end..]);
+        match (lexer.next(), lexer.next()) {
+            (Some(Lexeme { token: Token::Ident("gnu"), .. }), None) => self.gnu = true,
+            (Some(Lexeme { token: Token::Ident("native"), .. }), None) => self.gnu = false,
+            _ => logger.log_error_at(span, "expected 'gnu' or 'native' after '.syntax'".to_owned()),
+        }
+    }
+    
+    // `.namespace name`, or `.namespace` alone to go back to the global namespace
+    fn set_namespace(&mut self, logger: &mut Logger, source: &str, span: Span) {
+        let mut lexer = Lexer::new(&source[span.end..]);
+        match (lexer.next(), lexer.next()) {
+            (Some(Lexeme { token: Token::Ident(name), .. }), None) if !name.contains('.') => self.namespace = Some(name.to_owned()),
+            (None, None) => self.namespace = None,
+            _ => logger.log_error_at(span, "expected a namespace name after '.namespace'".to_owned()),
+        }
+    }
+    
+    fn define(&mut self, logger: &mut Logger, source: &str) {
+        let mut lexer = Lexer::new(source).skip(1);
+        let name = match lexer.next() {
+            Some(Lexeme { token: Token::Ident(name), .. }) => name.to_owned(),
+            Some(Lexeme { slice, span, .. }) => {
+                logger.log_error_at(span, format!("expected a macro name, but got '{}'", slice));
+                return;
+            },
+            None => {
+                logger.log_error("expected a macro name after '.macro'".to_owned());
+                return;
+            },
+        };
+        let name = match &self.namespace {
+            Some(namespace) => format!("{}::{}", namespace, name),
+            None => name,
+        };
+        let mut params = Vec::new();
+        let mut variadic = false;
+        let mut lexer = lexer.peekable();
+        while let Some(Lexeme { token, slice, span }) = lexer.next() {
+            match token {
+                Token::Comma => {},
+                _ if variadic => logger.log_error_at(span, "a ':vararg' parameter must be the last macro parameter".to_owned()),
+                Token::Ident(param) if params.iter().any(|p| p == param) => logger.log_error_at(span, format!("duplicate macro parameter '{}'", param)),
+                Token::Ident(param) => params.push(param.to_owned()),
+                Token::Colon => match lexer.next() {
+                    Some(Lexeme { token: Token::Ident("vararg"), .. }) if !params.is_empty() => variadic = true,
+                    _ => logger.log_error_at(span, "expected 'vararg' after ':' in macro parameters".to_owned()),
+                },
+                _ => logger.log_error_at(span, format!("expected a macro parameter, but got '{}'", slice)),
+            }
+        }
+        self.defining = Some(Block::Macro(name, Macro { params, variadic, body: Vec::new() }));
+    }
+
+    fn expand(&mut self, logger: &mut Logger, name: &str, source: &str, depth: usize, output: &mut Vec<String>) {
+        if depth >= self.limits.depth {
+            self.exceed(logger, format!("macro expansion deeper than {} levels, '{}' may invoke itself", self.limits.depth, name));
+            return;
+        }
+        let Macro { params, variadic, body } = self.macros[name].clone();
+        let args = arguments(source);
+        if variadic && args.len() + 1 < params.len() {
+            logger.log_error(format!("macro '{}' takes at least {} arguments, but got {}", name, params.len() - 1, args.len()));
+            return;
+        } else if !variadic && args.len() != params.len() {
+            logger.log_error(format!("macro '{}' takes {} arguments, but got {}", name, params.len(), args.len()));
+            return;
+        }
+        // Parameters shadow the built-in `\argc` and `\args`
+        let mut bindings = vec![("argc", args.len().to_string()), ("args", args.join(", "))];
+        for (i, param) in params.iter().enumerate() {
+            let value = if variadic && i + 1 == params.len() { args[i..].join(", ") } else { args[i].to_owned() };
+            bindings.insert(0, (param.as_str(), value));
+        }
+        let expansion = self.expansions;
+        self.expansions += 1;
+        let namespace = std::mem::replace(&mut self.namespace, name.rsplit_once("::").map(|(namespace, _)| namespace.to_owned()));
+        for line in body {
+            let line = substitute(&line, &bindings, expansion);
+            self.line(logger, &line, depth + 1, output);
+        }
+        self.namespace = namespace;
+    }
+
+    fn repeat(&mut self, logger: &mut Logger, param: &str, values: &[String], body: &[String], depth: usize, output: &mut Vec<String>) {
+        for value in values {
+            let expansion = self.expansions;
+            self.expansions += 1;
+            for line in body {
+                let line = substitute(line, &[(param, value.clone())], expansion);
+                self.line(logger, &line, depth + 1, output);
+            }
+        }
+    }
+
+    fn start_repeat(&mut self, logger: &mut Logger, source: &str, span: Span) {
+        let rest = &source[span.end..];
+        let (param, values) = rest.split_once(',').unwrap_or((rest, ""));
+        let param = param.trim();
+        if param.is_empty() || !param.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            logger.log_error_at(span, "expected a parameter name after '.irp'".to_owned());
+        }
+        let values = arguments(values).into_iter().filter(|v| !v.is_empty()).map(str::to_owned).collect();
+        self.defining = Some(Block::Repeat { param: param.to_owned(), values, body: Vec::new(), nested: 0 });
+    }
+
+    fn line(&mut self, logger: &mut Logger, source: &str, depth: usize, output: &mut Vec<String>) {
+        if self.exceeded {
+            return;
+        }
+        let directive = directive(&Lexer::new(source).next());
+        match (self.defining.take(), directive.as_deref()) {
+            (Some(Block::Macro(name, definition)), Some(".endm")) => match self.macros.entry(name) {
+                Entry::Occupied(entry) => logger.log_error(format!("macro '{}' is already defined", entry.key())),
+                Entry::Vacant(entry) => {
+                    entry.insert(definition);
+                },
+            },
+            (Some(Block::Macro(name, definition)), Some(".macro")) => {
+                logger.log_error(format!("macro definitions cannot be nested, '{}' is missing '.endm'", name));
+                self.defining = Some(Block::Macro(name, definition));
+            },
+            (Some(Block::Macro(name, mut definition)), _) => {
+                definition.body.push(source.to_owned());
+                self.defining = Some(Block::Macro(name, definition));
+            },
+            (Some(Block::Repeat { param, values, body, nested: 0 }), Some(".endr")) => self.repeat(logger, &param, &values, &body, depth, output),
+            (Some(Block::Repeat { param, values, mut body, mut nested }), directive) => {
+                match directive {
+                    Some(".irp") => nested += 1,
+                    Some(".endr") => nested -= 1,
+                    _ => {},
+                }
+                body.push(source.to_owned());
+                self.defining = Some(Block::Repeat { param, values, body, nested });
+            },
+            (None, _) => self.statement(logger, source, depth, output),
+        }
+    }
+
+    fn statement(&mut self, logger: &mut Logger, source: &str, depth: usize, output: &mut Vec<String>) {
+        let source = &self.substitute_builtins(logger, source);
+        let first = Lexer::new(source).next();
+        let directive = directive(&first);
+        if let (Some(Lexeme { span, .. }), Some(".radix")) = (&first, directive.as_deref()) {
+            return self.set_radix(logger, source, span.clone());
+        }
+        if let (Some(Lexeme { span, .. }), Some(".syntax")) = (&first, directive.as_deref()) {
+            return self.set_syntax(logger, source, span.clone());
+        }
+        if let (Some(Lexeme { span, .. }), Some(".namespace")) = (&first, directive.as_deref()) {
+            return self.set_namespace(logger, source, span.clone());
+        }
+        let source = &self.substitute_numbers(logger, source);
+        let mut lexer = Lexer::new(source);
+        let first = lexer.next();
+        if let (Some(".section"), Some(Lexeme { slice, token: Token::Directive(_) | Token::Ident(_), .. })) = (directive.as_deref(), lexer.next()) {
+            self.section = Some(slice.to_owned());
+        }
+        let name = leading_name(source);
+        let qualified = name.as_ref().is_some_and(|(name, _)| name.contains("::"));
+        let invoked = name.as_ref().and_then(|(name, end)| self.resolve(name).map(|name| (name, *end)));
+        match (first, directive.as_deref()) {
+            (_, Some(".macro")) => self.define(logger, source),
+            (Some(Lexeme { span, .. }), Some(".irp")) => self.start_repeat(logger, source, span),
+            (Some(Lexeme { span, .. }), Some(".endm")) => logger.log_error_at(span, "'.endm' without a matching '.macro'".to_owned()),
+            (Some(Lexeme { span, .. }), Some(".endr")) => logger.log_error_at(span, "'.endr' without a matching '.irp'".to_owned()),
+            // A label is a statement of its own, followed by whatever comes after it on the line
+            (Some(Lexeme { token: Token::Ident(_), .. }), _) if !qualified && matches!(Lexer::new(source).nth(1), Some(Lexeme { token: Token::Colon, .. })) => {
+                let colon = Lexer::new(source).nth(1).unwrap().span;
+                self.emit(logger, &source[..colon.end], depth, output);
+                if !source[colon.end..].trim().is_empty() {
+                    self.statement(logger, &source[colon.end..], depth, output);
+                }
+            },
+            (Some(Lexeme { token: Token::Ident(_), .. }), _) if invoked.is_some() => {
+                let (name, end) = invoked.unwrap();
+                self.expanded.push(Expanded::Invocation(depth, source.trim().to_owned()));
+                self.stack.push(source.trim().to_owned());
+                self.expand(logger, &name, &source[end..], depth, output);
+                self.stack.pop();
+            },
+            // Only macros can be qualified by a namespace
+            (Some(Lexeme { span, .. }), _) if qualified => {
+                let (name, end) = name.unwrap();
+                logger.log_error_at(span.start..end, format!("unknown macro '{}'", name));
+            },
+            _ => self.emit(logger, source, depth, output),
+        }
+    }
+
+    // Returns a statement, unless the line has already expanded to as many as it can
+    fn emit(&mut self, logger: &mut Logger, statement: &str, depth: usize, output: &mut Vec<String>) {
+        if output.len() >= self.limits.statements {
+            self.exceed(logger, format!("the line expands to more than {} statements", self.limits.statements));
+            return;
+        }
+        output.push(statement.to_owned());
+        self.expanded.push(Expanded::Statement(depth));
+    }
+
+    // Feeds one line of source, returning the statements it expands to
+    pub fn process(&mut self, line: usize, source: &str) -> LoggedResult<Vec<String>> {
+        let mut logger = Logger::new(None);
+        self.line = line;
+        self.expanded.clear();
+        self.exceeded = false;
+        let mut output = Vec::new();
+        let source = match self.gnu {
+            true => gnu_line(source),
+            false => source.to_owned(),
+        };
+        match self.dialect == Dialect::default() {
+            true => self.line(&mut logger, &source, 0, &mut output),
+            false => dialect_statements(&source, self.dialect).iter().for_each(|s| self.line(&mut logger, s, 0, &mut output)),
+        }
+        logger.into_result(|| output)
+    }
+
+    // Reports a definition left open at the end of the source
+    pub fn finish(&self) -> LoggedResult<()> {
+        let mut logger = Logger::new(None);
+        match &self.defining {
+            Some(Block::Macro(name, _)) => logger.log_error(format!("macro '{}' is missing '.endm'", name)),
+            Some(Block::Repeat { .. }) => logger.log_error("'.irp' is missing '.endr'".to_owned()),
+            None => {},
+        }
+        logger.into_result(|| ())
+    }
+}
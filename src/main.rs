@@ -1,29 +1,1245 @@
 use std::fs::File;
-use std::io::{Write, Read};
+use std::io::{BufWriter, Write, Read};
+use std::path::Path;
 
-mod config;
-mod lexer;
-mod log;
-mod parser;
+use assembler::{analysis, archive, ast, bindiff, cache, cfg, config, coverage, deadcode, debugger, devices, directives, disasm, doc, dump, emitter, emulator, executable, export, files, fmt, grammar, hexdump, import, incremental, isa, lint, macros, linker, log, lsp, manifest, map, new_parser, parser, patch, postprocess, profile, remote, repl, report, snapshot, vectors, viewer, wizard, xref};
 
-fn main() {
-    // read file to string
-    let mut file = File::open("x69-bravo.conf").unwrap();
+const DEFAULT_CONFIG: &str = "x69-bravo.conf";
+
+fn read_to_string<P: AsRef<Path>>(path: P) -> String {
+    let mut file = File::open(path).unwrap();
     let mut source = String::new();
     file.read_to_string(&mut source).unwrap();
-    let (assembler, logs) = config::create_assembler_from_config(&source).unwrap();
+    files::decode(source)
+}
+
+// Source of a config, which is built in for an `isa:<name>` path given by `--isa`
+fn read_config(path: &str) -> String {
+    match isa::source(path) {
+        Some(source) => source.to_owned(),
+        None => read_to_string(path),
+    }
+}
+
+// Path of the built in config `--isa` names, exiting with the names there are for an unknown one
+fn builtin_isa(name: Option<&String>) -> &'static str {
+    match isa::path(name.expect("expected an ISA name")) {
+        Ok(path) => path,
+        Err(message) => {
+            println!("{}", log::Log::new(log::LogLevel::Error, None, message));
+            std::process::exit(1);
+        },
+    }
+}
+
+// Loads the assembler for a config, preferring an up to date compiled form next to it
+fn load_config(path: &str) -> Option<parser::Assembler> {
+    load_configs(&[path])
+}
+
+// Loads a base config merged with extensions. Only a single config uses the compiled form.
+fn load_configs<S: AsRef<str>>(paths: &[S]) -> Option<parser::Assembler> {
+    load_configs_with(paths, false)
+}
+
+// With `strict`, permissive configs are rejected. The compiled form is never used then, since
+// loading it doesn't repeat the warnings the config had.
+fn load_configs_with<S: AsRef<str>>(paths: &[S], strict: bool) -> Option<parser::Assembler> {
+    let mut sources = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let source = read_config(path);
+        // Opcode tables are converted to config source first
+        match import::convert(path, source) {
+            Ok(source) => sources.push(source),
+            Err(message) => {
+                println!("{}", log::Log::new(log::LogLevel::Error, None, format!("{}: {}", path, message)));
+                return None;
+            },
+        }
+    }
+    // Built in configs have no compiled form next to them
+    if let ([path], [source], false) = (paths, sources.as_slice(), strict || paths.iter().any(|path| isa::source(path.as_ref()).is_some())) {
+        let compiled = Path::new(path.as_ref()).with_extension("x69c");
+        if let Ok(bytes) = std::fs::read(&compiled) {
+            if let Some(assembler) = cache::load(&bytes, Some(cache::hash_source(source))) {
+                return Some(assembler);
+            }
+        }
+    }
+    let configs: Vec<_> = paths.iter().zip(&sources).map(|(path, source)| (path.as_ref(), source.as_str())).collect();
+    let mut result = config::create_assembler_from_configs(&configs);
+    if strict {
+        result = result.strict();
+    }
+    let (assembler, logs) = result.unwrap();
+    logs.iter().for_each(|l| println!("{}", l));
+    assembler
+}
+
+// Time recorded by `.buildid`, taken from SOURCE_DATE_EPOCH when it is set so a build can be
+// reproduced, or None with `--no-timestamp`
+fn build_timestamp(enabled: bool) -> Option<u64> {
+    if !enabled {
+        return None;
+    }
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => Some(epoch.parse().expect("SOURCE_DATE_EPOCH must be a number of seconds")),
+        Err(_) => Some(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()),
+    }
+}
+
+// Seed mixed into `rand(seed, max)`, which is the one given by `--seed`, or else SOURCE_DATE_EPOCH
+// when it is set so a build can be reproduced, or else the time
+fn build_seed(seed: Option<u64>) -> u64 {
+    if let Some(seed) = seed {
+        return seed;
+    }
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.parse().expect("SOURCE_DATE_EPOCH must be a number of seconds"),
+        Err(_) => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64,
+    }
+}
+
+// Configs given with `--config`, or the default config if there are none
+fn configs_or_default(configs: Vec<&str>) -> Vec<&str> {
+    if configs.is_empty() {
+        vec![DEFAULT_CONFIG]
+    } else {
+        configs
+    }
+}
+
+fn compile_config(args: &[String]) {
+    let mut input = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ => input = Some(arg),
+        }
+    }
+    let input = input.map_or(DEFAULT_CONFIG, String::as_str);
+    let output = output.cloned().unwrap_or_else(|| Path::new(input).with_extension("x69c").to_string_lossy().into_owned());
+
+    let source = read_config(input);
+    let (assembler, logs) = config::Config::new(input, &source).load().unwrap();
     logs.iter().for_each(|l| println!("{}", l));
     if let Some(assembler) = assembler {
-        let path = std::env::args().nth(1).unwrap();
-        let mut file = File::open(path).unwrap();
-        let mut source = String::new();
-        file.read_to_string(&mut source).unwrap();
-        
-        let (code, logs) = assembler.assemble(&source).unwrap();
+        let mut file = File::create(output).unwrap();
+        file.write_all(&cache::compile(&assembler, cache::hash_source(&source))).unwrap();
+    }
+}
+
+// Assembles the files together, like the sources of a build
+fn assemble(args: &[String]) {
+    let mut paths = Vec::new();
+    let mut configs = Vec::new();
+    let mut no_config = false;
+    // Configs of other ISAs the sources can switch to with `.arch`
+    let mut archs = Vec::new();
+    // Overrides the operand order of the config
+    let mut src_first = None;
+    let mut timestamp = true;
+    let mut budgets = Vec::new();
+    let mut listing = None;
+    let mut symbols = Vec::new();
+    let mut format = manifest::Format::Binary;
+    let mut map = None;
+    let mut libraries = Vec::new();
+    let mut script = None;
+    let mut gc_sections = false;
+    let mut xref = None;
+    let mut debug_dump = None;
+    let mut source_map = None;
+    // Makes warnings about truncated values and misnumbered operands errors
+    let mut strict = false;
+    let mut seed = None;
+    // Makes the rewrites given by `.peephole` in the config, and reports them
+    let mut peephole = false;
+    // Removes jumps to the next statement and redirects jumps to jumps, and reports them
+    let mut thread_jumps = false;
+    // Fills the delay slots given by `.delay_slot` in the config, and reports how
+    let mut fill_delay_slots = false;
+    // Leaves labels not named by `.global` out of the map
+    let mut strip = false;
+    // Templates for the outputs of each region and each file, e.g. `out_{region}.bin`
+    let mut split_regions = None;
+    let mut split_files = None;
+    // Keeps the image of an assembly with errors to write the listing, map, and cross-reference
+    // from, and with `--partial-output` the code as well
+    let mut keep_going = false;
+    let mut partial_output = false;
+    // Lets `.include_cmd` run commands, which sources can't do on their own
+    let mut include_commands = false;
+    // Golden binary the image is compared with, and how many differing regions are shown
+    let mut verify = None;
+    let mut verify_limit = 10;
+    // JSON summary of the run, for build dashboards
+    let mut report = None;
+    // Steps run on the image before it is written
+    let mut post = Vec::new();
+    // How far macros and includes can expand before the assembly stops
+    let mut limits = macros::Limits::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            "--no-config" => no_config = true,
+            "--arch" => archs.push(args.next().expect("expected a config").as_str()),
+            "--listing" => listing = Some(args.next().expect("expected a listing file")),
+            "--format" => format = match args.next().map(String::as_str) {
+                Some("binary") => manifest::Format::Binary,
+                Some("hex") => manifest::Format::Hex,
+                Some("exe") => manifest::Format::Executable,
+                _ => panic!("expected an output format of 'binary', 'hex', or 'exe'"),
+            },
+            "--symbols" => symbols.extend(load_symbols(args.next().expect("expected a symbol file"))),
+            "--map" => map = Some(args.next().expect("expected a map file")),
+            "--source-map" => source_map = Some(args.next().expect("expected a source map file")),
+            "--strip" => strip = true,
+            "--split-regions" => split_regions = Some(args.next().expect("expected an output name with '{region}' in it")),
+            "--split-files" => split_files = Some(args.next().expect("expected an output name with '{file}' in it")),
+            "--strict" => strict = true,
+            "--seed" => seed = Some(args.next().and_then(|s| s.parse().ok()).expect("expected a seed")),
+            "--peephole" => peephole = true,
+            "--thread-jumps" => thread_jumps = true,
+            "--fill-delay-slots" => fill_delay_slots = true,
+            "--keep-going" => keep_going = true,
+            "--partial-output" => (keep_going, partial_output) = (true, true),
+            "--allow-include-cmd" => include_commands = true,
+            "--verify" => verify = Some(args.next().expect("expected a golden binary")),
+            "--verify-limit" => verify_limit = args.next().and_then(|n| n.parse().ok()).expect("expected a number of regions"),
+            "--report" => report = Some(args.next().expect("expected a report file")),
+            "--post" => post.push(postprocess::parse(args.next().expect("expected a post-processing step"), Path::new("")).unwrap_or_else(|message| panic!("{}", message))),
+            "--gc-sections" => gc_sections = true,
+            "--max-depth" => limits.depth = args.next().and_then(|n| n.parse().ok()).expect("expected a number of levels"),
+            "--max-expansion" => limits.statements = args.next().and_then(|n| n.parse().ok()).expect("expected a number of statements"),
+            "--debug-dump" => debug_dump = Some(args.next().expect("expected a directory to dump to")),
+            "--xref" => xref = Some(args.next().expect("expected a cross-reference file")),
+            "--script" => script = Some(args.next().expect("expected a linker script")),
+            "--library" => libraries.push(args.next().expect("expected an archive").to_owned()),
+            "--src-first" => src_first = Some(true),
+            "--dst-first" => src_first = Some(false),
+            "--no-timestamp" => timestamp = false,
+            "--budget" => {
+                let budget = args.next().expect("expected '<section>=<size>'");
+                let (section, size) = budget.split_once('=').expect("expected '<section>=<size>'");
+                budgets.push((section.to_owned(), parse_address(size)));
+            },
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        panic!("expected a file to assemble");
+    }
+    // Files written from the image, each by the emitter for its form
+    let mut outputs: Vec<(String, Box<dyn emitter::CodeEmitter>)> = vec![("a.out".to_owned(), emitter::emitter(format))];
+    if let Some(path) = listing {
+        outputs.push((path.clone(), Box::new(emitter::Listing)));
+    }
+    if let Some(path) = source_map {
+        outputs.push((path.clone(), Box::new(emitter::SourceMap)));
+    }
+    if no_config && !configs.is_empty() {
+        panic!("'--no-config' can't be given along with '--config' or '--isa'");
+    }
+    // Without a config, only data directives are assembled
+    let configs = if no_config { Vec::new() } else { configs_or_default(configs) };
+    let assembler = match no_config {
+        true => Some(parser::Assembler::raw()),
+        false => load_configs_with(&configs, strict),
+    };
+    if let Some(mut assembler) = assembler {
+        if let Some(config) = configs.first() {
+            assembler.arch = isa::arch_name(config);
+        }
+        for config in archs {
+            match load_configs_with(&[config], strict) {
+                Some(mut other) => {
+                    other.arch = isa::arch_name(config);
+                    assembler.archs.push(other);
+                },
+                None => return,
+            }
+        }
+        assembler.src_first = src_first.unwrap_or(assembler.src_first);
+        assembler.timestamp = build_timestamp(timestamp);
+        assembler.seed = build_seed(seed);
+        assembler.budgets = budgets;
+        assembler.gc_sections = gc_sections;
+        assembler.xref = xref.is_some();
+        assembler.peephole = peephole;
+        assembler.thread_jumps = thread_jumps;
+        assembler.fill_delay_slots = fill_delay_slots;
+        assembler.keep_going = keep_going;
+        assembler.include_commands = include_commands;
+        assembler.limits = limits;
+        if let Some(script) = script {
+            match apply_script(&mut assembler, Path::new(script)) {
+                Some(defines) => symbols.extend(defines),
+                None => return,
+            }
+        }
+        outputs.extend(split_outputs(&assembler, &paths, split_regions.map(String::as_str), split_files.map(String::as_str), format));
+        assembler.listing = verify.is_some() || outputs.iter().any(|(_, emitter)| emitter.needs_rows());
+        let mut files: Vec<_> = paths.iter().map(|path| (path.to_string(), read_to_string(path))).collect();
+        link_libraries(&mut files, &libraries);
+        let (mut image, passes) = assembler.assemble_files_with_passes(&files, &symbols);
+        if strict {
+            image = image.strict_keeping(keep_going);
+        }
+        let failed = image.is_error();
+        // Written even when assembly fails, since that's usually what the report is about
+        if let Some(dir) = debug_dump {
+            dump::write(Path::new(dir), &assembler, &files, &passes).unwrap();
+        }
+        let (mut image, logs) = image.unwrap();
+        logs.iter().for_each(|l| println!("{}", l));
+        if let (Some(image), false) = (&mut image, failed) {
+            post_process(&post, image);
+        }
+        // Files written, which the report names
+        let mut written = Vec::new();
+        if let Some(image) = &image {
+            image.rewrites.iter().for_each(|r| println!("{}", r));
+            if failed {
+                let written = if partial_output { "the code and reports written leave out the statements that failed" } else { "only the reports were written, and '--partial-output' writes the code as well" };
+                println!("{}", log::Log::new(log::LogLevel::Warning, None, format!("assembly failed; {}", written)));
+            }
+            for (path, emitter) in outputs.iter().filter(|(_, emitter)| !failed || partial_output || emitter.is_report()) {
+                std::fs::write(path, emitter.emit(image)).unwrap();
+                written.push(path.clone());
+            }
+            if let Some(path) = map {
+                std::fs::write(path, symbol_map(image, strip).render()).unwrap();
+                written.push(path.clone());
+            }
+            if let Some(path) = xref {
+                std::fs::write(path, xref::render(&image.xref)).unwrap();
+                written.push(path.clone());
+            }
+            for (bank, image) in image.banks.iter().filter(|_| !failed || partial_output) {
+                let path = format!("a.bank{}.out", bank);
+                std::fs::write(&path, &image.code).unwrap();
+                written.push(path);
+            }
+        }
+        if let Some(path) = report {
+            let inputs: Vec<_> = paths.iter().map(|path| path.to_string()).collect();
+            let configs: Vec<_> = configs.iter().map(|config| config.to_string()).collect();
+            let summary = report::Report { inputs: &inputs, configs: &configs, config_hash: assembler.config_hash, regions: &assembler.regions, outputs: written };
+            std::fs::write(path, report::render(&summary, image.as_ref(), &logs)).unwrap();
+        }
+        if let (Some(image), Some(golden), false) = (&image, verify, failed) {
+            if let Some(differences) = bindiff::verify(&std::fs::read(golden).unwrap(), image, verify_limit) {
+                print!("{}", differences);
+                println!("{}", log::Log::new(log::LogLevel::Error, None, format!("the image differs from '{}'", golden)));
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Prints the statement each line parses to, as JSON with --json
+fn syntax_tree(args: &[String]) {
+    let json = args.iter().any(|a| a == "--json");
+    let path = args.iter().find(|a| *a != "--json").expect("expected a file to parse");
+    let source = read_to_string(path);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ast::source_json(&source)).unwrap());
+        return;
+    }
+    for (line, text) in source.lines().enumerate() {
+        if let Some(directive) = ast::directive(text) {
+            println!("{}: {}", line + 1, directive);
+            continue;
+        }
+        let (statement, logs) = new_parser::parse_statement(text).unwrap();
+        match statement {
+            Some(statement) => println!("{}: {:?}", line + 1, statement),
+            None => logs.iter().for_each(|l| println!("{}: {}", line + 1, l)),
+        }
+    }
+}
+
+// Lays out memory as the linker script says, returning the symbols it defines
+fn apply_script(assembler: &mut parser::Assembler, path: &Path) -> Option<Vec<(String, usize)>> {
+    let (script, logs) = linker::Script::parse(&path.to_string_lossy(), &read_to_string(path)).unwrap();
+    logs.iter().for_each(|l| println!("{}", l));
+    let script = script?;
+    if !script.regions.is_empty() {
+        assembler.replace_regions(script.regions);
+    }
+    // Sections the script places leave out the defaults the config gives them
+    let placements = &script.placements;
+    assembler.placements.retain(|p| !placements.iter().any(|s| s.section == p.section));
+    assembler.placements.extend(script.placements);
+    Some(script.symbols)
+}
+
+// Appends the members of each archive that the files before it need, named `archive(member)`
+fn link_libraries(files: &mut Vec<(String, String)>, libraries: &[String]) {
+    for library in libraries {
+        let members = archive::read(&std::fs::read(library).unwrap()).unwrap_or_else(|message| panic!("{}: {}", library, message));
+        let sources: Vec<_> = files.iter().map(|(_, source)| source.as_str()).collect();
+        let needed: Vec<_> = archive::resolve(&members, &sources).into_iter()
+            .map(|member| (format!("{}({})", library, member.name), member.source.clone()))
+            .collect();
+        files.extend(needed);
+    }
+}
+
+// Bundles sources into an archive, or lists the members of one and the names they export
+fn archive(args: &[String]) {
+    match args {
+        [list, path] if list == "--list" => {
+            let members = archive::read(&std::fs::read(path).unwrap()).unwrap_or_else(|message| panic!("{}: {}", path, message));
+            for member in members {
+                println!("{}: {}", member.name, member.exports.join(", "));
+            }
+        },
+        [path, sources @ ..] if !sources.is_empty() => {
+            let members: Vec<_> = sources.iter().map(|source| {
+                let name = Path::new(source).file_name().unwrap().to_string_lossy();
+                archive::Member::new(&name, read_to_string(source))
+            }).collect();
+            std::fs::write(path, archive::write(&members)).unwrap();
+        },
+        _ => panic!("expected 'ar <archive> <sources>...' or 'ar --list <archive>'"),
+    }
+}
+
+// Labels and exports of an image, or only its exports when stripped
+fn symbol_map(image: &directives::Image, strip: bool) -> map::SymbolMap {
+    let mut symbols = map::SymbolMap::default();
+    for (name, address) in &image.exports {
+        symbols.insert(*address, name);
+    }
+    if !strip {
+        for (name, address) in image.labels.iter().filter(|(name, _)| !image.exports.iter().any(|(n, _)| n == name)) {
+            symbols.insert(*address, name);
+        }
+    }
+    symbols
+}
+
+// Runs the post-processing steps on an image that assembled, exiting when one fails
+fn post_process(steps: &[Box<dyn postprocess::PostProcessor>], image: &mut directives::Image) {
+    if let Err(message) = postprocess::apply(steps, image) {
+        println!("{}", log::Log::new(log::LogLevel::Error, None, message));
+        std::process::exit(1);
+    }
+}
+
+// Writes an image in the manifest's format, returning how many bytes the file holds
+fn write_image(path: &Path, format: manifest::Format, image: &directives::Image) -> usize {
+    let bytes = emitter::emitter(format).emit(image);
+    std::fs::write(path, &bytes).unwrap();
+    bytes.len()
+}
+
+// An output for each region of the config or script, and for each file, named by the templates.
+// They are Intel HEX placed at their addresses in that format, and binary otherwise.
+fn split_outputs<S: AsRef<str>>(assembler: &parser::Assembler, files: &[S], regions: Option<&str>, names: Option<&str>, format: manifest::Format) -> Vec<(String, Box<dyn emitter::CodeEmitter>)> {
+    let hex = format == manifest::Format::Hex;
+    let mut outputs: Vec<(String, Box<dyn emitter::CodeEmitter>)> = Vec::new();
+    if let Some(template) = regions {
+        if !template.contains("{region}") {
+            panic!("expected '{{region}}' in the output name '{}'", template);
+        }
+        if assembler.regions.is_empty() {
+            panic!("'--split-regions' needs regions given by '.region' in the config or by a linker script");
+        }
+        for region in &assembler.regions {
+            outputs.push((emitter::split_name(template, "{region}", &region.name), Box::new(emitter::RegionSplit { region: region.clone(), hex })));
+        }
+    }
+    if let Some(template) = names {
+        if !template.contains("{file}") {
+            panic!("expected '{{file}}' in the output name '{}'", template);
+        }
+        for file in files {
+            let file = file.as_ref().to_owned();
+            outputs.push((emitter::split_name(template, "{file}", &file), Box::new(emitter::FileSplit { file, hex })));
+        }
+    }
+    outputs
+}
+
+// Assembles and links the project described by a manifest, with paths relative to the manifest
+fn build(args: &[String]) {
+    let timestamp = !args.iter().any(|a| a == "--no-timestamp");
+    let gc_sections = args.iter().any(|a| a == "--gc-sections");
+    let strict = args.iter().any(|a| a == "--strict");
+    let peephole = args.iter().any(|a| a == "--peephole");
+    let thread_jumps = args.iter().any(|a| a == "--thread-jumps");
+    let fill_delay_slots = args.iter().any(|a| a == "--fill-delay-slots");
+    // Writes the code of a build with errors, leaving out the statements that failed
+    let partial_output = args.iter().any(|a| a == "--partial-output");
+    let include_commands = args.iter().any(|a| a == "--allow-include-cmd");
+    let seed = args.iter().position(|a| a == "--seed").map(|i| args.get(i + 1).and_then(|s| s.parse().ok()).expect("expected a seed"));
+    let split_regions = args.iter().position(|a| a == "--split-regions").map(|i| args.get(i + 1).expect("expected an output name with '{region}' in it").as_str());
+    let split_files = args.iter().position(|a| a == "--split-files").map(|i| args.get(i + 1).expect("expected an output name with '{file}' in it").as_str());
+    let report = args.iter().position(|a| a == "--report").map(|i| args.get(i + 1).expect("expected a report file").as_str());
+    let mut limits = macros::Limits::default();
+    if let Some(i) = args.iter().position(|a| a == "--max-depth") {
+        limits.depth = args.get(i + 1).and_then(|n| n.parse().ok()).expect("expected a number of levels");
+    }
+    if let Some(i) = args.iter().position(|a| a == "--max-expansion") {
+        limits.statements = args.get(i + 1).and_then(|n| n.parse().ok()).expect("expected a number of statements");
+    }
+    let valued = ["--seed", "--split-regions", "--split-files", "--report", "--max-depth", "--max-expansion"];
+    let path = Path::new(args.iter().enumerate().find(|(i, a)| !a.starts_with("--") && (*i == 0 || !valued.contains(&args[i - 1].as_str()))).map(|(_, a)| a).map_or(manifest::DEFAULT_MANIFEST, String::as_str));
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let (project, logs) = manifest::Manifest::parse(&path.to_string_lossy(), &read_to_string(path)).unwrap();
+    logs.iter().for_each(|l| println!("{}", l));
+    let project = match project {
+        Some(project) => project,
+        None => return,
+    };
+    let configs: Vec<_> = project.configs.iter().map(|config| dir.join(config).to_string_lossy().into_owned()).collect();
+    let mut assembler = match load_configs_with(&configs, strict) {
+        Some(assembler) => assembler,
+        None => return,
+    };
+    if !project.regions.is_empty() {
+        assembler.replace_regions(project.regions.clone());
+    }
+    let mut defines = project.defines.clone();
+    if let Some(script) = &project.script {
+        match apply_script(&mut assembler, &dir.join(script)) {
+            Some(symbols) => defines.extend(symbols),
+            None => return,
+        }
+    }
+    let ambles = project.preambles.iter().map(|a| (a, false)).chain(project.postambles.iter().map(|a| (a, true)));
+    for ((region, bytes), postamble) in ambles {
+        if let Err(message) = assembler.set_amble(region, postamble, bytes.clone()) {
+            panic!("{}: {}", path.display(), message);
+        }
+    }
+    assembler.timestamp = build_timestamp(timestamp);
+    assembler.seed = build_seed(seed);
+    assembler.budgets = project.budgets.clone();
+    assembler.gc_sections = gc_sections;
+    assembler.peephole = peephole;
+    assembler.thread_jumps = thread_jumps;
+    assembler.fill_delay_slots = fill_delay_slots;
+    assembler.keep_going = partial_output;
+    assembler.include_commands = include_commands;
+    assembler.limits = limits;
+    let mut files: Vec<_> = project.sources.iter().map(|source| {
+        let path = dir.join(source);
+        (path.to_string_lossy().into_owned(), read_to_string(&path))
+    }).collect();
+    let sources: Vec<_> = files.iter().map(|(path, _)| path.clone()).collect();
+    let splits = split_outputs(&assembler, &sources, split_regions, split_files, project.format);
+    assembler.listing = splits.iter().any(|(_, emitter)| emitter.needs_rows());
+    let libraries: Vec<_> = project.libraries.iter().map(|library| dir.join(library).to_string_lossy().into_owned()).collect();
+    link_libraries(&mut files, &libraries);
+    let mut image = assembler.assemble_files(&files, &defines);
+    if strict {
+        image = image.strict_keeping(partial_output);
+    }
+    let failed = image.is_error();
+    let (mut image, logs) = image.unwrap();
+    logs.iter().for_each(|l| println!("{}", l));
+    if let (Some(image), false) = (&mut image, failed) {
+        let steps: Vec<_> = project.post.iter().map(|step| postprocess::parse(step, dir).unwrap()).collect();
+        post_process(&steps, image);
+    }
+    // Files written, which the report names
+    let mut written = Vec::new();
+    if let Some(image) = &image {
+        image.rewrites.iter().for_each(|r| println!("{}", r));
+        if failed {
+            println!("{}", log::Log::new(log::LogLevel::Warning, None, "build failed; the code written leaves out the statements that failed".to_owned()));
+        }
+        let output = dir.join(&project.output);
+        let length = write_image(&output, project.format, image);
+        written.push(output.to_string_lossy().into_owned());
+        for (bank, image) in &image.banks {
+            let extension = output.extension().map_or(String::new(), |e| format!(".{}", e.to_string_lossy()));
+            let path = output.with_extension(format!("bank{}{}", bank, extension));
+            write_image(&path, project.format, image);
+            written.push(path.to_string_lossy().into_owned());
+        }
+        for (name, emitter) in &splits {
+            std::fs::write(dir.join(name), emitter.emit(image)).unwrap();
+            written.push(dir.join(name).to_string_lossy().into_owned());
+        }
+        println!("built {} ({} bytes)", output.display(), length);
+    }
+    if let Some(path) = report {
+        let summary = report::Report { inputs: &sources, configs: &configs, config_hash: assembler.config_hash, regions: &assembler.regions, outputs: written };
+        std::fs::write(path, report::render(&summary, image.as_ref(), &logs)).unwrap();
+    }
+}
+
+// Reassembles the file every time it changes, re-encoding only the statements that changed
+fn watch(args: &[String]) {
+    let tui = args.iter().any(|a| a == "--tui");
+    let path = args.iter().find(|a| *a != "--tui").expect("expected a file to watch");
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut assembler = match load_config(DEFAULT_CONFIG) {
+        Some(assembler) => assembler,
+        None => return,
+    };
+    let mut config_modified = modified(DEFAULT_CONFIG);
+    let mut cache = incremental::EncodingCache::new();
+    let mut last_modified = None;
+    loop {
+        // A changed config rebuilds the instruction tables and encodes every statement again. If
+        // it no longer loads, the previous tables are kept until it is fixed.
+        if modified(DEFAULT_CONFIG) != config_modified {
+            config_modified = modified(DEFAULT_CONFIG);
+            println!("reloading {}", DEFAULT_CONFIG);
+            if let Some(reloaded) = load_config(DEFAULT_CONFIG) {
+                assembler = reloaded;
+                cache = incremental::EncodingCache::new();
+                last_modified = None;
+            }
+        }
+        if modified(path) != last_modified {
+            last_modified = modified(path);
+            let source = read_to_string(path);
+            let (code, logs) = assembler.assemble_incremental(path, &source, &mut cache).unwrap();
+            if tui {
+                print!("{}{}", viewer::CLEAR, viewer::render(&source, &cache, viewer::terminal_width()));
+                std::io::stdout().flush().unwrap();
+            } else {
+                logs.iter().for_each(|l| println!("{}", l));
+            }
+            if let Some(code) = code {
+                let mut file = File::create("a.out").unwrap();
+                file.write_all(&code).unwrap();
+                let (hits, misses) = cache.stats();
+                println!("assembled {} bytes ({} statements reused, {} encoded)", code.len(), hits, misses);
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
+
+fn disassemble(args: &[String]) {
+    let binary = args.first().expect("expected a binary to disassemble");
+    let config = args.get(1).map_or(DEFAULT_CONFIG, String::as_str);
+    if let Some(assembler) = load_config(config) {
+        let bytes = std::fs::read(binary).unwrap();
+        disasm::Disassembler::new(&assembler).disassemble(&bytes).iter().for_each(|l| println!("{}", l));
+    }
+}
+
+fn parse_address(s: &str) -> usize {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).unwrap(),
+        None => s.parse().unwrap(),
+    }
+}
+
+fn emulate(args: &[String]) {
+    let mut binary = None;
+    let mut config = DEFAULT_CONFIG;
+    let mut max_steps = 1_000_000;
+    let mut breakpoints = Vec::new();
+    let mut trace = None;
+    let mut trace_format = emulator::TraceFormat::Json;
+    let mut mapped = Vec::new();
+    let mut coverage = None;
+    let mut profile = None;
+    let mut gdb = None;
+    let mut load_state = None;
+    let mut save_state = None;
+    let mut debug = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--steps" => max_steps = args.next().expect("expected a step count").parse().unwrap(),
+            "--break" => breakpoints.push(parse_address(args.next().expect("expected a breakpoint address"))),
+            "--trace" => trace = args.next(),
+            "--trace-format" => trace_format = match args.next().map(String::as_str) {
+                Some("json") => emulator::TraceFormat::Json,
+                Some("text") => emulator::TraceFormat::Text,
+                _ => panic!("expected 'json' or 'text' after '--trace-format'"),
+            },
+            "--device" => {
+                let device = args.next().expect("expected '<device>@<address>'");
+                let (name, address) = device.split_once('@').expect("expected '<device>@<address>'");
+                mapped.push((name.to_owned(), parse_address(address)));
+            },
+            "--coverage" => coverage = args.next(),
+            "--profile" => profile = args.next(),
+            "--load-state" => load_state = args.next(),
+            "--save-state" => save_state = args.next(),
+            "--debug" => debug = true,
+            "--gdb" => gdb = Some(args.next().expect("expected a port").parse::<u16>().unwrap()),
+            _ if binary.is_none() => binary = Some(arg),
+            _ => config = arg,
+        }
+    }
+    if let Some(mut assembler) = load_config(config) {
+        let program = std::fs::read(binary.expect("expected a binary to emulate")).unwrap();
+        let coverage = coverage.map(|path| {
+            let (source, image) = assemble_for_emulator(&mut assembler, path, &program, "coverage");
+            (source, image.listing)
+        });
+        // The program gives each address the mnemonic its cycles are looked up by
+        let profile = profile.map(|path| {
+            assembler.program = true;
+            assemble_for_emulator(&mut assembler, path, &program, "a profile")
+        });
+        // Executables are loaded at the addresses of their segments, and raw binaries at 0
+        let mut emulator = match executable::is_executable(&program) {
+            true => {
+                let executable = executable::read(&program).unwrap_or_else(|message| panic!("{}", message));
+                let mut emulator = emulator::Emulator::new(&assembler, &[]);
+                emulator.load(&executable).unwrap_or_else(|message| panic!("{}", message));
+                emulator
+            },
+            false => emulator::Emulator::new(&assembler, &program),
+        };
+        if coverage.is_some() {
+            emulator.executed = Some(Default::default());
+        }
+        if profile.is_some() {
+            emulator.counts = Some(Default::default());
+        }
+        emulator.breakpoints.extend(breakpoints);
+        for (name, address) in mapped {
+            let (size, device): (_, Box<dyn devices::Device>) = match name.as_str() {
+                "console" => (devices::Console::SIZE, Box::new(devices::Console::new(Box::new(std::io::stdout())))),
+                "timer" => (devices::Timer::SIZE, Box::new(devices::Timer::default())),
+                _ => panic!("unknown device '{}', expected 'console' or 'timer'", name),
+            };
+            emulator.map(address, size, device).unwrap_or_else(|message| panic!("{}", message));
+        }
+        if let Some(path) = load_state {
+            let bytes = std::fs::read(path).unwrap();
+            emulator.machine = snapshot::restore(&bytes).unwrap_or_else(|message| panic!("{}: {}", path, message));
+        }
+        if let Some(trace) = trace {
+            emulator.trace = Some(Box::new(BufWriter::new(File::create(trace).unwrap())));
+            emulator.trace_format = trace_format;
+        }
+        if debug {
+            let stdin = std::io::stdin();
+            debugger::run(&mut emulator, stdin.lock(), std::io::stdout(), max_steps).unwrap();
+        } else if let Some(port) = gdb {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap();
+            println!("waiting for a debugger on port {}", port);
+            let (stream, _) = listener.accept().unwrap();
+            remote::serve(&mut emulator, std::io::BufReader::new(stream.try_clone().unwrap()), stream, max_steps).unwrap();
+            println!("{}", emulator.machine);
+        } else {
+            match emulator.run(max_steps) {
+                Ok(stop) => println!("{}", stop),
+                Err(message) => println!("{}", log::Log::new(log::LogLevel::Error, None, message)),
+            }
+            println!("{}", emulator.machine);
+        }
+        // Saved wherever the run stopped, so it can be picked up from there with --load-state
+        if let Some(path) = save_state {
+            std::fs::write(path, snapshot::save(&emulator.machine)).unwrap();
+        }
+        if let (Some((source, rows)), Some(executed)) = (coverage, &emulator.executed) {
+            print!("{}", coverage::report(&source, &rows, executed));
+        }
+        if let (Some((source, image)), Some(counts)) = (profile, &emulator.counts) {
+            print!("{}", profile::report(&source, &image.listing, &image.program, counts, &assembler));
+        }
+    }
+}
+
+// The source is assembled again for its listing, which ties addresses to lines
+fn assemble_for_emulator(assembler: &mut parser::Assembler, path: &str, program: &[u8], purpose: &str) -> (String, directives::Image) {
+    assembler.listing = true;
+    let source = read_to_string(path);
+    let (image, logs) = assembler.assemble_image(path, &source).unwrap();
+    logs.iter().for_each(|l| println!("{}", l));
+    let image = image.unwrap_or_else(|| panic!("could not assemble '{}' for {}", path, purpose));
+    let assembled = match executable::is_executable(program) {
+        true => executable::write(&image),
+        false => image.code.clone(),
+    };
+    if assembled != program {
+        println!("{}", log::Log::new(log::LogLevel::Warning, None, format!("'{}' does not assemble to the binary being emulated, so {} may be misattributed", path, purpose)));
+    }
+    (source, image)
+}
+
+// Formats files in place, or with --check lists the files that are not formatted
+fn format(args: &[String]) {
+    let check = args.iter().any(|a| a == "--check");
+    let mut unformatted = false;
+    for path in args.iter().filter(|a| *a != "--check") {
+        let source = read_to_string(path);
+        let formatted = fmt::format_source(&source);
+        if formatted != source {
+            if check {
+                println!("{} is not formatted", path);
+                unformatted = true;
+            } else {
+                std::fs::write(path, formatted).unwrap();
+            }
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+}
+
+fn document(args: &[String]) {
+    let mut config = DEFAULT_CONFIG;
+    let mut output = None;
+    let mut format = doc::Format::Markdown;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--html" => format = doc::Format::Html,
+            "--markdown" => format = doc::Format::Markdown,
+            "-o" => output = args.next(),
+            _ => config = arg,
+        }
+    }
+    if let Some(assembler) = load_config(config) {
+        let text = doc::generate(&assembler, format);
+        match output {
+            Some(path) => std::fs::write(path, text).unwrap(),
+            None => print!("{}", text),
+        }
+    }
+}
+
+// Dumps the parsed ISA as JSON; `--json` is accepted for when other formats are added
+fn export_isa(args: &[String]) {
+    let mut config = DEFAULT_CONFIG;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => {},
+            "-o" => output = args.next(),
+            _ => config = arg,
+        }
+    }
+    if let Some(assembler) = load_config(config) {
+        let text = export::isa_json(&assembler);
+        match output {
+            Some(path) => std::fs::write(path, text).unwrap(),
+            None => print!("{}", text),
+        }
+    }
+}
+
+// Asks about an ISA and writes a starter config for it, `isa.conf` unless `-o` names another file
+fn init_isa(args: &[String]) {
+    let path = match args {
+        [flag, path] if flag == "-o" => path.as_str(),
+        [] => "isa.conf",
+        _ => panic!("expected 'x69asm init-isa [-o output]'"),
+    };
+    if Path::new(path).exists() {
+        println!("{}", log::Log::new(log::LogLevel::Error, None, format!("'{}' already exists; give another file with '-o'", path)));
+        return;
+    }
+    let stdin = std::io::stdin();
+    match wizard::run(stdin.lock(), std::io::stdout()) {
+        Ok(text) => {
+            std::fs::write(path, text).unwrap();
+            println!("wrote {}; assemble with '--config {}'", path, path);
+        },
+        Err(error) => println!("\n{}", log::Log::new(log::LogLevel::Error, None, error.to_string())),
+    }
+}
+
+// Reports config mistakes that still load; `--word n` also checks encodings are whole n-byte words
+fn lint_config(args: &[String]) {
+    let mut config = DEFAULT_CONFIG;
+    let mut word = 1;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--word" => word = args.next().expect("expected a word size in bytes").parse().unwrap(),
+            _ => config = arg,
+        }
+    }
+    let (_, logs) = lint::lint(config, &read_config(config), word).unwrap();
+    logs.iter().for_each(|l| println!("{}", l));
+    if logs.is_empty() {
+        println!("no problems found in {}", config);
+    }
+}
+
+fn analyze(args: &[String]) {
+    let mut config = DEFAULT_CONFIG;
+    let mut depth = 1;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" => depth = args.next().expect("expected a prefix depth").parse().unwrap(),
+            _ => config = arg,
+        }
+    }
+    if let Some(assembler) = load_config(config) {
+        print!("{}", analysis::analyze(&assembler, depth));
+    }
+}
+
+fn dead_code(args: &[String]) {
+    let mut configs = Vec::new();
+    let mut entries = Vec::new();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            "--entry" => entries.push(args.next().expect("expected an entry label")),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.expect("expected a file to check");
+    if let Some(assembler) = load_configs(&configs_or_default(configs)) {
+        let source = read_to_string(path);
+        let (image, logs) = assembler.assemble_image(path, &source).unwrap();
+        logs.iter().for_each(|l| println!("{}", l));
+        let image = match image {
+            Some(image) => image,
+            None => return,
+        };
+        // Labels given with --entry and those exported by `.global`, or the start of the image
+        let mut addresses: Vec<_> = image.exports.iter().map(|(_, address)| *address).collect();
+        for entry in entries {
+            let address = image.labels.iter().find(|(name, _)| name == entry).map(|(_, address)| *address);
+            addresses.push(address.unwrap_or_else(|| panic!("unknown entry label '{}'", entry)));
+        }
+        if addresses.is_empty() {
+            addresses.push(image.base);
+        }
+        print!("{}", deadcode::report(&deadcode::dead_code(&assembler, &image, &addresses), &image));
+    }
+}
+
+// Writes the basic blocks of the program and the edges between them, as DOT or with --json as JSON
+fn control_flow(args: &[String]) {
+    let mut configs = Vec::new();
+    let mut json = false;
+    let mut output = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            "--json" => json = true,
+            "-o" => output = args.next(),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.expect("expected a file to graph");
+    if let Some(mut assembler) = load_configs(&configs_or_default(configs)) {
+        assembler.program = true;
+        let source = read_to_string(path);
+        let (image, logs) = assembler.assemble_image(path, &source).unwrap();
         logs.iter().for_each(|l| println!("{}", l));
-        if let Some(code) = code {
-            let mut file = File::create("a.out").unwrap();
-            file.write_all(&code).unwrap();
+        let image = match image {
+            Some(image) => image,
+            None => return,
+        };
+        let text = |file: &str, line: usize| (file == path).then(|| source.lines().nth(line)).flatten().map(|text| text.trim().to_owned());
+        let blocks = cfg::blocks(&assembler, &image, &text);
+        let text = if json { cfg::json(&blocks) } else { cfg::dot(&blocks) };
+        match output {
+            Some(path) => std::fs::write(path, text).unwrap(),
+            None => print!("{}", text),
         }
     }
 }
+
+fn test_vectors(args: &[String]) {
+    let mut config = DEFAULT_CONFIG;
+    let mut count = 16;
+    let mut seed = 0x69;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--count" => count = args.next().expect("expected a count").parse().unwrap(),
+            "--seed" => seed = args.next().expect("expected a seed").parse().unwrap(),
+            "-o" => output = args.next(),
+            _ => config = arg,
+        }
+    }
+    if let Some(assembler) = load_config(config) {
+        let (vectors, failures) = vectors::generate(&assembler, count, seed);
+        for source in failures {
+            println!("{}", log::Log::new(log::LogLevel::Error, None, format!("generated statement failed to assemble: '{}'", source)));
+        }
+        let text = vectors::render(&vectors, seed);
+        match output {
+            Some(path) => std::fs::write(path, text).unwrap(),
+            None => print!("{}", text),
+        }
+    }
+}
+
+// Reports every pattern matching the given bytes (hex, optionally 0x prefixed)
+fn decode(args: &[String]) {
+    let mut configs = Vec::new();
+    let mut bytes = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            byte => {
+                let digits = byte.trim_start_matches("0x").trim_start_matches("0X");
+                bytes.push(u8::from_str_radix(digits, 16).unwrap_or_else(|_| panic!("'{}' is not a hex byte", byte)));
+            },
+        }
+    }
+    if let Some(assembler) = load_configs(&configs_or_default(configs)) {
+        let disassembler = disasm::Disassembler::new(&assembler);
+        let matches = disassembler.matches_at(&bytes, 0);
+        if matches.is_empty() {
+            println!("no pattern matches these bytes");
+        }
+        for m in matches {
+            print!("{}", disasm::explain(&m, &bytes));
+            if m.length < bytes.len() {
+                println!("  ({} trailing bytes not part of this instruction)", bytes.len() - m.length);
+            }
+        }
+    }
+}
+
+// Reads a map file, or a JSON object of symbol addresses for files ending in `.json`
+fn load_map(path: &str) -> map::SymbolMap {
+    let source = read_to_string(path);
+    let map = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("json") => map::SymbolMap::parse_json(&source),
+        _ => map::SymbolMap::parse(&source),
+    };
+    map.unwrap_or_else(|message| panic!("{}: {}", path, message))
+}
+
+// Symbols of an existing image, defined so new code can refer to its routines by name
+fn load_symbols(path: &str) -> Vec<(String, usize)> {
+    load_map(path).symbols().map(|(name, address)| (name.to_owned(), address)).collect()
+}
+
+// Assembles a source and writes the code of each section over an existing binary at its address
+fn patch(args: &[String]) {
+    let mut configs = Vec::new();
+    let mut output = "a.out";
+    let mut base = 0;
+    let mut symbols = Vec::new();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            "--symbols" => symbols.extend(load_symbols(args.next().expect("expected a symbol file"))),
+            "--base" => base = parse_address(args.next().expect("expected the address the binary starts at")),
+            "-o" => output = args.next().expect("expected an output file"),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        println!("expected a binary and a source to patch it with");
+        return;
+    }
+    if let Some(mut assembler) = load_configs(&configs_or_default(configs)) {
+        assembler.timestamp = None;
+        let mut binary = std::fs::read(files[0]).unwrap();
+        let source = read_to_string(files[1]);
+        let (image, logs) = assembler.assemble_files(&[(files[1].clone(), source)], &symbols).unwrap();
+        logs.iter().for_each(|l| println!("{}", l));
+        let image = match image {
+            Some(image) => image,
+            None => return,
+        };
+        let (patches, logs) = patch::apply(&mut binary, base, &image.segments).unwrap();
+        logs.iter().for_each(|l| println!("{}", l));
+        if let Some(patches) = patches {
+            print!("{}", patch::report(&patches));
+            std::fs::write(output, binary).unwrap();
+        }
+    }
+}
+
+fn binary_diff(args: &[String]) {
+    let mut configs = Vec::new();
+    let mut symbols = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            "--map" => symbols = Some(load_map(args.next().expect("expected a map file"))),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() != 2 {
+        println!("expected two binaries to compare");
+        return;
+    }
+    if let Some(assembler) = load_configs(&configs_or_default(configs)) {
+        let old = std::fs::read(files[0]).unwrap();
+        let new = std::fs::read(files[1]).unwrap();
+        print!("{}", bindiff::diff(&disasm::Disassembler::new(&assembler), &old, &new, symbols.as_ref()));
+    }
+}
+
+fn hex_dump(args: &[String]) {
+    let mut configs = Vec::new();
+    let mut symbols = None;
+    let mut binary = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => configs.push(args.next().expect("expected a config").as_str()),
+            "--isa" => configs.push(builtin_isa(args.next())),
+            "--map" => symbols = Some(load_map(args.next().expect("expected a map file"))),
+            _ => binary = Some(arg),
+        }
+    }
+    if let Some(assembler) = load_configs(&configs_or_default(configs)) {
+        let bytes = std::fs::read(binary.expect("expected a binary to dump")).unwrap();
+        print!("{}", hexdump::dump(&disasm::Disassembler::new(&assembler), &bytes, symbols.as_ref()));
+    }
+}
+
+fn editor_grammar(args: &[String]) {
+    let mut config = DEFAULT_CONFIG;
+    let mut format = grammar::Format::Vim;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vim" => format = grammar::Format::Vim,
+            "--textmate" => format = grammar::Format::TextMate,
+            "--json" => format = grammar::Format::Json,
+            "-o" => output = args.next(),
+            _ => config = arg,
+        }
+    }
+    if let Some(assembler) = load_config(config) {
+        let text = grammar::generate(&assembler, format);
+        match output {
+            Some(path) => std::fs::write(path, text).unwrap(),
+            None => print!("{}", text),
+        }
+    }
+}
+
+// Arguments with each `@file` replaced by the arguments in the file, so build systems can pass
+// lists longer than the command line allows. They are separated by whitespace, quoted with `"` or
+// `'` to hold it, and can name further response files.
+fn expand_response_files(args: impl IntoIterator<Item = String>, including: &mut Vec<String>) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        let path = match arg.strip_prefix('@') {
+            Some(path) if !path.is_empty() => path.to_owned(),
+            _ => {
+                expanded.push(arg);
+                continue;
+            },
+        };
+        if including.contains(&path) {
+            panic!("response file '{}' includes itself", path);
+        }
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("could not read response file '{}': {}", path, e));
+        including.push(path);
+        expanded.extend(expand_response_files(split_response_file(&text), including));
+        including.pop();
+    }
+    expanded
+}
+
+fn split_response_file(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = None;
+    let mut quote = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => arg.get_or_insert_with(String::new).extend(chars.next()),
+            (c, Some(q)) if c == q => quote = None,
+            (c, Some(_)) => arg.get_or_insert_with(String::new).push(c),
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            },
+            (c, None) if c.is_whitespace() => args.extend(arg.take()),
+            (c, None) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+// Takes `--tab-width n` out of the arguments of any command, setting how wide tabs are shown in
+// the lines under diagnostics
+fn take_tab_width(args: Vec<String>) -> Vec<String> {
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tab-width" => log::set_tab_width(args.next().and_then(|n| n.parse().ok()).expect("expected a tab width")),
+            _ => rest.push(arg),
+        }
+    }
+    rest
+}
+
+fn main() {
+    let args = take_tab_width(expand_response_files(std::env::args(), &mut Vec::new()));
+    match args.get(1).map(String::as_str) {
+        Some("compile-config") => compile_config(&args[2..]),
+        Some("emulate") => emulate(&args[2..]),
+        Some("grammar") => editor_grammar(&args[2..]),
+        Some("hexdump") => hex_dump(&args[2..]),
+        Some("bindiff") => binary_diff(&args[2..]),
+        Some("patch") => patch(&args[2..]),
+        Some("decode") => decode(&args[2..]),
+        Some("disasm") => disassemble(&args[2..]),
+        Some("testvectors") => test_vectors(&args[2..]),
+        Some("analyze") => analyze(&args[2..]),
+        Some("deadcode") => dead_code(&args[2..]),
+        Some("cfg") => control_flow(&args[2..]),
+        Some("doc") => document(&args[2..]),
+        Some("export-isa") => export_isa(&args[2..]),
+        Some("init-isa") => init_isa(&args[2..]),
+        Some("lint-config") => lint_config(&args[2..]),
+        Some("fmt") => format(&args[2..]),
+        Some("repl") => {
+            if let Some(assembler) = load_config(args.get(2).map_or(DEFAULT_CONFIG, String::as_str)) {
+                let stdin = std::io::stdin();
+                repl::Repl::new(&assembler).run(stdin.lock(), std::io::stdout()).unwrap();
+            }
+        },
+        Some("lsp") => {
+            let config = args.get(2).map_or(DEFAULT_CONFIG, String::as_str);
+            if let Some(assembler) = load_config(config) {
+                let stdin = std::io::stdin();
+                lsp::Server::with_config(assembler, config).run(stdin.lock(), std::io::stdout()).unwrap();
+            }
+        },
+        Some("watch") => watch(&args[2..]),
+        Some("build") => build(&args[2..]),
+        Some("ar") => archive(&args[2..]),
+        Some("ast") => syntax_tree(&args[2..]),
+        Some("tokens") => match args.get(2) {
+            Some(path) => print!("{}", dump::tokens(&read_to_string(path))),
+            None => panic!("expected a file to print the tokens of"),
+        },
+        Some(_) => assemble(&args[1..]),
+        None => println!("usage: x69asm <files>... [--config config | --isa name | --no-config]... [--arch config]... [--src-first | --dst-first] [--no-timestamp] [--budget section=size]... [--listing file] [--source-map file] [--xref file] [--debug-dump dir] [--map file] [--strip] [--symbols file]... [--library archive]... [--script file] [--gc-sections] [--strict] [--seed n] [--peephole] [--thread-jumps] [--fill-delay-slots] [--keep-going] [--partial-output] [--allow-include-cmd] [--max-depth n] [--max-expansion n] [--verify golden] [--verify-limit n] [--report file] [--post step]... [--split-regions name] [--split-files name] [--format binary | hex | exe] | x69asm build [manifest] [--no-timestamp] [--gc-sections] [--strict] [--seed n] [--peephole] [--thread-jumps] [--fill-delay-slots] [--partial-output] [--allow-include-cmd] [--max-depth n] [--max-expansion n] [--report file] [--split-regions name] [--split-files name] | x69asm ar <archive> <sources>... | x69asm tokens <file> | x69asm ast <file> [--json] | x69asm ar --list <archive> | x69asm watch [--tui] <file> | x69asm lsp [config] | x69asm repl [config] | x69asm fmt [--check] <files> | x69asm doc [config] [--html] [-o output] | x69asm export-isa [config] [--json] [-o output] | x69asm init-isa [-o output] | x69asm lint-config [config] [--word n] | x69asm analyze [config] [--depth n] | x69asm deadcode <file> [--entry label]... [--config config | --isa name]... | x69asm cfg <file> [--config config | --isa name]... [--json] [-o output] | x69asm testvectors [config] [--count n] [--seed n] [-o output] | x69asm disasm <binary> [config] | x69asm decode <bytes> [--config config | --isa name]... | x69asm bindiff <a> <b> [--map file] [--config config | --isa name]... | x69asm patch <binary> <file> [--base addr] [--symbols file]... [--config config | --isa name]... [-o output] | x69asm hexdump <binary> [--map file] [--config config | --isa name]... | x69asm grammar [config] [--vim | --textmate | --json] [-o output] | x69asm emulate <binary> [config] [--steps n] [--break addr] [--trace file] [--trace-format json | text] [--device console@addr | timer@addr]... [--coverage source] [--profile source] [--load-state file] [--save-state file] [--debug | --gdb port] | x69asm compile-config [config] [-o output] (any argument can be @file to read arguments from the file, any config can be isa:name for a built in one, and any command can be given --tab-width n to show tabs under diagnostics as spaces)"),
+    }
+}
@@ -0,0 +1,259 @@
+use crate::log::{Logger, LoggedResult, Origin};
+use crate::parser::Region;
+use crate::postprocess;
+use std::path::Path;
+
+pub const DEFAULT_MANIFEST: &str = "x69.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Format {
+    Binary,
+    // Intel HEX records
+    Hex,
+    // The bootloader's format, with the entry point and a segment table
+    Executable,
+}
+
+// A project read from an `x69.toml`, which is a small subset of TOML:
+//
+//     config = ["x69-bravo.conf", "peripherals.conf"]
+//     sources = ["main.s", "lib.s"]
+//     libraries = ["std.a"]
+//     script = "x69.ld"
+//     output = "game.bin"
+//     format = "binary"
+//     post = ["crc32 __crc", "run ./sign.sh"]
+//
+//     [defines]
+//     DEBUG = 1
+//
+//     [memory]
+//     ROM = [0x0000, 0x7fff]
+//
+//     [preamble]
+//     ROM = [0x55, 0xaa]
+//
+//     [postamble]
+//     ROM = [0x58, 0x36, 0x39]
+//
+//     [budgets]
+//     .text = 0x4000
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    // A base config followed by any extensions merged into it
+    pub configs: Vec<String>,
+    // Assembled in order as one program, so later files can use labels and macros of earlier ones
+    pub sources: Vec<String>,
+    // Archives made by `x69asm ar`, whose members are assembled after the sources when they need them
+    pub libraries: Vec<String>,
+    // Linker script laying out memory, which replaces the regions of the config and `[memory]`
+    pub script: Option<String>,
+    pub output: String,
+    pub format: Format,
+    // Symbols defined before the first line of the first source
+    pub defines: Vec<(String, usize)>,
+    // Replaces the regions of the config when given
+    pub regions: Vec<Region>,
+    // Bytes placed at the start and at the end of regions by name, over those the config gives
+    pub preambles: Vec<(String, Vec<u8>)>,
+    pub postambles: Vec<(String, Vec<u8>)>,
+    // Largest size allowed for each named section
+    pub budgets: Vec<(String, usize)>,
+    // Steps run on the image before it is written, in order, as `postprocess::parse` reads them
+    pub post: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    String(String),
+    Integer(usize),
+    Array(Vec<Value>),
+}
+
+fn integer(text: &str) -> Option<usize> {
+    let text = text.replace('_', "");
+    match text.get(..2) {
+        Some("0x" | "0X") => usize::from_str_radix(&text[2..], 16).ok(),
+        Some("0b" | "0B") => usize::from_str_radix(&text[2..], 2).ok(),
+        Some("0o" | "0O") => usize::from_str_radix(&text[2..], 8).ok(),
+        _ => text.parse().ok(),
+    }
+}
+
+// Splits off the value at the start of the text, returning it with the rest of the text
+fn value(text: &str) -> Result<(Value, &str), String> {
+    let text = text.trim_start();
+    if let Some(rest) = text.strip_prefix('"') {
+        let end = rest.find('"').ok_or_else(|| "unterminated string".to_owned())?;
+        return Ok((Value::String(rest[..end].to_owned()), &rest[end + 1..]));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (element, after) = value(rest)?;
+            values.push(element);
+            rest = after.trim_start();
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None if rest.starts_with(']') => {},
+                None => return Err("expected ',' or ']' in array".to_owned()),
+            }
+        }
+    }
+    let end = text.find(|c: char| c == ',' || c == ']' || c.is_whitespace()).unwrap_or(text.len());
+    match integer(&text[..end]) {
+        Some(int) => Ok((Value::Integer(int), &text[end..])),
+        None => Err(format!("expected a string, integer, or array, but got '{}'", &text[..end])),
+    }
+}
+
+// Strips a `#` comment, ignoring `#` inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+fn string(value: Value, key: &str) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(format!("'{}' must be a string", key)),
+    }
+}
+
+impl Manifest {
+    pub fn parse(origin: &str, source: &str) -> LoggedResult<Manifest> {
+        let mut logger = Logger::new(None);
+        let mut table = String::new();
+        let mut manifest = Manifest { configs: Vec::new(), sources: Vec::new(), libraries: Vec::new(), script: None, output: "a.out".to_owned(), format: Format::Binary, defines: Vec::new(), regions: Vec::new(), preambles: Vec::new(), postambles: Vec::new(), budgets: Vec::new(), post: Vec::new() };
+        
+        for (line, text) in source.lines().enumerate() {
+            logger.origin = Some(Origin::new(origin, line));
+            let text = strip_comment(text).trim();
+            if text.is_empty() {
+                continue;
+            }
+            if let Some(name) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+                table = name.trim().to_owned();
+                if !matches!(table.as_str(), "defines" | "memory" | "preamble" | "postamble" | "budgets") {
+                    logger.log_error(format!("unknown table '[{}]'", table));
+                }
+                continue;
+            }
+            let (key, rest) = match text.split_once('=') {
+                Some((key, rest)) => (key.trim(), rest),
+                None => {
+                    logger.log_error("expected 'key = value'".to_owned());
+                    continue;
+                },
+            };
+            let value = match value(rest) {
+                Ok((value, rest)) if rest.trim().is_empty() => value,
+                Ok((_, rest)) => {
+                    logger.log_error(format!("unexpected '{}' after the value of '{}'", rest.trim(), key));
+                    continue;
+                },
+                Err(message) => {
+                    logger.log_error(message);
+                    continue;
+                },
+            };
+            if let Err(message) = manifest.set(&table, key, value) {
+                logger.log_error(message);
+            }
+        }
+        
+        logger.origin = None;
+        if manifest.configs.is_empty() {
+            logger.log_error(format!("{}: 'config' is not given", origin));
+        }
+        if manifest.sources.is_empty() {
+            logger.log_error(format!("{}: 'sources' is not given", origin));
+        }
+        logger.into_result(|| manifest)
+    }
+    
+    fn set(&mut self, table: &str, key: &str, value: Value) -> Result<(), String> {
+        match (table, key, value) {
+            ("", "config", Value::Array(values)) => {
+                self.configs = values.into_iter().map(|v| string(v, key)).collect::<Result<_, _>>()?;
+            },
+            ("", "config", value) => self.configs = vec![string(value, key)?],
+            ("", "output", value) => self.output = string(value, key)?,
+            ("", "script", value) => self.script = Some(string(value, key)?),
+            ("", "format", value) => self.format = match string(value, key)?.as_str() {
+                "binary" => Format::Binary,
+                "hex" => Format::Hex,
+                "exe" => Format::Executable,
+                format => return Err(format!("unknown output format '{}', expected 'binary', 'hex', or 'exe'", format)),
+            },
+            ("", "sources", Value::Array(values)) => {
+                self.sources = values.into_iter().map(|v| string(v, key)).collect::<Result<_, _>>()?;
+            },
+            ("", "sources", _) => return Err("'sources' must be an array of strings".to_owned()),
+            ("", "libraries", Value::Array(values)) => {
+                self.libraries = values.into_iter().map(|v| string(v, key)).collect::<Result<_, _>>()?;
+            },
+            ("", "libraries", _) => return Err("'libraries' must be an array of strings".to_owned()),
+            ("", "post", Value::Array(values)) => {
+                self.post = values.into_iter().map(|v| string(v, key)).collect::<Result<_, _>>()?;
+                for step in &self.post {
+                    postprocess::parse(step, Path::new(""))?;
+                }
+            },
+            ("", "post", _) => return Err("'post' must be an array of strings".to_owned()),
+            ("", key, _) => return Err(format!("unknown key '{}'", key)),
+            ("defines", name, Value::Integer(value)) => {
+                if self.defines.iter().any(|(n, _)| n == name) {
+                    return Err(format!("'{}' is already defined", name));
+                }
+                self.defines.push((name.to_owned(), value));
+            },
+            ("defines", name, _) => return Err(format!("define '{}' must be an integer", name)),
+            ("memory", name, Value::Array(bounds)) => match bounds.as_slice() {
+                [Value::Integer(start), Value::Integer(end)] => {
+                    let (start, end) = (*start, *end);
+                    if end < start {
+                        return Err(format!("region '{}' ends before it starts", name));
+                    }
+                    if let Some(other) = self.regions.iter().find(|r| r.name == name || r.start <= end && start <= r.end) {
+                        return Err(format!("region '{}' overlaps region '{}'", name, other.name));
+                    }
+                    self.regions.push(Region::new(name, start, end));
+                },
+                _ => return Err(format!("region '{}' must be '[start, end]'", name)),
+            },
+            ("memory", name, _) => return Err(format!("region '{}' must be '[start, end]'", name)),
+            (table @ ("preamble" | "postamble"), name, Value::Array(values)) => {
+                let bytes = values.into_iter().map(|v| match v {
+                    Value::Integer(byte @ 0..=255) => Ok(byte as u8),
+                    _ => Err(format!("the {} of region '{}' must be an array of bytes", table, name)),
+                }).collect::<Result<_, _>>()?;
+                let ambles = match table {
+                    "preamble" => &mut self.preambles,
+                    _ => &mut self.postambles,
+                };
+                if ambles.iter().any(|(n, _)| n == name) {
+                    return Err(format!("the {} of region '{}' is already given", table, name));
+                }
+                ambles.push((name.to_owned(), bytes));
+            },
+            (table @ ("preamble" | "postamble"), name, _) => return Err(format!("the {} of region '{}' must be an array of bytes", table, name)),
+            ("budgets", section, Value::Integer(size)) => self.budgets.push((section.to_owned(), size)),
+            ("budgets", section, _) => return Err(format!("budget of '{}' must be an integer", section)),
+            _ => {},
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Symbol addresses read from a map file, one `<hex address> <name>` pair per line
+#[derive(Debug, Default)]
+pub struct SymbolMap {
+    symbols: BTreeMap<usize, Vec<String>>,
+}
+
+impl SymbolMap {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for (line, text) in source.lines().enumerate() {
+            let text = text.split("//").next().unwrap_or_default().trim();
+            if text.is_empty() {
+                continue;
+            }
+            let mut words = text.split_whitespace();
+            let (address, name) = match (words.next(), words.next(), words.next()) {
+                (Some(address), Some(name), None) => (address, name),
+                _ => return Err(format!("line {}: expected '<address> <name>'", line + 1)),
+            };
+            let digits = address.trim_start_matches("0x").trim_start_matches("0X");
+            let address = usize::from_str_radix(digits, 16).map_err(|_| format!("line {}: '{}' is not a hex address", line + 1, address))?;
+            map.symbols.entry(address).or_default().push(name.to_owned());
+        }
+        Ok(map)
+    }
+    
+    // Symbol addresses from a JSON object mapping names to addresses, which are numbers or hex
+    // strings such as `"0x0120"`
+    pub fn parse_json(source: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(source).map_err(|e| e.to_string())?;
+        let object = value.as_object().ok_or("expected an object mapping symbol names to addresses")?;
+        let mut map = Self::default();
+        for (name, address) in object {
+            let address = match address {
+                Value::Number(n) => n.as_u64().map(|n| n as usize),
+                Value::String(s) => s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).and_then(|digits| usize::from_str_radix(digits, 16).ok()),
+                _ => None,
+            };
+            map.insert(address.ok_or_else(|| format!("'{}' does not have an address", name))?, name);
+        }
+        Ok(map)
+    }
+    
+    pub fn insert(&mut self, address: usize, name: &str) {
+        self.symbols.entry(address).or_default().push(name.to_owned());
+    }
+    
+    // Every symbol with its address, in address order
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.symbols.iter().flat_map(|(address, names)| names.iter().map(move |name| (name.as_str(), *address)))
+    }
+    
+    // The map file `parse` reads back
+    pub fn render(&self) -> String {
+        self.symbols().map(|(name, address)| format!("{:#06x} {}\n", address, name)).collect()
+    }
+    
+    // Names of the symbols defined exactly at the address
+    pub fn at(&self, address: usize) -> &[String] {
+        self.symbols.get(&address).map_or(&[], Vec::as_slice)
+    }
+    
+    // The closest symbol at or before the address, rendered as `name` or `name+0x4`
+    pub fn nearest(&self, address: usize) -> Option<String> {
+        let (start, names) = self.symbols.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => names[0].clone(),
+            offset => format!("{}+0x{:X}", names[0], offset),
+        })
+    }
+}
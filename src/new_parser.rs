@@ -0,0 +1,926 @@
+use crate::lexer::{Lexer, Lexeme, Operator, Span, Token};
+use crate::log::{LogLevel, Logger, LoggedResult};
+use crate::semantics::{BinaryOp, UnaryOp};
+use std::iter::Peekable;
+
+type Lexemes<'a> = Peekable<Lexer<'a, Token<'a>>>;
+
+// Errors carry the span they are about, or None if the line ended unexpectedly
+type Error = (String, Option<Span>);
+
+// An AST node along with the source text and byte range it was parsed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<'a, T> {
+    pub node: T,
+    pub slice: &'a str,
+    pub span: Span,
+}
+
+impl<'a, T> Spanned<'a, T> {
+    fn new(node: T, source: &'a str, span: Span) -> Self {
+        Self { node, slice: &source[span.clone()], span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr<'a> {
+    Integer(usize),
+    Symbol(&'a str),
+    // `bank(label)` and `offset(label)`, the bank a label was placed in and its offset within it
+    Bank(&'a str),
+    Offset(&'a str),
+    Unary(UnaryOp, Box<Expr<'a>>),
+    Binary(BinaryOp, Box<Expr<'a>>, Box<Expr<'a>>),
+    Call(Function, Vec<Expr<'a>>),
+}
+
+// The expression as it could be written, with operands that are operations themselves in
+// parentheses, e.g. `(end - start) * 4`
+impl std::fmt::Display for Expr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let operand = |expr: &Expr| match expr {
+            Expr::Binary(..) => format!("({})", expr),
+            expr => expr.to_string(),
+        };
+        match self {
+            Expr::Integer(int) if *int > 0xFFFF => write!(f, "{:#x}", int),
+            Expr::Integer(int) => write!(f, "{}", int),
+            Expr::Symbol(symbol) => write!(f, "{}", symbol),
+            Expr::Bank(label) => write!(f, "bank({})", label),
+            Expr::Offset(label) => write!(f, "offset({})", label),
+            Expr::Unary(op, value) => write!(f, "{}{}", op.symbol(), operand(value)),
+            Expr::Binary(op, lhs, rhs) => write!(f, "{} {} {}", operand(lhs), op.symbol(), operand(rhs)),
+            Expr::Call(function, arguments) => {
+                let arguments: Vec<_> = arguments.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", function.name(), arguments.join(", "))
+            },
+        }
+    }
+}
+
+// Builtin functions of constant expressions, e.g. `align_up(end - start, 16)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Min,
+    Max,
+    // Of the value as a signed integer
+    Abs,
+    // Rounded down, so `log2(x) + 1` is the bits needed to hold x
+    Log2,
+    AlignUp,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "abs" => Self::Abs,
+            "log2" => Self::Log2,
+            "align_up" => Self::AlignUp,
+            _ => return None,
+        })
+    }
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Abs => "abs",
+            Self::Log2 => "log2",
+            Self::AlignUp => "align_up",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OperandKind<'a> {
+    Register(usize),
+    Immediate(Expr<'a>),
+    // `=value`, placed in a literal pool and encoded as the offset to it
+    Literal(Expr<'a>),
+    // Commas are part of an instruction's syntax, so they are kept in the operand list
+    Comma,
+    // Punctuation such as `(` or `#` in addressing modes, one of `PUNCTUATION`
+    Punct(&'static str),
+}
+
+pub type Operand<'a> = Spanned<'a, OperandKind<'a>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement<'a> {
+    Empty,
+    Instruction { mnemonic: Spanned<'a, &'a str>, operands: Vec<Operand<'a>> },
+    // `{ op1 ; op2 }`, instructions issued together in one packet
+    Bundle(Vec<Statement<'a>>, Span),
+    // `name:` followed by the statement on the rest of the line, which may be empty
+    Label(Spanned<'a, &'a str>, Box<Statement<'a>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternItem<'a> {
+    Register(usize),
+    // Immediate number and width in bits
+    Immediate(usize, usize),
+    Comma,
+    // Punctuation the operands must be written with, such as the parentheses in `ld r0, (r1)`
+    Punct(&'static str),
+    // A word the operands must be written with, such as `port` in `ld r0, port i0:4`
+    Keyword(&'a str),
+}
+
+// Symbols that can separate operands besides commas. `=` starts a literal, `;` and `{}` delimit
+// bundles and `[,` starts an optional operand in patterns, so they can't.
+pub const PUNCTUATION: [&str; 26] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<<", ">>", "<", ">", "<=", ">=", "==", "!=", "&&", "||",
+    "(", ")", "[", "]", "#", ":",
+];
+
+// The punctuation a lexeme is, if any
+pub fn punctuation<'a>(lexeme: &Lexeme<'a, Token<'a>>) -> Option<&'static str> {
+    match lexeme.token {
+        Token::Operator(_) | Token::OpenParen | Token::CloseParen | Token::OpenBracket | Token::CloseBracket | Token::Hash | Token::Colon => {
+            PUNCTUATION.iter().find(|p| **p == lexeme.slice).copied()
+        },
+        _ => None,
+    }
+}
+
+// Appends an operand or punctuation to a rendered syntax, keeping `(r0)` and `#i0:8` together
+pub fn push_item(text: &mut String, item: &str) {
+    if !(matches!(item, "," | ")" | "]") || text.ends_with(['(', '[', '#'])) {
+        text.push(' ');
+    }
+    *text += item;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nibble {
+    Integer(usize),
+    Register(usize),
+    Immediate(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodegenItem {
+    Integer(usize),
+    Register(usize),
+    // Immediate number, and the width to emit it in when it is narrower than in the pattern
+    Immediate(usize, Option<usize>),
+    Bracket(Nibble, Nibble),
+}
+
+// `i0 < 16` in an `if` of the codegen, comparing an immediate as an unsigned value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub immediate: usize,
+    pub op: Operator,
+    pub value: usize,
+}
+
+impl Condition {
+    pub fn holds(&self, value: usize) -> bool {
+        match self.op {
+            Operator::Lt => value < self.value,
+            Operator::Le => value <= self.value,
+            Operator::Gt => value > self.value,
+            Operator::Ge => value >= self.value,
+            Operator::Eq => value == self.value,
+            _ => value != self.value,
+        }
+    }
+
+    // The condition under which an `else` is taken
+    pub fn negate(self) -> Self {
+        let op = match self.op {
+            Operator::Lt => Operator::Ge,
+            Operator::Le => Operator::Gt,
+            Operator::Gt => Operator::Le,
+            Operator::Ge => Operator::Lt,
+            Operator::Eq => Operator::Ne,
+            _ => Operator::Eq,
+        };
+        Self { op, ..self }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "i{} {} {}", self.immediate, self.op, self.value)
+    }
+}
+
+// One line of the config, e.g. `add r0, i0:4 -> 0 0 [i0 | r0] { r0 = r0 + i0 }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition<'a> {
+    // The prefix a form for a mode is declared after, as `wide` is in `wide ld r0, i0:16 -> ...`
+    pub prefix: Option<Spanned<'a, &'a str>>,
+    pub mnemonic: Spanned<'a, &'a str>,
+    pub pattern: Vec<Spanned<'a, PatternItem<'a>>>,
+    pub codegen: Vec<Spanned<'a, CodegenItem>>,
+    pub semantics: Option<Spanned<'a, &'a str>>,
+    // Value of the last immediate when it is left out, given as `shl r0 [, i0:4 = 1]`
+    pub default: Option<Spanned<'a, usize>>,
+    // Conditions that must all hold for this codegen to be used, from the `if` branch it is in
+    pub guards: Vec<Condition>,
+}
+
+impl Definition<'_> {
+    // The name the instruction is kept under, which is the mnemonic after the prefix of its mode
+    pub fn name(&self) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{} {}", prefix.node, self.mnemonic.node).to_lowercase(),
+            None => self.mnemonic.node.to_lowercase(),
+        }
+    }
+    
+    // The pattern in canonical notation, e.g. `add r0, i0:4`
+    pub fn syntax(&self) -> String {
+        let mut text = self.name();
+        for item in &self.pattern {
+            match item.node {
+                PatternItem::Comma => push_item(&mut text, ","),
+                PatternItem::Immediate(im, width) => push_item(&mut text, &format!("i{}:{}", im, width)),
+                PatternItem::Register(r) => push_item(&mut text, &format!("r{}", r)),
+                PatternItem::Punct(p) => push_item(&mut text, p),
+                PatternItem::Keyword(word) => push_item(&mut text, &word.to_lowercase()),
+            }
+        }
+        text
+    }
+    
+    // The same definition read without a prefix, when what looked like the prefix and the mnemonic
+    // of its mode are the mnemonic and a keyword, as in `jmp far i0:16`
+    pub fn with_leading_keyword(&self) -> Option<Self> {
+        let prefix = self.prefix.clone()?;
+        let keyword = Spanned { node: PatternItem::Keyword(self.mnemonic.node), slice: self.mnemonic.slice, span: self.mnemonic.span.clone() };
+        let pattern = std::iter::once(keyword).chain(self.pattern.iter().cloned()).collect();
+        Some(Self { prefix: None, mnemonic: prefix, pattern, ..self.clone() })
+    }
+}
+
+// Errors without a span were found at the end of the source, where something else was expected
+fn log(logger: &mut Logger, source: &str, (message, span): Error) {
+    logger.log_at(LogLevel::Error, Some(span.unwrap_or(source.len()..source.len())), message);
+}
+
+fn operand<'a>(source: &'a str, lexeme: Lexeme<'a, Token<'a>>) -> Result<Operand<'a>, Error> {
+    if let Some(p) = punctuation(&lexeme) {
+        return Ok(Spanned::new(OperandKind::Punct(p), source, lexeme.span));
+    }
+    let kind = match lexeme.token {
+        Token::Register(r) => OperandKind::Register(r),
+        Token::Integer(int) => OperandKind::Immediate(Expr::Integer(int)),
+        Token::Ident(name) => OperandKind::Immediate(Expr::Symbol(name)),
+        Token::Comma => OperandKind::Comma,
+        _ => return Err((format!("unexpected token: '{}'", lexeme.slice), Some(lexeme.span))),
+    };
+    Ok(Spanned::new(kind, source, lexeme.span))
+}
+
+// Parses a constant expression such as `table + 2 * 4` from the start of the operands, which the
+// parser splits at every operator. Returns the expression along with how many operands it spans.
+pub fn expression<'a>(operands: &[Operand<'a>]) -> Option<(Expr<'a>, usize)> {
+    let mut index = 0;
+    let expr = binary(operands, &mut index, 0)?;
+    Some((expr, index))
+}
+
+// Parses the operand of a directive as a constant expression, which must take all of its lexemes
+pub fn constant<'a>(lexemes: &[Lexeme<'a, Token<'a>>]) -> Option<Expr<'a>> {
+    let operands = lexemes.iter().map(|lexeme| {
+        let node = match lexeme.token {
+            Token::Integer(int) => OperandKind::Immediate(Expr::Integer(int)),
+            Token::Ident(name) => OperandKind::Immediate(Expr::Symbol(name)),
+            Token::Comma => OperandKind::Comma,
+            _ => OperandKind::Punct(punctuation(lexeme)?),
+        };
+        Some(Spanned { node, slice: lexeme.slice, span: lexeme.span.clone() })
+    }).collect::<Option<Vec<_>>>()?;
+    match expression(&operands)? {
+        (expr, length) if length == operands.len() => Some(expr),
+        _ => None,
+    }
+}
+
+fn binary_op(operand: Option<&Operand>) -> Option<(BinaryOp, u8)> {
+    match operand?.node {
+        OperandKind::Punct(p) => BinaryOp::from_token(&Lexer::new(p).next()?.token),
+        _ => None,
+    }
+}
+
+// Precedence climbing like in semantics. An operator only continues the expression when an
+// operand follows it, so the `+` in `(r1 + 4)` after a register is left as punctuation.
+fn binary<'a>(operands: &[Operand<'a>], index: &mut usize, min_power: u8) -> Option<Expr<'a>> {
+    let mut lhs = primary(operands, index)?;
+    while let Some((op, power)) = binary_op(operands.get(*index)) {
+        if power < min_power {
+            break;
+        }
+        let mut next = *index + 1;
+        let rhs = match binary(operands, &mut next, power + 1) {
+            Some(rhs) => rhs,
+            None => break,
+        };
+        *index = next;
+        lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+    Some(lhs)
+}
+
+fn primary<'a>(operands: &[Operand<'a>], index: &mut usize) -> Option<Expr<'a>> {
+    let operand = operands.get(*index)?;
+    *index += 1;
+    let unary = |op, index: &mut usize| Some(Expr::Unary(op, Box::new(primary(operands, index)?)));
+    match operand.node {
+        OperandKind::Immediate(Expr::Symbol(name)) if matches!(operands.get(*index), Some(Spanned { node: OperandKind::Punct("("), .. })) && Function::from_name(name).is_some() => {
+            *index += 1;
+            let mut arguments = Vec::new();
+            loop {
+                arguments.push(binary(operands, index, 0)?);
+                *index += 1;
+                match operands.get(*index - 1)?.node {
+                    OperandKind::Comma => {},
+                    OperandKind::Punct(")") => return Some(Expr::Call(Function::from_name(name)?, arguments)),
+                    _ => return None,
+                }
+            }
+        },
+        OperandKind::Immediate(ref expr) => Some(expr.clone()),
+        OperandKind::Punct("(") => {
+            let expr = binary(operands, index, 0)?;
+            match operands.get(*index)?.node {
+                OperandKind::Punct(")") => {
+                    *index += 1;
+                    Some(expr)
+                },
+                _ => None,
+            }
+        },
+        OperandKind::Punct("-") => unary(UnaryOp::Negate, index),
+        OperandKind::Punct("~") => unary(UnaryOp::Not, index),
+        OperandKind::Punct("!") => unary(UnaryOp::LogicalNot, index),
+        _ => None,
+    }
+}
+
+// The rest of a `bank(label)` or `offset(label)` operand, after its name
+fn bank_operand<'a>(source: &'a str, lexer: &mut Lexemes<'a>, name: &str, name_span: Span) -> Result<Operand<'a>, Error> {
+    let expected = || format!("expected '{}(<label>)'", name);
+    lexer.next();
+    let label = match lexer.next_if(|l| !ends_instruction(&l.token)) {
+        Some(Lexeme { token: Token::Ident(label), .. }) => label,
+        Some(Lexeme { span, .. }) => return Err((expected(), Some(span))),
+        None => return Err((expected(), Some(name_span))),
+    };
+    let end = match lexer.next_if(|l| !ends_instruction(&l.token)) {
+        Some(Lexeme { token: Token::CloseParen, span, .. }) => span.end,
+        Some(Lexeme { span, .. }) => return Err((expected(), Some(span))),
+        None => return Err((expected(), Some(name_span))),
+    };
+    let expr = match name {
+        "bank" => Expr::Bank(label),
+        _ => Expr::Offset(label),
+    };
+    Ok(Spanned::new(OperandKind::Immediate(expr), source, name_span.start..end))
+}
+
+// Skips past the next token matching `sync`, or to the end of the line
+fn synchronize<'a>(lexer: &mut Lexemes<'a>, sync: impl Fn(&Token) -> bool) {
+    lexer.any(|lexeme| sync(&lexeme.token));
+}
+
+// Whether the token ends an instruction within a bundle
+fn ends_instruction(token: &Token) -> bool {
+    matches!(token, Token::Semicolon | Token::CloseBrace)
+}
+
+// Parses one line of assembly. A bad operand is reported and skipped up to the next comma, so
+// every operand on the line is checked.
+pub fn parse_statement(source: &str) -> LoggedResult<Statement<'_>> {
+    parse_statement_with(source, "")
+}
+
+// Parses one line of assembly whose mnemonics can also hold the characters given by `.mnemonic_chars`
+pub fn parse_statement_with<'a>(source: &'a str, mnemonic_chars: &'a str) -> LoggedResult<Statement<'a>> {
+    let mut logger = Logger::new(None);
+    let mut lexer = Lexer::with_mnemonic_chars(source, mnemonic_chars).peekable();
+    let label = match (lexer.peek(), Lexer::with_mnemonic_chars(source, mnemonic_chars).nth(1)) {
+        (Some(Lexeme { token: Token::Ident(name), span, .. }), Some(Lexeme { token: Token::Colon, .. })) => {
+            let label = Spanned::new(*name, source, span.clone());
+            lexer.nth(1);
+            Some(label)
+        },
+        _ => None,
+    };
+    let statement = match lexer.peek() {
+        None => Some(Statement::Empty),
+        Some(Lexeme { token: Token::OpenBrace, .. }) => bundle(&mut logger, source, &mut lexer),
+        Some(_) => instruction(&mut logger, source, &mut lexer),
+    };
+    let statement = match label {
+        Some(label) => statement.map(|statement| Statement::Label(label, Box::new(statement))),
+        None => statement,
+    };
+    match (statement, lexer.next()) {
+        (Some(statement), None) => logger.into_result(|| statement),
+        (Some(_), Some(Lexeme { slice, span, .. })) => {
+            logger.log_error_at(span, format!("unexpected token: '{}'", slice));
+            logger.into_none()
+        },
+        (None, _) => logger.into_none(),
+    }
+}
+
+fn bundle<'a>(logger: &mut Logger, source: &'a str, lexer: &mut Lexemes<'a>) -> Option<Statement<'a>> {
+    let start = lexer.next()?.span.start;
+    let mut instructions = Vec::new();
+    loop {
+        instructions.push(instruction(logger, source, lexer)?);
+        match lexer.next() {
+            Some(Lexeme { token: Token::Semicolon, .. }) => {},
+            Some(Lexeme { token: Token::CloseBrace, span, .. }) => return Some(Statement::Bundle(instructions, start..span.end)),
+            _ => {
+                logger.log_error_at(start..source.len(), "expected '}' at the end of the bundle".to_owned());
+                return None;
+            },
+        }
+    }
+}
+
+// Parses an instruction up to the end of the line, or the `;` or `}` ending it within a bundle
+fn instruction<'a>(logger: &mut Logger, source: &'a str, lexer: &mut Lexemes<'a>) -> Option<Statement<'a>> {
+    let mnemonic = match lexer.next() {
+        Some(Lexeme { token: Token::Ident(mnemonic), span, .. }) => Spanned::new(mnemonic, source, span),
+        Some(Lexeme { slice, span, .. }) => {
+            logger.log_error_at(span, format!("unexpected token: '{}'", slice));
+            return None;
+        },
+        None => {
+            logger.log_error_at(source.len()..source.len(), "expected an instruction".to_owned());
+            return None;
+        },
+    };
+    let mut operands = Vec::new();
+    while let Some(lexeme) = lexer.next_if(|l| !ends_instruction(&l.token)) {
+        if lexeme.token == Token::Assign {
+            let kind = match lexer.next_if(|l| !ends_instruction(&l.token)) {
+                Some(Lexeme { token: Token::Integer(int), span, .. }) => Ok((Expr::Integer(int), span)),
+                Some(Lexeme { token: Token::Ident(name), span, .. }) => Ok((Expr::Symbol(name), span)),
+                Some(Lexeme { slice, span, .. }) => Err((format!("expected a literal value after '=', but got '{}'", slice), Some(span))),
+                None => Err(("expected a literal value after '='".to_owned(), Some(lexeme.span.clone()))),
+            };
+            match kind {
+                Ok((expr, span)) => operands.push(Spanned::new(OperandKind::Literal(expr), source, lexeme.span.start..span.end)),
+                Err(error) => {
+                    log(logger, source, error);
+                    recover(lexer);
+                },
+            }
+            continue;
+        }
+        let parsed = match lexeme.token {
+            Token::Ident(name @ ("bank" | "offset")) if lexer.peek().is_some_and(|l| l.token == Token::OpenParen) => bank_operand(source, lexer, name, lexeme.span),
+            _ => operand(source, lexeme),
+        };
+        match parsed {
+            Ok(operand) => operands.push(operand),
+            Err(error) => {
+                log(logger, source, error);
+                recover(lexer);
+            },
+        }
+    }
+    Some(Statement::Instruction { mnemonic, operands })
+}
+
+// Skips past the next comma, or up to the end of the instruction
+fn recover<'a>(lexer: &mut Lexemes<'a>) {
+    while lexer.next_if(|l| !ends_instruction(&l.token) && l.token != Token::Comma).is_some() {}
+    lexer.next_if(|l| l.token == Token::Comma);
+}
+
+fn expect<'a>(lexer: &mut Lexemes<'a>, what: &str) -> Result<Lexeme<'a, Token<'a>>, Error> {
+    lexer.next().ok_or_else(|| (format!("expected {}", what), None))
+}
+
+fn nibble<'a>(lexer: &mut Lexemes<'a>, after: char) -> Result<Nibble, Error> {
+    let lexeme = expect(lexer, &format!("a literal or register after '{}'", after))?;
+    match lexeme.token {
+        Token::Integer(int) => Ok(Nibble::Integer(int)),
+        Token::Immediate(im) => Ok(Nibble::Immediate(im)),
+        Token::Register(r) => Ok(Nibble::Register(r)),
+        _ => Err((format!("expected a literal or register after '{}', but got '{}'", after, lexeme.slice), Some(lexeme.span))),
+    }
+}
+
+// Returns the end of the symbol
+fn symbol<'a>(lexer: &mut Lexemes<'a>, token: Token, symbol: char) -> Result<usize, Error> {
+    let lexeme = expect(lexer, &format!("'{}' in bracket group", symbol))?;
+    if lexeme.token == token {
+        Ok(lexeme.span.end)
+    } else {
+        Err((format!("expected '{}' in bracket group, but got '{}'", symbol, lexeme.slice), Some(lexeme.span)))
+    }
+}
+
+// Returns the item along with the end of its last token
+fn pattern_item<'a>(lexer: &mut Lexemes<'a>, lexeme: &Lexeme<'a, Token<'a>>) -> Result<(PatternItem<'a>, usize), Error> {
+    if let Some(p) = punctuation(lexeme) {
+        return Ok((PatternItem::Punct(p), lexeme.span.end));
+    }
+    match lexeme.token {
+        Token::Register(r) => Ok((PatternItem::Register(r), lexeme.span.end)),
+        Token::Comma => Ok((PatternItem::Comma, lexeme.span.end)),
+        Token::Ident(word) => Ok((PatternItem::Keyword(word), lexeme.span.end)),
+        Token::Immediate(im) => {
+            let colon = expect(lexer, "width of immediate")?;
+            if colon.token != Token::Colon {
+                return Err((format!("expected width of immediate, but got '{}'", colon.slice), Some(colon.span)));
+            }
+            match expect(lexer, "width of immediate")? {
+                Lexeme { token: Token::Integer(0), span, .. } => Err(("immediates must be at least 1 bit wide".to_owned(), Some(span))),
+                Lexeme { token: Token::Integer(width), span, .. } => Ok((PatternItem::Immediate(im, width), span.end)),
+                Lexeme { slice, span, .. } => Err((format!("expected width of immediate, but got: '{}'", slice), Some(span))),
+            }
+        },
+        _ => Err((format!("unexpected token in instruction pattern: '{}'", lexeme.slice), Some(lexeme.span.clone()))),
+    }
+}
+
+// Returns the group along with the end of its closing bracket
+fn bracket_group<'a>(lexer: &mut Lexemes<'a>) -> Result<(CodegenItem, usize), Error> {
+    let upper = nibble(lexer, '[')?;
+    symbol(lexer, Token::Operator(Operator::Or), '|')?;
+    let lower = nibble(lexer, '|')?;
+    let end = symbol(lexer, Token::CloseBracket, ']')?;
+    Ok((CodegenItem::Bracket(upper, lower), end))
+}
+
+// Bits of the literal fields of a word within `[start, start + width)`, or None if an operand field
+// overlaps them
+fn word_literal(fields: &[WordField], start: usize, width: usize) -> Option<usize> {
+    let mut overlapping = fields.iter().filter(|(_, lsb, w, _)| *lsb < start + width && lsb + w > start);
+    overlapping.try_fold(0, |bits, (field, lsb, w, _)| match field {
+        Nibble::Integer(int) => {
+            let (low, high) = ((*lsb).max(start), (lsb + w).min(start + width));
+            Some(bits | (int >> (low - lsb) & ((1 << (high - low)) - 1)) << (low - start))
+        },
+        _ => None,
+    })
+}
+
+// A field of a word along with the bit it starts at, counting from the least significant
+type WordField = (Nibble, usize, usize, Span);
+
+// `word { 0x2:4, r0:4, i0:8 }` after `word`, with fields given from the most significant bit down.
+// The word is emitted little endian like immediates are, or big endian with `word be { ... }`.
+// Returns the bytes it is made of along with the end of its closing brace.
+fn word<'a>(lexer: &mut Lexemes<'a>, start: usize) -> Result<(Vec<(CodegenItem, Span)>, usize), Error> {
+    let mut lexeme = expect(lexer, "'{' after 'word'")?;
+    let big = matches!(lexeme.token, Token::Ident("be"));
+    if matches!(lexeme.token, Token::Ident("be" | "le")) {
+        lexeme = expect(lexer, "'{' after the byte order of the word")?;
+    }
+    if lexeme.token != Token::OpenBrace {
+        return Err((format!("expected '{{' to start the word, but got '{}'", lexeme.slice), Some(lexeme.span)));
+    }
+    let mut fields = Vec::new();
+    let end = loop {
+        let lexeme = expect(lexer, "a field of the word")?;
+        let field = match lexeme.token {
+            Token::Integer(int) => Nibble::Integer(int),
+            Token::Register(r) => Nibble::Register(r),
+            Token::Immediate(im) => Nibble::Immediate(im),
+            _ => return Err((format!("expected a literal, register, or immediate field, but got '{}'", lexeme.slice), Some(lexeme.span))),
+        };
+        match expect(lexer, "':' and the width of the field")? {
+            Lexeme { token: Token::Colon, .. } => {},
+            Lexeme { slice, span, .. } => return Err((format!("expected ':' and the width of the field, but got '{}'", slice), Some(span))),
+        }
+        let width = match expect(lexer, "the width of the field")? {
+            Lexeme { token: Token::Integer(width), .. } if width > 0 => width,
+            Lexeme { slice, span, .. } => return Err((format!("expected the width of the field, but got '{}'", slice), Some(span))),
+        };
+        if let Nibble::Integer(int) = field {
+            if width < usize::BITS as usize && int >> width != 0 {
+                return Err((format!("{} does not fit in {} bits", lexeme.slice, width), Some(lexeme.span)));
+            }
+        }
+        fields.push((field, width, lexeme.span));
+        match expect(lexer, "',' or '}' in the word")? {
+            Lexeme { token: Token::Comma, .. } => {},
+            Lexeme { token: Token::CloseBrace, span, .. } => break span.end,
+            Lexeme { slice, span, .. } => return Err((format!("expected ',' or '}}' in the word, but got '{}'", slice), Some(span))),
+        }
+    };
+    let bits: usize = fields.iter().map(|(_, width, _)| width).sum();
+    if bits != 16 && bits != 32 {
+        return Err((format!("fields of the word add up to {} bits, but words are 16 or 32", bits), Some(start..end)));
+    }
+    let mut lsb = bits;
+    let fields: Vec<WordField> = fields.into_iter().map(|(field, width, span)| {
+        lsb -= width;
+        (field, lsb, width, span)
+    }).collect();
+    
+    // Each byte is made of literals, one operand, two nibbles, or starts an immediate of whole bytes
+    let operand = |start: usize, width: usize| fields.iter().find(|(field, lsb, w, _)| !matches!(field, Nibble::Integer(_)) && *lsb == start && *w == width);
+    let nibble = |start: usize| word_literal(&fields, start, 4).map(Nibble::Integer).or_else(|| operand(start, 4).map(|(field, ..)| *field));
+    let mut bytes = Vec::new();
+    let mut byte = 0;
+    while byte < bits / 8 {
+        let bit = byte * 8;
+        let span = fields.iter().find(|(field, lsb, w, _)| !matches!(field, Nibble::Integer(_)) && *lsb < bit + 8 && lsb + w > bit).map(|(.., span)| span.clone());
+        let wide = fields.iter().find(|(field, lsb, w, _)| matches!(field, Nibble::Immediate(_)) && *lsb == bit && w % 8 == 0);
+        let item = match (word_literal(&fields, bit, 8), operand(bit, 8), wide) {
+            (Some(int), ..) => CodegenItem::Integer(int),
+            (_, Some((Nibble::Register(r), ..)), _) => CodegenItem::Register(*r),
+            (_, _, Some((Nibble::Immediate(im), _, width, span))) if !big || *width == 8 => {
+                bytes.push((CodegenItem::Immediate(*im, Some(*width)), span.clone()));
+                byte += width / 8;
+                continue;
+            },
+            (_, _, Some((.., span))) => return Err(("immediates spanning several bytes can only be emitted by little endian words (for now)".to_owned(), Some(span.clone()))),
+            _ => match (nibble(bit + 4), nibble(bit)) {
+                (Some(upper), Some(lower)) => CodegenItem::Bracket(upper, lower),
+                _ => return Err(("operand fields of a word must be whole nibbles or bytes (for now)".to_owned(), span)),
+            },
+        };
+        bytes.push((item, span.unwrap_or(start..end)));
+        byte += 1;
+    }
+    if big {
+        bytes.reverse();
+    }
+    Ok((bytes, end))
+}
+
+// Parses `[, i0:4 = 1]` after its opening bracket, adding the comma and the immediate to the
+// pattern and returning the default value
+fn optional_operand<'a>(lexer: &mut Lexemes<'a>, source: &'a str, pattern: &mut Vec<Spanned<'a, PatternItem<'a>>>) -> Result<Spanned<'a, usize>, Error> {
+    let comma = expect(lexer, "',' in optional operand")?;
+    if comma.token != Token::Comma {
+        return Err((format!("expected ',' in optional operand, but got '{}'", comma.slice), Some(comma.span)));
+    }
+    let lexeme = expect(lexer, "an immediate in optional operand")?;
+    let immediate = match pattern_item(lexer, &lexeme)? {
+        (item @ PatternItem::Immediate(..), end) => Spanned::new(item, source, lexeme.span.start..end),
+        _ => return Err((format!("only immediates can be optional, but got '{}'", lexeme.slice), Some(lexeme.span))),
+    };
+    match expect(lexer, "'=' and a default value")? {
+        Lexeme { token: Token::Assign, .. } => {},
+        Lexeme { slice, span, .. } => return Err((format!("expected '=' and a default value, but got '{}'", slice), Some(span))),
+    }
+    let default = match expect(lexer, "a default value")? {
+        Lexeme { token: Token::Integer(value), span, .. } => Spanned::new(value, source, span),
+        Lexeme { slice, span, .. } => return Err((format!("expected a default value, but got '{}'", slice), Some(span))),
+    };
+    match expect(lexer, "']' after optional operand")? {
+        Lexeme { token: Token::CloseBracket, .. } => {},
+        Lexeme { slice, span, .. } => return Err((format!("expected ']' after optional operand, but got '{}'", slice), Some(span))),
+    }
+    match lexer.peek() {
+        Some(Lexeme { token: Token::Arrow, .. }) | None => {
+            pattern.extend([Spanned::new(PatternItem::Comma, source, comma.span), immediate]);
+            Ok(default)
+        },
+        Some(lexeme) => Err(("only the last operand can be optional".to_owned(), Some(lexeme.span.clone()))),
+    }
+}
+
+// Errors in the pattern are skipped up to the next comma or the arrow, and errors in the codegen
+// up to the next item, so every mistake on the line is reported at once
+fn definition<'a>(logger: &mut Logger, source: &'a str, mnemonic_chars: &'a str, semantics: Option<Spanned<'a, &'a str>>) -> Option<Vec<Definition<'a>>> {
+    let mut lexer = Lexer::with_mnemonic_chars(source, mnemonic_chars).peekable();
+    let mnemonic = match lexer.next() {
+        Some(Lexeme { token: Token::Ident(name), span, .. }) => Spanned::new(name, source, span),
+        None => return None,
+        Some(Lexeme { span, .. }) => {
+            logger.log_error_at(span, "only instruction patterns are supported in the assembler config at the moment".to_owned());
+            return None;
+        },
+    };
+
+    // Two names start a form for the mode of a prefix, unless the config has no such prefix, when
+    // the second is a keyword of the pattern instead
+    let (prefix, mnemonic) = match lexer.next_if(|l| matches!(l.token, Token::Ident(_))) {
+        Some(Lexeme { token: Token::Ident(name), span, .. }) => (Some(mnemonic), Spanned::new(name, source, span)),
+        _ => (None, mnemonic),
+    };
+
+    let mut pattern = Vec::new();
+    let mut default = None;
+    let mut arrow = false;
+    while let Some(lexeme) = lexer.next() {
+        if lexeme.token == Token::Arrow {
+            arrow = true;
+            break;
+        }
+        if lexeme.token == Token::OpenBracket && lexer.peek().is_some_and(|l| l.token == Token::Comma) {
+            match optional_operand(&mut lexer, source, &mut pattern) {
+                Ok(value) => default = Some(value),
+                Err(error) => {
+                    log(logger, source, error);
+                    while lexer.next_if(|l| l.token != Token::Arrow).is_some() {}
+                },
+            }
+            continue;
+        }
+        match pattern_item(&mut lexer, &lexeme) {
+            Ok((item, end)) => pattern.push(Spanned::new(item, source, lexeme.span.start..end)),
+            Err(error) => {
+                log(logger, source, error);
+                while lexer.next_if(|l| !matches!(l.token, Token::Comma | Token::Arrow)).is_some() {}
+            },
+        }
+    }
+    if !arrow {
+        logger.log_error_at(source.len()..source.len(), "expected '->' following an instruction pattern".to_owned());
+        return None;
+    }
+
+    let branches = match lexer.peek() {
+        Some(Lexeme { token: Token::Ident("if"), .. }) => branches(logger, source, &mut lexer)?,
+        _ => vec![(Vec::new(), codegen_items(logger, source, &mut lexer, false))],
+    };
+    let definitions = branches.into_iter()
+        .map(|(guards, codegen)| Definition { prefix: prefix.clone(), mnemonic: mnemonic.clone(), pattern: pattern.clone(), codegen, semantics: semantics.clone(), default: default.clone(), guards })
+        .collect();
+    Some(definitions)
+}
+
+// `i0 < 16` following an `if`
+fn condition<'a>(lexer: &mut Lexemes<'a>) -> Result<Condition, Error> {
+    let immediate = match expect(lexer, "an immediate after 'if'")? {
+        Lexeme { token: Token::Immediate(im), .. } => im,
+        Lexeme { slice, span, .. } => return Err((format!("expected an immediate after 'if', but got '{}'", slice), Some(span))),
+    };
+    let op = match expect(lexer, "a comparison")? {
+        Lexeme { token: Token::Operator(op @ (Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge | Operator::Eq | Operator::Ne)), .. } => op,
+        Lexeme { slice, span, .. } => return Err((format!("expected a comparison, but got '{}'", slice), Some(span))),
+    };
+    match expect(lexer, "a value to compare with")? {
+        Lexeme { token: Token::Integer(value), .. } => Ok(Condition { immediate, op, value }),
+        Lexeme { slice, span, .. } => Err((format!("expected a value to compare with, but got '{}'", slice), Some(span))),
+    }
+}
+
+// Codegen of a branch along with the conditions under which it is taken
+type Branch<'a> = (Vec<Condition>, Vec<Spanned<'a, CodegenItem>>);
+
+// `if i0 < 16 { ... } else if i0 < 256 { ... } else { ... }`
+fn branches<'a>(logger: &mut Logger, source: &'a str, lexer: &mut Lexemes<'a>) -> Option<Vec<Branch<'a>>> {
+    let mut branches = Vec::new();
+    // Negations of the conditions of earlier branches
+    let mut otherwise = Vec::new();
+    loop {
+        let mut block = |lexer: &mut Lexemes<'a>| match lexer.next() {
+            Some(Lexeme { token: Token::OpenBrace, .. }) => Some(codegen_items(logger, source, lexer, true)),
+            Some(Lexeme { slice, span, .. }) => {
+                logger.log_error_at(span, format!("expected '{{' to start the branch, but got '{}'", slice));
+                None
+            },
+            None => {
+                logger.log_error_at(source.len()..source.len(), "expected '{' to start the branch".to_owned());
+                None
+            },
+        };
+        match lexer.next() {
+            Some(Lexeme { token: Token::Ident("if"), .. }) => {
+                let condition = match condition(lexer) {
+                    Ok(condition) => condition,
+                    Err(error) => {
+                        log(logger, source, error);
+                        return None;
+                    },
+                };
+                let codegen = block(lexer)?;
+                let mut guards = otherwise.clone();
+                guards.push(condition);
+                branches.push((guards, codegen));
+                otherwise.push(condition.negate());
+            },
+            Some(Lexeme { token: Token::OpenBrace, .. }) => {
+                let codegen = codegen_items(logger, source, lexer, true);
+                branches.push((otherwise, codegen));
+                break;
+            },
+            Some(Lexeme { slice, span, .. }) => {
+                logger.log_error_at(span, format!("expected 'if' or '{{' after 'else', but got '{}'", slice));
+                return None;
+            },
+            None => {
+                logger.log_error_at(source.len()..source.len(), "expected 'if' or '{' after 'else'".to_owned());
+                return None;
+            },
+        }
+        match lexer.next() {
+            Some(Lexeme { token: Token::Ident("else"), .. }) => {},
+            Some(Lexeme { slice, span, .. }) => {
+                logger.log_error_at(span, format!("expected 'else' or the end of the codegen, but got '{}'", slice));
+                return None;
+            },
+            None => break,
+        }
+    }
+    if let Some(lexeme) = lexer.next() {
+        logger.log_error_at(lexeme.span, format!("unexpected '{}' after the last branch", lexeme.slice));
+        return None;
+    }
+    Some(branches)
+}
+
+// Codegen items up to the end of the line, or up to the closing brace of a branch
+fn codegen_items<'a>(logger: &mut Logger, source: &'a str, lexer: &mut Lexemes<'a>, in_branch: bool) -> Vec<Spanned<'a, CodegenItem>> {
+    let mut codegen = Vec::new();
+    while let Some(lexeme) = lexer.next() {
+        if lexeme.token == Token::Ident("word") {
+            match word(lexer, lexeme.span.start) {
+                Ok((bytes, _)) => codegen.extend(bytes.into_iter().map(|(item, span)| Spanned::new(item, source, span))),
+                Err(error) => {
+                    synchronize(lexer, |t| *t == Token::CloseBrace);
+                    log(logger, source, error);
+                },
+            }
+            continue;
+        }
+        let item = match lexeme.token {
+            Token::CloseBrace if in_branch => return codegen,
+            Token::Integer(int) => Ok((CodegenItem::Integer(int), lexeme.span.end)),
+            Token::Immediate(im) if lexer.next_if(|l| l.token == Token::Colon).is_some() => match lexer.next() {
+                Some(Lexeme { token: Token::Integer(width), span, .. }) => Ok((CodegenItem::Immediate(im, Some(width)), span.end)),
+                Some(Lexeme { slice, span, .. }) => Err((format!("expected width of immediate, but got '{}'", slice), Some(span))),
+                None => Err(("expected width of immediate".to_owned(), None)),
+            },
+            Token::Immediate(im) => Ok((CodegenItem::Immediate(im, None), lexeme.span.end)),
+            Token::Register(r) => Ok((CodegenItem::Register(r), lexeme.span.end)),
+            Token::OpenBracket => bracket_group(lexer).inspect_err(|_| {
+                synchronize(lexer, |t| *t == Token::CloseBracket);
+            }),
+            _ => Err((format!("codegen only supports literal values, registers, bracket groups, and words, but got '{}'", lexeme.slice), Some(lexeme.span.clone()))),
+        };
+        match item {
+            Ok((item, end)) => codegen.push(Spanned::new(item, source, lexeme.span.start..end)),
+            Err(error) => log(logger, source, error),
+        }
+    }
+    if in_branch {
+        logger.log_error_at(source.len()..source.len(), "expected '}' at the end of the branch".to_owned());
+    }
+    codegen
+}
+
+// Finds the `{` that starts the semantics, skipping the braces of `if` branches in the codegen
+fn semantics_start(source: &str) -> Option<usize> {
+    let mut depth = 0;
+    // Start of the text since the last closing brace, or the arrow
+    let mut start = source.find("->").unwrap_or(0);
+    for (i, c) in source.char_indices() {
+        match c {
+            '/' if depth == 0 && source[i..].starts_with("//") => return None,
+            '{' if depth == 0 => {
+                // The braces of a `word { ... }` are skipped the same way
+                let branch = Lexer::new(&source[start.min(i)..i]).any(|l| matches!(l.token, Token::Ident("if" | "else" | "word")));
+                if !branch {
+                    return Some(i);
+                }
+                depth = 1;
+            },
+            '{' => depth += 1,
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    start = i + 1;
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+// Parses one line of the config into a definition for each branch of its codegen, which is None if
+// the line has no definition
+pub fn parse_definition(source: &str) -> LoggedResult<Option<Vec<Definition<'_>>>> {
+    parse_definition_with(source, "")
+}
+
+// Parses a line of the config whose mnemonic can also hold the characters given by `.mnemonic_chars`
+pub fn parse_definition_with<'a>(source: &'a str, mnemonic_chars: &'a str) -> LoggedResult<Option<Vec<Definition<'a>>>> {
+    let mut logger = Logger::new(None);
+    // Semantics are given in an optional `{ ... }` clause following the codegen
+    let (pattern, semantics) = match semantics_start(source).map(|i| (&source[..i], &source[i + 1..])) {
+        Some((pattern, rest)) => match rest.trim_end().strip_suffix('}') {
+            Some(semantics) => (pattern, Some(Spanned::new(semantics, source, pattern.len() + 1..pattern.len() + 1 + semantics.len()))),
+            None => {
+                logger.log_error_at(pattern.len()..source.len(), "expected '}' at the end of instruction semantics".to_owned());
+                (pattern, None)
+            },
+        },
+        None => (source, None),
+    };
+    let definition = definition(&mut logger, pattern, mnemonic_chars, semantics);
+    logger.into_result(|| definition)
+}
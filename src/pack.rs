@@ -0,0 +1,96 @@
+use crate::directives::Image;
+
+// Sections packed into a smaller ROM, for data too large to fit as is. Packed sections are cut
+// from the end of the image and written after it compressed, following a table a decompression
+// stub copies them back to their addresses by:
+//
+//     count     u16, the number of packed sections
+//     table     count times the ROM address of the packed bytes, the address they unpack to, and
+//               the unpacked size, all u32
+//     data      the packed bytes of each section in table order
+//
+// Integers are little endian. The table starts right after the last section that isn't packed,
+// which is where that section's end label given by `symbols` in the linker script points.
+//
+// Packed bytes are runs, each starting with a control byte n. For n below 0x80, the n + 1 bytes
+// after it are copied as they are, and otherwise the byte after it is repeated n - 0x7D times.
+const LITERALS: usize = 0x80;
+const MIN_REPEAT: usize = 3;
+const MAX_REPEAT: usize = 0xFF - 0x7D;
+
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let repeat = bytes[i..].iter().take(MAX_REPEAT).take_while(|b| **b == bytes[i]).count();
+        if repeat >= MIN_REPEAT {
+            flush(&mut packed, &mut literals);
+            packed.push((repeat + 0x7D) as u8);
+            packed.push(bytes[i]);
+            i += repeat;
+        } else {
+            literals.push(bytes[i]);
+            if literals.len() == LITERALS {
+                flush(&mut packed, &mut literals);
+            }
+            i += 1;
+        }
+    }
+    flush(&mut packed, &mut literals);
+    packed
+}
+
+fn flush(packed: &mut Vec<u8>, literals: &mut Vec<u8>) {
+    if !literals.is_empty() {
+        packed.push((literals.len() - 1) as u8);
+        packed.append(literals);
+    }
+}
+
+// The bytes packed by `compress`, or None when they end in the middle of a run
+pub fn decompress(packed: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < packed.len() {
+        let control = packed[i] as usize;
+        if control < LITERALS {
+            bytes.extend(packed.get(i + 1..i + 2 + control)?);
+            i += 2 + control;
+        } else {
+            bytes.extend(std::iter::repeat_n(*packed.get(i + 1)?, control - 0x7D));
+            i += 2;
+        }
+    }
+    Some(bytes)
+}
+
+// The image with the sections packed after it, which must come after every section that isn't
+pub fn pack(image: &Image, sections: &[String]) -> Result<Vec<u8>, String> {
+    // Sections outside banks, which are the ones in the code
+    let placed: Vec<_> = image.sections.iter().filter(|(_, address, size)| {
+        image.segments.iter().any(|(base, code)| base == address && code.len() == *size)
+    }).collect();
+    for name in sections {
+        if !placed.iter().any(|(section, _, _)| section == name) {
+            return Err(format!("'{}' isn't a section of the image outside any bank", name));
+        }
+    }
+    let (packed, kept): (Vec<_>, Vec<_>) = placed.into_iter().partition(|(name, _, _)| sections.contains(name));
+    let end = kept.iter().map(|(_, address, size)| address + size - image.base).max().unwrap_or(0);
+    if let Some((name, _, _)) = packed.iter().find(|(_, address, _)| address - image.base < end) {
+        return Err(format!("'{}' is followed by a section that isn't packed; packed sections must come last", name));
+    }
+    let data: Vec<_> = packed.iter().map(|(_, address, size)| compress(&image.code[address - image.base..address - image.base + size])).collect();
+    let mut bytes = image.code[..end].to_vec();
+    bytes.extend((packed.len() as u16).to_le_bytes());
+    let mut source = image.base + end + 2 + packed.len() * 12;
+    for ((_, address, size), data) in packed.iter().zip(&data) {
+        bytes.extend((source as u32).to_le_bytes());
+        bytes.extend((*address as u32).to_le_bytes());
+        bytes.extend((*size as u32).to_le_bytes());
+        source += data.len();
+    }
+    bytes.extend(data.concat());
+    Ok(bytes)
+}
@@ -1,21 +1,44 @@
-use crate::log::{Logger, LoggedResult, Origin};
-use crate::lexer::{Lexer, Lexeme, Token};
+use crate::cache::hash_source;
+use crate::cancel::Cancel;
+use crate::progress::{Event, Progress, STATEMENTS_PER_EVENT};
+use crate::directives::{fill, BuildId, Directives, Image};
+use crate::files::{self, Disk, FileProvider};
+use crate::lexer::{written_width, BlockComments, Lexeme, Lexer, Span, Token};
+use crate::ir::{Adjustment, Immediate, Item, Operation};
+use crate::linker::Placement;
+use crate::listing::Row;
+use crate::log::{LogLevel, Logger, LoggedResult, Origin};
+use crate::macros::{invocation_stack, Dialect, Expanded, Limits, Macro, Preprocessor};
+use crate::new_parser::{expression, parse_statement_with, push_item, Condition, Expr, Operand, OperandKind, Spanned, Statement};
+use crate::hazard::Hazard;
+use crate::peephole::{Previous, Rule};
+use crate::semantics;
+use crate::style::OperandStyle;
+use crate::symbols::SymbolTable;
+use crate::xref::Site;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::process::Command;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CodegenData {
     Byte(u8),
     Immediate(usize, usize),
     Register(usize),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Codegen {
     // Writes the data directly to the output buffer
     Data(CodegenData),
     
     // Writes the data to the upper and lower half bytes of the output buffer
     UpperLower(CodegenData, CodegenData),
+    
+    // Writes nothing, but the form is only used when the condition holds for the immediate
+    Guard(Condition),
 }
 impl Codegen {
     pub fn byte(b: u8) -> Self { Codegen::Data(CodegenData::Byte(b)) }
@@ -23,155 +46,1508 @@ impl Codegen {
     pub fn register(r: usize) -> Self { Codegen::Data(CodegenData::Register(r)) }
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for CodegenData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            // Always in hex, so every field of an encoding reads in the same radix, e.g. the `nop`
+            // of the x69-bravo config is shown as `0x00 0x00 0x00 0x00`
+            CodegenData::Byte(b) => write!(f, "0x{:02X}", b),
+            CodegenData::Immediate(imm, _) => write!(f, "i{}", imm),
+            CodegenData::Register(r) => write!(f, "r{}", r),
+        }
+    }
+}
+
+impl std::fmt::Display for Codegen {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Codegen::Data(data) => write!(f, "{}", data),
+            Codegen::UpperLower(upper, lower) => write!(f, "[{} | {}]", upper, lower),
+            Codegen::Guard(condition) => write!(f, "if({})", condition),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
 pub enum Transition {
+    #[default]
     Reject,
     NextState(usize),
 }
-impl Default for Transition {
-    fn default() -> Self { Self::Reject }
-}
 
 #[derive(Debug, Default)]
 pub struct TransitionTable {
     pub register: Transition,
     pub immediate: Transition,
     pub comma: Transition,
-    // TODO: more options for syntax
+    // Punctuation such as `(` or `#` along with the state each leads to
+    pub punctuation: Vec<(&'static str, usize)>,
+    // Keywords such as `port` in lowercase along with the state each leads to
+    pub keywords: Vec<(String, usize)>,
     
     // If some, the state can accept the input and proceed to codegen
     pub accept_codegen: Option<Vec<Codegen>>,
+    
+    // Optional register transfer semantics of the accepted pattern, used by the emulator
+    pub accept_semantics: Option<String>,
+    
+    // Longer encodings of the same pattern along with their semantics, in order of length. The
+    // first encoding whose immediates fit is used, so short forms are relaxed to long ones.
+    pub relaxations: Vec<(Vec<Codegen>, Option<String>)>,
+}
+
+// Number of bytes the codegen emits
+pub fn encoded_length(codegen: &[Codegen]) -> usize {
+    codegen.iter().map(|c| match c {
+        Codegen::Data(CodegenData::Immediate(_, width)) => width / 8,
+        Codegen::Guard(_) => 0,
+        _ => 1,
+    }).sum()
+}
+
+// Whether the value fits in the width, either unsigned or as a negative number such as a relative
+// offset backwards
+pub fn fits_in(value: usize, width: usize) -> bool {
+    width >= usize::BITS as usize || value >> width == 0 || width > 0 && (value as isize) < 0 && (value as isize) >= -(1 << (width - 1))
+}
+
+// Fewest bits that hold the value, as a signed integer if it is negative
+pub fn width_of(value: usize) -> usize {
+    match (value as isize) < 0 {
+        true => (usize::BITS - (!value).leading_zeros()) as usize + 1,
+        false => ((usize::BITS - value.leading_zeros()) as usize).max(1),
+    }
+}
+
+// Whether every immediate the codegen emits fits in its width
+fn fits(codegen: &[Codegen], immediates: &[Immediate]) -> bool {
+    let fits = |data: &CodegenData| match *data {
+        CodegenData::Immediate(imm, width) => immediates.get(imm).is_none_or(|immediate| fits_in(immediate.value, width)),
+        _ => true,
+    };
+    codegen.iter().all(|c| match c {
+        Codegen::Data(data) => fits(data),
+        Codegen::UpperLower(upper, lower) => fits(upper) && fits(lower),
+        Codegen::Guard(_) => true,
+    })
+}
+
+// Whether the conditions of the `if` branch the codegen is in hold
+fn taken(codegen: &[Codegen], immediates: &[Immediate]) -> bool {
+    codegen.iter().all(|c| match c {
+        Codegen::Guard(condition) => immediates.get(condition.immediate).is_none_or(|immediate| condition.holds(immediate.value)),
+        _ => true,
+    })
+}
+
+// Takes the code of an instruction a rewrite took back out of the row it was listed on, which is
+// the latest with code since nothing was emitted after it. The row of the line moves back with it
+// if it has no code yet.
+fn unlist(expansion: &mut [Row], row: &mut Row, listing: Option<&mut Vec<Row>>, address: usize, length: usize) {
+    let mut rows = expansion.iter_mut().rev().chain(Some(&mut *row)).chain(listing.into_iter().flatten().rev());
+    if let Some(listed) = rows.find(|listed| listed.instructions.contains(&address)) {
+        listed.code.truncate(listed.code.len() - length);
+        listed.instructions.retain(|a| *a != address);
+    }
+    if row.code.is_empty() {
+        row.address = address;
+    }
 }
 
 #[derive(Debug)]
 pub struct Instruction {
-    pub syntaxes: Vec<String>,
     pub states: Vec<TransitionTable>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternToken<'a> {
+    Register,
+    Immediate,
+    Comma,
+    Punct(&'static str),
+    Keyword(&'a str),
+}
+
+// A path through an instruction's DFA that ends in an accepting state
 #[derive(Debug)]
+pub struct Pattern<'a> {
+    pub tokens: Vec<PatternToken<'a>>,
+    pub codegen: &'a [Codegen],
+    pub semantics: Option<&'a str>,
+}
+
+impl Pattern<'_> {
+    pub fn registers(&self) -> usize { self.tokens.iter().filter(|t| **t == PatternToken::Register).count() }
+    pub fn immediates(&self) -> usize { self.tokens.iter().filter(|t| **t == PatternToken::Immediate).count() }
+    
+    // Conditions from the `if` branch the codegen is in
+    pub fn guards(&self) -> impl Iterator<Item = &Condition> {
+        self.codegen.iter().filter_map(|c| match c {
+            Codegen::Guard(condition) => Some(condition),
+            _ => None,
+        })
+    }
+    
+    // Width of an immediate as used by the codegen, if the codegen emits it
+    pub fn immediate_width(&self, immediate: usize) -> Option<usize> {
+        let width = |data: &CodegenData| match *data {
+            CodegenData::Immediate(i, width) if i == immediate => Some(width),
+            _ => None,
+        };
+        self.codegen.iter().find_map(|codegen| match codegen {
+            Codegen::Data(data) => width(data),
+            Codegen::UpperLower(upper, lower) => width(upper).or_else(|| width(lower)),
+            Codegen::Guard(_) => None,
+        })
+    }
+    
+    // Renders the operand pattern in config notation, e.g. `add r0, i0:4`
+    pub fn syntax(&self, name: &str) -> String {
+        self.source_syntax(name, false)
+    }
+    
+    // Renders the operand pattern in the operand order sources use, so with the source first a
+    // two operand `add r0, r1` is written `add r1, r0`
+    pub fn source_syntax(&self, name: &str, src_first: bool) -> String {
+        self.styled_syntax(name, src_first, &OperandStyle::default())
+    }
+    
+    // Renders the operand pattern with immediates prefixed as the style writes them, e.g. `ld r0, #i0:8`
+    pub fn styled_syntax(&self, name: &str, src_first: bool, style: &OperandStyle) -> String {
+        let mut operands = vec![String::new()];
+        let (mut registers, mut immediates) = (0, 0);
+        for token in &self.tokens {
+            let text = operands.last_mut().unwrap();
+            match token {
+                PatternToken::Register => {
+                    push_item(text, &format!("r{}", registers));
+                    registers += 1;
+                },
+                PatternToken::Immediate => {
+                    let placeholder = match self.immediate_width(immediates) {
+                        Some(width) => format!("i{}:{}", immediates, width),
+                        None => format!("i{}", immediates),
+                    };
+                    let placeholder = style.prefixed(text, placeholder);
+                    push_item(text, &placeholder);
+                    immediates += 1;
+                },
+                PatternToken::Comma => operands.push(String::new()),
+                PatternToken::Punct(p) => push_item(text, p),
+                PatternToken::Keyword(word) => push_item(text, word),
+            }
+        }
+        if src_first && operands.len() == 2 {
+            operands.swap(0, 1);
+        }
+        name.to_owned() + &operands.join(",")
+    }
+    
+    // Renders the whole pattern in config notation, e.g. `add r0, i0:4 -> 0 0 [i0 | r0]`
+    pub fn describe(&self, name: &str) -> String {
+        let mut text = self.syntax(name) + " ->";
+        for codegen in self.codegen {
+            text += &format!(" {}", codegen);
+        }
+        text
+    }
+}
+
+impl Instruction {
+    // Each operand pattern the DFA accepts, once however many forms it has
+    pub fn syntaxes(&self, name: &str, src_first: bool, style: &OperandStyle) -> Vec<String> {
+        let mut syntaxes = Vec::new();
+        for pattern in self.patterns() {
+            let syntax = pattern.styled_syntax(name, src_first, style);
+            if !syntaxes.contains(&syntax) {
+                syntaxes.push(syntax);
+            }
+        }
+        syntaxes
+    }
+    
+    // Every operand sequence the DFA accepts along with the codegen it produces, in state order
+    pub fn patterns(&self) -> Vec<Pattern<'_>> {
+        let mut patterns = Vec::new();
+        let mut stack = vec![(0, Vec::new())];
+        while let Some((state, tokens)) = stack.pop() {
+            let table = match self.states.get(state) {
+                Some(table) => table,
+                None => continue,
+            };
+            if let Some(codegen) = &table.accept_codegen {
+                patterns.push((state, Pattern { tokens: tokens.clone(), codegen, semantics: table.accept_semantics.as_deref() }));
+                for (codegen, semantics) in &table.relaxations {
+                    patterns.push((state, Pattern { tokens: tokens.clone(), codegen, semantics: semantics.as_deref() }));
+                }
+            }
+            let transitions = [
+                (&table.register, PatternToken::Register),
+                (&table.immediate, PatternToken::Immediate),
+                (&table.comma, PatternToken::Comma),
+            ];
+            let punctuation = table.punctuation.iter().map(|(p, next)| (*next, PatternToken::Punct(p)));
+            let keywords = table.keywords.iter().map(|(word, next)| (*next, PatternToken::Keyword(word)));
+            let next_states = transitions.iter().filter_map(|(transition, token)| match **transition {
+                Transition::NextState(next) => Some((next, *token)),
+                Transition::Reject => None,
+            });
+            for (next, token) in next_states.chain(punctuation).chain(keywords) {
+                // States are only ever created by a transition out of an earlier state
+                if next > state {
+                    let mut tokens = tokens.clone();
+                    tokens.push(token);
+                    stack.push((next, tokens));
+                }
+            }
+        }
+        patterns.sort_by_key(|(state, _)| *state);
+        patterns.into_iter().map(|(_, pattern)| pattern).collect()
+    }
+}
+
+// A packet of instructions issued together, given in the config as `.bundle alu, mem -> 0xB0`.
+// The header bytes are followed by the encoding of the instruction in each slot.
+#[derive(Debug, Clone)]
+pub struct BundleFormat {
+    pub slots: Vec<String>,
+    pub header: Vec<u8>,
+}
+
+// Bytes given by `.prefix rep 0xF3 : movs stos` that a source writes before an instruction as
+// `rep movs r0, r1`, which come before its code. The instructions listed after the colon are the
+// only ones it can come before, and it can come before any if there are none. Forms declared
+// after the prefix's name, such as `wide ld r0, i0:16 -> ...`, are the prefix's mode and are only
+// used after it.
+#[derive(Debug, Clone)]
+pub struct Prefix {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub mnemonics: Vec<String>,
+}
+
+impl Prefix {
+    pub fn allows(&self, mnemonic: &str) -> bool {
+        self.mnemonics.is_empty() || self.mnemonics.iter().any(|m| m == mnemonic)
+    }
+}
+
+// An address range sections are placed in, given in the config as `.region ROM 0x0000, 0x7FFF`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Region {
+    pub name: String,
+    pub start: usize,
+    // Last address in the region
+    pub end: usize,
+    // Bytes placed at the start of the region before any section, and at its very end, such as a
+    // boot signature, given by `.preamble` and `.postamble`
+    pub preamble: Vec<u8>,
+    pub postamble: Vec<u8>,
+}
+
+impl Region {
+    pub fn new(name: &str, start: usize, end: usize) -> Self {
+        Self { name: name.to_owned(), start, end, preamble: Vec::new(), postamble: Vec::new() }
+    }
+    
+    // The preamble and postamble have to fit in the region together
+    pub fn check_ambles(&self) -> Result<(), String> {
+        let size = self.end - self.start + 1;
+        match self.preamble.len() + self.postamble.len() > size {
+            true => Err(format!("the preamble and postamble of region '{}' take {} bytes, but it only has {}", self.name, self.preamble.len() + self.postamble.len(), size)),
+            false => Ok(()),
+        }
+    }
+}
+
+// What an instruction does, given in the config as `.category branch jmp jz`, for tools that need
+// to tell kinds of instructions apart, such as those following control flow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Branch,
+    LoadStore,
+    Alu,
+}
+
+impl Category {
+    pub const ALL: [Category; 3] = [Category::Branch, Category::LoadStore, Category::Alu];
+    
+    // Name the config gives the category by
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Branch => "branch",
+            Category::LoadStore => "load_store",
+            Category::Alu => "alu",
+        }
+    }
+    
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct Assembler {
-    pub instructions: HashMap<String, Instruction>
+    pub instructions: HashMap<String, Instruction>,
+    // Mnemonics accepted by each bundle slot, declared with `.slot name mnemonics...`
+    pub slots: HashMap<String, Vec<String>>,
+    pub bundles: Vec<BundleFormat>,
+    pub prefixes: Vec<Prefix>,
+    pub regions: Vec<Region>,
+    // Hash of the config sources, and the time to put in `.buildid` if the build should have one
+    pub config_hash: u64,
+    pub timestamp: Option<u64>,
+    // Mixed into the values of `rand(seed, max)`, given by `--seed`
+    pub seed: u64,
+    // Largest size allowed for each named section, given on the command line or in a manifest
+    pub budgets: Vec<(String, usize)>,
+    // Regions and alignment of sections given by a linker script, along with its `regions`
+    pub placements: Vec<Placement>,
+    // Set by `.operand_order src_first`, for sources that write two operands source first while
+    // the config documents them destination first
+    pub src_first: bool,
+    // Defined by `.macro` blocks in the config, so every source can use them without defining them
+    pub macros: HashMap<String, Macro>,
+    // Registers given by `.reserved r15 sp`, with the role they are reserved for. Writing one is
+    // warned about unless the source allows it with `.allow`.
+    pub reserved: Vec<(usize, String)>,
+    // Set to record a listing of the final pass in the image
+    pub listing: bool,
+    // Set to leave out sections nothing kept refers to, given by `--gc-sections`
+    pub gc_sections: bool,
+    // Set to record the statements that mention each symbol in the final pass
+    pub xref: bool,
+    // Set to record the statements of the final pass as operations in the image
+    pub program: bool,
+    // Entries each mnemonic given by `.stack_push` or `.stack_pop` adds to the stack, negative for
+    // pops, and the mnemonics given by `.stack_return`, where a routine's stack must be balanced
+    pub stack_effects: Vec<(String, isize)>,
+    pub returns: Vec<String>,
+    // Cycles each mnemonic given by `.cycles` takes, shown in listings
+    pub cycles: Vec<(String, usize)>,
+    // Multiple of bytes each mnemonic given by `.instruction_align` must be placed at
+    pub alignments: Vec<(String, usize)>,
+    pub misaligned: Misaligned,
+    // Statement terminator and label syntax given by `.terminator` and `.labels`
+    pub dialect: Dialect,
+    // Characters besides letters, digits, and underscores mnemonics can hold, given by
+    // `.mnemonic_chars`, for ISAs with mnemonics such as `add.8` or `?jmp`
+    pub mnemonic_chars: String,
+    // How instructions are written back out, given by `.display`
+    pub style: OperandStyle,
+    // Rewrites given by `.peephole`, which are only made when asked for with `--peephole`
+    pub peepholes: Vec<Rule>,
+    pub peephole: bool,
+    // Set to thread jumps, given by `--thread-jumps`
+    pub thread_jumps: bool,
+    // Delay slots of each mnemonic given by `.delay_slot`, which the assembler fills itself when
+    // asked to with `--fill-delay-slots`
+    pub delay_slots: Vec<(String, usize)>,
+    pub fill_delay_slots: bool,
+    // Sequences given by `.hazard` and `.illegal`, checked in every program
+    pub hazards: Vec<Hazard>,
+    // Checked between statements to stop an assembly that is no longer wanted
+    pub cancel: Option<Cancel>,
+    // Told how far along each assembly is
+    pub progress: Option<Progress>,
+    // Set to keep the code of an assembly with errors, which leaves out the statements that
+    // failed, given by `--keep-going`
+    pub keep_going: bool,
+    // Set to let `.include_cmd` run commands, given by `--allow-include-cmd`
+    pub include_commands: bool,
+    // Encoding of the no-op given by `.nop`, which padding and the gaps between sections are filled
+    // with so that running into them is harmless. They are zeros without it.
+    pub nop: Vec<u8>,
+    // Set by `.identifiers unicode` to let labels and symbols hold letters outside of ASCII
+    pub unicode_identifiers: bool,
+    // Category of each mnemonic given by `.category`
+    pub categories: Vec<(String, Category)>,
+    // Name `.arch` chooses this ISA by, such as `x69-bravo`, and the other ISAs a source can
+    // switch to with `.arch`, such as that of a coprocessor whose code is in the same program
+    pub arch: String,
+    pub archs: Vec<Assembler>,
+    // How deep macros and includes can nest and how many statements a line can expand to
+    pub limits: Limits,
+}
+
+// What to do with an instruction `.instruction_align` requires to be aligned when it isn't, set by
+// `.misaligned error`, `.misaligned pad`, or `.misaligned pad byte`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Misaligned {
+    #[default]
+    Error,
+    // Pads with the no-op
+    Pad,
+    PadWith(u8),
+}
+
+// The mnemonic an instruction statement starts with, in lowercase
+fn mnemonic(statement: &str, mnemonic_chars: &str) -> Option<String> {
+    match Lexer::with_mnemonic_chars(statement, mnemonic_chars).next() {
+        Some(Lexeme { token: Token::Ident(mnemonic), .. }) => Some(mnemonic.to_lowercase()),
+        _ => None,
+    }
+}
+
+// What an `.include` or `.include_cmd` statement assembles in place of itself
+pub(crate) enum Included {
+    File(String),
+    // A command whose output is assembled, e.g. `.include_cmd "python gen_table.py"`
+    Command(String),
+}
+
+// The file an `.include "file"` statement names, or the command an `.include_cmd` runs
+pub(crate) fn include(statement: &str) -> Option<Result<Included, String>> {
+    let lexemes: Vec<_> = Lexer::new(statement).collect();
+    match lexemes.as_slice() {
+        [Lexeme { token: Token::Directive(directive), .. }, rest @ ..] if directive.eq_ignore_ascii_case(".include") => match rest {
+            [Lexeme { token: Token::String(path), .. }] => Some(Ok(Included::File(path.to_string()))),
+            _ => Some(Err("expected '.include \"file\"'".to_owned())),
+        },
+        [Lexeme { token: Token::Directive(directive), .. }, rest @ ..] if directive.eq_ignore_ascii_case(".include_cmd") => match rest {
+            [Lexeme { token: Token::String(command), .. }] => Some(Ok(Included::Command(command.to_string()))),
+            _ => Some(Err("expected '.include_cmd \"command\"'".to_owned())),
+        },
+        _ => None,
+    }
+}
+
+// Logs the block comment left open at the end of a file at the `/*` starting it, which is the last
+// one on its line
+pub(crate) fn log_unclosed(logger: &mut Logger, origin: &str, line: usize, text: &str) {
+    logger.origin = Some(Origin::with_text(origin, line, text));
+    let span = text.rfind("/*").map(|start| start..start + 2);
+    logger.log_at(LogLevel::Error, span, "'/*' is never closed by '*/'".to_owned());
+}
+
+// The ISA an `.arch name` statement switches to, whose name is the rest of the statement since
+// names like `x69-bravo` aren't identifiers
+pub(crate) fn arch(statement: &str) -> Option<Result<&str, String>> {
+    match Lexer::new(statement).next() {
+        Some(Lexeme { token: Token::Directive(directive), span, .. }) if directive.eq_ignore_ascii_case(".arch") => match statement[span.end..].trim() {
+            "" => Some(Err("expected '.arch <name>'".to_owned())),
+            name => Some(Ok(name)),
+        },
+        _ => None,
+    }
+}
+
+// Where the passes of one assembly read sources from, along with the output of each command
+// `.include_cmd` ran, so a command runs once however many passes the assembly takes but again in
+// the next assembly
+struct Sources<'a> {
+    provider: &'a dyn FileProvider,
+    commands: RefCell<Vec<(String, Result<String, String>)>>,
+}
+
+impl<'a> Sources<'a> {
+    fn new(provider: &'a dyn FileProvider) -> Self {
+        Sources { provider, commands: RefCell::new(Vec::new()) }
+    }
+}
+
+// What the command writes to standard output, run by the shell in the directory
+fn run_command(dir: &Path, command: &str) -> Result<String, String> {
+    let shell = if cfg!(windows) { ["cmd", "/C"] } else { ["sh", "-c"] };
+    let output = Command::new(shell[0]).arg(shell[1]).arg(command).current_dir(dir).output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("it failed with {}{}", output.status, stderr.lines().next().map(|l| format!(": {}", l)).unwrap_or_default()));
+    }
+    String::from_utf8(output.stdout).map_err(|_| "its output isn't UTF-8".to_owned())
+}
+
+// Limit on assembly passes, after which label addresses are considered not to converge
+pub const MAX_PASSES: usize = 16;
+
+// Runs `pass` with the symbols of the previous pass until no symbol changes value. The logs of all
+// but the final pass are discarded, since forward references are unknown in the first pass.
+pub(crate) fn converge<T, F: FnMut(SymbolTable) -> (LoggedResult<T>, SymbolTable)>(cancel: Option<&Cancel>, progress: Option<&Progress>, mut pass: F) -> LoggedResult<T> {
+    let mut logger = Logger::new(None);
+    let mut previous = SymbolTable::new();
+    let mut seen: Vec<SymbolTable> = Vec::new();
+    for number in 1..=MAX_PASSES {
+        if let Some(progress) = progress {
+            progress.report(Event::PassStarted { pass: number });
+        }
+        let (result, symbols) = pass(previous.next_pass());
+        if let Some(progress) = progress {
+            progress.report(Event::PassDone { pass: number, changed: symbols.changed(&previous).len() });
+        }
+        // A cancelled pass reports it, and no more are made
+        if symbols.changed(&previous).is_empty() || cancel.is_some_and(|cancel| cancel.reason().is_some()) {
+            return result;
+        }
+        if seen.iter().any(|s| symbols.changed(s).is_empty()) {
+            logger.log_error(format!("label addresses oscillate between passes: {}", symbols.changed(&previous).join(", ")));
+            return logger.into_none();
+        }
+        seen.push(previous);
+        previous = symbols;
+    }
+    logger.log_error(format!("label addresses did not converge after {} passes", MAX_PASSES));
+    logger.into_none()
 }
 
 impl Assembler {
+    // An assembler without an ISA, which builds binaries out of data directives, labels, and
+    // expressions alone, such as lookup tables and file headers
+    pub fn raw() -> Self {
+        Self::default()
+    }
+    
+    // The ISA instructions are encoded with after the last `.arch`
+    fn isa(&self, directives: &Directives) -> &Assembler {
+        directives.arch.map_or(self, |i| &self.archs[i])
+    }
+    
+    // Switches to the ISA with the name, which is either this one or one of the others
+    fn switch_arch(&self, name: &str, directives: &mut Directives) -> Result<(), String> {
+        if name == self.arch {
+            directives.arch = None;
+            return Ok(());
+        }
+        match self.archs.iter().position(|a| a.arch == name) {
+            Some(i) => {
+                directives.arch = Some(i);
+                Ok(())
+            },
+            None => {
+                let names: Vec<_> = std::iter::once(&self.arch).chain(self.archs.iter().map(|a| &a.arch)).map(|a| format!("'{}'", a)).collect();
+                Err(format!("unknown ISA '{}'; '.arch' can switch to {}", name, names.join(", ")))
+            },
+        }
+    }
+    
+    // Settings tools outside the crate give an assembler, which the prelude keeps stable while
+    // the fields change with the assembler's own needs
+    pub fn set_cancel(&mut self, cancel: Option<Cancel>) { self.cancel = cancel; }
+    pub fn set_progress(&mut self, progress: Option<Progress>) { self.progress = progress; }
+    pub fn set_seed(&mut self, seed: u64) { self.seed = seed; }
+    pub fn set_limits(&mut self, limits: Limits) { self.limits = limits; }
+    pub fn set_keep_going(&mut self, keep_going: bool) { self.keep_going = keep_going; }
+    pub fn regions(&self) -> &[Region] { &self.regions }
+    
+    // The mnemonics of the ISA, sorted
+    pub fn mnemonics(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.instructions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+    
+    // Sets the bytes placed at the start of a region, or at its end for the postamble
+    pub fn set_amble(&mut self, name: &str, postamble: bool, bytes: Vec<u8>) -> Result<(), String> {
+        let region = self.regions.iter_mut().find(|r| r.name == name).ok_or_else(|| format!("unknown region '{}'", name))?;
+        match postamble {
+            false => region.preamble = bytes,
+            true => region.postamble = bytes,
+        }
+        region.check_ambles()
+    }
+    
+    // Lays memory out in other regions, as a linker script or a manifest does, keeping the preamble
+    // and postamble the config gave each region of the same name
+    pub fn replace_regions(&mut self, regions: Vec<Region>) {
+        let previous = std::mem::replace(&mut self.regions, regions);
+        for region in &mut self.regions {
+            if let Some(given) = previous.iter().find(|r| r.name == region.name) {
+                if region.preamble.is_empty() && region.postamble.is_empty() {
+                    (region.preamble, region.postamble) = (given.preamble.clone(), given.postamble.clone());
+                }
+            }
+        }
+    }
+    
+    // Category `.category` gives the mnemonic. A form for the mode of a prefix has the category of
+    // its mnemonic.
+    pub fn category(&self, name: &str) -> Option<Category> {
+        let mnemonic = name.rsplit(' ').next().unwrap_or(name);
+        self.categories.iter().find(|(m, _)| m == name || m == mnemonic).map(|(_, category)| *category)
+    }
+    
+    // Whether the instruction can change where execution continues, which it can when it is a
+    // branch by its category or assigns `pc` in the semantics of one of its forms
+    pub fn alters_flow(&self, name: &str) -> bool {
+        self.category(name) == Some(Category::Branch) || self.instructions.get(name).is_some_and(|instruction| instruction.patterns().iter().any(|pattern| {
+            let statements = pattern.semantics.and_then(|s| semantics::parse(s).ok()).unwrap_or_default();
+            statements.iter().any(|s| matches!(s, semantics::Statement::Assign(semantics::Place::Pc, _)))
+        }))
+    }
+    
+    // Why the assembly has to stop, when the caller has cancelled it or its time is up
+    pub(crate) fn cancelled(&self) -> Option<String> {
+        self.cancel.as_ref().and_then(Cancel::reason)
+    }
+    
+    // Counts a statement the pass has encoded, reporting every so many
+    fn encoded(&self, directives: &mut Directives) {
+        directives.statements += 1;
+        if let (Some(progress), 0) = (&self.progress, directives.statements % STATEMENTS_PER_EVENT) {
+            progress.report(Event::Encoded { statements: directives.statements });
+        }
+    }
+    
     pub fn assemble(&self, source: &str) -> LoggedResult<Vec<u8>> {
-        let origin = "[unknown]";
-        let mut captured_registers = Vec::new();
-        let mut captured_immediates = Vec::new();
-        let mut output = Vec::new();
+        self.assemble_source("[unknown]", source)
+    }
+    
+    pub fn assemble_lines<I: IntoIterator<Item = S>, S: AsRef<str>>(&self, lines: I) -> LoggedResult<Vec<u8>> {
+        let lines: Vec<_> = lines.into_iter().collect();
+        let sources = Sources::new(&Disk);
+        converge(self.cancel.as_ref(), self.progress.as_ref(), |symbols| self.pass(&sources, &[("[unknown]", &lines)], &[], symbols)).map(|image| image.code)
+    }
+    
+    // Assembles the whole source in as many passes as it takes for label addresses to settle
+    pub fn assemble_source(&self, origin: &str, source: &str) -> LoggedResult<Vec<u8>> {
+        self.assemble_image(origin, source).map(|image| image.code)
+    }
+    
+    // Like `assemble_source`, but also returns the code of each `.bank`
+    pub fn assemble_image(&self, origin: &str, source: &str) -> LoggedResult<Image> {
+        let lines: Vec<_> = source.lines().collect();
+        let sources = Sources::new(&Disk);
+        converge(self.cancel.as_ref(), self.progress.as_ref(), |symbols| self.pass(&sources, &[(origin, &lines)], &[], symbols))
+    }
+    
+    // Assembles the files one after another as a single program, with the symbols in `defines`
+    // defined before the first line
+    pub fn assemble_files(&self, files: &[(String, String)], defines: &[(String, usize)]) -> LoggedResult<Image> {
+        self.assemble_files_with_passes(files, defines).0
+    }
+    
+    // Like `assemble_files`, but also returns the symbols each pass defined, first to last
+    pub fn assemble_files_with_passes(&self, files: &[(String, String)], defines: &[(String, usize)]) -> (LoggedResult<Image>, Vec<SymbolTable>) {
+        self.assemble_through(&Disk, files, defines)
+    }
+    
+    // Assembles the files the provider gives for the paths, reading the files they include through
+    // it as well, so sources that only exist in memory are reported by their paths
+    pub fn assemble_from(&self, provider: &dyn FileProvider, paths: &[&str], defines: &[(String, usize)]) -> LoggedResult<Image> {
+        let mut logger = Logger::new(None);
+        let mut files = Vec::new();
+        for path in paths {
+            match provider.read(path) {
+                Ok(source) => files.push((path.to_string(), source)),
+                Err(message) => logger.log_error(format!("could not read '{}': {}", path, message)),
+            }
+        }
+        if logger.is_error() {
+            return logger.into_none();
+        }
+        self.assemble_through(provider, &files, defines).0
+    }
+    
+    fn assemble_through(&self, provider: &dyn FileProvider, files: &[(String, String)], defines: &[(String, usize)]) -> (LoggedResult<Image>, Vec<SymbolTable>) {
+        let mut passes = Vec::new();
+        let lines: Vec<Vec<_>> = files.iter().map(|(_, source)| source.lines().collect()).collect();
+        let files: Vec<_> = files.iter().zip(&lines).map(|((origin, _), lines)| (origin.as_str(), lines.as_slice())).collect();
+        let origins: Vec<_> = files.iter().map(|(origin, _)| *origin).collect();
+        let sources = Sources::new(provider);
+        let image = converge(self.cancel.as_ref(), self.progress.as_ref(), |symbols| {
+            let (image, symbols) = self.pass(&sources, &files, defines, symbols);
+            passes.push(symbols.clone());
+            (image, symbols)
+        });
+        (image.group_by_file(&origins), passes)
+    }
+    
+    // One pass over the sources, returning the code along with the symbols it defined
+    fn pass<S: AsRef<str>>(&self, sources: &Sources, files: &[(&str, &[S])], defines: &[(String, usize)], symbols: SymbolTable) -> (LoggedResult<Image>, SymbolTable) {
+        let mut output = Image::default();
         let mut logger = Logger::new(None);
         
-        'outer: for (line, source) in source.lines().enumerate() {
-            let mut lexer = Lexer::new(source);
-            logger.origin = Some(Origin { file: origin.to_owned(), line });
-            captured_registers.clear();
-            
-            if let Some(lexeme) = lexer.next() {
-                match lexeme.token {
-                    // Instruction
-                    Token::Ident(ident) => {
-                        let name = ident.to_lowercase();
-                        let instruction = if let Some(ins) = self.instructions.get(&name) {
-                            ins
-                        } else {
-                            logger.log_error(format!("unknown instruction: '{}'", lexeme.slice));
-                            continue;
-                        };
-                        
-                        let mut current_state = 0;
-                        
-                        let codegen = loop {
-                            match lexer.next() {
-                                Some(Lexeme{ token: Token::Integer(int), slice }) => {
-                                    if let Transition::NextState(next) = instruction.states[current_state].immediate {
-                                        captured_immediates.push(int);
-                                        current_state = next;
-                                    } else {
-                                        logger.log_error(format!("unexpected immediate: '{}'", slice));
-                                        logger.log_error(format!("syntaxes available for {}: {:?}", name, instruction.syntaxes));
-                                        continue 'outer;
-                                    }
-                                },
-                                
-                                Some(Lexeme{ token: Token::Register(r), slice }) => {
-                                    if let Transition::NextState(next) = instruction.states[current_state].register {
-                                        if r > 15 {
-                                            logger.log_error(format!("register out of bounds: '{}'", slice));
-                                            continue 'outer;
-                                        }
-                                        captured_registers.push(r as u8);
-                                        current_state = next;
-                                    } else {
-                                        logger.log_error(format!("unexpected register: '{}'", slice));
-                                        logger.log_error(format!("syntaxes available for {}: {:?}", name, instruction.syntaxes));
-                                        continue 'outer;
-                                    }
-                                },
-                                
-                                Some(Lexeme{ token: Token::Comma, .. }) => {
-                                    if let Transition::NextState(next) = instruction.states[current_state].comma {
-                                        current_state = next;
-                                    } else {
-                                        logger.log_error("unexpected comma".to_owned());
-                                        logger.log_error(format!("syntaxes available for {}: {:?}", name, instruction.syntaxes));
-                                        continue 'outer;
-                                    }
-                                },
-                                
-                                None => {
-                                    if let Some(ref codegen) = instruction.states[current_state].accept_codegen {
-                                        break codegen;
-                                    } else {
-                                        logger.log_error("syntax error".to_owned());
-                                        logger.log_error(format!("syntaxes available for {}: {:?}", name, instruction.syntaxes));
-                                        continue 'outer;
-                                    }
-                                },
-                                
-                                Some(Lexeme{ slice, .. }) => {
-                                    logger.log_error(format!("unexpected token: '{}'", slice));
-                                    logger.log_error(format!("syntaxes available for {}: {:?}", name, instruction.syntaxes));
-                                    continue 'outer;
-                                },
+        let mut preprocessor = Preprocessor::with_macros("", &self.macros);
+        preprocessor.set_dialect(self.dialect);
+        preprocessor.set_seed(self.seed);
+        preprocessor.set_limits(self.limits);
+        let mut directives = Directives::with_layout(symbols, &self.regions, &self.placements);
+        let source: Vec<_> = files.iter().flat_map(|(_, lines)| lines.iter().map(AsRef::as_ref)).collect();
+        directives.budgets = self.budgets.clone();
+        directives.nop = self.nop.clone();
+        directives.gc_sections = self.gc_sections;
+        if self.listing {
+            directives.listing = Some(Vec::new());
+        }
+        if self.xref {
+            directives.references = Some(Vec::new());
+        }
+        if self.program {
+            directives.program = Some(Vec::new());
+        }
+        directives.build_id = Some(BuildId { source: hash_source(&source.join("\n")), config: self.config_hash, timestamp: self.timestamp });
+        for (name, value) in defines {
+            if let Err(message) = directives.symbols.define(name, *value) {
+                logger.log_error(message);
+            }
+        }
+        
+        for (origin, lines) in files {
+            preprocessor.set_file(origin);
+            self.pass_file(&mut logger, &mut preprocessor, &mut directives, origin, lines, sources);
+        }
+        if let Some(reason) = self.cancelled() {
+            logger.origin = None;
+            logger.log_error(reason);
+            return (logger.into_none(), directives.symbols);
+        }
+        directives.finish().if_ok(&mut logger, |image| output = image);
+        
+        if self.keep_going {
+            return (logger.into_partial(output), directives.symbols);
+        }
+        (logger.into_result(||output), directives.symbols)
+    }
+    
+    fn pass_file<S: AsRef<str>>(&self, logger: &mut Logger, preprocessor: &mut Preprocessor, directives: &mut Directives, origin: &str, lines: &[S], sources: &Sources) {
+        let mut comments = BlockComments::new();
+        for (line, written) in lines.iter().enumerate() {
+            let includes = self.pass_line(logger, preprocessor, directives, (origin, line), written.as_ref(), &mut comments);
+            if self.cancelled().is_some() {
+                return;
+            }
+            for included in includes {
+                match included {
+                    Included::File(path) => self.include(logger, preprocessor, directives, origin, &path, sources),
+                    Included::Command(command) => self.include_command(logger, preprocessor, directives, origin, &command, sources),
+                }
+            }
+        }
+        if let Some(line) = comments.unclosed() {
+            log_unclosed(logger, origin, line, lines[line].as_ref());
+        }
+        preprocessor.finish().if_ok(logger, |_| {});
+    }
+    
+    // Assembles the statements of a line, emitting their code to the current section, and returns
+    // the files it names with `.include` for the caller to assemble once the line is. Every path
+    // that assembles source goes through it, so none skips the checks made on each statement.
+    fn pass_line(&self, logger: &mut Logger, preprocessor: &mut Preprocessor, directives: &mut Directives, (origin, line): (&str, usize), written: &str, comments: &mut BlockComments) -> Vec<Included> {
+        // Statements are read with comments blanked out, and the line is listed as written
+        logger.origin = Some(Origin::with_text(origin, line, written));
+        let source = &comments.line(line, written);
+        self.check_identifiers(logger, source);
+        let mut statements = Vec::new();
+        preprocessor.process(line, source).if_ok(logger, |s| statements = s);
+        // A line with a macro invocation is listed before the statements it expanded to, which
+        // are indented like the line
+        let indent = &source[..source.len() - source.trim_start().len()];
+        let mut row = Row { file: origin.to_owned(), line: Some(line), address: directives.address(), text: written.to_owned(), ..Row::default() };
+        let mut expansion = Vec::new();
+        // Files named by `.include`, assembled once the line is
+        let mut includes = Vec::new();
+        // Where the statements before are written in the line, and the macro invocation
+        // written in it that later statements were expanded from
+        let mut cursor = 0;
+        let mut invocation = None;
+        let mut statements = statements.iter();
+        for expanded in preprocessor.expanded() {
+            let mut address = directives.address();
+            let depth = match expanded {
+                Expanded::Statement(depth) => *depth,
+                Expanded::Invocation(depth, text) => {
+                    if let (Some(origin), 0) = (&mut logger.origin, depth) {
+                        origin.locate(source, &mut cursor, text);
+                        invocation = origin.statement.clone();
+                    }
+                    self.track_stack(logger, text, directives);
+                    // The line itself is listed for invocations written in it
+                    if *depth > 0 {
+                        expansion.push(Row { file: origin.to_owned(), line: None, address, text: format!("{}{}", indent, text), depth: *depth, ..Row::default() });
+                    }
+                    continue;
+                },
+            };
+            let statement = match statements.next() {
+                Some(statement) => statement,
+                None => break,
+            };
+            if let Some(origin) = &mut logger.origin {
+                match depth {
+                    0 => origin.locate(source, &mut cursor, statement),
+                    _ => origin.expanded_from(invocation.clone()),
+                }
+            }
+            if self.cancelled().is_some() {
+                return Vec::new();
+            }
+            match include(statement) {
+                Some(Ok(included)) => {
+                    includes.push(included);
+                    continue;
+                },
+                Some(Err(message)) => {
+                    logger.log_error(message);
+                    continue;
+                },
+                None => {},
+            }
+            if let Some(name) = arch(statement) {
+                if let Err(message) = name.and_then(|name| self.switch_arch(name, directives)) {
+                    logger.log_error(message);
+                }
+                continue;
+            }
+            // Instructions are encoded by the ISA `.arch` chose, and rewritten only in the
+            // assembler's own and while the code before them can still be taken back, which
+            // code already streamed out can't
+            let isa = self.isa(directives);
+            let rewritable = directives.arch.is_none() && !directives.streaming;
+            let mut cycles = None;
+            // Addresses of instructions, as opposed to data
+            let mut instructions = Vec::new();
+            // Operands truncated or relaxed, listed under the row
+            let mut notes = Vec::new();
+            let mut code = None;
+            if let Some(jump) = (rewritable && self.thread_jumps).then(|| self.fall_through(statement, directives)).flatten() {
+                address = directives.address();
+                unlist(&mut expansion, &mut row, directives.listing.as_mut(), address, jump.length);
+            }
+            directives.process(statement).if_ok(logger, |c| code = Some(c));
+            directives.record_references(statement, Site { file: origin.to_owned(), line, address });
+            let code = match code {
+                Some(Some(code)) => {
+                    directives.record_item(origin, line, address, Item::Bytes(code.clone()));
+                    code
+                },
+                Some(None) => {
+                    let mut code = isa.align(logger, statement, directives);
+                    let padding = code.len();
+                    let mut item = None;
+                    isa.lower_statement_with(statement, directives).if_ok(logger, |i| item = Some(i));
+                    let site = format!("{}:{}", origin, line + 1);
+                    // Text and site the statement is reported by if a rewrite takes it back
+                    let mut reported = None;
+                    if let Some(mut lowered) = item.take_if(|_| rewritable && (self.peephole || self.thread_jumps || self.fill_delay_slots)) {
+                        if let (Item::Operation(operation), true) = (&mut lowered, self.thread_jumps) {
+                            *operation = self.redirect(statement, &site, std::mem::take(operation), directives);
+                        }
+                        let rewrite = self.rewrite(logger, statement, site.clone(), lowered, padding == 0, directives);
+                        if let Some(length) = rewrite.retracted {
+                            address = directives.address();
+                            unlist(&mut expansion, &mut row, directives.listing.as_mut(), address, length);
+                        }
+                        item = Some(rewrite.item);
+                        reported = Some((rewrite.text, rewrite.site));
+                    }
+                    // Instruction moved into the delay slot of the branch, which goes after it
+                    let mut hoisted = None;
+                    if let Some(Item::Operation(operation)) = item.as_mut().filter(|_| rewritable && self.fill_delay_slots) {
+                        if let Some((branch, previous)) = self.hoist(statement, operation, padding == 0, directives) {
+                            address = directives.address();
+                            unlist(&mut expansion, &mut row, directives.listing.as_mut(), address, previous.length);
+                            *operation = branch;
+                            hoisted = Some(previous);
+                        }
+                    }
+                    if let Some(item) = item {
+                        let encoded = item.encode(logger);
+                        let length = encoded.len();
+                        if let Item::Operation(operation) = &item {
+                            let adjustments = operation.adjustments(statement, origin, line, address + padding);
+                            notes.extend(adjustments.iter().map(Adjustment::describe));
+                            directives.adjustments.extend(adjustments);
+                        }
+                        if let (Item::Operation(operation), Some((text, site)), false) = (&item, reported, encoded.is_empty()) {
+                            if self.thread_jumps {
+                                self.mark_trampoline(operation, address + padding, directives);
+                            }
+                            directives.previous = Some(Previous { operation: operation.clone(), text, site, address: address + padding, length: encoded.len() });
+                        }
+                        code.extend(encoded);
+                        let mut slots = Vec::new();
+                        if let Item::Operation(operation) = &item {
+                            cycles = isa.count_cycles(&operation.mnemonic, directives);
+                            if !self.delay_slots.is_empty() {
+                                self.check_delay_slot(logger, operation, &site, statement.trim(), directives);
                             }
-                        };
-                        
-                        let decode = |codegen: &CodegenData| match *codegen {
-                            CodegenData::Byte(b) => b,
-                            CodegenData::Register(r) => captured_registers[r],
-                            CodegenData::Immediate(imm, _) => captured_immediates[imm] as u8,
-                        };
-                        
-                        for data in codegen {
-                            match data {
-                                Codegen::Data(data) => {
-                                    match *data {
-                                        CodegenData::Immediate(imm, b) => {
-                                            let imm = captured_immediates[imm];
-                                            if imm.leading_zeros() < (64-b+1) as u32 {
-                                                logger.log_warning(format!("'{}' will be truncated to {} bits", imm, b));
-                                            }
-                                            let bytes = b / 8;
-                                            output.extend(&imm.to_le_bytes()[..bytes]);
-                                        },
-                                        _ => output.push(decode(data)),
-                                    }
-                                },
-                                Codegen::UpperLower(upper, lower) => {
-                                    let upper = decode(upper);
-                                    let lower = decode(lower);
-                                    output.push((upper & 0xF) << 4 | (lower & 0xF));
-                                }
+                            let executed = Previous { operation: operation.clone(), text: statement.trim().to_owned(), site: site.clone(), address: address + padding, length };
+                            self.check_hazards(logger, executed, directives);
+                            if self.fill_delay_slots {
+                                let moved = hoisted.as_ref().map(|h| (h.text.clone(), h.site.clone()));
+                                slots = self.fill_slots(logger, operation, statement.trim(), &site, hoisted, directives).into_iter().map(|slot| (slot, moved.clone())).collect();
                             }
                         }
-                    },
-                    
-                    _ => logger.log_error(format!("unexpected token: '{}'", lexeme.slice))
+                        directives.record_item(origin, line, address + padding, item);
+                        // The hoisted instruction was warned about where it was written
+                        for (slot, moved) in slots {
+                            let slot_address = address + code.len();
+                            let encoded = slot.encode(&mut Logger::new(None));
+                            if let (Item::Operation(operation), Some((text, site))) = (&slot, moved) {
+                                let executed = Previous { operation: operation.clone(), text, site, address: slot_address, length: encoded.len() };
+                                self.check_hazards(logger, executed, directives);
+                            }
+                            code.extend(encoded);
+                            directives.record_item(origin, line, slot_address, slot);
+                        }
+                    }
+                    self.track_stack(logger, statement, directives);
+                    if code.len() > padding {
+                        instructions.push(address + padding);
+                        if !self.archs.is_empty() {
+                            if let Err(message) = directives.mark_arch(&isa.arch) {
+                                logger.log_error(message);
+                            }
+                        }
+                    }
+                    code
+                },
+                None => Vec::new(),
+            };
+            directives.emit(&code);
+            self.encoded(directives);
+            match depth {
+                0 => {
+                    row.code.extend(&code);
+                    row.instructions.extend(instructions);
+                    row.notes.extend(notes);
+                    if let Some((cycles, total)) = cycles {
+                        row.cycles = Some((row.cycles.map_or(0, |(c, _)| c) + cycles, total));
+                    }
+                },
+                _ => expansion.push(Row { file: origin.to_owned(), line: None, address, code, text: format!("{}{}", indent, statement.trim()), depth, cycles, instructions, notes }),
+            }
+        }
+        if let Some(listing) = &mut directives.listing {
+            listing.push(row);
+            listing.extend(expansion);
+        }
+        includes
+    }
+    
+    // Assembles the file an `.include` names in place of it, relative to the including file
+    fn include(&self, logger: &mut Logger, preprocessor: &mut Preprocessor, directives: &mut Directives, including: &str, path: &str, sources: &Sources) {
+        let resolved = files::resolve(including, path);
+        if resolved == including || directives.including.contains(&resolved) {
+            logger.log_error(format!("'{}' includes itself", resolved));
+            return;
+        }
+        if !self.nests(logger, directives, including, &resolved) {
+            return;
+        }
+        let source = match sources.provider.read(&resolved) {
+            Ok(source) => source,
+            Err(message) => {
+                logger.log_error(format!("could not include '{}': {}", resolved, message));
+                return;
+            },
+        };
+        self.pass_included(logger, preprocessor, directives, including, (&resolved, &source), sources);
+    }
+    
+    // Assembles the output of the command an `.include_cmd` names in place of it, which is run by
+    // the shell in the directory of the including file. Diagnostics name the output after the
+    // command, and files it includes are relative to the working directory.
+    fn include_command(&self, logger: &mut Logger, preprocessor: &mut Preprocessor, directives: &mut Directives, including: &str, command: &str, sources: &Sources) {
+        if !self.include_commands {
+            logger.log_error(format!("'.include_cmd' would run `{}`, but running commands is only allowed with '--allow-include-cmd'", command));
+            return;
+        }
+        let name = format!("`{}`", command);
+        if name == including || directives.including.contains(&name) {
+            logger.log_error(format!("{} includes itself", name));
+            return;
+        }
+        if !self.nests(logger, directives, including, &name) {
+            return;
+        }
+        let dir = Path::new(including).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut outputs = sources.commands.borrow_mut();
+        let key = format!("{}\n{}", dir.display(), command);
+        let output = match outputs.iter().find(|(k, _)| *k == key) {
+            Some((_, output)) => output.clone(),
+            None => {
+                let output = run_command(dir, command);
+                outputs.push((key, output.clone()));
+                output
+            },
+        };
+        drop(outputs);
+        match output {
+            Ok(source) => self.pass_included(logger, preprocessor, directives, including, (&name, &source), sources),
+            Err(message) => logger.log_error(format!("could not include the output of `{}`: {}", command, message)),
+        }
+    }
+    
+    // Whether a file can be included without nesting includes deeper than the limit, reporting
+    // the files that include each other when it can't
+    fn nests(&self, logger: &mut Logger, directives: &Directives, including: &str, included: &str) -> bool {
+        if directives.including.len() + 1 < self.limits.depth {
+            return true;
+        }
+        logger.log_error(format!("'.include' nests deeper than {} files", self.limits.depth));
+        let files: Vec<_> = directives.including.iter().map(String::as_str).chain([including, included]).map(str::to_owned).collect();
+        logger.log_note(format!("files including each other, outermost first: {}", invocation_stack(&files)));
+        false
+    }
+    
+    // Assembles the included file, given by its name and source, in place of the statement
+    fn pass_included(&self, logger: &mut Logger, preprocessor: &mut Preprocessor, directives: &mut Directives, including: &str, (name, source): (&str, &str), sources: &Sources) {
+        let lines: Vec<_> = source.lines().collect();
+        directives.including.push(including.to_owned());
+        let saved = preprocessor.enter_file(name);
+        self.pass_file(logger, preprocessor, directives, name, &lines, sources);
+        preprocessor.leave_file(saved);
+        directives.including.pop();
+    }
+    
+    // Reports identifiers holding letters outside of ASCII unless the config allows them
+    fn check_identifiers(&self, logger: &mut Logger, source: &str) {
+        if self.unicode_identifiers {
+            return;
+        }
+        for Lexeme { token, span, .. } in Lexer::new(source) {
+            match token {
+                Token::Ident(name) if !name.is_ascii() => logger.log_error_at(span, format!("'{}' holds letters outside of ASCII, which identifiers can only hold with '.identifiers unicode' in the config", name)),
+                _ => {},
+            }
+        }
+    }
+    
+    // Padding before an instruction `.instruction_align` requires to be aligned, which moves the
+    // statement and any labels just before it. Misaligned instructions are errors without
+    // `.misaligned pad`.
+    pub(crate) fn align(&self, logger: &mut Logger, statement: &str, directives: &mut Directives) -> Vec<u8> {
+        let alignment = match mnemonic(statement, &self.mnemonic_chars).and_then(|m| self.alignments.iter().find(|(a, _)| *a == m)) {
+            Some((_, alignment)) => *alignment,
+            None => return Vec::new(),
+        };
+        match self.misaligned {
+            Misaligned::Pad => fill(&self.nop, directives.align(alignment)),
+            Misaligned::PadWith(byte) => vec![byte; directives.align(alignment)],
+            Misaligned::Error => {
+                let address = directives.address();
+                if !address.is_multiple_of(alignment) {
+                    let span = Lexer::new(statement).next().map(|l| l.span);
+                    logger.log_at(LogLevel::Error, span, format!("'{}' must be placed at a multiple of {} bytes, but is at {:#06x}", mnemonic(statement, &self.mnemonic_chars).unwrap_or_default(), alignment, address));
+                }
+                Vec::new()
+            },
+        }
+    }
+    
+    // Cycles the instruction takes and the total since its basic block started, which ends at a
+    // branch or a return. Only counted for listings.
+    fn count_cycles(&self, mnemonic: &str, directives: &mut Directives) -> Option<(usize, usize)> {
+        directives.listing.as_ref()?;
+        let cycles = self.cycles.iter().find(|(m, _)| m == mnemonic)?.1;
+        directives.block_cycles += cycles;
+        let total = directives.block_cycles;
+        if self.alters_flow(mnemonic) || self.returns.iter().any(|r| r == mnemonic) {
+            directives.block_cycles = 0;
+        }
+        Some((cycles, total))
+    }
+    
+    // Follows the stack depth through the instructions and macros the config gives a stack effect,
+    // and at a return checks the routine popped everything it pushed
+    fn track_stack(&self, logger: &mut Logger, statement: &str, directives: &mut Directives) {
+        let mnemonic = match mnemonic(statement, &self.mnemonic_chars) {
+            Some(mnemonic) => mnemonic,
+            None => return,
+        };
+        if let Some((_, effect)) = self.stack_effects.iter().find(|(m, _)| *m == mnemonic) {
+            directives.stack_depth += effect;
+        }
+        if !self.returns.contains(&mnemonic) {
+            return;
+        }
+        let routine = directives.routine.take().map_or_else(|| "the routine".to_owned(), |label| format!("'{}'", label));
+        if directives.stack_depth != 0 {
+            logger.log_warning(format!("'{}' returns from {} with unbalanced pushes and pops (stack depth {:+})", mnemonic, routine, directives.stack_depth));
+        }
+        directives.stack_depth = 0;
+    }
+    
+    // Assembles line by line from the reader, writing code as it is produced so neither the source
+    // nor the output has to fit in memory. Nothing more is written once an error has been logged,
+    // unless the assembler keeps going.
+    // This is a single pass, so labels can only be referenced after they are defined.
+    pub fn assemble_reader<R: BufRead, W: Write>(&self, origin: &str, reader: R, mut writer: W) -> std::io::Result<LoggedResult<usize>> {
+        let mut written = 0;
+        let mut logger = Logger::new(None);
+        let mut preprocessor = Preprocessor::with_macros(origin, &self.macros);
+        preprocessor.set_dialect(self.dialect);
+        preprocessor.set_seed(self.seed);
+        preprocessor.set_limits(self.limits);
+        let mut directives = Directives::with_symbols(SymbolTable::new(), &self.regions);
+        directives.streaming = true;
+        directives.nop = self.nop.clone();
+        let sources = Sources::new(&Disk);
+        let mut comments = BlockComments::new();
+        // The line a comment that is still open starts on, since the lines aren't kept
+        let mut opened = None;
+        
+        for (line, text) in reader.lines().enumerate() {
+            let text = text?;
+            // As `files::decode` would, though only a whole line is read at a time
+            let text = text.strip_prefix('\u{feff}').filter(|_| line == 0).unwrap_or(&text).trim_end_matches('\r').to_owned();
+            let includes = self.pass_line(&mut logger, &mut preprocessor, &mut directives, (origin, line), &text, &mut comments);
+            if comments.unclosed() == Some(line) {
+                opened = Some(text);
+            }
+            // What was written stays written, but nothing more is
+            if let Some(reason) = self.cancelled() {
+                logger.origin = None;
+                logger.log_error(reason);
+                writer.flush()?;
+                return Ok(logger.into_none());
+            }
+            // Included files are assembled into the section like the line, and written out with it
+            for included in includes {
+                match included {
+                    Included::File(path) => self.include(&mut logger, &mut preprocessor, &mut directives, origin, &path, &sources),
+                    Included::Command(command) => self.include_command(&mut logger, &mut preprocessor, &mut directives, origin, &command, &sources),
+                }
+            }
+            let code = directives.take_code();
+            if !logger.is_error() || self.keep_going {
+                writer.write_all(&code)?;
+                written += code.len();
+            }
+        }
+        if let (Some(line), Some(text)) = (comments.unclosed(), &opened) {
+            log_unclosed(&mut logger, origin, line, text);
+            logger.origin = None;
+        }
+        preprocessor.finish().if_ok(&mut logger, |_| {});
+        // Only the literals placed at the end are left in the section
+        let mut code = Vec::new();
+        directives.finish().if_ok(&mut logger, |image| code = image.code);
+        if !logger.is_error() || self.keep_going {
+            writer.write_all(&code)?;
+            written += code.len();
+        }
+        writer.flush()?;
+        
+        if self.keep_going {
+            return Ok(logger.into_partial(written));
+        }
+        Ok(logger.into_result(||written))
+    }
+    
+    // Encodes a single line of source into its own buffer
+    pub fn assemble_statement(&self, source: &str) -> LoggedResult<Vec<u8>> {
+        self.assemble_statement_with(source, &Directives::new())
+    }
+    
+    // Encodes a single line of source, resolving symbols and literals from the directives so far
+    pub fn assemble_statement_with(&self, source: &str, directives: &Directives) -> LoggedResult<Vec<u8>> {
+        let mut logger = Logger::new(None);
+        let mut item = None;
+        self.lower_statement_with(source, directives).if_ok(&mut logger, |i| item = Some(i));
+        let output = item.map(|item| item.encode(&mut logger));
+        match output {
+            Some(output) if !logger.is_error() => logger.into_result(|| output),
+            _ => logger.into_none(),
+        }
+    }
+    
+    // Matches a single line of source against the config, without encoding it
+    pub fn lower_statement_with(&self, source: &str, directives: &Directives) -> LoggedResult<Item> {
+        let mut logger = Logger::new(None);
+        let mut statement = None;
+        parse_statement_with(source, &self.mnemonic_chars).if_ok(&mut logger, |s| statement = Some(s));
+        let item = statement.and_then(|statement| self.lower_statement(&mut logger, source, statement, directives));
+        match item {
+            Some(item) if !logger.is_error() => logger.into_result(|| item),
+            _ => logger.into_none(),
+        }
+    }
+    
+    // Notes that the statement is encoded with a form declared after one with the same operands
+    // that an immediate doesn't fit, as `ld r0, i0:16` is for `ld r0, 300` after `ld r0, i0:8`,
+    // since the longer encoding is easy to miss
+    // Returns the immediate that didn't fit and the syntax of the form skipped
+    fn note_overload(&self, logger: &mut Logger, name: &str, instruction: &Instruction, skipped: &[Codegen], chosen: &[Codegen], immediates: &[Immediate]) -> Option<(usize, String)> {
+        let data = skipped.iter().flat_map(|codegen| match codegen {
+            Codegen::Data(data) => vec![data],
+            Codegen::UpperLower(upper, lower) => vec![upper, lower],
+            Codegen::Guard(_) => Vec::new(),
+        });
+        let overflow = data.into_iter().find_map(|data| match *data {
+            CodegenData::Immediate(imm, width) => immediates.get(imm).filter(|i| !fits_in(i.value, width)).map(|i| (imm, i, width)),
+            _ => None,
+        });
+        let patterns = instruction.patterns();
+        let syntax = |codegen: &[Codegen]| patterns.iter().find(|p| std::ptr::eq(p.codegen, codegen)).map(|p| p.styled_syntax(name, self.src_first, &self.style));
+        let ((index, immediate, width), skipped, chosen) = (overflow?, syntax(skipped)?, syntax(chosen)?);
+        let value = match immediate.value as isize {
+            value if value < 0 => value.to_string(),
+            _ => immediate.value.to_string(),
+        };
+        logger.log_note_at(immediate.span.clone(), format!("encoded as '{}', since {} doesn't fit in the {} bits of '{}', which is declared first", chosen, value, width, skipped));
+        Some((index, skipped))
+    }
+    
+    // Labels are defined by the directives before a statement gets here, so only what follows
+    // one is lowered
+    fn lower_statement<'a>(&self, logger: &mut Logger, source: &'a str, statement: Statement<'a>, directives: &Directives) -> Option<Item> {
+        match statement {
+            Statement::Instruction { mnemonic, operands } => self.lower(logger, source, &mnemonic, &operands, directives).map(Item::Operation),
+            Statement::Bundle(instructions, span) => self.encode_bundle(logger, source, &instructions, span, directives).map(Item::Bytes),
+            Statement::Label(_, statement) => self.lower_statement(logger, source, *statement, directives),
+            Statement::Empty => Some(Item::Bytes(Vec::new())),
+        }
+    }
+    
+    // Encodes the instructions of a bundle with the first format whose slots accept them in order
+    fn encode_bundle<'a>(&self, logger: &mut Logger, source: &'a str, instructions: &[Statement<'a>], span: Span, directives: &Directives) -> Option<Vec<u8>> {
+        let mut mnemonics = Vec::new();
+        let mut codes = Vec::new();
+        for instruction in instructions {
+            if let Statement::Instruction { mnemonic, operands } = instruction {
+                mnemonics.push(mnemonic.node.to_lowercase());
+                codes.push(self.lower(logger, source, mnemonic, operands, directives).map(|operation| operation.encode(logger)));
+            }
+        }
+        let accepts = |format: &&BundleFormat| format.slots.len() == mnemonics.len()
+            && format.slots.iter().zip(&mnemonics).all(|(slot, mnemonic)| self.slots.get(slot).is_some_and(|m| m.contains(mnemonic)));
+        let format = match self.bundles.iter().find(accepts) {
+            Some(format) => format,
+            None => {
+                logger.log_error_at(span, format!("no bundle format accepts '{}'", mnemonics.join(" ; ")));
+                let formats: Vec<_> = self.bundles.iter().map(|f| f.slots.join(", ")).collect();
+                logger.log_error(format!("bundle formats available: {:?}", formats));
+                return None;
+            },
+        };
+        let mut output = format.header.clone();
+        for code in codes {
+            output.extend(code?);
+        }
+        Some(output)
+    }
+    
+    // Warns about reserved registers the instruction writes. The semantics tell which operands are
+    // written; without them, the first register operand is taken to be the destination.
+    fn check_reserved(&self, logger: &mut Logger, semantics: Option<&str>, registers: &[u8], spans: &[Span], directives: &Directives) {
+        let mut written: Vec<usize> = match semantics.and_then(|s| semantics::parse(s).ok()) {
+            Some(statements) => statements.iter().filter_map(|statement| match statement {
+                semantics::Statement::Assign(semantics::Place::Register(r), _) => Some(*r),
+                _ => None,
+            }).collect(),
+            None => vec![0],
+        };
+        written.sort_unstable();
+        written.dedup();
+        for operand in written {
+            let register = match registers.get(operand) {
+                Some(register) => *register as usize,
+                None => continue,
+            };
+            if let Some((_, role)) = self.reserved.iter().find(|(r, _)| *r == register) {
+                if !directives.allows(register) {
+                    logger.log_warning_at(spans[operand].clone(), format!("r{} is reserved as '{}'; write it with '.allow r{}' in effect if this is intended", register, role, register));
                 }
             }
         }
+    }
+    
+    // Two operands written source first are swapped back into the order of the config
+    fn lower<'a>(&self, logger: &mut Logger, source: &'a str, mnemonic: &Spanned<'a, &'a str>, operands: &[Operand<'a>], directives: &Directives) -> Option<Operation> {
+        let (prefixes, mnemonic, operands) = self.split_prefixes(logger, mnemonic, operands)?;
+        let name = mnemonic.node.to_lowercase();
+        // The form for the mode of a prefix is used when the config declares one, and the other
+        // prefixes must be allowed before the instruction
+        let mode = prefixes.iter().find(|p| self.instructions.contains_key(&format!("{} {}", p.name, name)));
+        for prefix in prefixes.iter().filter(|p| mode.is_none_or(|mode| mode.name != p.name)) {
+            if !prefix.allows(&name) {
+                logger.log_error_at(mnemonic.span.clone(), format!("prefix '{}' can't come before '{}', only before {}", prefix.name, name, prefix.mnemonics.join(", ")));
+                return None;
+            }
+        }
+        let key = mode.map(|mode| format!("{} {}", mode.name, name));
+        let mnemonic = Spanned { node: key.as_deref().unwrap_or(mnemonic.node), ..mnemonic };
+        let mut operation = match (self.src_first, operands) {
+            (true, [first, comma @ Spanned { node: OperandKind::Comma, .. }, second]) => {
+                let swapped = [second.clone(), comma.clone(), first.clone()];
+                self.lower_ordered(logger, source, &mnemonic, &swapped, directives)
+            },
+            _ => self.lower_ordered(logger, source, &mnemonic, operands, directives),
+        }?;
+        let bytes = prefixes.iter().flat_map(|prefix| &prefix.bytes).map(|b| Codegen::Data(CodegenData::Byte(*b)));
+        operation.codegen = bytes.chain(operation.codegen).collect();
+        operation.mnemonic = name;
+        Some(operation)
+    }
+    
+    // Takes the prefixes off the front of an instruction, such as the `rep` of `rep movs r0, r1`,
+    // which the parser reads as an instruction whose first operand is the mnemonic
+    fn split_prefixes<'a, 'o>(&self, logger: &mut Logger, mnemonic: &Spanned<'a, &'a str>, mut operands: &'o [Operand<'a>]) -> Option<(Vec<&Prefix>, Spanned<'a, &'a str>, &'o [Operand<'a>])> {
+        let mut prefixes: Vec<&Prefix> = Vec::new();
+        let mut mnemonic = mnemonic.clone();
+        while let Some(prefix) = self.prefixes.iter().find(|p| p.name.eq_ignore_ascii_case(mnemonic.node)) {
+            if prefixes.iter().any(|p| p.name == prefix.name) {
+                logger.log_error_at(mnemonic.span.clone(), format!("prefix '{}' is given twice", prefix.name));
+                return None;
+            }
+            prefixes.push(prefix);
+            match operands {
+                [Spanned { node: OperandKind::Immediate(Expr::Symbol(next)), slice, span }, rest @ ..] => {
+                    mnemonic = Spanned { node: *next, slice, span: span.clone() };
+                    operands = rest;
+                },
+                _ => {
+                    logger.log_error_at(mnemonic.span.clone(), format!("expected an instruction after prefix '{}'", prefix.name));
+                    return None;
+                },
+            }
+        }
+        Some((prefixes, mnemonic, operands))
+    }
+    
+    // Runs the operands through the instruction's DFA into an operation with the accepted form of
+    // its codegen. The source is the statement's, for the text of expressions.
+    pub(crate) fn lower_ordered<'a>(&self, logger: &mut Logger, source: &'a str, mnemonic: &Spanned<&str>, operands: &[Operand<'a>], directives: &Directives) -> Option<Operation> {
+        let name = mnemonic.node.to_lowercase();
+        let mut operation = Operation::new(&name);
+        let instruction = match self.instructions.get(&name) {
+            Some(instruction) => instruction,
+            None if self.instructions.is_empty() => {
+                logger.log_error_at(mnemonic.span.clone(), format!("unknown instruction: '{}'; without an ISA config only data directives can be assembled", mnemonic.slice));
+                return None;
+            },
+            None => {
+                logger.log_error_at(mnemonic.span.clone(), format!("unknown instruction: '{}'", mnemonic.slice));
+                return None;
+            },
+        };
+        let syntax_error = |logger: &mut Logger, span: Option<Span>, message: String| {
+            logger.log_at(LogLevel::Error, span, message);
+            logger.log_error(format!("syntaxes available for {}: {:?}", name, instruction.syntaxes(&name, self.src_first, &self.style)));
+        };
         
-        logger.into_result(||output)
+        let mut current_state = 0;
+        let mut rest = operands;
+        while let Some(operand) = rest.first() {
+            let table = &instruction.states[current_state];
+            // A keyword the pattern expects here is matched as written rather than read as a symbol
+            if let OperandKind::Immediate(Expr::Symbol(word)) = operand.node {
+                if let Some((_, next)) = table.keywords.iter().find(|(k, _)| k.eq_ignore_ascii_case(word)) {
+                    current_state = *next;
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            // An immediate can be an expression spanning several operands. Punctuation the pattern
+            // expects here is matched first, so the `(` of `(r1)` doesn't start one.
+            let expected = matches!(operand.node, OperandKind::Punct(p) if table.punctuation.iter().any(|(q, _)| *q == p));
+            if let (Transition::NextState(next), false) = (&table.immediate, expected) {
+                if let Some((expr, length @ 2..)) = expression(rest) {
+                    let span = operand.span.start..rest[length - 1].span.end;
+                    let value = directives.evaluate(logger, &span, &expr).unwrap_or_else(|message| {
+                        logger.log_error_at(span.clone(), message);
+                        0
+                    });
+                    operation.expression(value, span.clone(), &source[span]);
+                    current_state = *next;
+                    rest = &rest[length..];
+                    continue;
+                }
+            }
+            rest = &rest[1..];
+            let next = match (&operand.node, &table.immediate, &table.register, &table.comma) {
+                // Bad operand values are reported but still advance the DFA, so the rest of the
+                // operands are checked as well
+                (OperandKind::Immediate(expr), Transition::NextState(next), _, _) => {
+                    let value = directives.evaluate(logger, &operand.span, expr).unwrap_or_else(|message| {
+                        logger.log_error_at(operand.span.clone(), message);
+                        0
+                    });
+                    let (written, label) = match expr {
+                        Expr::Integer(_) => (written_width(operand.slice), None),
+                        Expr::Symbol(symbol) => (None, Some(*symbol)),
+                        _ => (None, None),
+                    };
+                    operation.immediate(value, operand.span.clone(), written, label);
+                    *next
+                },
+                // A `=value` operand is the offset to the value's entry in the next literal pool,
+                // which is only known once the pool has been placed
+                (OperandKind::Literal(expr), Transition::NextState(next), _, _) => {
+                    let value = match expr {
+                        Expr::Symbol(symbol) => directives.symbols.get(symbol).ok_or_else(|| format!("unknown symbol: '{}'", symbol)),
+                        expr => directives.evaluate(logger, &operand.span, expr),
+                    };
+                    let offset = match value {
+                        Ok(value) => directives.literal(value).unwrap_or(0),
+                        Err(message) => {
+                            logger.log_error_at(operand.span.clone(), message);
+                            0
+                        },
+                    };
+                    operation.immediate(offset, operand.span.clone(), None, None);
+                    *next
+                },
+                (OperandKind::Register(r), _, Transition::NextState(next), _) => {
+                    if *r > 15 {
+                        logger.log_error_at(operand.span.clone(), format!("register out of bounds: '{}'", operand.slice));
+                    }
+                    operation.registers.push(*r as u8);
+                    operation.register_spans.push(operand.span.clone());
+                    *next
+                },
+                (OperandKind::Comma, _, _, Transition::NextState(next)) => *next,
+                (OperandKind::Punct(p), ..) => match table.punctuation.iter().find(|(q, _)| q == p) {
+                    Some((_, next)) => *next,
+                    None => {
+                        syntax_error(logger, Some(operand.span.clone()), format!("unexpected '{}'", p));
+                        return None;
+                    },
+                },
+                (OperandKind::Immediate(_), ..) if !table.keywords.is_empty() => {
+                    let keywords: Vec<_> = table.keywords.iter().map(|(k, _)| format!("'{}'", k)).collect();
+                    syntax_error(logger, Some(operand.span.clone()), format!("expected {}, but got '{}'", keywords.join(" or "), operand.slice));
+                    return None;
+                },
+                (OperandKind::Immediate(_) | OperandKind::Literal(_), ..) => {
+                    syntax_error(logger, Some(operand.span.clone()), format!("unexpected immediate: '{}'", operand.slice));
+                    return None;
+                },
+                (OperandKind::Register(_), ..) => {
+                    syntax_error(logger, Some(operand.span.clone()), format!("unexpected register: '{}'", operand.slice));
+                    return None;
+                },
+                (OperandKind::Comma, ..) => {
+                    syntax_error(logger, Some(operand.span.clone()), "unexpected comma".to_owned());
+                    return None;
+                },
+            };
+            current_state = next;
+        }
+        let table = &instruction.states[current_state];
+        let (codegen, semantics) = match table.accept_codegen {
+            // Falls back to the longest form, which reports the truncation
+            Some(ref codegen) => {
+                let forms = std::iter::once((codegen, &table.accept_semantics)).chain(table.relaxations.iter().map(|(c, s)| (c, s)));
+                let taken: Vec<_> = forms.filter(|(c, _)| taken(c, &operation.immediates)).collect();
+                let chosen = taken.iter().position(|(c, _)| fits(c, &operation.immediates));
+                if let Some(chosen @ 1..) = chosen {
+                    operation.relaxed = self.note_overload(logger, &name, instruction, taken[0].0, taken[chosen].0, &operation.immediates);
+                }
+                match (chosen.map(|i| &taken[i]), taken.last()) {
+                    (Some(form), _) | (None, Some(form)) => *form,
+                    (None, None) => {
+                        logger.log_error_at(mnemonic.span.clone(), format!("no branch of the codegen of '{}' is taken for these operands", name));
+                        return None;
+                    },
+                }
+            },
+            None => {
+                syntax_error(logger, None, "syntax error".to_owned());
+                return None;
+            },
+        };
+        if logger.is_error() {
+            return None;
+        }
+        if operation.registers.iter().any(|r| self.reserved.iter().any(|(reserved, _)| *reserved == *r as usize)) {
+            self.check_reserved(logger, semantics.as_deref(), &operation.registers, &operation.register_spans, directives);
+        }
+        operation.codegen = codegen.clone();
+        operation.semantics = semantics.clone();
+        Some(operation)
     }
 }
@@ -0,0 +1,52 @@
+use crate::log::{Logger, LoggedResult};
+
+// Bytes written over an image at an address, and the bytes they replaced
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub address: usize,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+// Writes each segment over the image, which starts at `base`. Segments must lie within the image
+// and not overlap one another, so a patch never grows the image or silently undoes another.
+pub fn apply(image: &mut [u8], base: usize, segments: &[(usize, Vec<u8>)]) -> LoggedResult<Vec<Patch>> {
+    let mut logger = Logger::new(None);
+    let mut segments: Vec<_> = segments.iter().filter(|(_, code)| !code.is_empty()).collect();
+    segments.sort_by_key(|(address, _)| *address);
+    let mut patches = Vec::new();
+    let mut end = 0;
+    for (address, code) in segments {
+        let last = address + code.len() - 1;
+        if *address < base || last >= base + image.len() {
+            logger.log_error(format!("patch at {:#06x}-{:#06x} is outside the image ({:#06x}-{:#06x})", address, last, base, (base + image.len()).saturating_sub(1)));
+            continue;
+        }
+        if !patches.is_empty() && *address < end {
+            logger.log_error(format!("patch at {:#06x}-{:#06x} overlaps the patch before it", address, last));
+            continue;
+        }
+        let range = address - base..address - base + code.len();
+        patches.push(Patch { address: *address, old: image[range.clone()].to_vec(), new: code.clone() });
+        image[range].copy_from_slice(code);
+        end = last + 1;
+    }
+    logger.into_result(|| patches)
+}
+
+// One line per patch, e.g. `0x0010-0x0012: 00 00 11 -> 00 00 22`
+pub fn report(patches: &[Patch]) -> String {
+    let mut out = String::new();
+    for patch in patches {
+        out += &format!("{:#06x}-{:#06x}: {}", patch.address, patch.address + patch.new.len() - 1, hex(&patch.old));
+        match patch.old == patch.new {
+            true => out += " (unchanged)\n",
+            false => out += &format!(" -> {}\n", hex(&patch.new)),
+        }
+    }
+    out
+}
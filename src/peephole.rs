@@ -0,0 +1,230 @@
+use crate::directives::Directives;
+use crate::ir::{Item, Operation};
+use crate::lexer::{Lexeme, Lexer, Token};
+use crate::log::Logger;
+use crate::new_parser::{parse_statement, Statement};
+use crate::parser::Assembler;
+
+// A rewrite given by `.peephole`, such as `.peephole add r0, 0 ->` to remove adding zero, or
+//
+//     .peephole shl r0, i0 ; shl r0, i1 -> shl r0, i0 + i1
+//
+// to combine two shifts. Patterns are written like the patterns of instructions: `r0` and `i0`
+// stand for any register and immediate, the same one wherever they are repeated, and integers only
+// match themselves, while a mnemonic on its own matches the instruction with any operands. The
+// replacement is an instruction with them filled in, or nothing to remove the statements
+// altogether.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    // The rule as written after `.peephole`, which compiled configs keep it as
+    pub text: String,
+    patterns: Vec<Pattern>,
+    replacement: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern {
+    mnemonic: String,
+    registers: Vec<usize>,
+    immediates: Vec<Slot>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    Variable(usize),
+    Constant(usize),
+}
+
+// The instruction emitted just before, which a rule matching two statements takes back
+#[derive(Debug, Clone)]
+pub struct Previous {
+    pub operation: Operation,
+    pub text: String,
+    pub site: String,
+    pub address: usize,
+    pub length: usize,
+}
+
+// What a statement is emitted as after the rewrites, with the text and site the next rewrite
+// taking it back reports it by, which are those of the statements it replaced
+pub struct Rewrite {
+    pub item: Item,
+    pub retracted: Option<usize>,
+    pub text: String,
+    pub site: String,
+}
+
+// Registers and immediates the variables of a rule stand for once its patterns match
+#[derive(Debug, Default)]
+pub(crate) struct Bindings {
+    registers: Vec<(usize, u8)>,
+    immediates: Vec<(usize, usize)>,
+}
+
+// Binds the variable, or checks it is already bound to the same value
+fn bind<T: Copy + PartialEq>(bound: &mut Vec<(usize, T)>, variable: usize, value: T) -> bool {
+    match bound.iter().find(|(v, _)| *v == variable) {
+        Some((_, existing)) => *existing == value,
+        None => {
+            bound.push((variable, value));
+            true
+        },
+    }
+}
+
+impl Pattern {
+    pub(crate) fn matches(&self, operation: &Operation, bindings: &mut Bindings) -> bool {
+        let operands = !self.registers.is_empty() || !self.immediates.is_empty();
+        operation.mnemonic == self.mnemonic && (!operands || self.operands(operation, bindings))
+    }
+
+    fn operands(&self, operation: &Operation, bindings: &mut Bindings) -> bool {
+        operation.registers.len() == self.registers.len()
+            && operation.immediates.len() == self.immediates.len()
+            && self.registers.iter().zip(&operation.registers).all(|(v, r)| bind(&mut bindings.registers, *v, *r))
+            && self.immediates.iter().zip(&operation.immediates).all(|(slot, immediate)| match *slot {
+                Slot::Variable(v) => bind(&mut bindings.immediates, v, immediate.value),
+                Slot::Constant(c) => c == immediate.value,
+            })
+    }
+}
+
+impl Bindings {
+    // The replacement with its variables filled in
+    fn substitute(&self, text: &str) -> String {
+        let mut output = String::new();
+        let mut at = 0;
+        for lexeme in Lexer::new(text) {
+            let value = match lexeme.token {
+                Token::Register(v) => self.registers.iter().find(|(w, _)| *w == v).map(|(_, r)| format!("r{}", r)),
+                Token::Immediate(v) => self.immediates.iter().find(|(w, _)| *w == v).map(|(_, i)| i.to_string()),
+                _ => None,
+            };
+            if let Some(value) = value {
+                output += &text[at..lexeme.span.start];
+                output += &value;
+                at = lexeme.span.end;
+            }
+        }
+        output + &text[at..]
+    }
+}
+
+pub(crate) fn pattern(text: &str) -> Result<Pattern, String> {
+    let lexemes: Vec<_> = Lexer::new(text).collect();
+    let (mnemonic, operands) = match lexemes.as_slice() {
+        [Lexeme { token: Token::Ident(mnemonic), .. }, operands @ ..] => (mnemonic.to_lowercase(), operands),
+        _ => return Err(format!("expected an instruction, found '{}'", text.trim())),
+    };
+    let mut pattern = Pattern { mnemonic, registers: Vec::new(), immediates: Vec::new() };
+    for lexeme in operands {
+        match lexeme.token {
+            Token::Register(v) => pattern.registers.push(v),
+            Token::Immediate(v) => pattern.immediates.push(Slot::Variable(v)),
+            Token::Integer(c) => pattern.immediates.push(Slot::Constant(c)),
+            Token::Comma | Token::OpenBracket | Token::CloseBracket | Token::OpenParen | Token::CloseParen => {},
+            _ => return Err(format!("unexpected '{}' in pattern '{}'", lexeme.slice, text.trim())),
+        }
+    }
+    Ok(pattern)
+}
+
+// Parses what follows `.peephole`
+pub fn parse_rule(text: &str) -> Result<Rule, String> {
+    let (patterns, replacement) = text.split_once("->").ok_or_else(|| "expected '.peephole <pattern> [; <pattern>] -> [<replacement>]'".to_owned())?;
+    let patterns = patterns.split(';').map(pattern).collect::<Result<Vec<_>, _>>()?;
+    if patterns.len() > 2 {
+        return Err("a rewrite matches one or two statements".to_owned());
+    }
+    let replacement = Some(replacement.trim()).filter(|r| !r.is_empty());
+    for lexeme in replacement.map(Lexer::new).into_iter().flatten() {
+        let bound = match lexeme.token {
+            Token::Register(v) => patterns.iter().any(|p| p.registers.contains(&v)),
+            Token::Immediate(v) => patterns.iter().any(|p| p.immediates.contains(&Slot::Variable(v))),
+            Token::Semicolon => return Err("the replacement must be a single instruction".to_owned()),
+            _ => true,
+        };
+        if !bound {
+            return Err(format!("'{}' in the replacement isn't in the pattern", lexeme.slice));
+        }
+    }
+    Ok(Rule { text: text.trim().to_owned(), patterns, replacement: replacement.map(str::to_owned) })
+}
+
+impl Assembler {
+    // Applies the first rule that matches the operation, or the one before it along with it,
+    // taking back the code of the one before if so. Rewrites are made in every pass, so labels
+    // after them settle on the rewritten code.
+    pub(crate) fn rewrite(&self, logger: &mut Logger, statement: &str, site: String, item: Item, aligned: bool, directives: &mut Directives) -> Rewrite {
+        let taken = directives.previous.take();
+        let previous = taken.clone().filter(|p| aligned && p.address + p.length == directives.address() && !directives.labelled());
+        let text = statement.trim().to_owned();
+        let operation = match item {
+            Item::Operation(operation) => operation,
+            item => return Rewrite { item, retracted: None, text, site },
+        };
+        for rule in self.peepholes.iter().filter(|_| self.peephole) {
+            let mut bindings = Bindings::default();
+            let matched = match (rule.patterns.as_slice(), &previous) {
+                ([pattern], _) => pattern.matches(&operation, &mut bindings),
+                ([first, second], Some(previous)) => first.matches(&previous.operation, &mut bindings) && second.matches(&operation, &mut bindings),
+                _ => false,
+            };
+            if !matched {
+                continue;
+            }
+            // The statement moves back to where the one before it was
+            let (texts, site, retracted) = match (rule.patterns.len(), &previous) {
+                (2, Some(previous)) => {
+                    directives.retract(previous.length);
+                    (format!("{} ; {}", previous.text, text), previous.site.clone(), Some(previous.length))
+                },
+                _ => (text, site, None),
+            };
+            let (item, text) = match &rule.replacement {
+                Some(replacement) => {
+                    let replacement = bindings.substitute(replacement);
+                    match self.lower_replacement(&replacement, directives) {
+                        Ok(operation) => {
+                            directives.rewrites.push(format!("{}: replaced '{}' with '{}'", site, texts, replacement));
+                            (Item::Operation(operation), replacement)
+                        },
+                        Err(message) => {
+                            logger.log_error(format!("'.peephole {}' rewrites '{}' to '{}', which doesn't assemble: {}", rule.text, texts, replacement, message));
+                            (Item::Bytes(Vec::new()), texts)
+                        },
+                    }
+                },
+                None => {
+                    directives.rewrites.push(format!("{}: removed '{}'", site, texts));
+                    // Removing a statement on its own leaves the one before it last
+                    if retracted.is_none() {
+                        directives.previous = taken;
+                    }
+                    (Item::Bytes(Vec::new()), texts)
+                },
+            };
+            return Rewrite { item, retracted, text, site };
+        }
+        // Left for moving into the delay slot of the statement
+        directives.previous = taken;
+        Rewrite { item: Item::Operation(operation), retracted: None, text, site }
+    }
+
+    // Replacements are written in the order of the config, like the patterns they come from
+    fn lower_replacement(&self, text: &str, directives: &Directives) -> Result<Operation, String> {
+        let mut logger = Logger::new(None);
+        let (statement, logs) = parse_statement(text).unwrap();
+        let operation = match statement {
+            Some(Statement::Instruction { mnemonic, operands }) => self.lower_ordered(&mut logger, text, &mnemonic, &operands, directives),
+            _ => None,
+        };
+        let result = match operation {
+            Some(operation) if !logger.is_error() => logger.into_result(|| operation),
+            _ => logger.into_none(),
+        };
+        let (operation, mut errors) = result.unwrap();
+        errors.extend(logs);
+        operation.ok_or_else(|| errors.iter().find(|log| log.is_error()).map_or("not an instruction", |log| log.message()).to_owned())
+    }
+}
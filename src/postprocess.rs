@@ -0,0 +1,149 @@
+use crate::directives::Image;
+use crate::pack;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// A step run on the image after it is assembled and before it is written, such as filling in a
+// checksum, signing it, or compressing it. The manifest gives them in the order they run by
+//
+//     post = ["sum8 __checksum", "crc32 __crc", "pack .data .assets", "run ./sign.sh"]
+//
+// and programs using the assembler as a library can add their own.
+pub trait PostProcessor {
+    fn process(&self, image: &mut Image) -> Result<(), String>;
+}
+
+// Writes the byte at the symbol that makes the bytes of the image add up to 0
+pub struct Sum8 {
+    pub symbol: String,
+}
+
+// Writes the CRC-32 of the image at the symbol in little-endian, taken with those 4 bytes as 0
+pub struct Crc32 {
+    pub symbol: String,
+}
+
+// Compresses the sections into a table after the rest of the image, as `pack::pack` lays out
+pub struct Pack {
+    pub sections: Vec<String>,
+}
+
+// Runs the command in the directory with the image on standard input and the labels in
+// X69_SYMBOLS, one `name address` line each, and replaces the image with what it writes to
+// standard output
+pub struct Run {
+    pub command: String,
+    pub dir: PathBuf,
+}
+
+// The step given by a line of `post` in the manifest, whose commands run in the directory
+pub fn parse(text: &str, dir: &Path) -> Result<Box<dyn PostProcessor>, String> {
+    let (name, argument) = text.trim().split_once(char::is_whitespace).map_or((text.trim(), ""), |(n, a)| (n, a.trim()));
+    if argument.is_empty() {
+        let argument = match name {
+            "run" => "command",
+            "pack" => "sections",
+            _ => "symbol",
+        };
+        return Err(format!("expected '{} <{}>'", name, argument));
+    }
+    match name {
+        "sum8" => Ok(Box::new(Sum8 { symbol: argument.to_owned() })),
+        "crc32" => Ok(Box::new(Crc32 { symbol: argument.to_owned() })),
+        "pack" => Ok(Box::new(Pack { sections: argument.split_whitespace().map(str::to_owned).collect() })),
+        // The directory of a manifest given by a bare name is empty
+        "run" => Ok(Box::new(Run { command: argument.to_owned(), dir: if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir.to_owned() } })),
+        _ => Err(format!("unknown post-processing step '{}', expected 'sum8', 'crc32', 'pack', or 'run'", name)),
+    }
+}
+
+// Offset in the code of the label, which must leave room for `size` bytes
+fn offset(image: &Image, symbol: &str, size: usize) -> Result<usize, String> {
+    let address = image.labels.iter().find(|(name, _)| name == symbol).map(|(_, address)| *address).ok_or_else(|| format!("'{}' isn't a label of the image", symbol))?;
+    match address.checked_sub(image.base) {
+        Some(offset) if offset + size <= image.code.len() => Ok(offset),
+        _ => Err(format!("'{}' at 0x{:X} has no room for {} bytes in the image", symbol, address, size)),
+    }
+}
+
+// Writes the bytes at the offset, in the code and in the section holding them
+fn write(image: &mut Image, offset: usize, bytes: &[u8]) {
+    image.code[offset..offset + bytes.len()].copy_from_slice(bytes);
+    let address = image.base + offset;
+    for (base, code) in &mut image.segments {
+        if *base <= address && address + bytes.len() <= *base + code.len() {
+            code[address - *base..address - *base + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl PostProcessor for Sum8 {
+    fn process(&self, image: &mut Image) -> Result<(), String> {
+        let offset = offset(image, &self.symbol, 1)?;
+        write(image, offset, &[0]);
+        let sum = image.code.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        write(image, offset, &[sum.wrapping_neg()]);
+        Ok(())
+    }
+}
+
+impl PostProcessor for Crc32 {
+    fn process(&self, image: &mut Image) -> Result<(), String> {
+        let offset = offset(image, &self.symbol, 4)?;
+        write(image, offset, &[0; 4]);
+        let crc = crc32(&image.code);
+        write(image, offset, &crc.to_le_bytes());
+        Ok(())
+    }
+}
+
+impl PostProcessor for Pack {
+    fn process(&self, image: &mut Image) -> Result<(), String> {
+        image.code = pack::pack(image, &self.sections)?;
+        image.segments = vec![(image.base, image.code.clone())];
+        Ok(())
+    }
+}
+
+impl PostProcessor for Run {
+    // The image it writes is placed at the same base as one section, since its layout is no
+    // longer known
+    fn process(&self, image: &mut Image) -> Result<(), String> {
+        let shell = if cfg!(windows) { ["cmd", "/C"] } else { ["sh", "-c"] };
+        let symbols: String = image.labels.iter().map(|(name, address)| format!("{} 0x{:X}\n", name, address)).collect();
+        let mut child = Command::new(shell[0]).arg(shell[1]).arg(&self.command).current_dir(&self.dir).env("X69_SYMBOLS", symbols)
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().map_err(|e| format!("'{}' couldn't run: {}", self.command, e))?;
+        // Written from another thread so a command writing before it has read everything can't
+        // leave both waiting
+        let mut stdin = child.stdin.take().unwrap();
+        let code = image.code.clone();
+        let writer = std::thread::spawn(move || stdin.write_all(&code));
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        // A command that doesn't read the image closes its input early, which isn't an error
+        let _ = writer.join();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("'{}' failed with {}{}", self.command, output.status, stderr.lines().next().map(|l| format!(": {}", l)).unwrap_or_default()));
+        }
+        image.code = output.stdout;
+        image.segments = vec![(image.base, image.code.clone())];
+        Ok(())
+    }
+}
+
+// Runs the steps in order, stopping at the first that fails
+pub fn apply(steps: &[Box<dyn PostProcessor>], image: &mut Image) -> Result<(), String> {
+    steps.iter().try_for_each(|step| step.process(image))
+}
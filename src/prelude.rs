@@ -0,0 +1,25 @@
+// The stable API of the assembler, for emulators, editors, and other tools that build on it:
+//
+//     use assembler::prelude::*;
+//
+//     let (assembler, diagnostics) = Config::builtin("x69-bravo")?.load().unwrap();
+//     let image = assembler.unwrap().assemble_image("main.s", &source);
+//     let bytes = image.result().map(|image| OutputFormat::Hex.emit(image));
+//
+// What is covered follows semver: it only changes in ways that break callers with a new major
+// version, or a new minor version while the version is below 1.0. Anything renamed or replaced
+// stays behind as a deprecated shim for at least one release. The guarantee covers the names
+// exported here and their methods, such as `Image::code` and `Assembler::set_cancel`. The public
+// fields of `Assembler` and `Image` are what the assembler's own commands are built from, and
+// can change in any release. They are `#[non_exhaustive]`, as are the other structs and enums
+// exported here, so none can be built or matched exhaustively outside the crate. The other
+// modules can change in any release.
+
+pub use crate::cancel::Cancel;
+pub use crate::config::Config;
+pub use crate::directives::Image;
+pub use crate::log::{Log as Diagnostic, LogLevel, LoggedResult};
+pub use crate::macros::Limits;
+pub use crate::manifest::Format as OutputFormat;
+pub use crate::parser::{Assembler, Region};
+pub use crate::progress::{Event, Progress};
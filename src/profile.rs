@@ -0,0 +1,81 @@
+use crate::ir::{Item, Statement};
+use crate::listing::Row;
+use crate::parser::{Assembler, Category};
+use std::collections::BTreeMap;
+
+// Lines shown as the hottest after a profile
+const HOTTEST: usize = 5;
+
+// Times each line's instructions executed and the cycles they took. Statements a macro expanded
+// to are counted for the line that invoked it, and instructions the config gives no cycles for
+// take one.
+fn lines(rows: &[Row], program: &[Statement], counts: &BTreeMap<usize, usize>, cycles: &[(String, usize)]) -> BTreeMap<usize, (usize, usize)> {
+    let taken: BTreeMap<_, _> = program.iter().filter_map(|statement| match &statement.item {
+        Item::Operation(operation) => cycles.iter().find(|(m, _)| *m == operation.mnemonic).map(|(_, cycles)| (statement.address, *cycles)),
+        Item::Bytes(_) => None,
+    }).collect();
+    let mut lines = BTreeMap::new();
+    let mut current = None;
+    for row in rows {
+        current = row.line.or(current);
+        if let (Some(line), false) = (current, row.instructions.is_empty()) {
+            let (count, total) = lines.entry(line).or_insert((0, 0));
+            for address in &row.instructions {
+                let executed = counts.get(address).copied().unwrap_or(0);
+                *count += executed;
+                *total += executed * taken.get(address).copied().unwrap_or(1);
+            }
+        }
+    }
+    lines
+}
+
+// Times instructions of each category given by `.category` executed, in the order of
+// `Category::ALL`, followed by those without one
+fn categories(program: &[Statement], counts: &BTreeMap<usize, usize>, assembler: &Assembler) -> Vec<(&'static str, usize)> {
+    let mut executed: Vec<_> = Category::ALL.iter().map(|c| (c.name(), 0)).chain(Some(("uncategorized", 0))).collect();
+    for statement in program {
+        if let Item::Operation(operation) = &statement.item {
+            let index = assembler.category(&operation.mnemonic).map_or(Category::ALL.len(), |c| Category::ALL.iter().position(|a| *a == c).unwrap());
+            executed[index].1 += counts.get(&statement.address).copied().unwrap_or(0);
+        }
+    }
+    executed
+}
+
+// The source with the times each line holding instructions executed, the cycles they took, and
+// their share of all cycles, e.g. `   12      1000      3000   42.9%  inc r1`, followed by the
+// times each category of instruction executed when the config gives categories, and the hottest
+// lines
+pub fn report(source: &str, rows: &[Row], program: &[Statement], counts: &BTreeMap<usize, usize>, assembler: &Assembler) -> String {
+    let cycles = &assembler.cycles;
+    let lines = lines(rows, program, counts, cycles);
+    let total: usize = lines.values().map(|(_, cycles)| cycles).sum();
+    let share = |cycles: usize| if total == 0 { 0.0 } else { cycles as f64 * 100.0 / total as f64 };
+    let mut out = format!("{:>5} {:>9} {:>9} {:>7}  source\n", "line", "count", "cycles", "share");
+    for (i, text) in source.lines().enumerate() {
+        let columns = match lines.get(&i) {
+            Some((count, cycles)) => format!("{:>9} {:>9} {:>6.1}%", count, cycles, share(*cycles)),
+            None => format!("{:>27}", ""),
+        };
+        out += format!("{:>5} {}  {}", i + 1, columns, text).trim_end();
+        out.push('\n');
+    }
+    let executed: usize = lines.values().map(|(count, _)| count).sum();
+    out += &format!("executed {} instructions taking {} cycles", executed, total);
+    if cycles.is_empty() {
+        out += ", one for each instruction since the config gives none with '.cycles'";
+    }
+    out.push('\n');
+    if !assembler.categories.is_empty() {
+        let executed: Vec<_> = categories(program, counts, assembler).into_iter().map(|(name, count)| format!("{} {}", name, count)).collect();
+        out += &format!("executed by category: {}\n", executed.join(", "));
+    }
+    let mut hottest: Vec<_> = lines.iter().filter(|(_, (_, cycles))| *cycles > 0).collect();
+    hottest.sort_by_key(|(line, (_, cycles))| (std::cmp::Reverse(*cycles), **line));
+    for (line, (_, cycles)) in hottest.into_iter().take(HOTTEST) {
+        let text = source.lines().nth(*line).unwrap_or("").trim();
+        out += &format!("{:>6.1}%  line {}: {}\n", share(*cycles), line + 1, text);
+    }
+    out
+}
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+// Statements between the `Encoded` events of a pass
+pub const STATEMENTS_PER_EVENT: usize = 1024;
+
+// A stage an assembly has reached, reported as it happens so a frontend can show how far along a
+// large assembly is
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    // The configs have been parsed into an ISA with this many instructions
+    ConfigParsed { instructions: usize },
+    // A pass over the sources has started, counting from 1
+    PassStarted { pass: usize },
+    // Statements the current pass has encoded so far, reported every `STATEMENTS_PER_EVENT`
+    Encoded { statements: usize },
+    // A pass has finished, with the number of symbols whose values changed in it. The assembly is
+    // done after a pass that changed none.
+    PassDone { pass: usize, changed: usize },
+}
+
+// Called with each event of an assembly. Clones call the same function, so one callback can
+// follow assemblies on several threads.
+#[derive(Clone)]
+pub struct Progress {
+    callback: Arc<dyn Fn(&Event) + Send + Sync>,
+}
+
+impl Progress {
+    pub fn new(callback: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        Self { callback: Arc::new(callback) }
+    }
+
+    pub fn report(&self, event: Event) {
+        (self.callback)(&event)
+    }
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Progress")
+    }
+}
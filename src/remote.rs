@@ -0,0 +1,189 @@
+use crate::emulator::{Emulator, Stop, MEMORY_SIZE, REGISTERS};
+use std::io::{self, BufRead, Write};
+
+// A subset of the GDB remote serial protocol, enough for a frontend to set breakpoints, step,
+// continue, and read and write registers and memory:
+//
+//   ?                  reason the target stopped
+//   g / G<hex>         read / write all registers
+//   p<n> / P<n>=<hex>  read / write register n
+//   m<addr>,<len>      read memory
+//   M<addr>,<len>:<hex> write memory
+//   Z0,<addr>,<kind>   set a breakpoint, and z0 to remove one
+//   s / c              step / continue
+//   k                  kill, which ends the session
+//
+// Registers are r0 to r15 followed by pc, each 16 bits and sent little endian. Anything else gets
+// an empty reply, which tells the frontend the command isn't supported.
+
+// Signal reported when the machine stops at a breakpoint or after a step
+const SIGTRAP: u8 = 5;
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, b| sum.wrapping_add(*b))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn number(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// Reads the body of the next `$<body>#<checksum>` packet, acknowledging it. Acknowledgements and
+// interrupts sent outside a packet are skipped, since the target never runs in the background.
+fn read_packet<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<Option<String>> {
+    // Packets with a bad checksum are refused, and the next one is read in their place
+    loop {
+        let mut byte = [0];
+        loop {
+            if input.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut body = Vec::new();
+        if input.read_until(b'#', &mut body)? == 0 || body.pop() != Some(b'#') {
+            return Ok(None);
+        }
+        let mut sum = [0; 2];
+        input.read_exact(&mut sum)?;
+        let valid = std::str::from_utf8(&sum).ok().and_then(number) == Some(checksum(&body) as usize);
+        output.write_all(if valid { b"+" } else { b"-" })?;
+        output.flush()?;
+        if valid {
+            return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+        }
+    }
+}
+
+fn write_packet<W: Write>(output: &mut W, body: &str) -> io::Result<()> {
+    write!(output, "${}#{:02x}", body, checksum(body.as_bytes()))?;
+    output.flush()
+}
+
+fn read_register(emulator: &Emulator, n: usize) -> Option<u16> {
+    match n {
+        _ if n < REGISTERS => Some(emulator.machine.registers[n] as u16),
+        REGISTERS => Some(emulator.machine.pc as u16),
+        _ => None,
+    }
+}
+
+fn write_register(emulator: &mut Emulator, n: usize, value: u16) -> bool {
+    match n {
+        _ if n < REGISTERS => emulator.machine.registers[n] = value as u64,
+        REGISTERS => emulator.machine.pc = value as usize,
+        _ => return false,
+    }
+    true
+}
+
+// Reply to a stop, `S05` when the machine can go on and `W00` once it has halted or left the program
+fn stop_reply(stop: Result<Stop, String>) -> String {
+    match stop {
+        Ok(Stop::Halted | Stop::EndOfProgram) => "W00".to_owned(),
+        Ok(_) => format!("S{:02x}", SIGTRAP),
+        Err(_) => "E01".to_owned(),
+    }
+}
+
+// Reply to one packet, or None when the session should end
+fn reply(emulator: &mut Emulator, packet: &str, max_steps: u64) -> Option<String> {
+    let (command, rest) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+    Some(match command {
+        "?" => format!("S{:02x}", SIGTRAP),
+        "g" => hex(&(0..=REGISTERS).flat_map(|n| read_register(emulator, n).unwrap().to_le_bytes()).collect::<Vec<_>>()),
+        "G" => match unhex(rest) {
+            Some(bytes) if bytes.len() == (REGISTERS + 1) * 2 => {
+                for (n, value) in bytes.chunks(2).enumerate() {
+                    write_register(emulator, n, u16::from_le_bytes([value[0], value[1]]));
+                }
+                "OK".to_owned()
+            },
+            _ => "E01".to_owned(),
+        },
+        "p" => match number(rest).and_then(|n| read_register(emulator, n)) {
+            Some(value) => hex(&value.to_le_bytes()),
+            None => "E01".to_owned(),
+        },
+        "P" => {
+            let value = rest.split_once('=').and_then(|(n, value)| Some((number(n)?, unhex(value)?)));
+            match value {
+                Some((n, bytes)) if bytes.len() == 2 && write_register(emulator, n, u16::from_le_bytes([bytes[0], bytes[1]])) => "OK".to_owned(),
+                _ => "E01".to_owned(),
+            }
+        },
+        "m" => match rest.split_once(',').and_then(|(address, length)| Some((number(address)?, number(length)?))) {
+            Some((address, length)) => match address.checked_add(length).filter(|&end| end <= MEMORY_SIZE) {
+                Some(end) => hex(&emulator.machine.memory[address..end]),
+                None => "E01".to_owned(),
+            },
+            _ => "E01".to_owned(),
+        },
+        "M" => {
+            let write = rest.split_once(':').and_then(|(range, data)| {
+                let (address, length) = range.split_once(',')?;
+                Some((number(address)?, number(length)?, unhex(data)?))
+            });
+            let range = write.and_then(|(address, length, bytes)| {
+                let end = address.checked_add(length).filter(|&end| end <= MEMORY_SIZE && bytes.len() == length)?;
+                Some((address, end, bytes))
+            });
+            match range {
+                Some((address, end, bytes)) => {
+                    emulator.machine.memory[address..end].copy_from_slice(&bytes);
+                    "OK".to_owned()
+                },
+                None => "E01".to_owned(),
+            }
+        },
+        "Z" | "z" => match rest.split(',').collect::<Vec<_>>().as_slice() {
+            ["0", address, _] => match number(address) {
+                Some(address) if command == "Z" => {
+                    emulator.breakpoints.insert(address);
+                    "OK".to_owned()
+                },
+                Some(address) => {
+                    emulator.breakpoints.remove(&address);
+                    "OK".to_owned()
+                },
+                None => "E01".to_owned(),
+            },
+            // Only software breakpoints are supported
+            _ => String::new(),
+        },
+        "s" => stop_reply(emulator.run(1).map(|stop| if stop == Stop::StepLimit { Stop::Breakpoint(emulator.machine.pc) } else { stop })),
+        "c" => stop_reply(emulator.run(max_steps)),
+        "k" => return None,
+        _ if packet == "qAttached" => "1".to_owned(),
+        _ if packet.starts_with("qSupported") => "PacketSize=1000".to_owned(),
+        _ => String::new(),
+    })
+}
+
+// Serves a frontend until it kills the target, detaches, or disconnects. Continuing runs at most
+// `max_steps` instructions before reporting a stop.
+pub fn serve<R: BufRead, W: Write>(emulator: &mut Emulator, mut input: R, mut output: W, max_steps: u64) -> io::Result<()> {
+    while let Some(packet) = read_packet(&mut input, &mut output)? {
+        if packet == "D" {
+            write_packet(&mut output, "OK")?;
+            break;
+        }
+        match reply(emulator, &packet, max_steps) {
+            Some(reply) => write_packet(&mut output, &reply)?,
+            None => break,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use crate::lexer::{Lexer, Lexeme, Token};
+use crate::macros::Preprocessor;
+use crate::parser::Assembler;
+use crate::session::{self, Session};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+enter statements to assemble them at the current address
+  name:          define a label at the current address, optionally followed by a statement
+  name = value   define a constant, as do .set name, value and .equ name, value
+  .macro ... .endm  define a macro, invoked like an instruction
+  :symbols       list labels and constants
+  :save file     save symbols, the address, and macros to the file
+  :load file     pick up a session saved with :save
+  :reset         clear symbols and macros and return to address 0
+  :quit          exit";
+
+pub struct Repl<'a> {
+    assembler: &'a Assembler,
+    symbols: BTreeMap<String, usize>,
+    address: usize,
+    // Expands the macros of the config and those defined in the session
+    preprocessor: Preprocessor,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(assembler: &'a Assembler) -> Self {
+        Self { assembler, symbols: BTreeMap::new(), address: 0, preprocessor: Preprocessor::with_macros("[repl]", &assembler.macros) }
+    }
+    
+    // What the session has defined so far
+    pub fn session(&self) -> Session {
+        let macros = self.preprocessor.macros().iter()
+            .filter(|(name, _)| !self.assembler.macros.contains_key(*name))
+            .map(|(name, m)| (name.clone(), m.clone()))
+            .collect();
+        Session { symbols: self.symbols.clone(), address: self.address, macros }
+    }
+    
+    // Picks up a saved session in place of the current one
+    pub fn resume(&mut self, session: Session) {
+        let mut macros = self.assembler.macros.clone();
+        macros.extend(session.macros);
+        self.preprocessor = Preprocessor::with_macros("[repl]", &macros);
+        self.symbols = session.symbols;
+        self.address = session.address;
+    }
+
+    // Replaces symbol operands with their values since statements only accept literals
+    fn substitute(&self, line: &str) -> Result<String, String> {
+        let mut text = String::new();
+        for (i, Lexeme { token, slice, .. }) in Lexer::new(line).enumerate() {
+            let slice = match token {
+                Token::Ident(name) if i > 0 => match self.symbols.get(name) {
+                    Some(value) => value.to_string(),
+                    None => return Err(format!("unknown symbol '{}'", name)),
+                },
+                _ => slice.to_owned(),
+            };
+            if !text.is_empty() && token != Token::Comma {
+                text.push(' ');
+            }
+            text += &slice;
+        }
+        Ok(text)
+    }
+
+    // Handles one line of input, returning false once the session should end
+    pub fn handle<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<bool> {
+        let line = line.trim();
+        if line.starts_with(':') {
+            match line {
+                ":quit" => return Ok(false),
+                ":help" => writeln!(output, "{}", HELP)?,
+                ":symbols" => {
+                    for (name, value) in &self.symbols {
+                        writeln!(output, "{} = 0x{:04X}", name, value)?;
+                    }
+                },
+                ":reset" => self.resume(Session::default()),
+                _ => match line.split_once(' ') {
+                    Some((":save", path)) => if let Err(e) = std::fs::write(path.trim(), session::save(&self.session())) {
+                        writeln!(output, "could not save to '{}': {}", path.trim(), e)?;
+                    },
+                    Some((":load", path)) => match std::fs::read_to_string(path.trim()).map_err(|e| e.to_string()).and_then(|text| session::restore(&text)) {
+                        Ok(session) => self.resume(session),
+                        Err(message) => writeln!(output, "could not load '{}': {}", path.trim(), message)?,
+                    },
+                    _ => writeln!(output, "unknown command '{}', try ':help'", line)?,
+                },
+            }
+            return Ok(true);
+        }
+        let (statements, logs) = self.preprocessor.process(0, line).unwrap();
+        for log in logs {
+            writeln!(output, "{}", log)?;
+        }
+        for statement in statements.unwrap_or_default() {
+            self.statement(statement.trim(), output)?;
+        }
+        Ok(true)
+    }
+
+    // Defines the label or constant the statement gives, or else assembles it
+    fn statement<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<()> {
+        let lexemes: Vec<_> = Lexer::new(line).collect();
+        match lexemes.as_slice() {
+            [] => {},
+            // A statement can follow the label on the same line
+            [Lexeme { token: Token::Ident(name), .. }, Lexeme { token: Token::Colon, span, .. }, rest @ ..] => {
+                self.symbols.insert(name.to_string(), self.address);
+                writeln!(output, "{} = 0x{:04X}", name, self.address)?;
+                if !rest.is_empty() {
+                    self.assemble(&line[span.end..], output)?;
+                }
+            },
+            [Lexeme { token: Token::Ident(name), .. }, Lexeme { token: Token::Assign, .. }, Lexeme { token: Token::Integer(value), .. }] => {
+                self.symbols.insert(name.to_string(), *value);
+                writeln!(output, "{} = 0x{:04X}", name, value)?;
+            },
+            [Lexeme { token: Token::Directive(directive), .. }, Lexeme { token: Token::Ident(name), .. }, Lexeme { token: Token::Comma, .. }, Lexeme { token: Token::Integer(value), .. }]
+                if matches!(directive.to_lowercase().as_str(), ".set" | ".equ") => {
+                self.symbols.insert(name.to_string(), *value);
+                writeln!(output, "{} = 0x{:04X}", name, value)?;
+            },
+            [Lexeme { token: Token::Directive(directive), .. }, ..] if matches!(directive.to_lowercase().as_str(), ".set" | ".equ") =>
+                writeln!(output, "expected '{} <name>, <integer>'", directive)?,
+            // Statements are assembled one at a time without the directives of a source, so
+            // anything else, data included, would be mistaken for symbols
+            [Lexeme { token: Token::Directive(directive), .. }, ..] => writeln!(output, "directive '{}' is not supported in the REPL", directive)?,
+            _ => self.assemble(line, output)?,
+        }
+        Ok(())
+    }
+
+    // Assembles a statement at the current address and prints its encoding
+    fn assemble<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<()> {
+        match self.substitute(line) {
+            Ok(statement) => {
+                let (code, logs) = self.assembler.assemble_statement(&statement).unwrap();
+                for log in logs {
+                    writeln!(output, "{}", log)?;
+                }
+                if let Some(code) = code {
+                    let hex: Vec<String> = code.iter().map(|b| format!("{:02X}", b)).collect();
+                    let binary: Vec<String> = code.iter().map(|b| format!("{:08b}", b)).collect();
+                    writeln!(output, "0x{:04X}: {}  |  {}", self.address, hex.join(" "), binary.join(" "))?;
+                    self.address += code.len();
+                }
+            },
+            Err(message) => writeln!(output, "{}", message)?,
+        }
+        Ok(())
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            if !self.handle(&line?, &mut output)? {
+                break;
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::directives::Image;
+use crate::log::{Log, LogLevel};
+use crate::parser::Region;
+use serde_json::{json, Value};
+
+// Version of the report's layout, raised whenever a key changes meaning or goes away, so
+// dashboards reading reports from old builds can tell them apart
+const VERSION: usize = 1;
+
+// What a run of the assembler was given and what it wrote, written as JSON by `--report`
+pub struct Report<'a> {
+    pub inputs: &'a [String],
+    pub configs: &'a [String],
+    pub config_hash: u64,
+    // Regions of the config or linker script, which sections are reported in
+    pub regions: &'a [Region],
+    pub outputs: Vec<String>,
+}
+
+// e.g. `{ "version": 1, "success": true, "inputs": ["main.s"], "configs": ["x69-bravo.conf"],
+// "config_hash": "0x1f2e...", "base": 0, "size": 300, "sections": [{ "name": ".text", "region":
+// "ROM", "arch": null, "address": 0, "size": 300 }], "symbols": 12, "warnings": 0, "errors": 0,
+// "outputs": ["a.out"], "adjustments": [{ "file": "main.s", "line": 4, "address": 6, "expression":
+// "table", "value": 300, "encoded": 44, "field": "i0:8 of 'ld'", "relaxed_from": null }] }`, with
+// no image and no sections when assembly failed. A section's arch is the ISA its instructions were
+// encoded with when the sources switch between ISAs with `.arch`. Adjustments are the operands
+// truncated to fit their fields or relaxed to a longer form, with lines counted from 1.
+pub fn render(report: &Report, image: Option<&Image>, logs: &[Log]) -> String {
+    let count = |level: fn(&LogLevel) -> bool| logs.iter().filter(|log| level(log.level())).count();
+    let errors = count(|level| matches!(level, LogLevel::Error));
+    let sections: Vec<Value> = image.map_or(&[][..], |image| &image.sections).iter().map(|(name, address, size)| {
+        let region = report.regions.iter().find(|r| r.start <= *address && *address <= r.end).map(|r| r.name.as_str());
+        let arch = image.and_then(|image| image.archs.iter().find(|(section, _)| section == name)).map(|(_, arch)| arch);
+        json!({ "name": name, "region": region, "arch": arch, "address": address, "size": size })
+    }).collect();
+    let adjustments: Vec<Value> = image.map_or(&[][..], |image| &image.adjustments).iter().map(|adjustment| json!({
+        "file": adjustment.file,
+        "line": adjustment.line + 1,
+        "address": adjustment.address,
+        "expression": adjustment.expression,
+        "value": adjustment.value,
+        "encoded": adjustment.encoded,
+        "field": adjustment.field,
+        "relaxed_from": adjustment.relaxed_from,
+    })).collect();
+    let value = json!({
+        "version": VERSION,
+        "success": image.is_some() && errors == 0,
+        "inputs": report.inputs,
+        "configs": report.configs,
+        "config_hash": format!("{:#018x}", report.config_hash),
+        "base": image.map(|image| image.base),
+        "size": image.map(|image| image.code.len()),
+        "sections": sections,
+        "symbols": image.map_or(0, |image| image.labels.len()),
+        "warnings": count(|level| matches!(level, LogLevel::Warning)),
+        "errors": errors,
+        "outputs": report.outputs,
+        "adjustments": adjustments,
+    });
+    serde_json::to_string_pretty(&value).unwrap() + "\n"
+}
@@ -0,0 +1,293 @@
+use crate::lexer::{Keyword, Lexer, Lexeme, Mode, Operator, Span, Token};
+use std::iter::Peekable;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    Zero,
+    Carry,
+    Negative,
+    Overflow,
+}
+
+impl Flag {
+    // Parses the `.z` in `flags.z`
+    fn from_field(field: &str) -> Option<Self> {
+        match field {
+            ".z" => Some(Flag::Zero),
+            ".c" => Some(Flag::Carry),
+            ".n" => Some(Flag::Negative),
+            ".v" => Some(Flag::Overflow),
+            _ => None,
+        }
+    }
+    
+    // The field after `flags.`
+    pub fn name(self) -> &'static str {
+        match self {
+            Flag::Zero => "z",
+            Flag::Carry => "c",
+            Flag::Negative => "n",
+            Flag::Overflow => "v",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add, Sub, Mul, Div, Rem,
+    And, Or, Xor, Shl, Shr,
+    Eq, Ne, Lt, Le, Gt, Ge,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "~",
+            UnaryOp::LogicalNot => "!",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+        }
+    }
+    
+    pub fn from_token(token: &Token) -> Option<(Self, u8)> {
+        let operator = match token {
+            Token::Operator(operator) => operator,
+            _ => return None,
+        };
+        // (operator, binding power)
+        Some(match operator {
+            Operator::Eq => (BinaryOp::Eq, 1),
+            Operator::Ne => (BinaryOp::Ne, 1),
+            Operator::Lt => (BinaryOp::Lt, 2),
+            Operator::Le => (BinaryOp::Le, 2),
+            Operator::Gt => (BinaryOp::Gt, 2),
+            Operator::Ge => (BinaryOp::Ge, 2),
+            Operator::Or => (BinaryOp::Or, 3),
+            Operator::Xor => (BinaryOp::Xor, 4),
+            Operator::And => (BinaryOp::And, 5),
+            Operator::Shl => (BinaryOp::Shl, 6),
+            Operator::Shr => (BinaryOp::Shr, 6),
+            Operator::Add => (BinaryOp::Add, 7),
+            Operator::Sub => (BinaryOp::Sub, 7),
+            Operator::Mul => (BinaryOp::Mul, 8),
+            Operator::Div => (BinaryOp::Div, 8),
+            Operator::Rem => (BinaryOp::Rem, 8),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    // Operand registers and immediates are numbered like in codegen
+    Register(usize),
+    Immediate(usize),
+    Integer(u64),
+    // Address of the next instruction
+    Pc,
+    Flag(Flag),
+    Mem(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Place {
+    Register(usize),
+    Pc,
+    Flag(Flag),
+    Mem(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Assign(Place, Expr),
+    Halt,
+}
+
+fn flag(field: &str, slice: &str) -> Result<Flag, String> {
+    Flag::from_field(field).ok_or_else(|| format!("unknown flag '{}' in semantics, expected flags.z, flags.c, flags.n, or flags.v", slice))
+}
+
+struct Parser<'a> {
+    lexer: Peekable<Lexer<'a, Token<'a>>>,
+    // Span of the token last taken, or the end of the source once there are none left, which is
+    // where any error is found
+    last: Span,
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<(Token<'a>, &'a str)> {
+        match self.lexer.next() {
+            Some(Lexeme { token, slice, span }) => {
+                self.last = span;
+                Some((token, slice))
+            },
+            None => {
+                self.last = self.end..self.end;
+                None
+            },
+        }
+    }
+
+    fn expect(&mut self, token: Token, symbol: &str) -> Result<(), String> {
+        match self.next() {
+            Some((t, _)) if t == token => Ok(()),
+            Some((_, slice)) => Err(format!("expected '{}' in semantics, but got '{}'", symbol, slice)),
+            None => Err(format!("expected '{}' in semantics", symbol)),
+        }
+    }
+
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some((Token::Register(r), _)) => Ok(Expr::Register(r)),
+            Some((Token::Immediate(i), _)) => Ok(Expr::Immediate(i)),
+            Some((Token::Integer(int), _)) => Ok(Expr::Integer(int as u64)),
+            Some((Token::Keyword(Keyword::Flag(field)), slice)) => Ok(Expr::Flag(flag(field, slice)?)),
+            Some((Token::Keyword(Keyword::Pc), _)) => Ok(Expr::Pc),
+            Some((Token::Keyword(Keyword::Mem), _)) => {
+                self.expect(Token::OpenBracket, "[")?;
+                let address = self.expr(0)?;
+                self.expect(Token::CloseBracket, "]")?;
+                Ok(Expr::Mem(Box::new(address)))
+            },
+            Some((Token::OpenParen, _)) => {
+                let expr = self.expr(0)?;
+                self.expect(Token::CloseParen, ")")?;
+                Ok(expr)
+            },
+            Some((Token::Operator(Operator::Sub), _)) => Ok(Expr::Unary(UnaryOp::Negate, Box::new(self.primary()?))),
+            Some((Token::Operator(Operator::Not), _)) => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.primary()?))),
+            Some((Token::Operator(Operator::LogicalNot), _)) => Ok(Expr::Unary(UnaryOp::LogicalNot, Box::new(self.primary()?))),
+            Some((_, slice)) => Err(format!("expected an expression in semantics, but got '{}'", slice)),
+            None => Err("expected an expression in semantics".to_owned()),
+        }
+    }
+
+    // Precedence climbing; all binary operators are left associative
+    fn expr(&mut self, min_power: u8) -> Result<Expr, String> {
+        let mut lhs = self.primary()?;
+        while let Some((op, power)) = self.lexer.peek().and_then(|l| BinaryOp::from_token(&l.token)) {
+            if power < min_power {
+                break;
+            }
+            self.lexer.next();
+            let rhs = self.expr(power + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        let place = match self.next() {
+            Some((Token::Keyword(Keyword::Halt), _)) => return Ok(Statement::Halt),
+            Some((Token::Register(r), _)) => Place::Register(r),
+            Some((Token::Keyword(Keyword::Pc), _)) => Place::Pc,
+            Some((Token::Keyword(Keyword::Flag(field)), slice)) => Place::Flag(flag(field, slice)?),
+            Some((Token::Keyword(Keyword::Mem), _)) => {
+                self.expect(Token::OpenBracket, "[")?;
+                let address = self.expr(0)?;
+                self.expect(Token::CloseBracket, "]")?;
+                Place::Mem(address)
+            },
+            Some((_, slice)) => return Err(format!("expected a register, pc, flag, memory, or 'halt' in semantics, but got '{}'", slice)),
+            None => return Err("expected a statement in semantics".to_owned()),
+        };
+        self.expect(Token::Assign, "=")?;
+        Ok(Statement::Assign(place, self.expr(0)?))
+    }
+}
+
+// Parses `;` separated semantics statements such as `r0 = r0 + r1; flags.z = r0 == 0`
+pub fn parse(source: &str) -> Result<Vec<Statement>, String> {
+    parse_spanned(source).map_err(|(message, _)| message)
+}
+
+// Like `parse`, but errors also give the span of the token they were found at
+pub fn parse_spanned(source: &str) -> Result<Vec<Statement>, (String, Span)> {
+    let mut parser = Parser { lexer: Lexer::with_mode(source, Mode::Semantics).peekable(), last: 0..0, end: source.trim_end().len() };
+    let mut statements = Vec::new();
+    while parser.lexer.peek().is_some() {
+        if let Some(Lexeme { token: Token::Semicolon, .. }) = parser.lexer.peek() {
+            parser.lexer.next();
+            continue;
+        }
+        statements.push(parser.statement().map_err(|message| (message, parser.last.clone()))?);
+        match parser.next() {
+            Some((Token::Semicolon, _)) | None => {},
+            Some((_, slice)) => return Err((format!("expected ';' between semantics statements, but got '{}'", slice), parser.last)),
+        }
+    }
+    Ok(statements)
+}
+
+fn visit_expr(expr: &Expr, f: &mut impl FnMut(&Expr)) {
+    f(expr);
+    match expr {
+        Expr::Mem(address) | Expr::Unary(_, address) => visit_expr(address, f),
+        Expr::Binary(_, lhs, rhs) => {
+            visit_expr(lhs, f);
+            visit_expr(rhs, f);
+        },
+        _ => {},
+    }
+}
+
+// Checks that the semantics only refer to operands captured by the instruction pattern
+pub fn validate(statements: &[Statement], registers: usize, immediates: usize) -> Result<(), String> {
+    let mut error = None;
+    let mut check = |expr: &Expr| match *expr {
+        Expr::Register(r) if r >= registers => error = Some(format!("semantics use register {} which is not given in the instruction pattern", r)),
+        Expr::Immediate(i) if i >= immediates => error = Some(format!("semantics use immediate {} which is not given in the instruction pattern", i)),
+        _ => {},
+    };
+    for statement in statements {
+        match statement {
+            Statement::Assign(Place::Register(r), expr) => {
+                check(&Expr::Register(*r));
+                visit_expr(expr, &mut check);
+            },
+            Statement::Assign(Place::Mem(address), expr) => {
+                visit_expr(address, &mut check);
+                visit_expr(expr, &mut check);
+            },
+            Statement::Assign(_, expr) => visit_expr(expr, &mut check),
+            Statement::Halt => {},
+        }
+    }
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,61 @@
+use crate::directives::Image;
+use crate::macros::Macro;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+// Version of the saved layout, raised whenever a key changes meaning or goes away
+const VERSION: u64 = 1;
+
+// What an interactive session has defined so far, which the REPL saves with `:save` and picks up
+// again with `:load`, and which the LSP keeps for each document it has assembled
+#[derive(Debug, Default, Clone)]
+pub struct Session {
+    pub symbols: BTreeMap<String, usize>,
+    // Address the next statement is placed at
+    pub address: usize,
+    // Macros the session defined, leaving out those shipped with the config
+    pub macros: BTreeMap<String, Macro>,
+}
+
+impl Session {
+    // The labels of an assembled image, with the address just past its code
+    pub fn from_image(image: &Image) -> Self {
+        let symbols = image.labels.iter().map(|(name, address)| (name.clone(), *address)).collect();
+        Self { symbols, address: image.base + image.code.len(), macros: BTreeMap::new() }
+    }
+}
+
+// e.g. `{ "version": 1, "address": 16, "symbols": { "start": 0 }, "macros": { "twice": {
+// "params": ["reg"], "variadic": false, "body": ["add \\reg, \\reg"] } } }`
+pub fn save(session: &Session) -> String {
+    let macros: serde_json::Map<_, _> = session.macros.iter().map(|(name, m)| {
+        (name.clone(), json!({ "params": m.params, "variadic": m.variadic, "body": m.body }))
+    }).collect();
+    let value = json!({
+        "version": VERSION,
+        "address": session.address,
+        "symbols": session.symbols,
+        "macros": macros,
+    });
+    serde_json::to_string_pretty(&value).unwrap() + "\n"
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value.as_array()?.iter().map(|s| s.as_str().map(str::to_owned)).collect()
+}
+
+pub fn restore(text: &str) -> Result<Session, String> {
+    let value: Value = serde_json::from_str(text).map_err(|_| "not a saved session".to_owned())?;
+    if value["version"].as_u64() != Some(VERSION) {
+        return Err("session was saved by a different version".to_owned());
+    }
+    let malformed = || "saved session is malformed".to_owned();
+    let address = value["address"].as_u64().ok_or_else(malformed)? as usize;
+    let symbols = value["symbols"].as_object().ok_or_else(malformed)?.iter()
+        .map(|(name, value)| Some((name.clone(), value.as_u64()? as usize)))
+        .collect::<Option<_>>().ok_or_else(malformed)?;
+    let macros = value["macros"].as_object().ok_or_else(malformed)?.iter()
+        .map(|(name, m)| Some((name.clone(), Macro { params: strings(&m["params"])?, variadic: m["variadic"].as_bool()?, body: strings(&m["body"])? })))
+        .collect::<Option<_>>().ok_or_else(malformed)?;
+    Ok(Session { symbols, address, macros })
+}
@@ -0,0 +1,54 @@
+use crate::emulator::{Flags, Machine, MEMORY_SIZE, REGISTERS};
+
+// Saved machine states, so a long run can be resumed from a checkpoint. Devices mapped over memory
+// keep no state in the snapshot and start fresh when it is restored.
+const MAGIC: &[u8; 4] = b"X69S";
+const VERSION: u8 = 1;
+
+pub fn save(machine: &Machine) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    for register in machine.registers {
+        bytes.extend(register.to_le_bytes());
+    }
+    let flags = [machine.flags.zero, machine.flags.carry, machine.flags.negative, machine.flags.overflow];
+    bytes.push(flags.iter().enumerate().fold(0, |bits, (i, flag)| bits | (*flag as u8) << i));
+    bytes.extend((machine.pc as u64).to_le_bytes());
+    bytes.push(machine.halted as u8);
+    bytes.extend(machine.steps.to_le_bytes());
+    bytes.extend(&machine.memory);
+    bytes
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+pub fn restore(bytes: &[u8]) -> Result<Machine, String> {
+    if bytes.get(..4) != Some(MAGIC.as_slice()) {
+        return Err("not a machine snapshot".to_owned());
+    }
+    if bytes.get(4) != Some(&VERSION) {
+        return Err("snapshot was saved by a different version".to_owned());
+    }
+    let memory = 5 + REGISTERS * 8 + 1 + 8 + 1 + 8;
+    if bytes.len() != memory + MEMORY_SIZE {
+        return Err("snapshot is truncated".to_owned());
+    }
+    let mut registers = [0; REGISTERS];
+    for (i, register) in registers.iter_mut().enumerate() {
+        *register = u64_at(bytes, 5 + i * 8);
+    }
+    let offset = 5 + REGISTERS * 8;
+    let flags = bytes[offset];
+    Ok(Machine {
+        registers,
+        flags: Flags { zero: flags & 1 != 0, carry: flags & 2 != 0, negative: flags & 4 != 0, overflow: flags & 8 != 0 },
+        pc: u64_at(bytes, offset + 1) as usize,
+        halted: bytes[offset + 9] != 0,
+        steps: u64_at(bytes, offset + 10),
+        memory: bytes[memory..].to_vec(),
+    })
+}
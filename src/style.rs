@@ -0,0 +1,39 @@
+// How operands are written when the assembler prints instructions back out, in disassembly,
+// decoded bytes, and the syntaxes suggested when a statement doesn't match, given in the config by
+//
+//     .display hex
+//     .display prefix #
+//     .display register r13 sp
+//
+// so they read the way the ISA's manual writes them. Sources are still read the same way.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OperandStyle {
+    pub hex: bool,
+    // Written before every immediate the pattern doesn't already write it before
+    pub prefix: String,
+    // Names registers are shown by instead of `r13`
+    pub registers: Vec<(usize, String)>,
+}
+
+impl OperandStyle {
+    pub fn register(&self, r: usize) -> String {
+        match self.registers.iter().find(|(register, _)| *register == r) {
+            Some((_, name)) => name.clone(),
+            None => format!("r{}", r),
+        }
+    }
+
+    // The immediate after `text`, the operands rendered so far
+    pub fn immediate(&self, text: &str, value: usize) -> String {
+        let value = if self.hex { format!("0x{:X}", value) } else { value.to_string() };
+        self.prefixed(text, value)
+    }
+
+    // Adds the prefix to an immediate, or to the placeholder standing for one in a syntax
+    pub fn prefixed(&self, text: &str, immediate: String) -> String {
+        match text.ends_with(self.prefix.as_str()) {
+            true => immediate,
+            false => self.prefix.clone() + &immediate,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+
+// Values of named constants and labels defined while assembling
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    values: HashMap<String, usize>,
+    // Symbols that are addresses rather than constants, including provisional ones
+    labels: HashSet<String>,
+    // Values from the previous pass, used for symbols referenced before they are defined
+    provisional: HashMap<String, usize>,
+    // Symbols defined after `.weak` in this pass, which any other definition replaces
+    weak: HashSet<String>,
+    // FNV-1a over every value that can be looked up, so cached encodings can tell when symbols changed
+    fingerprint: u64,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self { values: HashMap::new(), labels: HashSet::new(), provisional: HashMap::new(), weak: HashSet::new(), fingerprint: 0xcbf29ce484222325 }
+    }
+    
+    // A table for the next pass, resolving forward references with the values from this one
+    pub fn next_pass(&self) -> Self {
+        let mut table = Self::new();
+        for (name, value) in self.sorted() {
+            table.mix(name, value);
+        }
+        table.provisional = self.values.clone();
+        table.labels = self.labels.clone();
+        table
+    }
+    
+    fn mix(&mut self, name: &str, value: usize) {
+        for byte in name.bytes().chain(value.to_le_bytes()) {
+            self.fingerprint = (self.fingerprint ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    
+    pub fn define(&mut self, name: &str, value: usize) -> Result<(), String> {
+        if self.weak.remove(name) {
+            self.labels.remove(name);
+            self.redefine(name, value);
+            return Ok(());
+        }
+        if self.values.contains_key(name) {
+            return Err(format!("symbol '{}' is already defined", name));
+        }
+        self.mix(name, value);
+        self.values.insert(name.to_owned(), value);
+        Ok(())
+    }
+    
+    pub fn define_label(&mut self, name: &str, address: usize) -> Result<(), String> {
+        self.define(name, address)?;
+        self.labels.insert(name.to_owned());
+        Ok(())
+    }
+    
+    // Defines a symbol that yields to any other definition of it, returning whether this one is
+    // used, which it isn't when the name was already defined
+    pub fn define_weak(&mut self, name: &str, value: usize) -> bool {
+        if self.values.contains_key(name) {
+            return false;
+        }
+        self.mix(name, value);
+        self.values.insert(name.to_owned(), value);
+        self.weak.insert(name.to_owned());
+        true
+    }
+    
+    pub fn define_weak_label(&mut self, name: &str, address: usize) -> bool {
+        let defined = self.define_weak(name, address);
+        if defined {
+            self.labels.insert(name.to_owned());
+        }
+        defined
+    }
+    
+    pub fn is_weak(&self, name: &str) -> bool { self.weak.contains(name) }
+    
+    // Moves a symbol already defined in this pass, such as a label placed before padding
+    pub fn redefine(&mut self, name: &str, value: usize) {
+        self.mix(name, value);
+        self.values.insert(name.to_owned(), value);
+    }
+    
+    pub fn is_label(&self, name: &str) -> bool { self.labels.contains(name) }
+    
+    pub fn get(&self, name: &str) -> Option<usize> {
+        // A weak symbol may still be replaced later in the pass, so the value it settled on last
+        // pass is the better guess
+        if self.weak.contains(name) {
+            if let Some(value) = self.provisional.get(name) {
+                return Some(*value);
+            }
+        }
+        self.values.get(name).or_else(|| self.provisional.get(name)).copied()
+    }
+    
+    pub fn fingerprint(&self) -> u64 { self.fingerprint }
+    
+    // Symbols sorted by name
+    pub fn sorted(&self) -> Vec<(&str, usize)> {
+        let mut symbols: Vec<_> = self.values.iter().map(|(name, value)| (name.as_str(), *value)).collect();
+        symbols.sort_unstable();
+        symbols
+    }
+    
+    // Names of symbols whose values differ between the tables, sorted
+    pub fn changed<'a>(&'a self, other: &'a SymbolTable) -> Vec<&'a str> {
+        let mut names: Vec<_> = self.values.iter()
+            .filter(|(name, value)| other.values.get(*name) != Some(value))
+            .map(|(name, _)| name.as_str())
+            .chain(other.values.keys().filter(|name| !self.values.contains_key(*name)).map(String::as_str))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
@@ -0,0 +1,84 @@
+use crate::new_parser::push_item;
+use crate::parser::*;
+
+// xorshift64*, so vectors are reproducible from a seed without extra dependencies
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self { Self(seed.max(1)) }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // Uniform-ish value with the given number of bits
+    pub fn bits(&mut self, bits: usize) -> u64 {
+        if bits >= 64 { self.next_u64() } else { self.next_u64() & ((1 << bits) - 1) }
+    }
+}
+
+pub struct Vector {
+    pub source: String,
+    pub bytes: Vec<u8>,
+}
+
+// A random statement matching the pattern, writing immediates in a random radix
+fn statement(name: &str, pattern: &Pattern, rng: &mut Rng) -> String {
+    let mut text = name.to_owned();
+    let mut immediates = 0;
+    for token in &pattern.tokens {
+        match token {
+            PatternToken::Register => push_item(&mut text, &format!("r{}", rng.bits(4))),
+            PatternToken::Immediate => {
+                let value = rng.bits(pattern.immediate_width(immediates).unwrap_or(8));
+                push_item(&mut text, &match rng.bits(2) {
+                    0 => format!("0x{:X}", value),
+                    1 => format!("0b{:b}", value),
+                    _ => format!("{}", value),
+                });
+                immediates += 1;
+            },
+            PatternToken::Comma => push_item(&mut text, ","),
+            PatternToken::Punct(p) => push_item(&mut text, p),
+            PatternToken::Keyword(word) => push_item(&mut text, word),
+        }
+    }
+    text
+}
+
+// Generates `count` statements per pattern and assembles each; statements that fail to assemble
+// are returned separately since they point at a bug in the config or the assembler
+pub fn generate(assembler: &Assembler, count: usize, seed: u64) -> (Vec<Vector>, Vec<String>) {
+    let mut rng = Rng::new(seed);
+    let mut names: Vec<_> = assembler.instructions.keys().collect();
+    names.sort();
+    let mut vectors = Vec::new();
+    let mut failures = Vec::new();
+    for name in names {
+        for pattern in assembler.instructions[name].patterns() {
+            // Patterns without operands only have one possible statement
+            let count = if pattern.tokens.is_empty() { count.min(1) } else { count };
+            for _ in 0..count {
+                let source = statement(name, &pattern, &mut rng);
+                match assembler.assemble_statement(&source).unwrap() {
+                    (Some(bytes), _) => vectors.push(Vector { source, bytes }),
+                    (None, _) => failures.push(source),
+                }
+            }
+        }
+    }
+    (vectors, failures)
+}
+
+// One vector per line as `source<TAB>hex bytes`
+pub fn render(vectors: &[Vector], seed: u64) -> String {
+    let mut out = format!("# x69asm test vectors, seed {}\n", seed);
+    for vector in vectors {
+        let bytes: Vec<String> = vector.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        out += &format!("{}\t{}\n", vector.source, bytes.join(" "));
+    }
+    out
+}
@@ -0,0 +1,61 @@
+use crate::incremental::EncodingCache;
+use colored::*;
+
+// Width used when the terminal size is unknown
+const DEFAULT_WIDTH: usize = 120;
+
+// Bytes shown per row before wrapping onto continuation rows
+const BYTES_PER_ROW: usize = 8;
+
+// Clears the terminal and moves the cursor to the top left
+pub const CLEAR: &str = "\x1b[2J\x1b[H";
+
+// Terminal width from $COLUMNS, since there is no portable way to query it without extra dependencies
+pub fn terminal_width() -> usize {
+    std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(DEFAULT_WIDTH)
+}
+
+fn fit(text: &str, width: usize) -> String {
+    let text = text.replace('\t', "    ");
+    if text.chars().count() > width {
+        let mut text: String = text.chars().take(width.saturating_sub(1)).collect();
+        text.push('…');
+        text
+    } else {
+        format!("{:width$}", text, width = width)
+    }
+}
+
+// Renders each source line next to the address and bytes it assembled to, using the encodings
+// left in `cache` by the latest `assemble_incremental`. Lines that failed show their first error.
+pub fn render(source: &str, cache: &EncodingCache, width: usize) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let number_width = lines.len().max(1).to_string().len();
+    // line number, separators, address, and hex bytes take up the right-hand side
+    let bytes_width = BYTES_PER_ROW * 3 - 1;
+    let source_width = width.saturating_sub(number_width + 3 + 3 + 6 + 2 + bytes_width).max(16);
+    let mut out = String::new();
+    let mut address = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let left = format!("{:>number_width$} | {}", i + 1, fit(line, source_width), number_width = number_width);
+        match cache.get(line) {
+            Some(result) => match result.result() {
+                Some(code) if code.is_empty() => out += &format!("{} |\n", left),
+                Some(code) => {
+                    for (row, chunk) in code.chunks(BYTES_PER_ROW).enumerate() {
+                        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+                        let left = if row == 0 { left.clone() } else { format!("{:number_width$} | {}", "", fit("", source_width), number_width = number_width) };
+                        out += &format!("{} | {}  {}\n", left, format!("{:04X}", address + row * BYTES_PER_ROW).dimmed(), hex.join(" "));
+                    }
+                    address += code.len();
+                },
+                None => {
+                    let message = result.logs().iter().find(|l| l.is_error()).map_or("error", |l| l.message());
+                    out += &format!("{} | {}\n", left, fit(message, 6 + 2 + bytes_width).red());
+                },
+            },
+            None => out += &format!("{} |\n", left),
+        }
+    }
+    out
+}
@@ -0,0 +1,149 @@
+use crate::config;
+use std::io::{self, BufRead, Write};
+
+// Instructions a starter config has when none are given
+const DEFAULT_SKELETONS: [&str; 7] = ["nop", "mov r, r", "add r, r", "sub r, r", "ld r, i", "jmp i", "halt"];
+
+// An instruction asked for by its mnemonic and operand kinds, e.g. `add r, r` or `jmp i`
+struct Skeleton {
+    mnemonic: String,
+    // True for each register operand and false for each immediate, in order
+    operands: Vec<bool>,
+}
+
+fn parse_skeleton(text: &str) -> Result<Skeleton, String> {
+    let (mnemonic, operands) = text.trim().split_once(char::is_whitespace).unwrap_or((text.trim(), ""));
+    if !mnemonic.starts_with(|c: char| c.is_ascii_alphabetic()) || !mnemonic.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("'{}' isn't a mnemonic; it must start with a letter and hold only letters, digits, and underscores", mnemonic));
+    }
+    let operands = operands.split(',').map(str::trim).filter(|o| !o.is_empty()).map(|operand| match operand {
+        "r" | "reg" => Ok(true),
+        "i" | "imm" => Ok(false),
+        _ => Err(format!("'{}' isn't an operand; write 'r' for a register or 'i' for an immediate", operand)),
+    }).collect::<Result<_, _>>()?;
+    Ok(Skeleton { mnemonic: mnemonic.to_lowercase(), operands })
+}
+
+impl Skeleton {
+    // The pattern and codegen of the instruction with the opcode, with registers packed two to a
+    // byte and immediates a word wide
+    fn definition(&self, opcode: usize, word: usize) -> String {
+        let (mut registers, mut immediates) = (0, 0);
+        let mut pattern = Vec::new();
+        for register in &self.operands {
+            if *register {
+                pattern.push(format!("r{}", registers));
+                registers += 1;
+            } else {
+                pattern.push(format!("i{}:{}", immediates, word));
+                immediates += 1;
+            }
+        }
+        let mut codegen = vec![format!("0x{:02X}", opcode)];
+        for pair in (0..registers).collect::<Vec<_>>().chunks(2) {
+            match pair {
+                [upper, lower] => codegen.push(format!("[r{} | r{}]", upper, lower)),
+                [single] => codegen.push(format!("r{}", single)),
+                _ => {},
+            }
+        }
+        codegen.extend((0..immediates).map(|i| format!("i{}", i)));
+        match pattern.is_empty() {
+            true => format!("{} -> {}", self.mnemonic, codegen.join(" ")),
+            false => format!("{} {} -> {}", self.mnemonic, pattern.join(", "), codegen.join(" ")),
+        }
+    }
+
+    // Operand kinds, which two skeletons can't share under one mnemonic
+    fn shape(&self) -> String {
+        let kinds: Vec<_> = self.operands.iter().map(|register| if *register { "r" } else { "i" }).collect();
+        format!("{} {}", self.mnemonic, kinds.join(", "))
+    }
+}
+
+// A starter config for an ISA with the registers, word size in bits, and instructions, e.g.
+//
+//     // 16 registers, r0-r15, and 16-bit words
+//     .reserved r15 sp
+//     nop -> 0x00
+//     add r0, r1 -> 0x01 [r0 | r1]
+//     ld r0, i0:16 -> 0x02 r0 i0
+fn generate(registers: usize, word: usize, stack_pointer: bool, skeletons: &[Skeleton]) -> String {
+    let mut text = format!("// Starter config written by 'x69asm init-isa'\n// {} registers, r0-r{}, and {}-bit words\n", registers, registers - 1, word);
+    text += "// Each line is a pattern, the bytes it encodes to, and optionally what it does in { }\n";
+    if stack_pointer {
+        text += &format!(".reserved r{} sp\n", registers - 1);
+    }
+    text += "\n";
+    for (opcode, skeleton) in skeletons.iter().enumerate() {
+        text += &skeleton.definition(opcode, word);
+        text += "\n";
+    }
+    if let Some(nop) = skeletons.iter().find(|s| s.mnemonic == "nop" && s.operands.is_empty()) {
+        text += &format!("\n// Padding and gaps between sections are filled with it\n.nop {}\n", nop.mnemonic);
+    }
+    text
+}
+
+// Asks the question until the answer is accepted, returning the default for an empty answer or
+// when the input ends
+fn ask<R: BufRead, W: Write, T>(input: &mut R, output: &mut W, question: &str, default: &str, accept: impl Fn(&str) -> Result<T, String>) -> io::Result<T> {
+    loop {
+        write!(output, "{} [{}] ", question, default)?;
+        output.flush()?;
+        let mut answer = String::new();
+        let ended = input.read_line(&mut answer)? == 0;
+        let answer = match answer.trim() {
+            "" => default,
+            answer => answer,
+        };
+        match accept(answer) {
+            Ok(value) => return Ok(value),
+            Err(message) if ended => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message)),
+            Err(message) => writeln!(output, "{}", message)?,
+        }
+    }
+}
+
+// Asks for the registers, word size, and instructions of an ISA, and returns a starter config for
+// it, which has been checked to load
+pub fn run<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<String> {
+    writeln!(output, "Describe the ISA, pressing enter to take the answer in brackets.")?;
+    let registers = ask(&mut input, &mut output, "How many registers does it have, from 1 to 16?", "16", |answer| {
+        answer.parse().ok().filter(|n| (1..=16).contains(n)).ok_or_else(|| "registers are r0-r15, so there can be 1 to 16 of them".to_owned())
+    })?;
+    let word = ask(&mut input, &mut output, "How many bits are in a word, 8, 16, or 32?", "16", |answer| {
+        answer.parse().ok().filter(|n| [8, 16, 32].contains(n)).ok_or_else(|| "words are 8, 16, or 32 bits".to_owned())
+    })?;
+    let stack_pointer = ask(&mut input, &mut output, &format!("Is r{} the stack pointer, so writes to it are warned about? (y/n)", registers - 1), "n", |answer| match answer {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err("answer 'y' or 'n'".to_owned()),
+    })?;
+    writeln!(output, "Give the instructions one per line as a mnemonic and its operands, 'r' for a register and 'i' for an immediate, such as 'add r, r' or 'jmp i'. An empty line ends the list, and with none given it has {}.", DEFAULT_SKELETONS.join(", "))?;
+    let mut skeletons: Vec<Skeleton> = Vec::new();
+    loop {
+        write!(output, "instruction {}: ", skeletons.len() + 1)?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        match parse_skeleton(&line) {
+            Ok(skeleton) if skeletons.iter().any(|s| s.shape() == skeleton.shape()) => writeln!(output, "'{}' is already given", skeleton.shape().trim())?,
+            Ok(_) if skeletons.len() == 256 => writeln!(output, "opcodes are one byte, so there can be at most 256 instructions")?,
+            Ok(skeleton) => skeletons.push(skeleton),
+            Err(message) => writeln!(output, "{}", message)?,
+        }
+    }
+    if skeletons.is_empty() {
+        skeletons = DEFAULT_SKELETONS.iter().map(|s| parse_skeleton(s).unwrap()).collect();
+    }
+    let text = generate(registers, word, stack_pointer, &skeletons);
+    let (assembler, logs) = config::create_assembler_from_configs(&[("[init-isa]", &text)]).unwrap();
+    if assembler.is_none() {
+        let reasons: Vec<_> = logs.iter().map(ToString::to_string).collect();
+        return Err(io::Error::other(format!("the config doesn't load:\n{}", reasons.join("\n"))));
+    }
+    Ok(text)
+}
@@ -0,0 +1,28 @@
+// A statement that mentions a symbol, by its file, line, and address
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub file: String,
+    pub line: usize,
+    pub address: usize,
+}
+
+// Every symbol with its value and the statements that mention it, e.g.
+//
+//     UART_DATA = 0xff00
+//         main.s:12  0x0004
+//         main.s:30  0x0020
+//     scratch = 0x8000, never referenced
+pub fn render(symbols: &[(String, usize, Vec<Site>)]) -> String {
+    let mut text = String::new();
+    for (name, value, sites) in symbols {
+        if sites.is_empty() {
+            text += &format!("{} = {:#06x}, never referenced\n", name, value);
+            continue;
+        }
+        text += &format!("{} = {:#06x}\n", name, value);
+        for site in sites {
+            text += &format!("    {}:{}  {:#06x}\n", site.file, site.line + 1, site.address);
+        }
+    }
+    text
+}